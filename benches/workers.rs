@@ -0,0 +1,64 @@
+//! Throughput of a task per request against a fixed `--workers` pool, sending
+//! to a local hyper server. Run with `cargo bench --bench workers`.
+
+use api_processor::{Client, Config, Endpoint};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::json;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+
+const REQUESTS: usize = 20_000;
+const REQUESTS_PER_SECOND: f64 = 100_000.0;
+
+/// Start a server echoing every request after a short delay, like a fast API
+async fn start_server() -> SocketAddr {
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            Ok::<_, Infallible>(Response::new(Body::from(json!({ "echo": body.len() }).to_string())))
+        }))
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+async fn run(addr: SocketAddr, workers: Option<usize>) {
+    let config = Config::builder()
+        .endpoint(Endpoint::builder(format!("http://{}/", addr)).api_key("bench").build().unwrap())
+        .max_requests_per_second(REQUESTS_PER_SECOND)
+        .max_attempts(1)
+        .workers(workers)
+        .build()
+        .unwrap();
+    let client = Client::new(config).unwrap();
+    let requests = (0..REQUESTS).map(|i| json!({ "input": format!("line-{}", i) }));
+
+    let start = Instant::now();
+    let outcomes: Vec<_> = client.stream(requests).collect().await;
+    let elapsed = start.elapsed();
+    let failed = outcomes.iter().filter(|outcome| outcome.is_err()).count();
+    let label = workers.map_or_else(|| "task per request".to_string(), |workers| format!("--workers {}", workers));
+    println!(
+        "{:<18} {:>7.2} s {:>9.0} requests/s {:>7} results {:>6} errors",
+        label,
+        elapsed.as_secs_f64(),
+        REQUESTS as f64 / elapsed.as_secs_f64(),
+        outcomes.len() - failed,
+        failed
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let addr = start_server().await;
+    println!("{} requests at {} requests/s, one attempt each", REQUESTS, REQUESTS_PER_SECOND);
+    for workers in [None, Some(64), Some(256)].iter() {
+        run(addr, *workers).await;
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+/// Exponential backoff applied between retries of a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackoffPolicy {
+    /// Delay before the first retry
+    pub base: Duration,
+    /// Factor the delay grows by with each further attempt
+    pub multiplier: f64,
+    /// Upper bound on any single delay
+    pub max: Duration,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        BackoffPolicy {
+            base: Duration::from_secs(1),
+            multiplier: 2.0,
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// Delay before retrying after `attempt` failed attempts: `min(base * multiplier^attempt, max)`
+    ///
+    /// Computed in floating point and clamped, so large attempt counts
+    /// saturate at `max` instead of overflowing.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.min(i32::MAX as u32) as i32;
+        let delay_secs = self.base.as_secs_f64() * self.multiplier.powi(exponent);
+        if delay_secs.is_finite() && delay_secs < self.max.as_secs_f64() {
+            Duration::from_secs_f64(delay_secs)
+        } else {
+            self.max
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::record::{fnv1a, load_response, save_response};
+use hyper::body::Bytes;
+use hyper::header::{HeaderMap, CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING};
+use hyper::{Body, Response, StatusCode, Uri};
+use log::{debug, error};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Successful responses kept on disk by `Config::cache_dir`, answering the same request in later runs
+///
+/// A response is kept under a hash of the request's URL and body as sent, so
+/// anything that changes the request, such as the endpoint's URL, query,
+/// payload template or model, makes it a different entry; so does each repeat
+/// of a line with `Config::repeat`. Responses are stored in the same two files
+/// as recordings, with the body already decoded. The files are read and
+/// written on tokio's blocking threads.
+#[derive(Debug)]
+pub(crate) struct ResponseCache {
+    dir: PathBuf,
+    /// How long a response answers requests after it was stored; forever without one
+    ttl: Option<Duration>,
+}
+
+impl ResponseCache {
+    /// Keep responses in a directory, creating it if needed
+    pub(crate) fn open(dir: &str, ttl: Option<Duration>) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(ResponseCache { dir: PathBuf::from(dir), ttl })
+    }
+
+    /// The key of a request to a URL with a body, for this repeat of its line
+    ///
+    /// The first repeat keeps the key of a line that is not repeated.
+    pub(crate) fn key(uri: &Uri, body: &[u8], repeat_index: usize) -> String {
+        let repeat = if repeat_index == 0 { String::new() } else { format!("\0{}", repeat_index) };
+        format!("{:016x}", fnv1a([uri.to_string().as_bytes(), b"\0", body, repeat.as_bytes()]))
+    }
+
+    /// The response stored under a key, unless there is none or it has expired
+    pub(crate) async fn get(&self, key: &str) -> Option<Response<Body>> {
+        let (dir, ttl, key) = (self.dir.clone(), self.ttl, key.to_string());
+        tokio::task::spawn_blocking(move || lookup(&dir, ttl, &key)).await.ok().flatten()
+    }
+
+    /// Store a response with its decoded body, logging rather than failing the request if that is not possible
+    pub(crate) async fn put(&self, key: &str, status: StatusCode, headers: &HeaderMap, body: Bytes) {
+        let mut headers = headers.clone();
+        for name in [CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING] {
+            headers.remove(name);
+        }
+        let (dir, owned_key) = (self.dir.clone(), key.to_string());
+        let saved = tokio::task::spawn_blocking(move || save_response(&dir, &owned_key, status, &headers, &body))
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+        match saved {
+            Ok(()) => debug!("Cached response {}", key),
+            Err(e) => error!("Failed to cache response {} in {}: {}", key, self.dir.display(), e),
+        }
+    }
+}
+
+fn lookup(dir: &Path, ttl: Option<Duration>, key: &str) -> Option<Response<Body>> {
+    let stored = std::fs::metadata(dir.join(format!("{}.json", key))).and_then(|metadata| metadata.modified()).ok()?;
+    let age = SystemTime::now().duration_since(stored).unwrap_or_default();
+    if ttl.is_some_and(|ttl| age > ttl) {
+        debug!("Cached response {} expired {:?} ago", key, age - ttl.unwrap_or_default());
+        return None;
+    }
+    match load_response(dir, key) {
+        Ok(response) => Some(response),
+        Err(e) => {
+            debug!("Cached response {} cannot be used: {}", key, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::config::Config;
+use crate::endpoint::LoadBalancer;
+use crate::error::ClientError;
+use crate::rate::Quota;
+use log::{debug, error, info, warn};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Version of the checkpoint file's layout
+const CHECKPOINT_VERSION: u64 = 1;
+
+/// How far a run has got, saved to `Config::checkpoint_filepath` for `Config::resume` to start from
+///
+/// The file is a small JSON object:
+///
+/// ```json
+/// {"version": 1, "saved_at": 1760000000.5, "repeat": 1,
+///  "completed_through": 119999, "next_task_id": 120000,
+///  "endpoints": {"https://api.example.com/v1/chat/completions": {"quota": {"remaining": 40, "reset_at": 1760000012.0}}}}
+/// ```
+///
+/// Every task before `next_task_id` has its outcome flushed to the save or
+/// error file, or never gets one, like lines of other shards; it is only ever
+/// written right after a flush, so it never gets ahead of the files.
+/// `completed_through` is the task before it, or `null` when there is none.
+/// `endpoints` keeps each endpoint's reported quota that has not reset yet.
+#[derive(Debug)]
+pub(crate) struct Checkpoint {
+    path: String,
+    every: usize,
+    interval: Duration,
+    repeat: usize,
+    endpoints: Vec<String>,
+    balancer: Arc<LoadBalancer>,
+    progress: Mutex<Progress>,
+}
+
+#[derive(Debug)]
+struct Progress {
+    /// Every task before this one was read, and has an outcome in `pending` or needs none
+    reached: usize,
+    /// Tasks whose outcome is not flushed yet
+    pending: BTreeSet<usize>,
+    /// Outcomes flushed since the file was last saved
+    flushed: usize,
+    saved_at: Instant,
+}
+
+impl Checkpoint {
+    pub(crate) fn new(config: &Config, balancer: Arc<LoadBalancer>) -> Option<Self> {
+        Some(Checkpoint {
+            path: config.checkpoint_filepath.clone()?,
+            every: config.checkpoint_every,
+            interval: config.checkpoint_interval,
+            repeat: config.repeat,
+            endpoints: config.endpoints.iter().map(|endpoint| endpoint.url.clone()).collect(),
+            balancer,
+            progress: Mutex::new(Progress {
+                reached: 0,
+                pending: BTreeSet::new(),
+                flushed: 0,
+                saved_at: Instant::now(),
+            }),
+        })
+    }
+
+    /// Start counting a run whose first task is this one
+    pub(crate) fn start(&self, first_task_id: usize) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.reached = first_task_id;
+        progress.pending.clear();
+    }
+
+    /// Every task before this one was read, and those with an outcome to come were `started`
+    pub(crate) fn reached(&self, task_id: usize) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.reached = progress.reached.max(task_id);
+    }
+
+    /// A task whose outcome is still to be written
+    pub(crate) fn started(&self, task_id: usize) {
+        self.progress.lock().unwrap().pending.insert(task_id);
+    }
+
+    /// Outcomes that were just flushed, saving the file when `Config::checkpoint_every` of them or `Config::checkpoint_interval` has passed
+    pub(crate) fn flushed(&self, task_ids: impl IntoIterator<Item = usize>) {
+        let due = {
+            let mut progress = self.progress.lock().unwrap();
+            for task_id in task_ids {
+                progress.pending.remove(&task_id);
+                progress.flushed += 1;
+            }
+            progress.flushed > 0 && (progress.flushed >= self.every || progress.saved_at.elapsed() >= self.interval)
+        };
+        if due {
+            self.save();
+        }
+    }
+
+    /// Whether enough outcomes are waiting to be flushed that the file is due
+    pub(crate) fn due(&self, unflushed: usize) -> bool {
+        self.progress.lock().unwrap().flushed + unflushed >= self.every
+    }
+
+    /// Replace the file with the progress made so far, logging rather than failing the run if that is not possible
+    ///
+    /// Written to a temporary file first, so a kill mid-write leaves the previous checkpoint.
+    pub(crate) fn save(&self) {
+        let next_task_id = {
+            let mut progress = self.progress.lock().unwrap();
+            progress.flushed = 0;
+            progress.saved_at = Instant::now();
+            progress.pending.iter().next().map_or(progress.reached, |first| progress.reached.min(*first))
+        };
+        let endpoints: Map<String, Value> = self
+            .endpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(index, url)| Some((url.clone(), json!({ "quota": self.balancer.quota(index)?.to_json() }))))
+            .collect();
+        let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let checkpoint = json!({
+            "version": CHECKPOINT_VERSION,
+            "saved_at": saved_at,
+            "repeat": self.repeat,
+            "completed_through": next_task_id.checked_sub(1),
+            "next_task_id": next_task_id,
+            "endpoints": endpoints,
+        });
+        let temp_path = format!("{}.tmp", self.path);
+        match std::fs::write(&temp_path, checkpoint.to_string() + "\n").and_then(|_| std::fs::rename(&temp_path, &self.path)) {
+            Ok(()) => debug!("Saved checkpoint at task {} to {}", next_task_id, self.path),
+            Err(e) => error!("Failed to save checkpoint to {}: {}", self.path, e),
+        }
+    }
+}
+
+/// The input lines a resumed run skips, read from the checkpoint for `Config::resume`
+///
+/// The endpoints' quotas are restored from it as well. A missing file starts
+/// the run from `Config::skip`; a checkpoint of a run with another
+/// `Config::repeat` is refused, as its task ids name other lines.
+pub(crate) fn resume(config: &Config, balancer: &LoadBalancer) -> Result<Option<usize>, ClientError> {
+    let path = match (&config.checkpoint_filepath, config.resume) {
+        (Some(path), true) => path,
+        _ => return Ok(None),
+    };
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("No checkpoint at {}, starting from the beginning", path);
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let invalid = |problem: &str| ClientError::InvalidConfig(format!("checkpoint {} {}", path, problem));
+    let checkpoint: Value = serde_json::from_str(&contents).map_err(|e| invalid(&format!("is not valid JSON: {}", e)))?;
+    if checkpoint["version"].as_u64() != Some(CHECKPOINT_VERSION) {
+        return Err(invalid("has an unknown version"));
+    }
+    let next_task_id = checkpoint["next_task_id"].as_u64().ok_or_else(|| invalid("has no next_task_id"))? as usize;
+    if checkpoint["repeat"].as_u64() != Some(config.repeat as u64) {
+        return Err(invalid(&format!("was saved by a run with repeat {}, not {}", checkpoint["repeat"], config.repeat)));
+    }
+    for (index, endpoint) in config.endpoints.iter().enumerate() {
+        if let Some(quota) = checkpoint["endpoints"][&endpoint.url].get("quota").and_then(Quota::from_json) {
+            balancer.record_quota(index, quota);
+        }
+    }
+    // A line with only some of its repeats done is sent again whole
+    let line = next_task_id / config.repeat;
+    info!("Resuming from checkpoint {} at line {}", path, line);
+    Ok(Some(line))
+}
@@ -0,0 +1,2499 @@
+use crate::cache::ResponseCache;
+use crate::checkpoint::{self, Checkpoint};
+use crate::concurrency::AdaptiveConcurrency;
+use crate::config::Config;
+use crate::connector::HttpClients;
+use crate::cost::Usage;
+use crate::endpoint::{Endpoint, LoadBalancer};
+use crate::error::ClientError;
+use crate::follow::follow_lines;
+use crate::har::HarLog;
+use crate::keys::KeyPool;
+use crate::lines::{count_lines, send_lines, LineError};
+use crate::merge::compact_result_file;
+use crate::oauth::TokenSource;
+use crate::observer::RequestObserver;
+use crate::otel::{self, AttemptSpan, Tracer};
+use crate::output::{truncate_outputs, write_partitioned_results};
+use crate::pause::PauseHandle;
+use crate::queue::{self, Spill};
+use crate::preflight::{probe_endpoints, CanaryResult, Probe, ProbeResult};
+use crate::rate::{Pacer, Quota, RetryLimiter, TokenLimiter};
+use crate::redirect::{send_following_redirects, SendError};
+use crate::report::FailureCategory;
+use crate::request::{task_id_generator, APIRequest, ProcessedResult};
+use crate::response::{decode_body, read_body, BodyError};
+use crate::retry_state::RetryState;
+use crate::shutdown::ShutdownHandle;
+use crate::sigv4::sign;
+use crate::tracker::StatusTracker;
+use crate::transform::EndpointTransform;
+use crate::url_template::UrlTemplate;
+use chrono::Local;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, USER_AGENT};
+use hyper::body::Bytes;
+use hyper::{Body, Method, Request, StatusCode, Uri};
+use log::{debug, error, info, trace, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::BufReader;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{sleep, sleep_until, timeout_at, Instant};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+/// Load-balanced API client
+pub struct Client {
+    http: Arc<HttpClients>,
+    config: Arc<Config>,
+    status_tracker: Arc<Mutex<StatusTracker>>,
+    balancer: Arc<LoadBalancer>,
+    /// Access tokens for OAuth endpoints, by endpoint index
+    tokens: Arc<Vec<Option<Arc<TokenSource>>>>,
+    /// Parsed URL and headers, by endpoint index
+    prepared: Arc<Vec<PreparedEndpoint>>,
+    user_agent: HeaderValue,
+    pause: PauseHandle,
+    shutdown: ShutdownHandle,
+    /// Set once the last run stopped at `Config::abort_after_consecutive_failures`
+    aborted: Arc<AtomicBool>,
+    /// Hooks told about every request, if an embedder set them
+    observer: Option<Arc<dyn RequestObserver>>,
+    /// Adapts requests and responses after the endpoints' provider profiles, if an embedder set it
+    transform: Option<Arc<dyn EndpointTransform>>,
+    /// Limit on requests in flight, with `Config::adaptive_concurrency`; kept across runs
+    concurrency: Option<Arc<AdaptiveConcurrency>>,
+    /// Exports spans of every task and attempt, with `Config::otel_endpoint`
+    tracer: Option<Arc<Tracer>>,
+    /// Made by the first run, so creating a client touches no file
+    files: Mutex<Option<Arc<RunFiles>>>,
+    /// Whether the save and error files were emptied for this client's first run
+    outputs_truncated: AtomicBool,
+    /// Save files of `Config::output_by` values emptied by an earlier run of this client
+    partitions: Mutex<HashSet<String>>,
+}
+
+/// Files and directories runs write to besides the save and error files, kept across runs
+#[derive(Debug, Default)]
+struct RunFiles {
+    /// Responses kept across runs, with `Config::cache_dir`
+    response_cache: Option<Arc<ResponseCache>>,
+    /// How far runs have got, saved to `Config::checkpoint_filepath`
+    checkpoint: Option<Arc<Checkpoint>>,
+    /// Every request and response of this client's runs, with `Config::har_filepath`
+    har: Option<Arc<HarLog>>,
+}
+
+/// Per-endpoint values computed once instead of on every request
+#[derive(Debug)]
+struct PreparedEndpoint {
+    url: UrlTemplate,
+    /// Header the static API keys are sent in, `Authorization` unless the endpoint names another
+    auth_header: HeaderName,
+    /// Value of that header for each static API key; OAuth endpoints send a bearer token instead
+    authorization: Vec<HeaderValue>,
+    /// Which key each request is sent with
+    keys: KeyPool,
+}
+
+impl PreparedEndpoint {
+    fn new(endpoint: &Endpoint) -> Result<Self, ClientError> {
+        let url = UrlTemplate::new(&endpoint.request_url()).map_err(ClientError::InvalidConfig)?;
+        let auth_header = match &endpoint.auth_header {
+            Some(header) => HeaderName::from_bytes(header.as_bytes())
+                .map_err(|_| ClientError::InvalidConfig(format!("invalid auth_header '{}' for endpoint {}", header, endpoint.url)))?,
+            None => AUTHORIZATION,
+        };
+        let authorization = endpoint
+            .keys()
+            .into_iter()
+            .map(|key| {
+                let value = match &endpoint.auth_header {
+                    Some(_) => key.to_string(),
+                    None => format!("Bearer {}", key),
+                };
+                let mut authorization = HeaderValue::from_str(&value)
+                    .map_err(|_| ClientError::InvalidConfig(format!("API key for endpoint {} is not a valid header value", endpoint.url)))?;
+                authorization.set_sensitive(true);
+                Ok(authorization)
+            })
+            .collect::<Result<Vec<_>, ClientError>>()?;
+        let keys = KeyPool::new(endpoint.key_rotation, authorization.len());
+        Ok(PreparedEndpoint {
+            url,
+            auth_header,
+            authorization,
+            keys,
+        })
+    }
+}
+
+impl Client {
+    /// Create a client for the given config
+    ///
+    /// Fails if a configured client certificate cannot be loaded or an API key
+    /// cannot be sent as a header. Files and directories are only read and
+    /// made once the first run starts, so a client can check a config without
+    /// touching them.
+    pub fn new(config: Config) -> Result<Self, ClientError> {
+        let http = HttpClients::new(&config)?;
+        let prepared = config.endpoints.iter().map(PreparedEndpoint::new).collect::<Result<Vec<_>, _>>()?;
+        let user_agent = HeaderValue::from_str(&config.user_agent)
+            .map_err(|_| ClientError::InvalidConfig(format!("invalid user agent '{}'", config.user_agent)))?;
+        let balancer = Arc::new(LoadBalancer::new(config.lb_strategy, &config.endpoints, config.seed).with_max_concurrency(config.max_concurrency));
+        let tokens = config
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let oauth = endpoint.oauth.clone()?;
+                Some(TokenSource::new(oauth, http.for_endpoint(endpoint).clone()))
+            })
+            .collect();
+        let tracer = otel::tracer(&config, http.default_client());
+        let concurrency = config.adaptive_concurrency.then(AdaptiveConcurrency::new);
+        Ok(Client {
+            tokens: Arc::new(tokens),
+            prepared: Arc::new(prepared),
+            user_agent,
+            http: Arc::new(http),
+            balancer,
+            config: Arc::new(config),
+            status_tracker: Arc::new(Mutex::new(StatusTracker::default())),
+            pause: PauseHandle::default(),
+            shutdown: ShutdownHandle::default(),
+            aborted: Arc::default(),
+            observer: None,
+            transform: None,
+            concurrency,
+            tracer,
+            files: Mutex::default(),
+            outputs_truncated: AtomicBool::new(false),
+            partitions: Mutex::default(),
+        })
+    }
+
+    /// Open the files and directories runs write to, the first time a run starts
+    ///
+    /// Also returns the input lines the checkpoint says are done, with
+    /// `Config::resume`, but only to the run that opened them: later runs of
+    /// the client start from `Config::skip`. A run that fails to open them
+    /// leaves them to the next.
+    fn open_run_files(&self) -> Result<(Arc<RunFiles>, Option<usize>), ClientError> {
+        let mut files = self.files.lock().unwrap();
+        if let Some(files) = &*files {
+            return Ok((Arc::clone(files), None));
+        }
+        let config = &self.config;
+        let resume_skip = checkpoint::resume(config, &self.balancer)?;
+        let har = match &config.har_filepath {
+            Some(path) => Some(Arc::new(HarLog::create(path, config.max_response_bytes)?)),
+            None => None,
+        };
+        if let Some(dir) = &config.queue_spill_dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        let response_cache = match &config.cache_dir {
+            Some(dir) => Some(Arc::new(ResponseCache::open(dir, config.cache_ttl)?)),
+            None => None,
+        };
+        let opened = Arc::new(RunFiles {
+            response_cache,
+            checkpoint: Checkpoint::new(config, Arc::clone(&self.balancer)).map(Arc::new),
+            har,
+        });
+        *files = Some(Arc::clone(&opened));
+        Ok((opened, resume_skip))
+    }
+
+    /// The config this client was created with
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Shared handle to the status counters, updated while requests are processed
+    pub fn status_tracker(&self) -> Arc<Mutex<StatusTracker>> {
+        Arc::clone(&self.status_tracker)
+    }
+
+    /// Handle for pausing and resuming this client's runs from another task
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause.clone()
+    }
+
+    /// Handle for stopping this client's runs early from another task, e.g. on a signal
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Tell the observer about every request this client sends, retries and finishes
+    pub fn with_observer(mut self, observer: Arc<dyn RequestObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Adapt every request and response with this transform, after the endpoints' provider profiles
+    pub fn with_transform(mut self, transform: Arc<dyn EndpointTransform>) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Send a probe to every endpoint, e.g. to catch typo'd URLs and expired keys before a run
+    pub async fn preflight(&self, probe: &Probe) -> Vec<ProbeResult> {
+        probe_endpoints(&self.http, &self.config.endpoints, probe).await
+    }
+
+    /// Send one input line to every endpoint through the whole request path, e.g. to catch auth and payload errors before a run
+    ///
+    /// Each endpoint gets a single attempt, judged like any request of the
+    /// run, and nothing is written, recorded, traced or counted. The line's
+    /// `route_key` is ignored so that it reaches every endpoint. Results are
+    /// in endpoint order.
+    pub async fn canary(&self, mut line: Value) -> Vec<CanaryResult> {
+        if let Some(fields) = line.as_object_mut() {
+            fields.remove("route_key");
+        }
+        let handles: Vec<_> = self
+            .config
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let config = self.config.for_canary(endpoint);
+                let transform = self.transform.clone();
+                let line = line.clone();
+                let url = endpoint.url.clone();
+                tokio::spawn(async move {
+                    let start = Instant::now();
+                    let client = match config.and_then(Client::new) {
+                        Ok(client) => client,
+                        Err(e) => {
+                            return CanaryResult {
+                                url,
+                                latency: start.elapsed(),
+                                outcome: Err(e.to_string()),
+                            }
+                        }
+                    };
+                    let client = Client { transform, ..client };
+                    let outcome = client.stream(Some(line)).next().await;
+                    let (latency, outcome) = match outcome {
+                        Some(Ok(result)) => (result.latency, Ok(())),
+                        Some(Err(ClientError::Request { error: Value::String(error), .. })) => (start.elapsed(), Err(error)),
+                        Some(Err(ClientError::Request { error, .. })) => (start.elapsed(), Err(error.to_string())),
+                        Some(Err(e)) => (start.elapsed(), Err(e.to_string())),
+                        None => (start.elapsed(), Err("no outcome".to_string())),
+                    };
+                    CanaryResult { url, latency, outcome }
+                })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(handles.len());
+        for handle in handles {
+            results.push(handle.await.expect("canary task panicked"));
+        }
+        results
+    }
+
+    /// Check an input line the way a run would before sending it, without sending anything
+    ///
+    /// Returns every problem found: a line that is not a JSON object, or fails
+    /// the input schema, control fields or derived fields, and for each
+    /// endpoint serving it, a payload template or URL placeholder it cannot fill.
+    pub fn check_line(&self, line: &Value) -> Vec<String> {
+        let object = match line.as_object() {
+            Some(object) => object,
+            None => return vec!["the line is not a JSON object".to_string()],
+        };
+        let mut fields = object.clone();
+        match check_request_json(&self.config, line) {
+            Ok(derived) => fields.extend(derived),
+            Err(problem) => return vec![problem],
+        }
+        let route_key = object.get("route_key").and_then(Value::as_str);
+        let fields = Value::Object(fields);
+        let mut problems = Vec::new();
+        let mut served = false;
+        for (endpoint, prepared) in self.config.endpoints.iter().zip(self.prepared.iter()) {
+            if route_key.is_some() && endpoint.route_key.as_deref() != route_key {
+                continue;
+            }
+            served = true;
+            if let Err(e) = self.config.request_body.build(&fields, endpoint.payload_template.as_ref()) {
+                problems.push(format!("endpoint {}: {}", endpoint.url, e));
+            }
+            if let Err(e) = prepared.url.render(&fields) {
+                problems.push(format!("endpoint {}: {}", endpoint.url, e));
+            }
+        }
+        if !served {
+            problems.push(format!("no endpoint for route key '{}'", route_key.unwrap_or_default()));
+        }
+        problems
+    }
+
+    /// Process API requests from a JSONL file, writing results to the save and error files
+    pub async fn process_file(&self, requests_filepath: &str) -> Result<(), ClientError> {
+        self.config.check_requests_file(requests_filepath)?;
+        let results = self.stream_file(requests_filepath).await?;
+        self.save(results).await
+    }
+
+    /// Process API requests from several JSONL files in turn, writing results to the save and error files
+    pub async fn process_files<P: AsRef<str>>(&self, requests_filepaths: &[P]) -> Result<(), ClientError> {
+        for path in requests_filepaths {
+            self.config.check_requests_file(path.as_ref())?;
+        }
+        self.save(self.stream_files(requests_filepaths)).await
+    }
+
+    /// Process API requests appended to a JSONL file until the process is stopped, like `tail -f`
+    pub async fn process_file_follow(&self, requests_filepath: &str) -> Result<(), ClientError> {
+        self.config.check_requests_file(requests_filepath)?;
+        self.save(self.stream_file_follow(requests_filepath)).await
+    }
+
+    /// Process API requests from an iterator of JSON objects, writing results to the save and error files
+    pub async fn process_requests<I>(&self, requests: I) -> Result<(), ClientError>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: Send + 'static,
+    {
+        self.save(self.stream(requests)).await
+    }
+
+    /// Write outcomes to the save and error files, counting a failed write as an error
+    ///
+    /// Unless `Config::append` is set, the files are emptied before the first
+    /// run of this client writes to them; later runs add to them.
+    async fn save<S>(&self, results: S) -> Result<(), ClientError>
+    where
+        S: Stream<Item = Result<ProcessedResult, ClientError>>,
+    {
+        let written = async {
+            if !self.config.append && !self.outputs_truncated.swap(true, Ordering::SeqCst) {
+                truncate_outputs(&self.config)?;
+            }
+            let mut partitions = std::mem::take(&mut *self.partitions.lock().unwrap());
+            let checkpoint = self.files.lock().unwrap().as_ref().and_then(|files| files.checkpoint.clone());
+            let written = write_partitioned_results(results, &self.config, &mut partitions, checkpoint.clone()).await;
+            self.partitions.lock().unwrap().extend(partitions);
+            if let Some(checkpoint) = &checkpoint {
+                checkpoint.save();
+            }
+            written?;
+            if self.config.upsert {
+                let partitions = self.partitions.lock().unwrap().clone();
+                for path in std::iter::once(&self.config.save_filepath).chain(&partitions) {
+                    let stats = compact_result_file(path)?;
+                    if stats.duplicates > 0 {
+                        info!("Replaced {} earlier records of the same task ids in {}", stats.duplicates, path);
+                    }
+                }
+            }
+            Ok::<(), ClientError>(())
+        };
+        written.await.map_err(|e| {
+            error!("Stopping the run: {}", e);
+            self.status_tracker.lock().unwrap().num_other_errors += 1;
+            e
+        })?;
+        match self.config.abort_after_consecutive_failures {
+            Some(failures) if self.aborted.load(Ordering::SeqCst) => Err(ClientError::Aborted(format!("{} tasks failed in a row", failures))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stream the outcome of each request in a JSONL file as it completes
+    pub async fn stream_file(
+        &self,
+        requests_filepath: &str,
+    ) -> Result<impl Stream<Item = Result<ProcessedResult, ClientError>>, ClientError> {
+        // Read the requests file
+        let file = File::open(requests_filepath).await?;
+        let path = requests_filepath.to_string();
+        let max_line_bytes = self.config.max_line_bytes;
+        // Only needed to tell whether the lines reach the task ids of reloaded retries
+        let input_lines = match self.config.retry_state_filepath {
+            Some(_) => Some(count_lines(requests_filepath, max_line_bytes).await?),
+            None => None,
+        };
+        let (lines_tx, lines_rx) = mpsc::channel(self.config.effective_queue_capacity());
+        tokio::spawn(async move {
+            send_lines(&path, BufReader::new(file), max_line_bytes, &lines_tx, parse_line).await;
+        });
+        Ok(self.run(ReceiverStream::new(lines_rx), true, input_lines))
+    }
+
+    /// Stream the outcome of each request in a JSONL file, waiting for more lines at the end
+    ///
+    /// Only complete lines are parsed, so a line still being written is never
+    /// dropped as invalid. Truncation and rotation of the file are followed.
+    /// The stream does not end on its own.
+    pub fn stream_file_follow(&self, requests_filepath: &str) -> impl Stream<Item = Result<ProcessedResult, ClientError>> {
+        let (lines_tx, lines_rx) = mpsc::channel(self.config.effective_queue_capacity());
+        tokio::spawn(follow_lines(requests_filepath.to_string(), self.config.max_line_bytes, lines_tx));
+        self.run(ReceiverStream::new(lines_rx).map(parse_line), false, None)
+    }
+
+    /// Stream the outcome of each request in several JSONL files, read one after another
+    ///
+    /// Task ids continue from one file to the next. A file that cannot be
+    /// opened or read is logged and the remaining files are still processed.
+    pub fn stream_files<P: AsRef<str>>(
+        &self,
+        requests_filepaths: &[P],
+    ) -> impl Stream<Item = Result<ProcessedResult, ClientError>> {
+        let paths: Vec<String> = requests_filepaths.iter().map(|path| path.as_ref().to_string()).collect();
+        let max_line_bytes = self.config.max_line_bytes;
+        let (lines_tx, lines_rx) = mpsc::channel(self.config.effective_queue_capacity());
+        tokio::spawn(async move {
+            for path in paths {
+                let file = match File::open(&path).await {
+                    Ok(file) => file,
+                    Err(e) => {
+                        error!("Failed to open {}: {}", path, e);
+                        continue;
+                    }
+                };
+                if !send_lines(&path, BufReader::new(file), max_line_bytes, &lines_tx, parse_line).await {
+                    return;
+                }
+            }
+        });
+        self.run(ReceiverStream::new(lines_rx), true, None)
+    }
+
+    /// Stream the outcome of each request from an iterator of JSON objects as it completes
+    pub fn stream<I>(&self, requests: I) -> impl Stream<Item = Result<ProcessedResult, ClientError>>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: Send + 'static,
+    {
+        let requests = requests.into_iter();
+        let input_lines = requests.size_hint().1;
+        self.run(tokio_stream::iter(requests.map(Ok)), true, input_lines)
+    }
+
+    /// Feed requests through the rate-limited producer and the consumer loop
+    ///
+    /// The returned stream ends once the input is exhausted and every task,
+    /// including pending retries, has finished. Each input item consumes a
+    /// task id, even if it is skipped or unreadable, so ids match line numbers.
+    ///
+    /// Once `Config::max_runtime` has passed, lines not yet sent are left
+    /// unprocessed; the rest of an input that ends is read to count them too.
+    ///
+    /// Requests reloaded from `Config::retry_state_filepath` keep their task
+    /// ids, so a run whose input could reach one of them is refused: its
+    /// stream holds only the error. `input_lines` bounds the number of input
+    /// items when known; otherwise the input is taken to have no end.
+    fn run<S>(&self, requests: S, input_ends: bool, input_lines: Option<usize>) -> impl Stream<Item = Result<ProcessedResult, ClientError>>
+    where
+        S: Stream<Item = Result<Value, LineError>> + Send + 'static,
+    {
+        // A run that cannot open them sends nothing
+        let (files, resume_skip, unopened) = match self.open_run_files() {
+            Ok((files, resume_skip)) => (files, resume_skip, None),
+            Err(e) => (Arc::default(), None, Some(e)),
+        };
+        // A larger skip than the checkpoint's wins
+        let skip = resume_skip.map_or(self.config.skip, |line| line.max(self.config.skip));
+        let mut limit = self.config.limit.unwrap_or(usize::MAX);
+        let shard = self.config.shard;
+        let sample = self.config.sample;
+        let mut task_id_gen = task_id_generator().skip(skip);
+        let batch_size = self.config.batch_size;
+        let mut batch: Vec<APIRequest> = Vec::with_capacity(batch_size);
+        let repeat = self.config.repeat;
+
+        // Queue for requests, highest priority first; when it is full the
+        // producer waits, which backpressures reading the input, unless
+        // requests are spilled to disk
+        let queue_capacity = self.config.effective_queue_capacity();
+        let spill = self.config.queue_spill_dir.as_ref().and_then(|dir| match Spill::create(Path::new(dir)) {
+            Ok(spill) => Some(spill),
+            Err(e) => {
+                error!("Failed to create a spill file in {}, waiting for room in the queue instead: {}", dir, e);
+                None
+            }
+        });
+        let (tx, mut rx) = queue::channel(queue_capacity, spill);
+
+        // Retries get their own channel so they never wait behind fresh work;
+        // it holds at most one request per task in progress
+        let (retry_tx, mut retry_rx) = mpsc::unbounded_channel::<APIRequest>();
+
+        // Channel for handing outcomes to the caller
+        let (results_tx, results_rx) = mpsc::channel(queue_capacity);
+
+        // Tells the caller's stream to end when requests in flight are abandoned
+        let (abandon_tx, abandon_rx) = mpsc::channel::<()>(1);
+
+        let retry_state = self.config.retry_state_filepath.clone().map(RetryState::new);
+        let mut saved_retries = match retry_state.as_ref().map(RetryState::load) {
+            Some(Ok(saved_retries)) => saved_retries,
+            Some(Err(e)) => {
+                error!("Failed to read retry state file {}: {}", retry_state.as_ref().map(RetryState::path).unwrap_or_default(), e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        // The input's tasks are numbered from `skip * repeat` up to the end of its lines
+        let first_input_id = skip * repeat;
+        let input_end = input_lines.map(|lines| lines.min(skip.saturating_add(limit)).saturating_mul(repeat));
+        let overlap = saved_retries
+            .iter()
+            .flat_map(|request| if request.batch.is_empty() { std::slice::from_ref(request) } else { &request.batch[..] })
+            .map(|member| member.task_id)
+            .filter(|task_id| *task_id >= first_input_id && input_end.is_none_or(|end| *task_id < end))
+            .min();
+        let refused = unopened.or_else(|| overlap.map(|task_id| {
+            ClientError::InvalidConfig(format!(
+                "task {} reloaded from retry state file {} would also be a task of the input; skip the input's lines up to it, or give an empty input to only finish the reloaded requests",
+                task_id,
+                retry_state.as_ref().map(RetryState::path).unwrap_or_default()
+            ))
+        }));
+        // Nothing is sent, and the file is left for a run that can take its requests
+        let retry_state = match refused {
+            Some(_) => {
+                saved_retries.clear();
+                limit = 0;
+                None
+            }
+            None => retry_state,
+        };
+
+        let deadline = self.config.max_runtime.map(|max_runtime| Instant::now() + max_runtime);
+        // A shutdown can stop any run early, so the file is made whether or not there is a deadline
+        let (resume_file, unprocessed_file) = match refused {
+            Some(_) => (None, None),
+            None => (
+                create_run_file(&self.config.resume_filepath, "resume"),
+                create_run_file(&self.config.unprocessed_filepath, "unprocessed"),
+            ),
+        };
+
+        // Each run counts its own failure streak
+        self.aborted.store(false, Ordering::SeqCst);
+        for source in self.tokens.iter().flatten() {
+            source.start();
+        }
+        if let Some(checkpoint) = &files.checkpoint {
+            checkpoint.start(skip * repeat);
+        }
+        if let Some(tracer) = &self.tracer {
+            tracer.start();
+        }
+
+        let context = Arc::new(RunContext {
+            clients: Arc::clone(&self.http),
+            config: Arc::clone(&self.config),
+            status_tracker: Arc::clone(&self.status_tracker),
+            balancer: Arc::clone(&self.balancer),
+            tokens: Arc::clone(&self.tokens),
+            prepared: Arc::clone(&self.prepared),
+            user_agent: self.user_agent.clone(),
+            observer: self.observer.clone(),
+            transform: self.transform.clone(),
+            concurrency: self.concurrency.clone(),
+            tracer: self.tracer.clone(),
+            response_cache: files.response_cache.clone(),
+            checkpoint: files.checkpoint.clone(),
+            har: files.har.clone(),
+            retry_tx,
+            results: results_tx,
+            changed: Notify::new(),
+            requests_sent: AtomicUsize::new(0),
+            retries_pending: AtomicUsize::new(0),
+            pending_writes: Arc::new(AtomicUsize::new(0)),
+            writes_drained: Arc::new(Notify::new()),
+            retry_limiter: self.config.max_retry_rate.map(RetryLimiter::new),
+            token_limiter: self.config.max_tokens_per_minute.map(TokenLimiter::new),
+            deadline,
+            shutdown: self.shutdown.clone(),
+            consecutive_failures: AtomicUsize::new(0),
+            aborted: Arc::clone(&self.aborted),
+            abort: ShutdownHandle::default(),
+            resume_file,
+            unprocessed_file,
+            in_flight: Mutex::default(),
+            retry_state,
+            abandoned: AtomicBool::new(false),
+            failed_groups: Mutex::default(),
+        });
+
+        if let Some(refused) = refused {
+            context.pending_writes.fetch_add(1, Ordering::SeqCst);
+            let _ = context.results.try_send(Err(refused));
+        }
+
+        // Requests a previous run was retrying go first, with the attempts they had left
+        for request in saved_retries {
+            let members = if request.batch.is_empty() { std::slice::from_ref(&request) } else { &request.batch[..] };
+            {
+                let mut tracker = context.status_tracker.lock().unwrap();
+                tracker.num_tasks_started += members.len();
+                tracker.num_tasks_in_progress += members.len();
+            }
+            for member in members {
+                context.track(member);
+            }
+            if let Some(retry_state) = &context.retry_state {
+                retry_state.insert(&request);
+            }
+            let _ = context.retry_tx.send(request);
+        }
+
+        // Save the retry state periodically, without keeping the run alive
+        if context.retry_state.is_some() {
+            let context = Arc::downgrade(&context);
+            let period = self.config.retry_state_interval;
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval_at(Instant::now() + period, period);
+                loop {
+                    ticks.tick().await;
+                    match context.upgrade() {
+                        Some(context) => context.save_retry_state(),
+                        None => break,
+                    }
+                }
+            });
+        }
+
+        // Producer task to enqueue requests at a steady rate
+        let producer_context = Arc::clone(&context);
+
+        // The producer owns the only sender for fresh work, so the queue
+        // closes as soon as the input is exhausted and the producer exits
+        tokio::spawn(async move {
+            let context = producer_context;
+            let delay = startup_delay(&context.config);
+            if !delay.is_zero() {
+                info!("Waiting {:.1} sec before sending, to stagger the start", delay.as_secs_f64());
+                tokio::select! {
+                    biased;
+                    _ = context.stopping() => {}
+                    _ = sleep(delay) => {}
+                }
+            }
+            // Made after the wait, so the warmup starts with the first request
+            let mut pacer = Pacer::new(context.config.max_requests_per_second, context.config.warmup);
+            // Skip applies first, then the limit counts the remaining lines
+            let requests = requests.skip(skip).take(limit);
+            pin_utils::pin_mut!(requests);
+            // The line read when the budget ran out, which is left unprocessed with the rest
+            let mut unsent = None;
+            'lines: loop {
+                let request_json = tokio::select! {
+                    biased;
+                    _ = context.stopping() => break,
+                    request_json = requests.next() => match request_json {
+                        Some(request_json) => request_json,
+                        None => {
+                            // Every line was read, and every task either started or needs no outcome
+                            context.reached(task_id_gen.next().unwrap() * repeat);
+                            break;
+                        }
+                    },
+                    // A partial batch is not held back by a slow input, such as a followed file
+                    _ = sleep(context.config.batch_linger), if !batch.is_empty() => {
+                        if !enqueue(&tx, &context, APIRequest::batch(std::mem::take(&mut batch))).await {
+                            break 'lines;
+                        }
+                        pacer.wait().await;
+                        continue;
+                    }
+                };
+                // Stop reading input once nobody takes the outcomes, e.g. after a failed write
+                if context.results.is_closed() {
+                    info!("Outcomes are no longer read, no longer reading input");
+                    break;
+                }
+                // Stop reading input once no more requests may be sent
+                if context.budget_exhausted() {
+                    info!("Request budget exhausted, no longer reading input");
+                    unsent = Some(request_json);
+                    break;
+                }
+                let line = task_id_gen.next().unwrap();
+                context.reached(line * repeat);
+                // Lines owned by other shards still use up their task ids
+                if shard.is_some_and(|shard| !shard.owns(line)) {
+                    continue;
+                }
+                // So do lines left out of the sample
+                if let Some(sample) = sample {
+                    let included = sample.includes(line);
+                    {
+                        let mut tracker = context.status_tracker.lock().unwrap();
+                        if included {
+                            tracker.num_lines_sampled += 1;
+                        } else {
+                            tracker.num_lines_not_sampled += 1;
+                        }
+                    }
+                    if !included {
+                        continue;
+                    }
+                }
+                // Each repeat of a line is a task of its own, numbered after the line's first
+                let task_id = line * repeat;
+                let request_json = match request_json {
+                    Ok(request_json) => request_json,
+                    Err(LineError::Invalid(e)) => {
+                        error!("{}", e);
+                        continue;
+                    }
+                    // Written to the error file, as the line was never looked at
+                    Err(LineError::TooLong(len)) => {
+                        let problem = format!(
+                            "line {} is {} bytes, longer than the limit of {} bytes",
+                            task_id,
+                            len,
+                            context.config.max_line_bytes.unwrap_or_default()
+                        );
+                        error!("Request {} is invalid: {}", task_id, problem);
+                        let error = Value::String(problem);
+                        {
+                            let mut tracker = context.status_tracker.lock().unwrap();
+                            tracker.num_tasks_invalid += 1;
+                            tracker.failures.record(FailureCategory::InvalidInput, task_id, &error);
+                        }
+                        let error = ClientError::Request {
+                            task_id,
+                            input: Value::Null,
+                            error,
+                            category: FailureCategory::InvalidInput,
+                        };
+                        context.expect_outcome(task_id);
+                        context.send_outcome(Err(error)).await;
+                        continue;
+                    }
+                };
+                // Lines that need no call are saved as they are, without waiting for the pacer
+                if context.config.skip_if.iter().any(|rule| rule.matches(&request_json)) {
+                    debug!("Skipping request {}", task_id);
+                    context.status_tracker.lock().unwrap().num_tasks_skipped += 1;
+                    let skipped = ProcessedResult {
+                        task_id,
+                        input: request_json,
+                        metadata: None,
+                        response: Value::Null,
+                        endpoint: String::new(),
+                        latency: Duration::ZERO,
+                        skipped: true,
+                        response_meta: None,
+                        cached: None,
+                    };
+                    context.expect_outcome(task_id);
+                    context.send_outcome(Ok(skipped)).await;
+                    continue;
+                }
+                match (request_json.as_object(), check_request_json(&context.config, &request_json)) {
+                    (Some(object), Err(problem)) => {
+                        error!("Request {} is invalid: {}", task_id, problem);
+                        let error = Value::String(problem);
+                        {
+                            let mut tracker = context.status_tracker.lock().unwrap();
+                            tracker.num_tasks_invalid += 1;
+                            tracker.failures.record(FailureCategory::InvalidInput, task_id, &error);
+                        }
+                        let error = ClientError::Request {
+                            task_id,
+                            input: Value::Object(object.clone()),
+                            error,
+                            category: FailureCategory::InvalidInput,
+                        };
+                        context.expect_outcome(task_id);
+                        context.send_outcome(Err(error)).await;
+                    }
+                    (Some(object), Ok(derived)) => {
+                        for next_request in line_requests(&context.config, task_id, object, derived) {
+                            // Lock and unlock the tracker in a limited scope
+                            {
+                                let mut tracker = context.status_tracker.lock().unwrap();
+                                tracker.num_tasks_started += 1;
+                                tracker.num_tasks_in_progress += 1;
+                            }
+                            context.track(&next_request);
+
+                            // A batch only holds lines for the same endpoints
+                            if batch.first().is_some_and(|first| first.route_key() != next_request.route_key()) {
+                                if !enqueue(&tx, &context, APIRequest::batch(std::mem::take(&mut batch))).await {
+                                    break 'lines;
+                                }
+                                pacer.wait().await;
+                            }
+                            batch.push(next_request);
+                            if batch.len() < batch_size {
+                                continue;
+                            }
+                            let next_request = if batch_size == 1 {
+                                batch.pop().unwrap()
+                            } else {
+                                APIRequest::batch(std::mem::take(&mut batch))
+                            };
+                            if !enqueue(&tx, &context, next_request).await {
+                                break 'lines;
+                            }
+                            pacer.wait().await;
+                        }
+                        // The pacer was waited on for each request sent
+                        continue;
+                    }
+                    (None, _) => {
+                        error!("Skipping request that is not a JSON object: {}", request_json);
+                    }
+                }
+                pacer.wait().await;
+            }
+            let stopping = context.is_stopping();
+            if stopping {
+                info!("Maximum runtime reached or shutting down, no longer reading input");
+                for request in batch {
+                    context.leave_unprocessed(request);
+                }
+            } else if !batch.is_empty() {
+                // Send the last, partial batch
+                enqueue(&tx, &context, APIRequest::batch(batch)).await;
+            }
+            // Count what is left of the input, and save it for a later run
+            if stopping || context.budget_exhausted() {
+                while let Some(request_json) = match unsent.take() {
+                    Some(request_json) => Some(request_json),
+                    None if input_ends => requests.next().await,
+                    None => None,
+                } {
+                    let line = task_id_gen.next().unwrap();
+                    let owned = shard.is_none_or(|shard| shard.owns(line)) && sample.is_none_or(|sample| sample.includes(line));
+                    if let (Ok(request_json), true) = (request_json, owned) {
+                        context.leave_line(line * repeat, &request_json);
+                    }
+                }
+            }
+        });
+
+        // The caller's stream must not keep the context, and so the outcome sender, alive
+        let pending_writes = Arc::clone(&context.pending_writes);
+        let writes_drained = Arc::clone(&context.writes_drained);
+
+        // Consumer tasks to process requests
+        let pause = self.pause.clone();
+        tokio::spawn(async move {
+            // Dropped when the loop ends, which stops the workers once they are done
+            let workers = context.config.workers.map(|workers| start_workers(&context, workers));
+            // The loop ends once the fresh-work queue is closed and drained
+            // and no task, including those waiting to retry, is in progress
+            let mut input_open = true;
+            let mut grace_deadline = deadline.map(|deadline| deadline + context.config.shutdown_grace);
+            let mut stopping = false;
+            loop {
+                // Send nothing more while the writer is behind
+                context.wait_for_writer().await;
+                // Retries are taken before fresh work
+                let next_request = tokio::select! {
+                    biased;
+                    _ = wait_until(grace_deadline) => {
+                        context.abandon();
+                        let _ = abandon_tx.try_send(());
+                        break;
+                    }
+                    Some(next_request) = retry_rx.recv() => next_request,
+                    // Fresh work still queued at the maximum runtime or a shutdown is left unprocessed
+                    _ = context.stopping(), if !stopping => {
+                        stopping = true;
+                        // Requests in flight get the grace period from now, unless the deadline's ends sooner
+                        let grace = Instant::now() + context.config.shutdown_grace;
+                        grace_deadline = Some(grace_deadline.map_or(grace, |deadline| deadline.min(grace)));
+                        if input_open {
+                            rx.close();
+                            while let Some(next_request) = rx.recv().await {
+                                context.leave_unprocessed(next_request);
+                            }
+                            input_open = false;
+                        }
+                        if context.is_idle() {
+                            break;
+                        }
+                        continue;
+                    }
+                    next_request = rx.recv(), if input_open => match next_request {
+                        Some(next_request) => next_request,
+                        None => {
+                            input_open = false;
+                            if context.is_idle() {
+                                break;
+                            }
+                            continue;
+                        }
+                    },
+                    _ = context.changed.notified() => {
+                        if !input_open && context.is_idle() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                // Nothing more is sent once nobody takes the outcomes
+                if context.results.is_closed() {
+                    break;
+                }
+                // Hold queued requests while paused
+                pause.wait_while_paused().await;
+                // Hold them while every endpoint is rate-limited too, rather than spend attempts on it
+                context.wait_out_throttle().await;
+
+                match &workers {
+                    // Waits for a free worker, holding back the queues meanwhile
+                    Some(workers) => {
+                        if workers.send(next_request).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => {
+                        let context_clone = Arc::clone(&context);
+                        tokio::spawn(async move {
+                            send_request(context_clone, next_request).await;
+                        });
+                    }
+                }
+            }
+            if let Some(concurrency) = &context.concurrency {
+                info!("Adaptive concurrency limit ended at {} requests in flight", concurrency.limit());
+            }
+            // Leaves the file empty once every retry has finished
+            context.save_retry_state();
+            if let Some(tracer) = &context.tracer {
+                tracer.export().await;
+            }
+        });
+
+        // Outcomes taken by the caller no longer count as waiting to be written;
+        // the stream ends early if requests in flight are abandoned
+        let abandoned = ReceiverStream::new(abandon_rx).map(|()| None);
+        ReceiverStream::new(results_rx)
+            .map(move |outcome| {
+                pending_writes.fetch_sub(1, Ordering::SeqCst);
+                writes_drained.notify_one();
+                Some(outcome)
+            })
+            .merge(abandoned)
+            .map_while(|outcome| outcome)
+    }
+}
+
+/// Start a fixed pool of tasks that send the requests handed to the returned sender
+///
+/// The workers share one queue and take the next request once their last one
+/// is done; they exit once the sender is dropped and the queue is empty.
+fn start_workers(context: &Arc<RunContext>, workers: usize) -> mpsc::Sender<APIRequest> {
+    let (work_tx, work_rx) = mpsc::channel::<APIRequest>(1);
+    let work_rx = Arc::new(tokio::sync::Mutex::new(work_rx));
+    for _ in 0..workers {
+        let context = Arc::clone(context);
+        let work_rx = Arc::clone(&work_rx);
+        tokio::spawn(async move {
+            loop {
+                // The lock is only held while waiting, not while sending
+                let next_request = work_rx.lock().await.recv().await;
+                match next_request {
+                    Some(next_request) => send_request(Arc::clone(&context), next_request).await,
+                    None => break,
+                }
+            }
+        });
+    }
+    work_tx
+}
+
+/// Random wait before the first request, up to `Config::startup_jitter`
+///
+/// Reproducible with `Config::seed`, and different for each shard of a run.
+fn startup_delay(config: &Config) -> Duration {
+    let jitter = match config.startup_jitter {
+        Some(jitter) if !jitter.is_zero() => jitter,
+        _ => return Duration::ZERO,
+    };
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed ^ config.shard.map_or(0, |shard| shard.index as u64)),
+        None => StdRng::from_entropy(),
+    };
+    jitter.mul_f64(rng.gen::<f64>())
+}
+
+/// Estimated tokens of a request for `Config::max_tokens_per_minute`, summed over a batch
+fn token_cost(config: &Config, request: &APIRequest) -> u64 {
+    if !request.batch.is_empty() {
+        return request.batch.iter().map(|member| token_cost(config, member)).sum();
+    }
+    let input = request.input();
+    let declared = config.token_cost_field.as_ref().and_then(|path| path.get(&input)).and_then(Value::as_u64);
+    // About four bytes per token for English text
+    declared.unwrap_or_else(|| (input.to_string().len() as u64).div_ceil(4))
+}
+
+/// Create a file written to as a run stops early, logging rather than failing if that is not possible
+fn create_run_file(path: &Option<String>, kind: &str) -> Option<Mutex<std::fs::File>> {
+    let path = path.as_ref()?;
+    match std::fs::File::create(path) {
+        Ok(file) => Some(Mutex::new(file)),
+        Err(e) => {
+            error!("Failed to create {} file {}: {}", kind, path, e);
+            None
+        }
+    }
+}
+
+/// Wait until the deadline, or forever without one
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Queue a request for the consumer loop, returning false once the loop has exited
+async fn enqueue(tx: &queue::Sender, context: &RunContext, request: APIRequest) -> bool {
+    let tasks = request.batch.len().max(1);
+    if let Err(mpsc::error::SendError(request)) = tx.send(request).await {
+        // The queue closes at the maximum runtime or a shutdown
+        if context.is_stopping() {
+            context.leave_unprocessed(request);
+            return false;
+        }
+        // Nothing would send the request
+        error!("Request queue closed, no longer reading input");
+        let mut tracker = context.status_tracker.lock().unwrap();
+        tracker.num_tasks_started -= tasks;
+        tracker.num_tasks_in_progress -= tasks;
+        return false;
+    }
+    true
+}
+
+/// Parse one line of a JSONL requests file
+fn parse_line(line: Result<String, LineError>) -> Result<Value, LineError> {
+    serde_json::from_str::<Value>(&line?).map_err(|e| LineError::Invalid(format!("Failed to parse JSON from line: {}", e)))
+}
+
+/// Highest `_temperature` a line may ask for, the top of OpenAI's range
+const MAX_TEMPERATURE: f64 = 2.0;
+/// Highest `_max_tokens` a line may ask for, well above any model's output limit
+const MAX_TOKENS_OVERRIDE: u64 = 1_000_000;
+
+/// Why an input line cannot be sent, or the fields derived from it if it can
+///
+/// Checks the input schema, the control fields and derived fields; lines that
+/// are not JSON objects are left to the caller.
+fn check_request_json(config: &Config, request_json: &Value) -> Result<serde_json::Map<String, Value>, String> {
+    // Reject lines that do not match the input schema
+    if let Some(schema) = &config.input_schema {
+        let messages: Vec<String> = schema
+            .iter_errors(request_json)
+            .map(|e| format!("{} at '{}'", e, e.instance_path()))
+            .collect();
+        if !messages.is_empty() {
+            return Err(format!("input failed schema validation: {}", messages.join("; ")));
+        }
+    }
+    if request_json.get("metadata").is_some_and(|metadata| !metadata.is_object()) {
+        Err("metadata must be a JSON object".to_string())
+    } else if request_json.get("route_key").is_some_and(|route_key| !route_key.is_string()) {
+        Err("route_key must be a string".to_string())
+    } else if request_json.get("max_attempts").is_some_and(|attempts| attempts.as_u64().is_none_or(|n| n == 0)) {
+        Err("max_attempts must be a positive integer".to_string())
+    } else if request_json.get("priority").is_some_and(|priority| priority.as_i64().is_none()) {
+        Err("priority must be an integer".to_string())
+    } else if request_json.get("group_id").is_some_and(|group_id| !(group_id.is_string() || group_id.is_number())) {
+        Err("group_id must be a string or a number".to_string())
+    } else if request_json.get("_temperature").is_some_and(|temperature| temperature.as_f64().is_none_or(|t| !(0.0..=MAX_TEMPERATURE).contains(&t))) {
+        Err(format!("_temperature must be a number from 0 to {}", MAX_TEMPERATURE))
+    } else if request_json.get("_max_tokens").is_some_and(|tokens| tokens.as_u64().is_none_or(|n| n == 0 || n > MAX_TOKENS_OVERRIDE)) {
+        Err(format!("_max_tokens must be an integer from 1 to {}", MAX_TOKENS_OVERRIDE))
+    } else {
+        // Computed only from lines that are otherwise valid
+        match (&config.derived_fields, request_json.as_object()) {
+            (Some(fields), Some(object)) => fields.derive(object).map_err(|e| format!("failed to derive fields: {}", e)),
+            _ => Ok(serde_json::Map::new()),
+        }
+    }
+}
+
+/// The requests of a valid input line, one for each repeat
+fn line_requests(
+    config: &Config,
+    task_id: usize,
+    object: &serde_json::Map<String, Value>,
+    derived: serde_json::Map<String, Value>,
+) -> Vec<APIRequest> {
+    // A line may ask for more or fewer attempts than the default
+    let max_attempts = object
+        .get("max_attempts")
+        .and_then(Value::as_u64)
+        .map_or(config.max_attempts, |attempts| attempts as usize);
+    (0..config.repeat)
+        .map(|repeat_index| {
+            // Derived fields and the repeat index are sent, but not saved as the input
+            let mut request_json = object.clone();
+            request_json.extend(derived.clone());
+            // The repeat index goes in the metadata, for the payload template and the output
+            if config.repeat > 1 {
+                if let Value::Object(metadata) = request_json.entry("metadata").or_insert_with(|| Value::Object(serde_json::Map::new())) {
+                    metadata.insert("repeat_index".to_string(), Value::from(repeat_index));
+                }
+            }
+            APIRequest {
+                task_id: task_id + repeat_index,
+                // Per-request values carried through to the payload and the output
+                metadata: request_json
+                    .get("metadata")
+                    .and_then(Value::as_object)
+                    .map(|metadata| metadata.clone().into_iter().collect()),
+                request_json: request_json.into_iter().collect(),
+                attempts_left: max_attempts,
+                max_attempts,
+                result: vec![],
+                // The line as read, for the output and the resume file
+                original_input: object.clone().into_iter().collect(),
+                last_endpoint: None,
+                attempts_sent: 0,
+                auth_refreshed: false,
+                batch: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+/// State shared by the producer, the consumer loop and every in-flight request of a run
+pub(crate) struct RunContext {
+    clients: Arc<HttpClients>,
+    config: Arc<Config>,
+    status_tracker: Arc<Mutex<StatusTracker>>,
+    balancer: Arc<LoadBalancer>,
+    tokens: Arc<Vec<Option<Arc<TokenSource>>>>,
+    prepared: Arc<Vec<PreparedEndpoint>>,
+    user_agent: HeaderValue,
+    observer: Option<Arc<dyn RequestObserver>>,
+    transform: Option<Arc<dyn EndpointTransform>>,
+    concurrency: Option<Arc<AdaptiveConcurrency>>,
+    tracer: Option<Arc<Tracer>>,
+    response_cache: Option<Arc<ResponseCache>>,
+    checkpoint: Option<Arc<Checkpoint>>,
+    har: Option<Arc<HarLog>>,
+    /// Queue that retries are sent through, drained before fresh work
+    retry_tx: mpsc::UnboundedSender<APIRequest>,
+    /// Outcomes handed to the caller
+    results: mpsc::Sender<Result<ProcessedResult, ClientError>>,
+    /// Signalled whenever a task finishes
+    changed: Notify,
+    /// HTTP requests sent so far, counted against `Config::max_requests`
+    requests_sent: AtomicUsize,
+    /// Retries waiting out their backoff
+    retries_pending: AtomicUsize,
+    /// Outcomes handed over but not yet taken by the caller, bounded by `Config::max_pending_writes`
+    pending_writes: Arc<AtomicUsize>,
+    /// Signalled whenever the caller takes an outcome
+    writes_drained: Arc<Notify>,
+    /// Spreads out retries whose backoff ends together
+    retry_limiter: Option<RetryLimiter>,
+    /// Holds attempts to `Config::max_tokens_per_minute`
+    token_limiter: Option<TokenLimiter>,
+    /// When fresh work stops being sent, from `Config::max_runtime`
+    deadline: Option<Instant>,
+    /// Stops fresh work being sent before the deadline
+    shutdown: ShutdownHandle,
+    /// Tasks sent that failed since the last success
+    consecutive_failures: AtomicUsize,
+    /// Set once those reach `Config::abort_after_consecutive_failures`
+    aborted: Arc<AtomicBool>,
+    /// Stops this run alone once it is aborted, leaving the client's shutdown switch alone for later runs
+    abort: ShutdownHandle,
+    /// Where lines left unprocessed at the deadline or a shutdown are written
+    resume_file: Option<Mutex<std::fs::File>>,
+    /// Where requests never attempted before the run stopped early are written, as serialized `APIRequest`s
+    unprocessed_file: Option<Mutex<std::fs::File>>,
+    /// The tasks in progress by task id, kept while there is a resume or unprocessed file
+    in_flight: Mutex<BTreeMap<usize, APIRequest>>,
+    /// Requests due another attempt, saved to `Config::retry_state_filepath`
+    retry_state: Option<RetryState>,
+    /// Set once the tasks in progress were given up on at the end of the shutdown grace period
+    abandoned: AtomicBool,
+    /// Groups a task failed in, whose unsent tasks are cancelled with `Config::cancel_groups`
+    failed_groups: Mutex<HashSet<TaskGroup>>,
+}
+
+/// Tasks that stand or fall together with `Config::cancel_groups`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum TaskGroup {
+    /// Lines with the same `group_id`, a string or number
+    Id(String),
+    /// The repeats of one line without a `group_id`
+    Line(usize),
+}
+
+impl std::fmt::Display for TaskGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskGroup::Id(group_id) => write!(f, "group '{}'", group_id),
+            TaskGroup::Line(line) => write!(f, "the repeats of line {}", line),
+        }
+    }
+}
+
+impl RunContext {
+    /// Hand a task's final outcome to the caller and mark the task as done
+    async fn finish_task(&self, outcome: Result<ProcessedResult, ClientError>) {
+        // Abandoned tasks were already counted as unprocessed
+        if self.abandoned.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Err(ClientError::Request { task_id, input, category, .. }) = &outcome {
+            // Tasks turned away by the budget are left for a later run, not failed
+            if *category != FailureCategory::BudgetExhausted {
+                self.fail_group(*task_id, input);
+            }
+        }
+        match &outcome {
+            Err(ClientError::Request { task_id, error, category, .. }) => self.status_tracker.lock().unwrap().failures.record(*category, *task_id, error),
+            Ok(ProcessedResult { cached: Some(true), .. }) => self.status_tracker.lock().unwrap().num_cache_hits += 1,
+            _ => {}
+        }
+        self.track_failure_streak(&outcome);
+        if let Some(observer) = &self.observer {
+            match &outcome {
+                Ok(result) => observer.on_success(result),
+                Err(e) => observer.on_failure(e),
+            }
+        }
+        if let Ok(ProcessedResult { task_id, .. }) | Err(ClientError::Request { task_id, .. }) = &outcome {
+            self.in_flight.lock().unwrap().remove(task_id);
+            if let Some(retry_state) = &self.retry_state {
+                retry_state.remove(*task_id);
+            }
+            if let Some(tracer) = &self.tracer {
+                let error = match &outcome {
+                    Err(ClientError::Request { error, .. }) => Some(error.as_str().map_or_else(|| error.to_string(), str::to_string)),
+                    _ => None,
+                };
+                tracer.end_task(*task_id, error);
+            }
+        }
+        self.send_outcome(outcome).await;
+
+        {
+            let mut tracker = self.status_tracker.lock().unwrap();
+            if !self.abandoned.load(Ordering::SeqCst) {
+                tracker.num_tasks_in_progress -= 1;
+            }
+        }
+        self.changed.notify_one();
+    }
+
+    /// The group of a task with `Config::cancel_groups`, if it is in one
+    fn group_of(&self, task_id: usize, input: &Value) -> Option<TaskGroup> {
+        if !self.config.cancel_groups {
+            return None;
+        }
+        match input.get("group_id") {
+            Some(Value::String(group_id)) => Some(TaskGroup::Id(group_id.clone())),
+            Some(group_id @ Value::Number(_)) => Some(TaskGroup::Id(group_id.to_string())),
+            _ if self.config.repeat > 1 => Some(TaskGroup::Line(task_id / self.config.repeat)),
+            _ => None,
+        }
+    }
+
+    /// Cancel the unsent tasks of a failed task's group
+    fn fail_group(&self, task_id: usize, input: &Value) {
+        if let Some(group) = self.group_of(task_id, input) {
+            if self.failed_groups.lock().unwrap().insert(group.clone()) {
+                info!("Task {} failed, cancelling the unsent tasks of {}", task_id, group);
+            }
+        }
+    }
+
+    /// Whether a request's group already had a task fail
+    fn is_cancelled(&self, request: &APIRequest) -> bool {
+        self.group_of(request.task_id, &request.input())
+            .is_some_and(|group| self.failed_groups.lock().unwrap().contains(&group))
+    }
+
+    /// Finish a cancelled task without sending it, saved as skipped
+    async fn cancel(&self, request: &APIRequest) {
+        debug!("Request {} cancelled, as a task of its group failed", request.task_id);
+        {
+            let mut tracker = self.status_tracker.lock().unwrap();
+            tracker.num_tasks_skipped += 1;
+            tracker.num_tasks_cancelled += 1;
+        }
+        let cancelled = ProcessedResult {
+            task_id: request.task_id,
+            input: request.input(),
+            metadata: request.metadata.clone(),
+            response: Value::Null,
+            endpoint: String::new(),
+            latency: Duration::ZERO,
+            skipped: true,
+            response_meta: None,
+            cached: None,
+        };
+        self.finish_task(Ok(cancelled)).await;
+    }
+
+    /// The endpoint's provider profile, then the embedder's transform if there is one
+    fn transforms<'a>(&'a self, endpoint: &'a Endpoint) -> impl Iterator<Item = &'a dyn EndpointTransform> {
+        let provider = endpoint.provider.as_ref().unwrap_or(&self.config.provider);
+        std::iter::once(provider as &dyn EndpointTransform).chain(self.transform.as_deref())
+    }
+
+    /// Normalize a parsed response, object by object for an NDJSON response
+    fn normalize_response(&self, endpoint: &Endpoint, response: Value) -> Value {
+        let normalize = |object| self.transforms(endpoint).fold(object, |object, transform| transform.normalize_response(endpoint, object));
+        match response {
+            Value::Array(objects) if self.config.ndjson_response => Value::Array(objects.into_iter().map(normalize).collect()),
+            response => normalize(response),
+        }
+    }
+
+    /// Count tasks failing in a row, shutting the run down at `Config::abort_after_consecutive_failures`
+    fn track_failure_streak(&self, outcome: &Result<ProcessedResult, ClientError>) {
+        let max_failures = match self.config.abort_after_consecutive_failures {
+            Some(max_failures) => max_failures,
+            None => return,
+        };
+        match outcome {
+            Ok(result) if !result.skipped => self.consecutive_failures.store(0, Ordering::SeqCst),
+            Err(ClientError::Request { category, .. }) if category.was_sent() => {
+                let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+                if failures >= max_failures && !self.aborted.swap(true, Ordering::SeqCst) {
+                    error!("{} tasks failed in a row without a success, aborting the run", failures);
+                    self.abort.shutdown();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Finish a request, reporting the failure of a batch as a failure of each of its requests
+    async fn finish_request(&self, batch: &[APIRequest], outcome: Result<ProcessedResult, ClientError>) {
+        match outcome {
+            Err(ClientError::Request { error, category, .. }) if !batch.is_empty() => {
+                // The failure was counted once for the whole batch
+                {
+                    let mut tracker = self.status_tracker.lock().unwrap();
+                    match category {
+                        FailureCategory::BudgetExhausted => tracker.num_budget_exhausted += batch.len() - 1,
+                        _ => tracker.num_tasks_failed += batch.len() - 1,
+                    }
+                }
+                for member in batch {
+                    self.finish_task(Err(member.failure(category, error.clone()))).await;
+                }
+            }
+            outcome => self.finish_task(outcome).await,
+        }
+    }
+
+    /// Hand an outcome to the caller, counting it as waiting to be written
+    async fn send_outcome(&self, outcome: Result<ProcessedResult, ClientError>) {
+        self.pending_writes.fetch_add(1, Ordering::SeqCst);
+        // The stream consumer may have stopped listening
+        if self.results.send(outcome).await.is_err() {
+            self.pending_writes.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Wait until fewer than `Config::max_pending_writes` outcomes are waiting to be written
+    async fn wait_for_writer(&self) {
+        let max_pending = match self.config.max_pending_writes {
+            Some(max_pending) => max_pending,
+            None => return,
+        };
+        if self.pending_writes.load(Ordering::SeqCst) >= max_pending {
+            debug!("Writer is behind, pausing sends");
+        }
+        while self.pending_writes.load(Ordering::SeqCst) >= max_pending && !self.results.is_closed() {
+            tokio::select! {
+                _ = self.writes_drained.notified() => {}
+                _ = self.results.closed() => {}
+            }
+        }
+    }
+
+    /// Wait while every endpoint is rate-limited, until the soonest of them resets
+    async fn wait_out_throttle(&self) {
+        let mut paused_at = None;
+        while let Some(until) = self.balancer.throttled_until(&self.config.endpoints) {
+            if paused_at.is_none() {
+                warn!("All endpoints are rate-limited, pausing sends for {:.1} sec", (until - Instant::now()).as_secs_f64());
+                paused_at = Some(Instant::now());
+            }
+            tokio::select! {
+                _ = sleep_until(until) => {}
+                _ = self.results.closed() => return,
+            }
+        }
+        if let Some(paused_at) = paused_at {
+            info!("Resuming sends after a rate-limit pause of {:.1} sec", paused_at.elapsed().as_secs_f64());
+        }
+    }
+
+    /// Take one request from the budget, returning false once it is spent
+    fn reserve_request(&self) -> bool {
+        match self.config.max_requests {
+            Some(max_requests) => self
+                .requests_sent
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |sent| {
+                    if sent < max_requests {
+                        Some(sent + 1)
+                    } else {
+                        None
+                    }
+                })
+                .is_ok(),
+            None => true,
+        }
+    }
+
+    /// Whether the request budget has been spent
+    fn budget_exhausted(&self) -> bool {
+        match self.config.max_requests {
+            Some(max_requests) => self.requests_sent.load(Ordering::SeqCst) >= max_requests,
+            None => false,
+        }
+    }
+
+    /// Whether no task is in progress, counting those waiting to retry
+    fn is_idle(&self) -> bool {
+        self.status_tracker.lock().unwrap().num_tasks_in_progress == 0
+    }
+
+    /// Whether `Config::max_runtime` has passed or a shutdown was requested
+    fn is_stopping(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline) || self.shutdown.is_requested() || self.abort.is_requested()
+    }
+
+    /// Wait until `Config::max_runtime` passes or a shutdown is requested
+    async fn stopping(&self) {
+        tokio::select! {
+            _ = wait_until(self.deadline) => {}
+            _ = self.shutdown.requested() => {}
+            _ = self.abort.requested() => {}
+        }
+    }
+
+    /// Count an input line that was never queued as unprocessed
+    fn leave_line(&self, task_id: usize, line: &Value) {
+        self.status_tracker.lock().unwrap().num_tasks_unprocessed += 1;
+        self.write_resume(line);
+        // Invalid lines would fail again, so only the resume file keeps them
+        if let (Some(_), Some(object)) = (&self.unprocessed_file, line.as_object()) {
+            if let Ok(derived) = check_request_json(&self.config, line) {
+                for request in line_requests(&self.config, task_id, object, derived) {
+                    self.write_unprocessed(&request);
+                }
+            }
+        }
+    }
+
+    /// Take a queued request out of the run, counting its lines as unprocessed instead of started
+    fn leave_unprocessed(&self, request: APIRequest) {
+        let members = if request.batch.is_empty() { vec![request] } else { request.batch };
+        {
+            let mut tracker = self.status_tracker.lock().unwrap();
+            tracker.num_tasks_started -= members.len();
+            tracker.num_tasks_in_progress -= members.len();
+            tracker.num_tasks_unprocessed += members.len();
+        }
+        for member in &members {
+            self.in_flight.lock().unwrap().remove(&member.task_id);
+            self.write_resume(&member.input());
+            self.write_unprocessed(member);
+        }
+        self.changed.notify_one();
+    }
+
+    /// Remember a started task's input, so it can be saved for a later run if it is abandoned
+    fn track(&self, request: &APIRequest) {
+        self.expect_outcome(request.task_id);
+        if self.resume_file.is_some() || self.unprocessed_file.is_some() {
+            self.in_flight.lock().unwrap().insert(request.task_id, request.clone());
+        }
+    }
+
+    /// Hold the checkpoint before a task until its outcome is flushed
+    fn expect_outcome(&self, task_id: usize) {
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.started(task_id);
+        }
+    }
+
+    /// Move the checkpoint up to a task, once every task before it was read
+    fn reached(&self, task_id: usize) {
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.reached(task_id);
+        }
+    }
+
+    /// Give up on the tasks still in progress, counting them as unprocessed
+    fn abandon(&self) {
+        self.abandoned.store(true, Ordering::SeqCst);
+        let in_flight = {
+            let mut tracker = self.status_tracker.lock().unwrap();
+            let in_flight = std::mem::take(&mut tracker.num_tasks_in_progress);
+            tracker.num_tasks_unprocessed += in_flight;
+            in_flight
+        };
+        warn!("Shutdown grace period over, abandoning {} requests still in flight", in_flight);
+        for (task_id, request) in std::mem::take(&mut *self.in_flight.lock().unwrap()) {
+            // Retries are saved with their remaining attempts instead
+            if !self.retry_state.as_ref().is_some_and(|retry_state| retry_state.contains(task_id)) {
+                self.write_resume(&request.input());
+                self.write_unprocessed(&request);
+            }
+        }
+    }
+
+    /// Close an attempt's span, if spans are exported
+    fn end_span(&self, span: Option<AttemptSpan>, status: Option<StatusCode>, error: Option<&str>, latency: Duration) {
+        if let (Some(tracer), Some(span)) = (&self.tracer, span) {
+            tracer.end_attempt(span, status, error, latency);
+        }
+    }
+
+    /// Rewrite the retry state file, if there is one
+    fn save_retry_state(&self) {
+        if let Some(retry_state) = &self.retry_state {
+            if let Err(e) = retry_state.save() {
+                error!("Failed to write retry state file {}: {}", retry_state.path(), e);
+            }
+        }
+    }
+
+    /// Add a line to the resume file, if there is one
+    fn write_resume(&self, line: &Value) {
+        if let Some(file) = &self.resume_file {
+            if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+                error!("Failed to write to {}: {}", self.config.resume_filepath.as_deref().unwrap_or_default(), e);
+            }
+        }
+    }
+
+    /// Add a request to the unprocessed file, if there is one
+    fn write_unprocessed(&self, request: &APIRequest) {
+        if let Some(file) = &self.unprocessed_file {
+            let written = serde_json::to_string(request)
+                .map_err(std::io::Error::from)
+                .and_then(|line| writeln!(file.lock().unwrap(), "{}", line));
+            if let Err(e) = written {
+                error!("Failed to write to {}: {}", self.config.unprocessed_filepath.as_deref().unwrap_or_default(), e);
+            }
+        }
+    }
+}
+
+/// Process API requests from a file with a new client
+pub async fn process_api_requests_from_file(
+    requests_filepath: &str,
+    config: Config,
+) -> Result<Arc<Mutex<StatusTracker>>, ClientError> {
+    let client = Client::new(config)?;
+    client.process_file(requests_filepath).await?;
+    Ok(client.status_tracker())
+}
+
+/// Send an API request and handle the response
+pub(crate) async fn send_request(context: Arc<RunContext>, mut request: APIRequest) {
+    let config = &context.config;
+    let status_tracker = &context.status_tracker;
+
+    // Tasks of a group that already failed are not sent, and a batch goes without them; retries still are
+    if config.cancel_groups && request.attempts_sent == 0 {
+        if request.batch.is_empty() {
+            if context.is_cancelled(&request) {
+                context.cancel(&request).await;
+                return;
+            }
+        } else {
+            let (cancelled, members): (Vec<_>, Vec<_>) = std::mem::take(&mut request.batch).into_iter().partition(|member| context.is_cancelled(member));
+            for member in &cancelled {
+                context.cancel(member).await;
+            }
+            if members.is_empty() {
+                return;
+            }
+            if cancelled.is_empty() {
+                request.batch = members;
+            } else {
+                request = APIRequest::batch(members);
+            }
+        }
+    }
+
+    // Kept for reporting each request's outcome once the batch is done
+    let batch = request.batch.clone();
+
+    let endpoint_index = match context.balancer.select(&config.endpoints, &request) {
+        Some(endpoint_index) => endpoint_index,
+        None => {
+            let route_key = request.route_key().unwrap_or_default();
+            error!("Request {} has no endpoint for route key '{}'", request.task_id, route_key);
+            status_tracker.lock().unwrap().num_tasks_failed += 1;
+            let error = Value::String(format!("no endpoint for route key '{}'", route_key));
+            let outcome = Err(request.failure(FailureCategory::NoEndpoint, error));
+            context.finish_request(&batch, outcome).await;
+            return;
+        }
+    };
+
+    // Build the body for the chosen endpoint before using the budget, so a request missing template fields does not use it up
+    let payload = match build_body(config, &config.endpoints[endpoint_index], &request) {
+        Ok(mut payload) => {
+            for transform in context.transforms(&config.endpoints[endpoint_index]) {
+                transform.transform_body(&config.endpoints[endpoint_index], &mut payload);
+            }
+            config.body_encoding.encode(&payload).await
+        }
+        Err(e) => Err(e),
+    };
+    let (payload, content_type) = match payload {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Request {} failed to build payload: {}", request.task_id, e);
+            status_tracker.lock().unwrap().num_tasks_failed += 1;
+            let outcome = Err(request.failure(FailureCategory::Payload, Value::String(e)));
+            context.finish_request(&batch, outcome).await;
+            return;
+        }
+    };
+
+    // Fill in the URL before using the budget; a batch takes its fields from its first request
+    let url = &context.prepared[endpoint_index].url;
+    let uri = match url.fixed() {
+        Some(uri) => Ok(uri.clone()),
+        None => {
+            let fields = request.batch.first().unwrap_or(&request);
+            url.render(&Value::Object(fields.request_json.clone().into_iter().collect()))
+        }
+    };
+    let uri = match uri {
+        Ok(uri) => uri,
+        Err(e) => {
+            error!("Request {} failed to build URL: {}", request.task_id, e);
+            status_tracker.lock().unwrap().num_tasks_failed += 1;
+            let outcome = Err(request.failure(FailureCategory::Payload, Value::String(e)));
+            context.finish_request(&batch, outcome).await;
+            return;
+        }
+    };
+
+    // A response kept by an earlier run answers the request without sending it, or using any limit
+    let cache_key = context.response_cache.as_ref().map(|_| ResponseCache::key(&uri, &payload, request.task_id % config.repeat));
+    let cache_hit = match (&context.response_cache, &cache_key) {
+        (Some(cache), Some(key)) => cache.get(key).await,
+        _ => None,
+    };
+    let from_cache = cache_hit.is_some();
+
+    // Never send more requests than the budget allows, including retries
+    if !from_cache && !context.reserve_request() {
+        status_tracker.lock().unwrap().num_budget_exhausted += 1;
+        // Saved with the attempts it has left, for a later run with a fresh budget
+        if batch.is_empty() {
+            context.write_unprocessed(&request);
+        }
+        for member in &batch {
+            context.write_unprocessed(member);
+        }
+        let outcome = Err(request.failure(FailureCategory::BudgetExhausted, Value::String("request budget exhausted".to_string())));
+        context.finish_request(&batch, outcome).await;
+        return;
+    }
+
+    // Admit the attempt against the tokens-per-minute budget by its estimated cost
+    let token_cost = match &context.token_limiter {
+        Some(limiter) if !from_cache => {
+            let cost = token_cost(config, &request);
+            limiter.acquire(cost).await;
+            Some(cost)
+        }
+        _ => None,
+    };
+
+    // Wait for room under the adaptive limit before taking an endpoint slot, so waiting holds none
+    let limit_permit = match &context.concurrency {
+        Some(concurrency) if !from_cache => Some(concurrency.acquire().await),
+        _ => None,
+    };
+    // Hold a global slot and one of the endpoint's until its response is read
+    let permit = if from_cache { None } else { Some(context.balancer.acquire_slot(endpoint_index).await) };
+
+    // Spread requests over what is left of the endpoint's reported quota
+    if let Some(slot) = (!from_cache).then(|| context.balancer.reserve_quota(endpoint_index)).flatten() {
+        if slot > Instant::now() {
+            debug!("Request {} waits {:?} for quota", request.task_id, slot - Instant::now());
+            sleep_until(slot).await;
+        }
+    }
+
+    let endpoint = &config.endpoints[endpoint_index];
+    request.last_endpoint = Some(endpoint.url.clone());
+    let prepared = &context.prepared[endpoint_index];
+    let endpoint_url = endpoint.url.clone();
+    // OAuth endpoints use the current access token in place of a fixed key
+    let token_source = context.tokens[endpoint_index].as_ref();
+    let token = match token_source {
+        Some(source) => Some(source.token().await),
+        None => None,
+    };
+    // Several static keys are rotated, and only then tracked one by one
+    let key_index = prepared.keys.select();
+    let rotating_keys = token_source.is_none() && endpoint.sigv4.is_none() && prepared.keys.len() > 1;
+    let api_key = token.as_deref().unwrap_or(endpoint.keys()[key_index]);
+
+    // Identifies this attempt in the server's logs as well as ours
+    request.attempts_sent += 1;
+    let request_id = format!("{}-{}", request.task_id, request.attempts_sent);
+    // Resends after an auth refresh repeat the attempt rather than use a new one
+    let attempt = format!("attempt {}/{}", request.max_attempts - request.attempts_left + 1, request.max_attempts);
+
+    let task_id = request.task_id;
+    let span = context.tracer.as_ref().map(|tracer| tracer.start_attempt(task_id, &endpoint_url));
+    let traceparent = span.as_ref().map(AttemptSpan::traceparent);
+    let start = Instant::now();
+    // The response headers, and then the first chunk of the body, must arrive before this deadline
+    let first_byte = config.ttfb_timeout.map(|timeout| (start + timeout, timeout));
+    // Builds the request again for each redirect it follows
+    let build = |uri: Uri, method: Method, with_body: bool| {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate"))
+            .header(USER_AGENT, context.user_agent.clone())
+            .header("X-Request-Id", request_id.as_str());
+        if with_body {
+            req = req.header(CONTENT_TYPE, content_type.clone());
+        }
+        req = match (&token, &endpoint.sigv4) {
+            (Some(token), _) => req.header(AUTHORIZATION, format!("Bearer {}", token)),
+            // Signed once the whole request is built
+            (None, Some(_)) => req,
+            (None, None) => req.header(prepared.auth_header.clone(), prepared.authorization[key_index].clone()),
+        };
+        if let Some(header) = &config.idempotency_key_header {
+            // Retries of the same task carry the same key
+            req = req.header(header.as_str(), task_id.to_string());
+        }
+        if let Some(traceparent) = &traceparent {
+            req = req.header("traceparent", traceparent.as_str());
+        }
+        if let (Some(header), Some((deadline, _))) = (&endpoint.deadline_header, first_byte) {
+            // What is left when this request, or redirect, is sent
+            req = req.header(header.name.as_str(), header.value(deadline.saturating_duration_since(Instant::now())));
+        }
+        let body = if with_body { payload.clone() } else { Bytes::new() };
+        let mut req = req.body(Body::from(body.clone())).unwrap();
+        for transform in context.transforms(endpoint) {
+            transform.transform_headers(endpoint, req.headers_mut());
+        }
+        if let Some(sigv4) = &endpoint.sigv4 {
+            sign(sigv4, &mut req, &body);
+        }
+        req
+    };
+    let req = build(uri, Method::POST, true);
+    // Redirects followed are left out, so the entry has the request as first sent and the last response
+    let har_request = context.har.as_ref().filter(|_| !from_cache).map(|har| har.request(&req, &payload, api_key));
+
+    let input = request.request_json.get(&config.input_field).and_then(Value::as_str).unwrap_or_default().to_string();
+
+    info!(
+        "{}: {} ({}) - {} to {} - {} - {}",
+        if from_cache { "Cached" } else { "Sent" },
+        task_id,
+        request_id,
+        attempt,
+        endpoint_url,
+        input,
+        Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    if let Some(observer) = &context.observer {
+        observer.on_start(&request, &endpoint_url);
+    }
+    trace!(
+        "Request {} to {} - {} - {}",
+        task_id,
+        req.uri(),
+        redacted_headers(req.headers()),
+        String::from_utf8_lossy(&payload)
+    );
+    if !from_cache {
+        let mut tracker = status_tracker.lock().unwrap();
+        let stats = tracker.endpoint(&endpoint_url);
+        stats.num_requests += 1;
+        if rotating_keys {
+            stats.keys.entry(key_index).or_default().num_requests += 1;
+        }
+    }
+
+    let sent = match cache_hit {
+        Some(response) => Ok(response),
+        None => {
+            let sent = send_following_redirects(context.clients.backend(endpoint), req, build, config.max_redirects);
+            match first_byte {
+                Some((deadline, timeout)) => timeout_at(deadline, sent).await.unwrap_or(Err(SendError::Timeout(timeout))),
+                None => sent.await,
+            }
+        }
+    };
+    let wait = start.elapsed();
+    let har_request = match (&context.har, har_request, &sent) {
+        (Some(har), Some(har_request), Err(e)) => {
+            har.add(&request_id, har_request, HarLog::no_response(&e.to_string()), wait, Duration::ZERO);
+            None
+        }
+        (_, har_request, _) => har_request,
+    };
+    let outcome = match sent {
+        Ok(response) => {
+            let status = response.status();
+            let har_head = har_request.as_ref().map(|_| (response.version(), response.headers().clone()));
+            // Kept to store the response once it is known to have succeeded
+            let cache_headers = cache_key.as_ref().filter(|_| !from_cache).map(|_| response.headers().clone());
+            match context.clients.is_new_connection(response.extensions()) {
+                Some(true) => {
+                    debug!("Response {} from {} came on a new connection", task_id, endpoint_url);
+                    status_tracker.lock().unwrap().num_connections_opened += 1;
+                }
+                Some(false) => {
+                    debug!("Response {} from {} came on a reused connection", task_id, endpoint_url);
+                    status_tracker.lock().unwrap().num_connections_reused += 1;
+                }
+                None => {}
+            }
+            // A throttling response's Retry-After says when the endpoint takes requests again
+            let retry_after = match status {
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => Quota::from_retry_after(response.headers()),
+                _ => None,
+            };
+            // A cached response's quota headers are out of date
+            if let Some(quota) = retry_after.or_else(|| Quota::from_headers(response.headers())).filter(|_| !from_cache) {
+                context.balancer.record_quota(endpoint_index, quota);
+            }
+            let response_meta = config.include_response_meta.then(|| response_meta(status, response.headers(), &config.response_meta_headers));
+            let cached = match &config.cache_header {
+                Some(name) => Some(from_cache || is_cache_hit(response.headers(), name, &config.cache_hit_value)),
+                None => context.response_cache.as_ref().map(|_| from_cache),
+            };
+            let encoding = response.headers().get(CONTENT_ENCODING).and_then(|v| v.to_str().ok()).map(str::to_string);
+            let body = read_body(response.into_body(), config.max_response_bytes, first_byte)
+                .await
+                .and_then(|body| decode_body(body, encoding.as_deref(), config.max_response_bytes));
+            let duration = start.elapsed();
+            if let (Some(har), Some(har_request), Some((version, headers))) = (&context.har, har_request, har_head) {
+                let har_body = body.as_deref().map_err(ToString::to_string);
+                let har_response = har.response(status, version, &headers, har_body, api_key);
+                har.add(&request_id, har_request, har_response, wait, duration.saturating_sub(wait));
+            }
+            context.end_span(span, Some(status), None, duration);
+            // Free the slot before any retry waits out its backoff
+            drop(permit);
+            if let Some(limit_permit) = limit_permit {
+                let overloaded = status == StatusCode::TOO_MANY_REQUESTS
+                    || status.is_server_error()
+                    || matches!(body, Err(BodyError::Timeout(_)) | Err(BodyError::Read(_)));
+                limit_permit.finish(duration, overloaded);
+            }
+            debug!("Response {} from {}: HTTP status {}", task_id, endpoint_url, status);
+            // Only responses that succeeded are kept, so a later run sends failed requests again
+            let kept = (&context.response_cache, &cache_key, &cache_headers);
+            let store = move |body: Bytes| async move {
+                if let (Some(cache), Some(key), Some(headers)) = kept {
+                    cache.put(key, status, headers, body).await;
+                }
+            };
+            if let Ok(body_bytes) = &body {
+                trace!("Response body {}: {}", task_id, redact(&String::from_utf8_lossy(body_bytes), api_key));
+            }
+            if !from_cache {
+                context.balancer.record_latency(endpoint_index, duration);
+                status_tracker.lock().unwrap().latency.record(duration);
+            }
+            info!(
+                "Response: {} ({}) - {} from {} - HTTP {} - {:.1} sec - {} - {}",
+                task_id,
+                request_id,
+                attempt,
+                endpoint_url,
+                status.as_u16(),
+                duration.as_secs_f64(),
+                input,
+                Local::now().format("%Y-%m-%d %H:%M:%S")
+            );
+            if let (StatusCode::UNAUTHORIZED, Some(source), false) = (status, token_source, request.auth_refreshed) {
+                // The token may have expired early; resend once with a fresh one, without using an attempt
+                warn!("Request {} was unauthorized by {}, refreshing its access token", task_id, endpoint_url);
+                source.invalidate(api_key);
+                {
+                    let mut tracker = status_tracker.lock().unwrap();
+                    tracker.num_api_errors += 1;
+                    tracker.num_retries += 1;
+                    tracker.endpoint(&endpoint_url).num_failed += 1;
+                }
+                request.auth_refreshed = true;
+                // The queue is only closed once the run has abandoned its requests
+                let _ = context.retry_tx.send(request);
+                return;
+            }
+            if rotating_keys && (status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN) {
+                let other_keys_left = prepared.keys.quarantine(key_index);
+                {
+                    let mut tracker = status_tracker.lock().unwrap();
+                    let key = tracker.endpoint(&endpoint_url).keys.entry(key_index).or_default();
+                    key.num_refused += 1;
+                    key.quarantined = true;
+                }
+                if other_keys_left {
+                    // Resend with another key, without using an attempt
+                    warn!("Key {} of {} was refused with HTTP status {}, quarantining it", key_index, endpoint_url, status);
+                    {
+                        let mut tracker = status_tracker.lock().unwrap();
+                        tracker.num_api_errors += 1;
+                        tracker.num_retries += 1;
+                        tracker.endpoint(&endpoint_url).num_failed += 1;
+                    }
+                    let _ = context.retry_tx.send(request);
+                    return;
+                }
+                error!("Every key of {} has been refused", endpoint_url);
+            }
+            if config.retry_on.retries_status(status) {
+                error!("Request {} got HTTP status {}", task_id, status);
+                {
+                    let mut tracker = status_tracker.lock().unwrap();
+                    if status == StatusCode::TOO_MANY_REQUESTS {
+                        tracker.num_rate_limit_errors += 1;
+                    } else {
+                        tracker.num_api_errors += 1;
+                    }
+                    tracker.endpoint(&endpoint_url).num_failed += 1;
+                }
+                match schedule_retry(&context, request, FailureCategory::of_status(status)).await {
+                    // The retried request is still in progress
+                    Ok(()) => return,
+                    Err(request) => {
+                        status_tracker.lock().unwrap().num_tasks_failed += 1;
+                        let error = Value::String(format!("HTTP status {}", status));
+                        Err(request.failure(FailureCategory::of_status(status), error))
+                    }
+                }
+            } else {
+                let statuses = endpoint.success_status.as_ref().unwrap_or(&config.success_status);
+                match body {
+                    // No content on a success status, e.g. 204 for accepted work, is saved as `null`
+                    Ok(body_bytes)
+                        if body_bytes.iter().all(u8::is_ascii_whitespace) && statuses.contains(status) && batch.is_empty() && !config.ndjson_response =>
+                    {
+                        debug!("Request {} got no content with HTTP status {}", task_id, status);
+                        store(body_bytes.clone()).await;
+                        let mut tracker = status_tracker.lock().unwrap();
+                        tracker.num_tasks_succeeded += 1;
+                        tracker.endpoint(&endpoint_url).num_succeeded += 1;
+                        Ok(ProcessedResult {
+                            task_id,
+                            input: request.input(),
+                            metadata: request.metadata.clone(),
+                            response: Value::Null,
+                            endpoint: endpoint_url.clone(),
+                            latency: duration,
+                            skipped: false,
+                            response_meta,
+                            cached,
+                        })
+                    }
+                    Ok(body_bytes) => {
+                        let result = if config.ndjson_response {
+                            parse_ndjson(task_id, &body_bytes)
+                        } else {
+                            serde_json::from_slice(&body_bytes).map_err(|e| e.to_string())
+                        };
+                        let result = result.map(|response| context.normalize_response(endpoint, response));
+                        // Tokens are billed whatever the outcome, so count them before judging the response; cached ones were billed before
+                        if let Some(usage) = result.as_ref().ok().filter(|_| !from_cache).and_then(|result| response_usage(config, result)) {
+                            status_tracker.lock().unwrap().record_usage(&endpoint_url, usage, endpoint.pricing.as_ref());
+                            if let (Some(limiter), Some(cost)) = (&context.token_limiter, token_cost) {
+                                limiter.reconcile(cost, usage.prompt_tokens + usage.completion_tokens);
+                            }
+                        }
+                        match result {
+                            // Transient errors signalled in the body go through the retry path
+                            Ok(result_json)
+                                if config
+                                    .retry_on_body_errors
+                                    .iter()
+                                    .any(|rule| response_objects(config, &result_json).iter().any(|object| rule.matches(object))) =>
+                            {
+                                error!("Request {} got a retryable error in the response body: {}", task_id, result_json);
+                                {
+                                    let mut tracker = status_tracker.lock().unwrap();
+                                    tracker.num_api_errors += 1;
+                                    tracker.endpoint(&endpoint_url).num_failed += 1;
+                                }
+                                match schedule_retry(&context, request, FailureCategory::Rejected).await {
+                                    // The retried request is still in progress
+                                    Ok(()) => return,
+                                    Err(request) => {
+                                        status_tracker.lock().unwrap().num_tasks_failed += 1;
+                                        Err(request.failure(FailureCategory::Rejected, result_json))
+                                    }
+                                }
+                            }
+                            // Each request of a batch succeeds or fails on its own
+                            Ok(result_json) if !batch.is_empty() => {
+                                let outcomes = split_batch(&context, endpoint, status, &batch, result_json, duration, response_meta);
+                                if outcomes.iter().all(Result::is_ok) {
+                                    store(body_bytes.clone()).await;
+                                }
+                                for outcome in outcomes {
+                                    let outcome = outcome.map(|result| ProcessedResult { cached, ..result });
+                                    context.finish_task(outcome).await;
+                                }
+                                return;
+                            }
+                            Ok(result_json) => {
+                                match judge_objects(config, endpoint, status, result_json) {
+                                    Err((category, error)) => {
+                                        error!("Request {} was rejected: {}", task_id, error);
+                                        let mut tracker = status_tracker.lock().unwrap();
+                                        tracker.record_rejection(category);
+                                        tracker.num_tasks_failed += 1;
+                                        tracker.endpoint(&endpoint_url).num_failed += 1;
+                                        Err(request.failure(category, error))
+                                    }
+                                    Ok(response) => {
+                                        store(body_bytes.clone()).await;
+                                        let mut tracker = status_tracker.lock().unwrap();
+                                        tracker.num_tasks_succeeded += 1;
+                                        tracker.endpoint(&endpoint_url).num_succeeded += 1;
+                                        Ok(ProcessedResult {
+                                            task_id,
+                                            input: request.input(),
+                                            metadata: request.metadata.clone(),
+                                            response,
+                                            endpoint: endpoint_url.clone(),
+                                            latency: duration,
+                                            skipped: false,
+                                            response_meta,
+                                            cached,
+                                        })
+                                    }
+                                }
+                            }
+                            Err(e) => {
+                                error!("Request {} failed to parse JSON: {}", task_id, e);
+                                // Log the raw response body for debugging
+                                error!("Raw response body: {:?}", String::from_utf8_lossy(&body_bytes));
+                                let mut tracker = status_tracker.lock().unwrap();
+                                tracker.num_tasks_failed += 1;
+                                tracker.endpoint(&endpoint_url).num_failed += 1;
+                                Err(request.failure(FailureCategory::Parse, Value::String(e)))
+                            }
+                        }
+                    }
+                    // The connection broke mid-body, a transport failure rather than a bad response
+                    Err(BodyError::Read(e)) => {
+                        error!("Request {} lost its connection while reading the response body: {}", task_id, e);
+                        {
+                            let mut tracker = status_tracker.lock().unwrap();
+                            tracker.num_other_errors += 1;
+                            tracker.endpoint(&endpoint_url).num_failed += 1;
+                        }
+                        let retried = if config.retry_on.retries_body_read() {
+                            schedule_retry(&context, request, FailureCategory::Body).await
+                        } else {
+                            Err(request)
+                        };
+                        match retried {
+                            // The retried request is still in progress
+                            Ok(()) => return,
+                            Err(request) => {
+                                status_tracker.lock().unwrap().num_tasks_failed += 1;
+                                Err(request.failure(FailureCategory::Body, Value::String(e.to_string())))
+                            }
+                        }
+                    }
+                    // The server answered but took too long to start sending the body
+                    Err(e @ BodyError::Timeout(_)) => {
+                        error!("Request {} ({}) - {} to {} - failed: {}", task_id, request_id, attempt, endpoint_url, e);
+                        {
+                            let mut tracker = status_tracker.lock().unwrap();
+                            tracker.num_other_errors += 1;
+                            tracker.endpoint(&endpoint_url).num_failed += 1;
+                        }
+                        let retried = if config.retry_on.retries_timeout() {
+                            schedule_retry(&context, request, FailureCategory::Timeout).await
+                        } else {
+                            Err(request)
+                        };
+                        match retried {
+                            // The retried request is still in progress
+                            Ok(()) => return,
+                            Err(request) => {
+                                status_tracker.lock().unwrap().num_tasks_failed += 1;
+                                Err(request.failure(FailureCategory::Timeout, Value::String(e.to_string())))
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Request {} failed to read response body: {}", task_id, e);
+                        let mut tracker = status_tracker.lock().unwrap();
+                        if let BodyError::TooLarge(_) = e {
+                            tracker.num_api_errors += 1;
+                        }
+                        tracker.num_tasks_failed += 1;
+                        tracker.endpoint(&endpoint_url).num_failed += 1;
+                        Err(request.failure(FailureCategory::Body, Value::String(e.to_string())))
+                    }
+                }
+            }
+        }
+        Err(SendError::Redirect(e)) => {
+            drop(permit);
+            drop(limit_permit);
+            context.end_span(span, None, Some(&e), start.elapsed());
+            error!("Request {} ({}) - {} to {} - failed: {}", task_id, request_id, attempt, endpoint_url, e);
+            let mut tracker = status_tracker.lock().unwrap();
+            tracker.num_api_errors += 1;
+            tracker.num_tasks_failed += 1;
+            tracker.endpoint(&endpoint_url).num_failed += 1;
+            Err(request.failure(FailureCategory::Redirect, Value::String(e)))
+        }
+        Err(SendError::Replay(e)) => {
+            drop(permit);
+            drop(limit_permit);
+            context.end_span(span, None, Some(&e), start.elapsed());
+            error!("Request {} ({}) - {} to {} - failed: {}", task_id, request_id, attempt, endpoint_url, e);
+            let mut tracker = status_tracker.lock().unwrap();
+            tracker.num_other_errors += 1;
+            tracker.num_tasks_failed += 1;
+            tracker.endpoint(&endpoint_url).num_failed += 1;
+            Err(request.failure(FailureCategory::NotRecorded, Value::String(e)))
+        }
+        Err(SendError::Timeout(timeout)) => {
+            drop(permit);
+            if let Some(limit_permit) = limit_permit {
+                limit_permit.finish(start.elapsed(), true);
+            }
+            let e = SendError::Timeout(timeout).to_string();
+            context.end_span(span, None, Some(&e), start.elapsed());
+            error!("Request {} ({}) - {} to {} - failed: {}", task_id, request_id, attempt, endpoint_url, e);
+            {
+                let mut tracker = status_tracker.lock().unwrap();
+                tracker.num_other_errors += 1;
+                tracker.endpoint(&endpoint_url).num_failed += 1;
+            }
+            let retried = if config.retry_on.retries_timeout() {
+                schedule_retry(&context, request, FailureCategory::Timeout).await
+            } else {
+                Err(request)
+            };
+            match retried {
+                // The retried request is still in progress
+                Ok(()) => return,
+                Err(request) => {
+                    status_tracker.lock().unwrap().num_tasks_failed += 1;
+                    Err(request.failure(FailureCategory::Timeout, Value::String(e)))
+                }
+            }
+        }
+        Err(SendError::Http(e)) => {
+            drop(permit);
+            if let Some(limit_permit) = limit_permit {
+                limit_permit.finish(start.elapsed(), true);
+            }
+            context.end_span(span, None, Some(&e.to_string()), start.elapsed());
+            error!("Request {} ({}) - {} to {} - failed: {}", task_id, request_id, attempt, endpoint_url, e);
+            status_tracker.lock().unwrap().endpoint(&endpoint_url).num_failed += 1;
+            if !config.retry_on.retries_error(&e) {
+                status_tracker.lock().unwrap().num_tasks_failed += 1;
+                Err(request.failure(FailureCategory::of_error(&e), Value::String(e.to_string())))
+            } else {
+                match schedule_retry(&context, request, FailureCategory::of_error(&e)).await {
+                    // The retried request is still in progress
+                    Ok(()) => return,
+                    Err(request) => {
+                        status_tracker.lock().unwrap().num_tasks_failed += 1;
+                        Err(request.failure(FailureCategory::of_error(&e), Value::String(e.to_string())))
+                    }
+                }
+            }
+        }
+    };
+
+    context.finish_request(&batch, outcome).await;
+}
+
+/// The HTTP status and the chosen headers of a response, saved as `response_meta`
+///
+/// A header sent more than once keeps its values joined by `, `; headers the response lacks are left out.
+fn response_meta(status: StatusCode, headers: &HeaderMap, names: &[String]) -> Value {
+    let captured: serde_json::Map<String, Value> = names
+        .iter()
+        .filter_map(|name| {
+            let values: Vec<String> = headers.get_all(name.as_str()).iter().map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned()).collect();
+            (!values.is_empty()).then(|| (name.clone(), Value::String(values.join(", "))))
+        })
+        .collect();
+    serde_json::json!({"status": status.as_u16(), "headers": captured})
+}
+
+/// Whether a response was answered from the endpoint's cache
+///
+/// Any comma-separated part of the header starting with the hit value counts, case-insensitively,
+/// so `HIT`, `hit` and `HIT from cdn-3, MISS` all match `HIT`.
+fn is_cache_hit(headers: &HeaderMap, name: &str, hit_value: &str) -> bool {
+    let hit_value = hit_value.to_ascii_lowercase();
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|part| part.trim().to_ascii_lowercase().starts_with(&hit_value))
+}
+
+/// The body for a request to this endpoint, or an array of bodies for a batch
+fn build_body(config: &Config, endpoint: &Endpoint, request: &APIRequest) -> Result<Value, String> {
+    let build = |request: &APIRequest| {
+        let fields = Value::Object(request.request_json.clone().into_iter().collect());
+        config.request_body.build(&fields, endpoint.payload_template.as_ref())
+    };
+    if request.batch.is_empty() {
+        build(request)
+    } else {
+        request.batch.iter().map(build).collect::<Result<Vec<_>, _>>().map(Value::Array)
+    }
+}
+
+/// Judge a response by the endpoint's success rule and the response schema
+///
+/// Keeps only the extracted value if one is set. A failure comes with its category.
+fn judge(config: &Config, endpoint: &Endpoint, status: StatusCode, response: Value) -> Result<Value, (FailureCategory, Value)> {
+    let rule = endpoint.success_rule.as_ref().unwrap_or(&config.success_rule);
+    let statuses = endpoint.success_status.as_ref().unwrap_or(&config.success_status);
+    rule.check(status, statuses, &response).map_err(|error| (FailureCategory::Rejected, error))?;
+    if let Some(schema) = &config.response_schema {
+        let messages: Vec<String> = schema
+            .iter_errors(&response)
+            .map(|e| format!("{} at '{}'", e, e.instance_path()))
+            .collect();
+        if !messages.is_empty() {
+            let error = Value::String(format!("response failed schema validation: {}", messages.join("; ")));
+            return Err((FailureCategory::SchemaMismatch, error));
+        }
+    }
+    // Keep only the extracted value, failing if the response lacks it
+    match &config.extract {
+        Some(path) => path
+            .get(&response)
+            .cloned()
+            .ok_or_else(|| (FailureCategory::Rejected, Value::String(format!("response has no value at '{}'", path)))),
+        None => Ok(response),
+    }
+}
+
+/// Judge each object of an NDJSON response on its own, or any other response as a whole
+///
+/// One failing object fails the response.
+fn judge_objects(config: &Config, endpoint: &Endpoint, status: StatusCode, response: Value) -> Result<Value, (FailureCategory, Value)> {
+    match response {
+        Value::Array(objects) if config.ndjson_response => objects
+            .into_iter()
+            .map(|object| judge(config, endpoint, status, object))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        response => judge(config, endpoint, status, response),
+    }
+}
+
+/// The objects a response is made of: those of an NDJSON response, or the response itself
+fn response_objects<'a>(config: &Config, response: &'a Value) -> &'a [Value] {
+    match response {
+        Value::Array(objects) if config.ndjson_response => objects,
+        response => std::slice::from_ref(response),
+    }
+}
+
+/// Token usage of a response, summed over its objects
+fn response_usage(config: &Config, response: &Value) -> Option<Usage> {
+    response_objects(config, response)
+        .iter()
+        .filter_map(Usage::from_response)
+        .reduce(|total, usage| Usage {
+            prompt_tokens: total.prompt_tokens + usage.prompt_tokens,
+            completion_tokens: total.completion_tokens + usage.completion_tokens,
+        })
+}
+
+/// Parse a newline-delimited JSON body into an array of its values
+///
+/// Lines that are not JSON are logged and dropped; a body without any JSON is an error.
+fn parse_ndjson(task_id: usize, body: &[u8]) -> Result<Value, String> {
+    let mut objects = Vec::new();
+    for line in body.split(|&byte| byte == b'\n') {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        match serde_json::from_slice(line) {
+            Ok(object) => objects.push(object),
+            Err(e) => warn!("Request {} got a response line that is not JSON, dropping it: {}", task_id, e),
+        }
+    }
+    if objects.is_empty() {
+        return Err("response has no JSON objects".to_string());
+    }
+    Ok(Value::Array(objects))
+}
+
+/// Pair the elements of a batch response with the batch's requests by position, judging each on its own
+///
+/// Every request fails if the response is not an array with one element per request.
+fn split_batch(
+    context: &RunContext,
+    endpoint: &Endpoint,
+    status: StatusCode,
+    batch: &[APIRequest],
+    response: Value,
+    latency: Duration,
+    response_meta: Option<Value>,
+) -> Vec<Result<ProcessedResult, ClientError>> {
+    let mut tracker = context.status_tracker.lock().unwrap();
+    let elements = match response {
+        Value::Array(elements) if elements.len() == batch.len() => elements,
+        response => {
+            error!("Batch {} got a response that is not an array of {} results: {}", batch[0].task_id, batch.len(), response);
+            tracker.num_api_errors += 1;
+            tracker.num_tasks_failed += batch.len();
+            tracker.endpoint(&endpoint.url).num_failed += 1;
+            let error = Value::String(format!("batch response is not an array of {} results", batch.len()));
+            return batch.iter().map(|member| Err(member.failure(FailureCategory::Rejected, error.clone()))).collect();
+        }
+    };
+    tracker.endpoint(&endpoint.url).num_succeeded += 1;
+    batch
+        .iter()
+        .zip(elements)
+        .map(|(member, element)| match judge(&context.config, endpoint, status, element) {
+            Ok(response) => {
+                tracker.num_tasks_succeeded += 1;
+                Ok(ProcessedResult {
+                    task_id: member.task_id,
+                    input: member.input(),
+                    metadata: member.metadata.clone(),
+                    response,
+                    endpoint: endpoint.url.clone(),
+                    latency,
+                    skipped: false,
+                    response_meta: response_meta.clone(),
+                    cached: None,
+                })
+            }
+            Err((category, error)) => {
+                error!("Request {} in batch {} failed: {}", member.task_id, batch[0].task_id, error);
+                tracker.record_rejection(category);
+                tracker.num_tasks_failed += 1;
+                Err(member.failure(category, error))
+            }
+        })
+        .collect()
+}
+
+/// Render request headers for logging, hiding credentials
+fn redacted_headers(headers: &HeaderMap) -> String {
+    let rendered: Vec<String> = headers
+        .iter()
+        .map(|(name, value)| {
+            let name = name.as_str();
+            if is_secret_header(name) {
+                format!("{}: <redacted>", name)
+            } else {
+                format!("{}: {}", name, value.to_str().unwrap_or("<binary>"))
+            }
+        })
+        .collect();
+    rendered.join(", ")
+}
+
+/// Whether a header's value is a credential, to be hidden wherever headers are shown
+pub(crate) fn is_secret_header(name: &str) -> bool {
+    name == "authorization" || name.contains("key") || name.contains("token")
+}
+
+/// Hide an API key echoed back in logged text
+pub(crate) fn redact(text: &str, api_key: &str) -> String {
+    if api_key.is_empty() {
+        text.to_string()
+    } else {
+        text.replace(api_key, "<redacted>")
+    }
+}
+
+/// Queue a failed request for another attempt after its backoff
+///
+/// Gives the request back when it has no attempts left, or when too many
+/// retries are already pending and this one is shed. The backoff, chosen by
+/// the category of the failure, is waited out in a task of its own, so it
+/// does not hold up a worker.
+async fn schedule_retry(context: &Arc<RunContext>, mut request: APIRequest, category: FailureCategory) -> Result<(), APIRequest> {
+    request.attempts_left -= 1;
+    if request.attempts_left == 0 {
+        return Err(request);
+    }
+    let max_pending = context.config.max_pending_retries.unwrap_or(usize::MAX);
+    let reserved = context
+        .retries_pending
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+            if pending < max_pending {
+                Some(pending + 1)
+            } else {
+                None
+            }
+        })
+        .is_ok();
+    if !reserved {
+        warn!("Too many retries pending, shedding retry of request {}", request.task_id);
+        context.status_tracker.lock().unwrap().num_retries_shed += 1;
+        return Err(request);
+    }
+    if let Some(retry_state) = &context.retry_state {
+        retry_state.insert(&request);
+    }
+    // Add exponential backoff, but never less than the minimum interval
+    let backoff_duration = context
+        .config
+        .backoff_for(category)
+        .delay((request.max_attempts - request.attempts_left) as u32)
+        .max(context.config.min_retry_interval);
+    if let Some(observer) = &context.observer {
+        observer.on_retry(&request, backoff_duration);
+    }
+    let context = Arc::clone(context);
+    tokio::spawn(async move {
+        sleep(backoff_duration).await;
+        if let Some(limiter) = &context.retry_limiter {
+            limiter.acquire().await;
+        }
+        // The queue is only closed once the run has abandoned its requests
+        let _ = context.retry_tx.send(request);
+        context.retries_pending.fetch_sub(1, Ordering::SeqCst);
+        context.status_tracker.lock().unwrap().num_retries += 1;
+    });
+    Ok(())
+}
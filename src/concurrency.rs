@@ -0,0 +1,116 @@
+use log::debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Limit the run starts with
+const INITIAL_LIMIT: f64 = 10.0;
+const MIN_LIMIT: f64 = 1.0;
+const MAX_LIMIT: f64 = 1000.0;
+/// How far a latency may rise above the long-term average before the limit shrinks
+const TOLERANCE: f64 = 1.5;
+/// Responses the long-term average latency is taken over
+const LONG_WINDOW: f64 = 600.0;
+/// Weight of each new estimate, so single responses do not swing the limit
+const SMOOTHING: f64 = 0.2;
+/// Factor the limit is cut by when an endpoint reports overload
+const BACKOFF_RATIO: f64 = 0.9;
+
+/// A limit on requests in flight across all endpoints that follows their latency
+///
+/// A gradient controller like Netflix's adaptive concurrency limits: each
+/// response compares its latency to the long-term average. While latency
+/// stays near the average the limit grows by about its square root, as
+/// latency climbs above it the limit shrinks in proportion, and a response
+/// showing overload, such as a 429, 5xx or timeout, cuts it by a tenth.
+#[derive(Debug)]
+pub(crate) struct AdaptiveConcurrency {
+    state: Mutex<State>,
+    /// Wakes requests waiting for a slot when one frees up or the limit changes
+    changed: Notify,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    in_flight: usize,
+    /// Exponential moving average of the latency, in seconds
+    long_latency: Option<f64>,
+}
+
+impl AdaptiveConcurrency {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(AdaptiveConcurrency {
+            state: Mutex::new(State {
+                limit: INITIAL_LIMIT,
+                in_flight: 0,
+                long_latency: None,
+            }),
+            changed: Notify::new(),
+        })
+    }
+
+    /// Requests allowed in flight right now
+    pub(crate) fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit as usize
+    }
+
+    /// Wait until the request fits under the limit, holding a slot until the permit is finished or dropped
+    pub(crate) async fn acquire(self: &Arc<Self>) -> ConcurrencyPermit {
+        loop {
+            // Made before checking, so a slot freed in between still wakes this request
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if (state.in_flight as f64) < state.limit.floor() {
+                    state.in_flight += 1;
+                    return ConcurrencyPermit { limit: Arc::clone(self) };
+                }
+            }
+            changed.await;
+        }
+    }
+
+    fn record(&self, latency: Duration, overloaded: bool) {
+        let mut state = self.state.lock().unwrap();
+        let previous = state.limit;
+        if overloaded {
+            state.limit = (state.limit * BACKOFF_RATIO).max(MIN_LIMIT);
+        } else {
+            let latency = latency.as_secs_f64().max(1e-6);
+            let long_latency = match state.long_latency {
+                Some(long_latency) => long_latency + (latency - long_latency) / LONG_WINDOW,
+                None => latency,
+            };
+            state.long_latency = Some(long_latency);
+            let gradient = (TOLERANCE * long_latency / latency).clamp(0.5, 1.0);
+            // Grow only while the limit is in use, or an idle run would raise it without bound
+            let headroom = if state.in_flight as f64 >= state.limit / 2.0 { state.limit.sqrt() } else { 0.0 };
+            let estimate = state.limit * gradient + headroom;
+            state.limit = (state.limit * (1.0 - SMOOTHING) + estimate * SMOOTHING).clamp(MIN_LIMIT, MAX_LIMIT);
+        }
+        if state.limit as usize != previous as usize {
+            debug!("Adaptive concurrency limit is now {}", state.limit as usize);
+        }
+    }
+}
+
+/// A request's slot under the adaptive limit
+#[derive(Debug)]
+pub(crate) struct ConcurrencyPermit {
+    limit: Arc<AdaptiveConcurrency>,
+}
+
+impl ConcurrencyPermit {
+    /// Free the slot, adjusting the limit by how the request went
+    pub(crate) fn finish(self, latency: Duration, overloaded: bool) {
+        self.limit.record(latency, overloaded);
+    }
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.limit.state.lock().unwrap().in_flight -= 1;
+        self.limit.changed.notify_waiters();
+    }
+}
@@ -0,0 +1,1589 @@
+use crate::backoff::BackoffPolicy;
+use crate::connector::MIN_HTTP1_BUF_SIZE;
+use crate::encoding::BodyEncoding;
+use crate::endpoint::{Endpoint, LbStrategy};
+use crate::error::ClientError;
+use crate::json_path::{FieldMatch, JsonPath};
+use crate::output::{OutputFormat, OUTPUT_FIELD_ROOTS};
+use crate::report::FailureCategory;
+use crate::retry::{BodyRetryRule, RetryPolicy};
+use crate::sample::Sample;
+use crate::shard::Shard;
+use crate::success::{SuccessRule, SuccessStatuses};
+use crate::template::{DerivedFields, PayloadTemplate, RequestBody};
+use crate::transform::Provider;
+use jsonschema::Validator;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Settings for a processing run
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub endpoints: Vec<Endpoint>,
+    pub save_filepath: String,
+    pub error_filepath: String,
+    /// JSONL file that the input lines of failed requests are written to, ready to be sent again
+    pub dead_letter_filepath: Option<String>,
+    /// Keep what the save and error files already hold instead of emptying them first
+    pub append: bool,
+    /// Keep only the last record of each task id in the save file once a run is written
+    pub upsert: bool,
+    /// Count and judge successful results without writing them; failures are still written
+    pub no_save: bool,
+    /// Longest time written records may stay buffered; zero writes each record through at once
+    pub flush_interval: Duration,
+    /// Flush once this many records are buffered, whatever the interval
+    pub flush_every: usize,
+    /// Also have the OS write flushed records to disk
+    pub fsync: bool,
+    /// Tasks rendering outcomes side by side before taking turns writing them
+    pub output_writers: usize,
+    /// May be fractional, e.g. `0.5` for one request every two seconds
+    pub max_requests_per_second: f64,
+    pub max_attempts: usize,
+    pub backoff: BackoffPolicy,
+    /// Backoff for retries after failures of these categories, in place of `backoff`
+    pub category_backoff: BTreeMap<FailureCategory, BackoffPolicy>,
+    /// Shortest wait before any retry, whatever the backoff gives
+    pub min_retry_interval: Duration,
+    pub skip: usize,
+    pub limit: Option<usize>,
+    /// Slice of the input this process handles, for splitting a file across machines
+    pub shard: Option<Shard>,
+    /// Random fraction of the lines to process; the other lines are dropped without a record
+    pub sample: Option<Sample>,
+    /// Input lines longer than this are written to the error file instead of being parsed
+    pub max_line_bytes: Option<usize>,
+    /// Lines matching any of these are saved as skipped instead of being sent
+    pub skip_if: Vec<FieldMatch>,
+    /// Compiled schema each input line must satisfy before it is sent
+    pub input_schema: Option<Arc<Validator>>,
+    /// Fields computed from each valid input line and added to it before its body is built
+    pub derived_fields: Option<DerivedFields>,
+    /// Responses that pass the success rule but not this schema are failures
+    pub response_schema: Option<Arc<Validator>>,
+    /// Responses larger than this are abandoned and treated as API errors
+    pub max_response_bytes: Option<usize>,
+    /// Redirects followed per attempt; 0 treats redirect responses like any other status
+    pub max_redirects: usize,
+    /// PEM client certificate presented to every endpoint without its own
+    pub client_cert: Option<String>,
+    /// PKCS#8 PEM private key for `client_cert`
+    pub client_key: Option<String>,
+    /// PEM file of root certificates trusted in addition to the system store
+    pub ca_cert: Option<String>,
+    /// Accept any server certificate and host name; for testing only
+    pub insecure_skip_verify: bool,
+    pub lb_strategy: LbStrategy,
+    /// Bound of the request queue; defaults to two seconds worth of requests
+    pub queue_capacity: Option<usize>,
+    /// Directory to spill requests to once the queue is full, instead of waiting for room
+    pub queue_spill_dir: Option<String>,
+    /// Long-lived tasks sending requests; without it each request gets a task of its own
+    pub workers: Option<usize>,
+    /// Limit the requests in flight across all endpoints by a limit that follows their latency and errors
+    pub adaptive_concurrency: bool,
+    /// Most requests in flight across all endpoints at once, on top of each endpoint's `max_concurrency`
+    pub max_concurrency: Option<usize>,
+    /// Most HTTP requests a run may send, counting retries
+    pub max_requests: Option<usize>,
+    /// Tasks failing in a row, without a success between them, that stop the run as broken
+    pub abort_after_consecutive_failures: Option<usize>,
+    /// How responses are judged for endpoints without their own rule
+    pub success_rule: SuccessRule,
+    /// HTTP statuses counted as success by `SuccessRule::HttpStatus`
+    pub success_status: SuccessStatuses,
+    /// Key-value pairs added to every saved result
+    pub tags: Vec<(String, String)>,
+    /// Which failures are retried
+    pub retry_on: RetryPolicy,
+    /// Responses matching any of these are retried instead of saved
+    pub retry_on_body_errors: Vec<BodyRetryRule>,
+    /// Header carrying the task id, so servers can deduplicate retried requests
+    pub idempotency_key_header: Option<String>,
+    /// `User-Agent` sent with every request
+    pub user_agent: String,
+    /// OTLP/HTTP traces URL of an OpenTelemetry collector that task and attempt spans are posted to
+    pub otel_endpoint: Option<String>,
+    /// `service.name` of the exported spans
+    pub otel_service_name: String,
+    /// Longest to wait for a TCP connection to an endpoint
+    pub connect_timeout: Option<Duration>,
+    /// Longest to wait from sending a request for the first byte of its response body
+    pub ttfb_timeout: Option<Duration>,
+    /// Wall-clock time after which no more fresh requests are sent
+    pub max_runtime: Option<Duration>,
+    /// How long requests in flight at `max_runtime` or a shutdown may take to finish before they are abandoned
+    pub shutdown_grace: Duration,
+    /// JSONL file that lines left unprocessed at `max_runtime` or a shutdown are written to
+    pub resume_filepath: Option<String>,
+    /// JSONL file that requests never attempted before a run stopped early are written to, as serialized `APIRequest`s
+    pub unprocessed_filepath: Option<String>,
+    /// JSONL file that requests waiting to be retried are saved to, and loaded from at the start of a run
+    pub retry_state_filepath: Option<String>,
+    /// How often `retry_state_filepath` is rewritten
+    pub retry_state_interval: Duration,
+    /// JSON file the run's progress and the endpoints' quotas are saved to, for `resume`
+    pub checkpoint_filepath: Option<String>,
+    /// Outcomes written between saves of `checkpoint_filepath`
+    pub checkpoint_every: usize,
+    /// Longest time between saves of `checkpoint_filepath`
+    pub checkpoint_interval: Duration,
+    /// Start from the input line after those `checkpoint_filepath` says are done, adding to the output files
+    pub resume: bool,
+    /// Interval of TCP keepalive probes on idle connections
+    pub tcp_keepalive: Option<Duration>,
+    /// Most connections open to one host at once
+    pub max_connections_per_host: Option<usize>,
+    /// Directory every endpoint response is recorded to, for replaying later
+    pub record_dir: Option<String>,
+    /// HTTP Archive file every request and response is written to, for debugging
+    pub har_filepath: Option<String>,
+    /// Directory recorded responses are answered from, in place of the network
+    pub replay_dir: Option<String>,
+    /// Directory successful responses are kept in, answering the same requests in later runs
+    pub cache_dir: Option<String>,
+    /// How long a response in `cache_dir` answers requests; forever without one
+    pub cache_ttl: Option<Duration>,
+    /// Largest HTTP/1 read buffer per connection, in bytes
+    pub http1_max_buf_size: Option<usize>,
+    /// Log whether each response came on a new or a reused connection
+    pub log_connections: bool,
+    /// Time over which the send rate ramps up to `max_requests_per_second`
+    pub warmup: Option<Duration>,
+    /// Longest random wait before the first request, to stagger many instances started together
+    pub startup_jitter: Option<Duration>,
+    /// Seed for endpoint selection, making the choice of endpoints reproducible
+    pub seed: Option<u64>,
+    /// Most retries waiting at once; further retries fail instead of queueing
+    pub max_pending_retries: Option<usize>,
+    /// Most retries re-dispatched per second across all requests
+    pub max_retry_rate: Option<f64>,
+    /// Most tokens sent per minute across all requests, by each request's estimated cost
+    pub max_tokens_per_minute: Option<u64>,
+    /// Field of each input line holding its estimated token cost; the cost is guessed from the line's length without it
+    pub token_cost_field: Option<JsonPath>,
+    /// Most outcomes waiting to be written before sending pauses
+    pub max_pending_writes: Option<usize>,
+    /// Input lines grouped into each API call, sent as an array of bodies
+    pub batch_size: usize,
+    /// Longest a partial batch waits for more lines before it is sent
+    pub batch_linger: Duration,
+    /// Times each input line is sent, each as a task of its own
+    pub repeat: usize,
+    /// Stop sending the tasks of a group once one of them fails for good, saving them as skipped
+    pub cancel_groups: bool,
+    pub output_format: OutputFormat,
+    /// Read response bodies as newline-delimited JSON, saving one record per object
+    pub ndjson_response: bool,
+    /// Part of each response to keep; responses without it are failures
+    pub extract: Option<JsonPath>,
+    /// Fields that make up each saved JSONL record, instead of the full record
+    pub output_fields: Option<Vec<JsonPath>>,
+    /// Save the HTTP status and `response_meta_headers` of each result under `response_meta`
+    pub include_response_meta: bool,
+    /// Response headers kept in `response_meta`, lowercase
+    pub response_meta_headers: Vec<String>,
+    /// Response header telling whether the endpoint answered from its cache, lowercase
+    pub cache_header: Option<String>,
+    /// Value of `cache_header` that marks a cache hit, compared case-insensitively
+    pub cache_hit_value: String,
+    /// Save whether each result was a cache hit under `cached`, with `cache_header`
+    pub include_cache_status: bool,
+    /// Field of the input line whose value picks the save file of each result
+    pub output_by: Option<JsonPath>,
+    /// Most output files held open at once when results are split by `output_by`
+    pub max_open_files: usize,
+    /// Body sent for each request, filled in from the input line
+    pub request_body: RequestBody,
+    /// How the body is serialized and which `Content-Type` it is sent with
+    pub body_encoding: BodyEncoding,
+    /// Request and response shape of the API for endpoints without their own
+    pub provider: Provider,
+    /// Field of each input line holding the user message, also logged and saved as the input
+    pub input_field: String,
+}
+
+impl Config {
+    /// Start building a config
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Backoff for a retry after a failure of this category
+    pub fn backoff_for(&self, category: FailureCategory) -> &BackoffPolicy {
+        self.category_backoff.get(&category).unwrap_or(&self.backoff)
+    }
+
+    /// Number of requests the queue between the producer and the consumers holds
+    pub fn effective_queue_capacity(&self) -> usize {
+        self.queue_capacity
+            .unwrap_or_else(|| ((self.max_requests_per_second * 2.0).ceil() as usize).max(1))
+    }
+
+    /// Config for a single canary request to `endpoint`
+    ///
+    /// Only the settings that build, send and judge a request are taken from
+    /// this config, the rest are defaults, so a canary is never recorded,
+    /// traced, cached, dead-lettered or counted like a task of the run.
+    pub(crate) fn for_canary(&self, endpoint: &Endpoint) -> Result<Config, ClientError> {
+        let endpoint = Endpoint {
+            weight: 1,
+            route_key: None,
+            ..endpoint.clone()
+        };
+        let defaults = Config::builder().endpoint(endpoint).ttfb_timeout(self.ttfb_timeout).build()?;
+        Ok(Config {
+            max_attempts: 1,
+            // The request
+            input_field: self.input_field.clone(),
+            input_schema: self.input_schema.clone(),
+            derived_fields: self.derived_fields.clone(),
+            request_body: self.request_body.clone(),
+            body_encoding: self.body_encoding,
+            provider: self.provider,
+            user_agent: self.user_agent.clone(),
+            idempotency_key_header: self.idempotency_key_header.clone(),
+            // How it is sent
+            connect_timeout: self.connect_timeout,
+            tcp_keepalive: self.tcp_keepalive,
+            http1_max_buf_size: self.http1_max_buf_size,
+            max_redirects: self.max_redirects,
+            max_response_bytes: self.max_response_bytes,
+            client_cert: self.client_cert.clone(),
+            client_key: self.client_key.clone(),
+            ca_cert: self.ca_cert.clone(),
+            insecure_skip_verify: self.insecure_skip_verify,
+            replay_dir: self.replay_dir.clone(),
+            // How the response is judged
+            success_rule: self.success_rule.clone(),
+            success_status: self.success_status.clone(),
+            retry_on: self.retry_on.clone(),
+            retry_on_body_errors: self.retry_on_body_errors.clone(),
+            response_schema: self.response_schema.clone(),
+            ndjson_response: self.ndjson_response,
+            extract: self.extract.clone(),
+            ..defaults
+        })
+    }
+
+    /// Fails if a run reading this requests file would also write to it, destroying the input
+    pub fn check_requests_file(&self, path: &str) -> Result<(), ClientError> {
+        match self.output_files().into_iter().find(|(_, file)| same_file(file, path)) {
+            Some((kind, _)) => Err(ClientError::InvalidConfig(format!("requests file {} is also the {} file", path, kind))),
+            None => Ok(()),
+        }
+    }
+
+    /// The save, error, dead letter and HAR files, with what each is for
+    fn output_files(&self) -> Vec<(&'static str, &str)> {
+        let mut files = Vec::new();
+        if !self.no_save {
+            files.push(("save", self.save_filepath.as_str()));
+        }
+        files.push(("error", self.error_filepath.as_str()));
+        if let Some(path) = &self.dead_letter_filepath {
+            files.push(("dead letter", path.as_str()));
+        }
+        if let Some(path) = &self.har_filepath {
+            files.push(("HAR", path.as_str()));
+        }
+        files
+    }
+
+    /// Fails if two of the output files are the same file, which would interleave their records
+    fn check_output_files(&self) -> Result<(), ClientError> {
+        let files = self.output_files();
+        for (i, (kind, path)) in files.iter().enumerate() {
+            if let Some((other, _)) = files[i + 1..].iter().find(|(_, other)| same_file(path, other)) {
+                return Err(ClientError::InvalidConfig(format!("the {} file and the {} file are both {}", kind, other, path)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether two paths name the same file, e.g. `out.jsonl` and `./out.jsonl`
+///
+/// Paths are resolved through the file system where they exist, so symlinks
+/// count too, and compared as written, without `.` parts, where they do not.
+fn same_file(a: &str, b: &str) -> bool {
+    resolve(a) == resolve(b)
+}
+
+fn resolve(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if let Ok(resolved) = std::fs::canonicalize(path) {
+        return resolved;
+    }
+    // A file not written yet, in a directory that may exist
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match (std::fs::canonicalize(dir), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.components().filter(|part| !matches!(part, Component::CurDir)).collect(),
+    }
+}
+
+/// Builder for `Config`
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    endpoints: Vec<Endpoint>,
+    secrets: HashMap<String, String>,
+    save_filepath: String,
+    error_filepath: String,
+    dead_letter_filepath: Option<String>,
+    append: bool,
+    upsert: bool,
+    no_save: bool,
+    flush_interval: Duration,
+    flush_every: usize,
+    fsync: bool,
+    output_writers: usize,
+    max_requests_per_second: f64,
+    max_attempts: usize,
+    backoff: BackoffPolicy,
+    category_backoff: BTreeMap<FailureCategory, BackoffPolicy>,
+    min_retry_interval: Duration,
+    skip: usize,
+    limit: Option<usize>,
+    shard: Option<Shard>,
+    sample: Option<Sample>,
+    max_line_bytes: Option<usize>,
+    skip_if: Vec<FieldMatch>,
+    input_schema: Option<Value>,
+    derived_fields: Option<DerivedFields>,
+    response_schema: Option<Value>,
+    max_response_bytes: Option<usize>,
+    max_redirects: usize,
+    client_cert: Option<String>,
+    client_key: Option<String>,
+    ca_cert: Option<String>,
+    insecure_skip_verify: bool,
+    lb_strategy: LbStrategy,
+    queue_capacity: Option<usize>,
+    queue_spill_dir: Option<String>,
+    workers: Option<usize>,
+    adaptive_concurrency: bool,
+    max_concurrency: Option<usize>,
+    max_requests: Option<usize>,
+    abort_after_consecutive_failures: Option<usize>,
+    success_rule: SuccessRule,
+    success_status: SuccessStatuses,
+    tags: Vec<(String, String)>,
+    retry_on: RetryPolicy,
+    retry_on_body_errors: Vec<BodyRetryRule>,
+    idempotency_key_header: Option<String>,
+    user_agent: String,
+    otel_endpoint: Option<String>,
+    otel_service_name: String,
+    connect_timeout: Option<Duration>,
+    ttfb_timeout: Option<Duration>,
+    max_runtime: Option<Duration>,
+    shutdown_grace: Duration,
+    resume_filepath: Option<String>,
+    unprocessed_filepath: Option<String>,
+    retry_state_filepath: Option<String>,
+    retry_state_interval: Duration,
+    checkpoint_filepath: Option<String>,
+    checkpoint_every: usize,
+    checkpoint_interval: Duration,
+    resume: bool,
+    tcp_keepalive: Option<Duration>,
+    max_connections_per_host: Option<usize>,
+    record_dir: Option<String>,
+    har_filepath: Option<String>,
+    replay_dir: Option<String>,
+    cache_dir: Option<String>,
+    cache_ttl: Option<Duration>,
+    http1_max_buf_size: Option<usize>,
+    log_connections: bool,
+    warmup: Option<Duration>,
+    startup_jitter: Option<Duration>,
+    seed: Option<u64>,
+    max_pending_retries: Option<usize>,
+    max_pending_writes: Option<usize>,
+    batch_size: usize,
+    batch_linger: Duration,
+    repeat: usize,
+    cancel_groups: bool,
+    max_retry_rate: Option<f64>,
+    max_tokens_per_minute: Option<u64>,
+    token_cost_field: Option<JsonPath>,
+    output_format: OutputFormat,
+    ndjson_response: bool,
+    extract: Option<JsonPath>,
+    output_fields: Option<Vec<JsonPath>>,
+    include_response_meta: bool,
+    response_meta_headers: Vec<String>,
+    cache_header: Option<String>,
+    cache_hit_value: String,
+    include_cache_status: bool,
+    output_by: Option<JsonPath>,
+    max_open_files: usize,
+    payload_template: Option<PayloadTemplate>,
+    passthrough_body: bool,
+    body_encoding: BodyEncoding,
+    provider: Provider,
+    input_field: String,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder {
+            endpoints: Vec::new(),
+            secrets: HashMap::new(),
+            save_filepath: "results.jsonl".to_string(),
+            error_filepath: "errors.jsonl".to_string(),
+            dead_letter_filepath: None,
+            flush_interval: Duration::from_secs(1),
+            flush_every: 100,
+            fsync: false,
+            output_writers: 1,
+            append: false,
+            upsert: false,
+            no_save: false,
+            max_requests_per_second: 10.0,
+            max_attempts: 3,
+            backoff: BackoffPolicy::default(),
+            category_backoff: BTreeMap::new(),
+            min_retry_interval: Duration::ZERO,
+            skip: 0,
+            limit: None,
+            shard: None,
+            sample: None,
+            max_line_bytes: None,
+            skip_if: Vec::new(),
+            input_schema: None,
+            derived_fields: None,
+            response_schema: None,
+            max_response_bytes: None,
+            max_redirects: 5,
+            client_cert: None,
+            client_key: None,
+            ca_cert: None,
+            insecure_skip_verify: false,
+            lb_strategy: LbStrategy::default(),
+            queue_capacity: None,
+            queue_spill_dir: None,
+            workers: None,
+            adaptive_concurrency: false,
+            max_concurrency: None,
+            max_requests: None,
+            abort_after_consecutive_failures: None,
+            success_rule: SuccessRule::default(),
+            success_status: SuccessStatuses::default(),
+            tags: Vec::new(),
+            retry_on: RetryPolicy::default(),
+            retry_on_body_errors: Vec::new(),
+            idempotency_key_header: None,
+            user_agent: concat!("api_processor/", env!("CARGO_PKG_VERSION")).to_string(),
+            otel_endpoint: None,
+            otel_service_name: "api_processor".to_string(),
+            connect_timeout: None,
+            ttfb_timeout: None,
+            max_runtime: None,
+            shutdown_grace: Duration::from_secs(30),
+            resume_filepath: None,
+            unprocessed_filepath: None,
+            retry_state_filepath: None,
+            retry_state_interval: Duration::from_secs(5),
+            checkpoint_filepath: None,
+            checkpoint_every: 10_000,
+            checkpoint_interval: Duration::from_secs(10),
+            resume: false,
+            tcp_keepalive: None,
+            max_connections_per_host: None,
+            record_dir: None,
+            har_filepath: None,
+            replay_dir: None,
+            cache_dir: None,
+            cache_ttl: None,
+            http1_max_buf_size: None,
+            log_connections: false,
+            warmup: None,
+            startup_jitter: None,
+            seed: None,
+            max_pending_retries: None,
+            max_pending_writes: None,
+            batch_size: 1,
+            batch_linger: Duration::from_millis(100),
+            repeat: 1,
+            cancel_groups: false,
+            max_retry_rate: None,
+            max_tokens_per_minute: None,
+            token_cost_field: None,
+            output_format: OutputFormat::default(),
+            ndjson_response: false,
+            extract: None,
+            output_fields: None,
+            include_response_meta: false,
+            response_meta_headers: Vec::new(),
+            cache_header: None,
+            cache_hit_value: "HIT".to_string(),
+            include_cache_status: false,
+            output_by: None,
+            max_open_files: 64,
+            payload_template: None,
+            passthrough_body: false,
+            body_encoding: BodyEncoding::default(),
+            provider: Provider::default(),
+            input_field: "input".to_string(),
+        }
+    }
+}
+
+impl ConfigBuilder {
+    /// Add an endpoint to the load-balanced pool
+    pub fn endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    /// API keys by endpoint id or URL, for endpoints that do not carry their own
+    pub fn secrets(mut self, secrets: HashMap<String, String>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Add several endpoints to the load-balanced pool
+    pub fn endpoints<I: IntoIterator<Item = Endpoint>>(mut self, endpoints: I) -> Self {
+        self.endpoints.extend(endpoints);
+        self
+    }
+
+    /// Path that successful responses are written to
+    pub fn save_filepath<S: Into<String>>(mut self, path: S) -> Self {
+        self.save_filepath = path.into();
+        self
+    }
+
+    /// Path that failed requests are written to
+    pub fn error_filepath<S: Into<String>>(mut self, path: S) -> Self {
+        self.error_filepath = path.into();
+        self
+    }
+
+    /// Also write the original input line of each failed request to this JSONL file
+    ///
+    /// Unlike the error file, which is meant for reading, the file can be fed
+    /// back in as the input of another run, e.g. with a fallback endpoint.
+    pub fn dead_letter_filepath(mut self, path: Option<String>) -> Self {
+        self.dead_letter_filepath = path;
+        self
+    }
+
+    /// Longest time written records may stay in memory before they are flushed to the output files
+    ///
+    /// Zero flushes after every record, as does a `flush_every` of 1.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Flush the output files once this many records are waiting
+    pub fn flush_every(mut self, records: usize) -> Self {
+        self.flush_every = records;
+        self
+    }
+
+    /// Render outcomes into records in this many tasks, 1 by default
+    ///
+    /// The tasks take turns writing to the same files, so records of
+    /// different tasks may be saved in a different order than they completed.
+    /// Only rendering runs side by side, so it can only help with several
+    /// cores and large responses; one writer keeps up with any API rate.
+    pub fn output_writers(mut self, writers: usize) -> Self {
+        self.output_writers = writers;
+        self
+    }
+
+    /// Sync the output files to disk on every flush, so records survive a crash of the machine
+    pub fn fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Append to existing save and error files instead of emptying them when processing starts
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Replace earlier records of a task id in the save file instead of adding another
+    ///
+    /// Once a run's outcomes are written, the save file, and any `output_by`
+    /// file, is compacted to the last record of each task id, so an `append`
+    /// run that redoes some lines leaves one record per line. Needs JSONL output.
+    pub fn upsert(mut self, upsert: bool) -> Self {
+        self.upsert = upsert;
+        self
+    }
+
+    /// Leave successful results out of the save file, for load tests and validation runs
+    ///
+    /// Successes are still judged and counted, and failures are written to
+    /// the error and dead-letter files. The save file is neither created nor
+    /// emptied, so it holds no record of which lines succeeded to resume from.
+    pub fn no_save(mut self, no_save: bool) -> Self {
+        self.no_save = no_save;
+        self
+    }
+
+    /// Maximum number of requests to send per second, e.g. `0.5` for one every two seconds
+    pub fn max_requests_per_second(mut self, rate: f64) -> Self {
+        self.max_requests_per_second = rate;
+        self
+    }
+
+    /// Maximum number of attempts for each request
+    pub fn max_attempts(mut self, attempts: usize) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Backoff applied between retries
+    pub fn backoff(mut self, backoff: BackoffPolicy) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Backoff for retries after failures of this category, e.g. a gentler ramp for `RateLimited`
+    ///
+    /// Categories without one use `backoff`. A retry takes the category its
+    /// attempt would have failed with, such as `Connect` or `ServerError`.
+    pub fn category_backoff(mut self, category: FailureCategory, backoff: BackoffPolicy) -> Self {
+        self.category_backoff.insert(category, backoff);
+        self
+    }
+
+    /// Floor on every retry delay, for APIs that need attempts of a task spaced out; the larger of it and the backoff applies
+    pub fn min_retry_interval(mut self, interval: Duration) -> Self {
+        self.min_retry_interval = interval;
+        self
+    }
+
+    /// Number of input lines to discard before enqueuing anything
+    pub fn skip(mut self, lines: usize) -> Self {
+        self.skip = lines;
+        self
+    }
+
+    /// Save lines matching the rule as skipped without sending them, e.g. `status=done`; may be called more than once
+    ///
+    /// Skipped lines still get a record in the save file, so results stay aligned with the input.
+    pub fn skip_if(mut self, rule: FieldMatch) -> Self {
+        self.skip_if.push(rule);
+        self
+    }
+
+    /// Maximum number of input lines to process, counted after `skip`
+    pub fn limit(mut self, lines: Option<usize>) -> Self {
+        self.limit = lines;
+        self
+    }
+
+    /// Process only the lines whose task id falls in this shard
+    ///
+    /// Task ids still count every line, so results from different shards line up.
+    pub fn shard(mut self, shard: Option<Shard>) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    /// Process only a random fraction of the lines, e.g. to try a prompt on part of a large file
+    ///
+    /// Lines left out still use up their task ids, and are counted but not saved.
+    pub fn sample(mut self, sample: Option<Sample>) -> Self {
+        self.sample = sample;
+        self
+    }
+
+    /// Longest input line to read, in bytes, not counting the line ending
+    pub fn max_line_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_line_bytes = max_bytes;
+        self
+    }
+
+    /// JSON Schema that each input line is validated against before sending
+    pub fn input_schema(mut self, schema: Value) -> Self {
+        self.input_schema = Some(schema);
+        self
+    }
+
+    /// Fields computed from each input line, e.g. a prompt built from several columns
+    ///
+    /// Computed once the line has passed `input_schema`, and available to the
+    /// payload template and the URL. Lines missing a field the templates use are invalid.
+    pub fn derived_fields(mut self, fields: DerivedFields) -> Self {
+        self.derived_fields = Some(fields);
+        self
+    }
+
+    /// JSON Schema that each successful response must match, catching APIs that degrade silently
+    pub fn response_schema(mut self, schema: Value) -> Self {
+        self.response_schema = Some(schema);
+        self
+    }
+
+    /// Largest response body to read into memory
+    pub fn max_response_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_response_bytes = max_bytes;
+        self
+    }
+
+    /// Most redirects to follow for one attempt
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Client certificate and private key (PEM files) for mutual TLS with every endpoint
+    pub fn client_identity<S: Into<String>>(mut self, cert_path: S, key_path: S) -> Self {
+        self.client_cert = Some(cert_path.into());
+        self.client_key = Some(key_path.into());
+        self
+    }
+
+    /// PEM file of root certificates to trust besides the system store, e.g. a private CA
+    pub fn ca_cert<S: Into<String>>(mut self, path: S) -> Self {
+        self.ca_cert = Some(path.into());
+        self
+    }
+
+    /// Skip verifying server certificates and host names
+    ///
+    /// Leaves connections open to interception; only for testing against
+    /// servers with self-signed certificates.
+    pub fn insecure_skip_verify(mut self, skip: bool) -> Self {
+        self.insecure_skip_verify = skip;
+        self
+    }
+
+    /// How requests are spread across endpoints
+    pub fn lb_strategy(mut self, strategy: LbStrategy) -> Self {
+        self.lb_strategy = strategy;
+        self
+    }
+
+    /// Bound of the request queue
+    ///
+    /// Once the queue is full the producer waits for a consumer to take a
+    /// request before reading more input, so reading never runs far ahead of
+    /// sending.
+    pub fn queue_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Directory to spill requests to once the queue is full
+    ///
+    /// Input is then read without waiting for the senders, and requests that
+    /// do not fit in the queue are kept in a file in this directory until
+    /// there is room, so memory stays bounded by `queue_capacity`. The
+    /// directory is created if needed.
+    pub fn queue_spill_dir(mut self, dir: Option<String>) -> Self {
+        self.queue_spill_dir = dir;
+        self
+    }
+
+    /// Send requests from a fixed pool of this many tasks, which also bounds the requests in flight
+    ///
+    /// Each worker takes the next request once its last one is answered;
+    /// retries wait out their backoff without holding a worker. Without a
+    /// pool, every request is sent from a task of its own.
+    pub fn workers(mut self, workers: Option<usize>) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Find the number of requests to keep in flight from the endpoints' latency instead of fixing it
+    ///
+    /// The limit starts at 10 and grows while latency stays level, shrinks as
+    /// it climbs, and is cut on 429s, 5xx responses, timeouts and connection
+    /// errors. It applies on top of `workers`, endpoint `max_concurrency` and pacing.
+    pub fn adaptive_concurrency(mut self, adaptive: bool) -> Self {
+        self.adaptive_concurrency = adaptive;
+        self
+    }
+
+    /// Limit the requests in flight across all endpoints at once
+    ///
+    /// A request to an endpoint with its own `max_concurrency` needs a slot
+    /// under both limits, so the smaller one applies. Slots are held from
+    /// sending a request until its response is read, not during a retry's backoff.
+    pub fn max_concurrency(mut self, max_concurrency: Option<usize>) -> Self {
+        self.max_concurrency = max_concurrency;
+        self
+    }
+
+    /// Cap on the number of HTTP requests sent, including retries
+    pub fn max_requests(mut self, max_requests: Option<usize>) -> Self {
+        self.max_requests = max_requests;
+        self
+    }
+
+    /// Stop the run once this many tasks fail in a row, as the endpoints are likely misconfigured
+    ///
+    /// Only tasks that were sent count, and any success resets the count. The
+    /// run then ends like a shutdown and returns `ClientError::Aborted`.
+    pub fn abort_after_consecutive_failures(mut self, failures: Option<usize>) -> Self {
+        self.abort_after_consecutive_failures = failures;
+        self
+    }
+
+    /// How responses are judged to be successes or failures
+    pub fn success_rule(mut self, rule: SuccessRule) -> Self {
+        self.success_rule = rule;
+        self
+    }
+
+    /// HTTP statuses the `status` success rule accepts, 200-299 by default
+    ///
+    /// For backends that answer 201 or 202 for created or accepted work.
+    pub fn success_status(mut self, statuses: SuccessStatuses) -> Self {
+        self.success_status = statuses;
+        self
+    }
+
+    /// Add a key-value pair to every saved result, e.g. a run id or model name
+    pub fn tag<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Which failures are retried; by default only those the server cannot have acted on
+    pub fn retry_on(mut self, policy: RetryPolicy) -> Self {
+        self.retry_on = policy;
+        self
+    }
+
+    /// Retry responses whose body matches the rule, e.g. `error.code=server_overloaded`; may be called more than once
+    pub fn retry_on_body_error(mut self, rule: BodyRetryRule) -> Self {
+        self.retry_on_body_errors.push(rule);
+        self
+    }
+
+    /// Send the task id in this header, e.g. `Idempotency-Key`
+    pub fn idempotency_key_header<S: Into<String>>(mut self, header: Option<S>) -> Self {
+        self.idempotency_key_header = header.map(Into::into);
+        self
+    }
+
+    /// `User-Agent` sent with every request, by default `api_processor/<version>`
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Post a span per task and per attempt to this OpenTelemetry collector, e.g. `http://localhost:4318/v1/traces`
+    ///
+    /// Needs the crate's `otel` feature.
+    pub fn otel_endpoint<S: Into<String>>(mut self, endpoint: Option<S>) -> Self {
+        self.otel_endpoint = endpoint.map(Into::into);
+        self
+    }
+
+    /// `service.name` of the exported spans, by default `api_processor`
+    pub fn otel_service_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.otel_service_name = name.into();
+        self
+    }
+
+    /// Longest to wait for a TCP connection to an endpoint
+    ///
+    /// Timed-out connections fail as connect errors, so they are retried
+    /// under the `connect` retry condition.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Longest to wait from sending a request until its response starts to arrive
+    ///
+    /// Covers the response headers, any redirects and the first chunk of the
+    /// body, but not reading the rest of the body. A timed-out attempt is
+    /// retried under the `timeout` retry condition.
+    pub fn ttfb_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.ttfb_timeout = timeout;
+        self
+    }
+
+    /// Stop sending fresh requests once a run has lasted this long
+    ///
+    /// Requests already in flight, including their retries, get
+    /// `shutdown_grace` to finish; lines not yet sent are counted as
+    /// unprocessed and written to `resume_filepath` if one is set.
+    pub fn max_runtime(mut self, max_runtime: Option<Duration>) -> Self {
+        self.max_runtime = max_runtime;
+        self
+    }
+
+    /// How long requests in flight at the maximum runtime or a shutdown may take to finish, 30 seconds by default
+    pub fn shutdown_grace(mut self, grace: Duration) -> Self {
+        self.shutdown_grace = grace;
+        self
+    }
+
+    /// Write lines left unprocessed at the maximum runtime or a shutdown to this JSONL file, to feed to a later run
+    pub fn resume_filepath(mut self, path: Option<String>) -> Self {
+        self.resume_filepath = path;
+        self
+    }
+
+    /// Write requests never attempted before the run stopped early to this JSONL file
+    ///
+    /// Covers the maximum runtime, a shutdown, an abort after consecutive
+    /// failures and a spent `max_requests` budget. Each line is a serialized
+    /// `APIRequest` with its attempts left, the format of `retry_state_filepath`,
+    /// so a later run given the file as its retry state sends them.
+    pub fn unprocessed_filepath(mut self, path: Option<String>) -> Self {
+        self.unprocessed_filepath = path;
+        self
+    }
+
+    /// Save requests waiting to be retried to this JSONL file, and retry those a previous run saved there
+    ///
+    /// Each line is a serialized `APIRequest` with the attempts it has left, so a
+    /// request killed mid-backoff is retried by the next run instead of being lost.
+    pub fn retry_state_filepath(mut self, path: Option<String>) -> Self {
+        self.retry_state_filepath = path;
+        self
+    }
+
+    /// How often the retry state file is rewritten, 5 seconds by default
+    pub fn retry_state_interval(mut self, interval: Duration) -> Self {
+        self.retry_state_interval = interval;
+        self
+    }
+
+    /// Save how far the run has got to this JSON file every `checkpoint_every` outcomes or `checkpoint_interval`
+    ///
+    /// It holds `next_task_id`, before which every task's outcome is flushed to
+    /// the output files, and the endpoints' reported quotas, so `resume` can
+    /// start a later run there without reading the output files.
+    pub fn checkpoint_filepath(mut self, path: Option<String>) -> Self {
+        self.checkpoint_filepath = path;
+        self
+    }
+
+    /// Outcomes written between saves of the checkpoint, 10000 by default
+    pub fn checkpoint_every(mut self, every: usize) -> Self {
+        self.checkpoint_every = every;
+        self
+    }
+
+    /// Longest time between saves of the checkpoint, 10 seconds by default
+    pub fn checkpoint_interval(mut self, interval: Duration) -> Self {
+        self.checkpoint_interval = interval;
+        self
+    }
+
+    /// Skip the input lines the checkpoint says are done, and add to the output files instead of emptying them
+    ///
+    /// A `skip` further into the input wins. Tasks finished after the
+    /// checkpoint's `next_task_id` are sent again; `upsert` drops their
+    /// earlier records.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Send TCP keepalive probes on connections idle for this long
+    ///
+    /// Keeps pooled connections from being dropped silently by NATs and load balancers.
+    pub fn tcp_keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.tcp_keepalive = interval;
+        self
+    }
+
+    /// Open at most this many connections to each host, across all endpoints on it
+    ///
+    /// Requests needing a connection while a host is at the limit wait for
+    /// one to free up. Over HTTP/1.1 a connection carries one request at a
+    /// time, so this also bounds the requests in flight to the host.
+    pub fn max_connections_per_host(mut self, max: Option<usize>) -> Self {
+        self.max_connections_per_host = max;
+        self
+    }
+
+    /// Save every response from the endpoints to this directory, for a later run to replay
+    ///
+    /// Responses are read whole before they are judged, so with a time to
+    /// first byte limit the whole body has to arrive in time.
+    pub fn record_dir(mut self, dir: Option<String>) -> Self {
+        self.record_dir = dir;
+        self
+    }
+
+    /// Write every request sent and the response it got to this HAR file, replacing it
+    ///
+    /// Each attempt is an entry with its headers, bodies, status and timings.
+    /// Credentials in headers and the API key are hidden, and bodies larger
+    /// than `max_response_bytes` are left out.
+    pub fn har_filepath(mut self, path: Option<String>) -> Self {
+        self.har_filepath = path;
+        self
+    }
+
+    /// Answer requests to the endpoints with the responses recorded in this directory, without any network access
+    ///
+    /// A request that was not recorded, including a retry the recorded run
+    /// did not make, fails without a retry.
+    pub fn replay_dir(mut self, dir: Option<String>) -> Self {
+        self.replay_dir = dir;
+        self
+    }
+
+    /// Keep every successful response in this directory, and answer the same request from it instead of sending it
+    ///
+    /// Requests are the same when their URL and body are, so a change to the
+    /// endpoint, payload template or model sends them again. Answered requests
+    /// do not use the request budget, tokens or a connection, and count as
+    /// cache hits.
+    pub fn cache_dir(mut self, dir: Option<String>) -> Self {
+        self.cache_dir = dir;
+        self
+    }
+
+    /// Send requests again once their response in `cache_dir` is older than this
+    pub fn cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Largest HTTP/1 read buffer per connection, at least 8192 bytes
+    pub fn http1_max_buf_size(mut self, size: Option<usize>) -> Self {
+        self.http1_max_buf_size = size;
+        self
+    }
+
+    /// Log at debug level whether each response came on a new or a reused connection
+    ///
+    /// The counts are added to the summary, making connection churn visible.
+    pub fn log_connections(mut self, log: bool) -> Self {
+        self.log_connections = log;
+        self
+    }
+
+    /// Ramp the send rate linearly from a tenth of the maximum over this long
+    pub fn warmup(mut self, warmup: Option<Duration>) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Wait a random time up to this long before sending anything
+    ///
+    /// Spreads out the first requests of a fleet of instances launched at
+    /// once. The wait is drawn from `seed`, mixed with the shard index so
+    /// shards of one run still start apart.
+    pub fn startup_jitter(mut self, jitter: Option<Duration>) -> Self {
+        self.startup_jitter = jitter;
+        self
+    }
+
+    /// Seed the endpoint selection so a run picks the same endpoints each time
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Most retries that may wait out their backoff at once
+    ///
+    /// Beyond this, failed requests go to the error file instead of being
+    /// retried, so a long outage cannot pile up retries without bound.
+    pub fn max_pending_retries(mut self, max_pending: Option<usize>) -> Self {
+        self.max_pending_retries = max_pending;
+        self
+    }
+
+    /// Most outcomes that may wait to be written at once
+    ///
+    /// Beyond this, no new requests are sent until the writer catches up, so a
+    /// slow disk backpressures the input instead of growing memory.
+    pub fn max_pending_writes(mut self, max_pending: Option<usize>) -> Self {
+        self.max_pending_writes = max_pending;
+        self
+    }
+
+    /// Input lines sent together in one API call, for endpoints that accept arrays
+    ///
+    /// With more than one, each body is an array of the lines' bodies and the
+    /// response must be an array with one element per line, in the same order.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Send a partial batch once no input line has arrived for this long (default 100 ms)
+    ///
+    /// Keeps a slow input, such as a followed file, from holding lines back
+    /// until their batch fills up.
+    pub fn batch_linger(mut self, linger: Duration) -> Self {
+        self.batch_linger = linger;
+        self
+    }
+
+    /// Times each input line is sent, e.g. to sample several completions per line
+    ///
+    /// Line `n` becomes tasks `n * repeat` to `n * repeat + repeat - 1`. With
+    /// more than one, each task's metadata gets a `repeat_index` from 0.
+    pub fn repeat(mut self, repeat: usize) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Save the tasks of a group that are not sent yet as skipped once one of its tasks fails for good
+    ///
+    /// A task's group is its line's `group_id`, or else, with `repeat`, the
+    /// other repeats of its line. Retries already under way still finish.
+    pub fn cancel_groups(mut self, cancel_groups: bool) -> Self {
+        self.cancel_groups = cancel_groups;
+        self
+    }
+
+    /// Most retries re-dispatched per second, across all requests
+    ///
+    /// Retries whose backoff ends at the same time are spread out to this
+    /// rate instead of all landing on the API together.
+    pub fn max_retry_rate(mut self, max_retries_per_second: Option<f64>) -> Self {
+        self.max_retry_rate = max_retries_per_second;
+        self
+    }
+
+    /// Most tokens sent per minute, across all requests
+    ///
+    /// Each attempt is admitted against this budget by its estimated cost,
+    /// which is corrected by the `usage` the response reports, if any.
+    pub fn max_tokens_per_minute(mut self, max_tokens_per_minute: Option<u64>) -> Self {
+        self.max_tokens_per_minute = max_tokens_per_minute;
+        self
+    }
+
+    /// Field of each input line holding its estimated token cost for `max_tokens_per_minute`
+    ///
+    /// Lines without a whole number there, or any line when this is not set,
+    /// are estimated at one token per four bytes of the line.
+    pub fn token_cost_field(mut self, path: Option<JsonPath>) -> Self {
+        self.token_cost_field = path;
+        self
+    }
+
+    /// Format successful results are saved in; the error file is always JSONL
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.output_format = format;
+        self
+    }
+
+    /// Read each response body as newline-delimited JSON, for endpoints that answer with several objects
+    ///
+    /// Each object is judged on its own and saved as a record of its own with
+    /// the request's task id. Lines that are not JSON are logged and dropped;
+    /// a response without any JSON is a failure.
+    pub fn ndjson_response(mut self, ndjson: bool) -> Self {
+        self.ndjson_response = ndjson;
+        self
+    }
+
+    /// Save only the value at this path of each response, e.g. `choices[0].message.content`
+    ///
+    /// Responses where the path does not resolve go to the error file.
+    pub fn extract(mut self, path: Option<JsonPath>) -> Self {
+        self.extract = path;
+        self
+    }
+
+    /// Save only these fields of each result in JSONL records, e.g. `task_id`, `input.id` and `response`
+    ///
+    /// Paths start from `task_id`, `input` (the input line), `response` (the
+    /// extracted value with `extract`), `metadata`, `endpoint`, `latency_ms`,
+    /// `skipped`, `response_meta` or `cached`. Each field is saved under its path.
+    pub fn output_fields(mut self, fields: Option<Vec<JsonPath>>) -> Self {
+        self.output_fields = fields;
+        self
+    }
+
+    /// Save each result's HTTP status and chosen response headers as `{"status": ..., "headers": {...}}`
+    pub fn include_response_meta(mut self, include: bool) -> Self {
+        self.include_response_meta = include;
+        self
+    }
+
+    /// Response headers to keep with `include_response_meta`, e.g. `x-ratelimit-remaining` and `x-request-id`
+    pub fn response_meta_headers<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.response_meta_headers = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Response header that tells whether the endpoint answered from its cache, e.g. `x-cache`
+    pub fn cache_header(mut self, name: Option<String>) -> Self {
+        self.cache_header = name;
+        self
+    }
+
+    /// Value of `cache_header` that marks a cache hit, `HIT` by default
+    pub fn cache_hit_value(mut self, value: impl Into<String>) -> Self {
+        self.cache_hit_value = value.into();
+        self
+    }
+
+    /// Save whether each result was a cache hit as `"cached": true` or `false`
+    pub fn include_cache_status(mut self, include: bool) -> Self {
+        self.include_cache_status = include;
+        self
+    }
+
+    /// Save each result to `<save file>_<value>.<extension>`, by the value of this field of its input line
+    ///
+    /// Values are made safe as file names; lines without the field are saved
+    /// to the save file itself. Failures still go to the one error file.
+    pub fn output_by(mut self, path: Option<JsonPath>) -> Self {
+        self.output_by = path;
+        self
+    }
+
+    /// Most output files to hold open at once; the least recently written is closed to open another
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files;
+        self
+    }
+
+    /// Body sent for each request; defaults to a chat completion with the `input` field as the user message
+    pub fn payload_template(mut self, template: PayloadTemplate) -> Self {
+        self.payload_template = Some(template);
+        self
+    }
+
+    /// Send each input line itself as the body instead of a chat completion
+    ///
+    /// Fields of a payload template, if one is set, are added where the line lacks them.
+    pub fn passthrough_body(mut self, passthrough: bool) -> Self {
+        self.passthrough_body = passthrough;
+        self
+    }
+
+    /// Send bodies as JSON (the default), form data or multipart form data
+    ///
+    /// Form and multipart bodies cannot hold a batch, so `batch_size` must then be 1.
+    pub fn body_encoding(mut self, encoding: BodyEncoding) -> Self {
+        self.body_encoding = encoding;
+        self
+    }
+
+    /// Adapt requests and responses to this provider's API, for endpoints that do not name one
+    ///
+    /// `Provider::OpenAi`, the default, leaves them as they are.
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// Field of each input line that feeds the user message, `input` by default
+    ///
+    /// Also the field logged and saved as the input, e.g. `prompt` or `question`.
+    pub fn input_field<S: Into<String>>(mut self, field: S) -> Self {
+        self.input_field = field.into();
+        self
+    }
+
+    /// Validate the settings and build the config
+    pub fn build(mut self) -> Result<Config, ClientError> {
+        if self.endpoints.is_empty() {
+            return Err(ClientError::InvalidConfig("at least one endpoint is required".to_string()));
+        }
+        for endpoint in &mut self.endpoints {
+            endpoint.validate().map_err(ClientError::InvalidConfig)?;
+            endpoint.resolve_api_key(&self.secrets).map_err(ClientError::InvalidConfig)?;
+        }
+        if let (Some(endpoint), None) = (self.endpoints.iter().find(|e| e.deadline_header.is_some()), self.ttfb_timeout) {
+            return Err(ClientError::InvalidConfig(format!("deadline_header for endpoint {} needs ttfb_timeout", endpoint.url)));
+        }
+        if self.endpoints.iter().all(|e| e.weight == 0) {
+            return Err(ClientError::InvalidConfig("at least one endpoint needs a non-zero weight".to_string()));
+        }
+        if !(self.max_requests_per_second.is_finite() && self.max_requests_per_second > 0.0) {
+            return Err(ClientError::InvalidConfig("max_requests_per_second must be a finite number above 0".to_string()));
+        }
+        if self.retry_state_interval.is_zero() {
+            return Err(ClientError::InvalidConfig("retry_state_interval must be above 0".to_string()));
+        }
+        if self.checkpoint_every == 0 {
+            return Err(ClientError::InvalidConfig("checkpoint_every must be at least 1".to_string()));
+        }
+        if self.checkpoint_interval.is_zero() {
+            return Err(ClientError::InvalidConfig("checkpoint_interval must be above 0".to_string()));
+        }
+        if self.resume && self.checkpoint_filepath.is_none() {
+            return Err(ClientError::InvalidConfig("resume needs a checkpoint file".to_string()));
+        }
+        if self.workers == Some(0) {
+            return Err(ClientError::InvalidConfig("workers must be at least 1".to_string()));
+        }
+        if self.max_concurrency == Some(0) {
+            return Err(ClientError::InvalidConfig("max_concurrency must be at least 1".to_string()));
+        }
+        if self.max_line_bytes == Some(0) {
+            return Err(ClientError::InvalidConfig("max_line_bytes must be at least 1".to_string()));
+        }
+        if self.max_open_files == 0 {
+            return Err(ClientError::InvalidConfig("max_open_files must be at least 1".to_string()));
+        }
+        if self.output_writers == 0 {
+            return Err(ClientError::InvalidConfig("output_writers must be at least 1".to_string()));
+        }
+        if self.flush_every == 0 {
+            return Err(ClientError::InvalidConfig("flush_every must be at least 1".to_string()));
+        }
+        if self.max_attempts == 0 {
+            return Err(ClientError::InvalidConfig("max_attempts must be at least 1".to_string()));
+        }
+        if self.abort_after_consecutive_failures == Some(0) {
+            return Err(ClientError::InvalidConfig("abort_after_consecutive_failures must be at least 1".to_string()));
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(ClientError::InvalidConfig("queue_capacity must be at least 1".to_string()));
+        }
+        if self.batch_size == 0 {
+            return Err(ClientError::InvalidConfig("batch_size must be at least 1".to_string()));
+        }
+        if self.repeat == 0 {
+            return Err(ClientError::InvalidConfig("repeat must be at least 1".to_string()));
+        }
+        if let Some(shard) = self.shard.filter(|shard| shard.index >= shard.count) {
+            return Err(ClientError::InvalidConfig(format!("shard index must be below the shard count, got {}", shard)));
+        }
+        if self.sample.is_some_and(|sample| !(sample.rate > 0.0 && sample.rate <= 1.0)) {
+            return Err(ClientError::InvalidConfig("sample rate must be above 0 and at most 1".to_string()));
+        }
+        if let Some(name) = self.response_meta_headers.iter().find(|name| hyper::header::HeaderName::from_bytes(name.as_bytes()).is_err()) {
+            return Err(ClientError::InvalidConfig(format!("invalid response header name '{}'", name)));
+        }
+        if let Some(name) = self.cache_header.as_ref().filter(|name| hyper::header::HeaderName::from_bytes(name.as_bytes()).is_err()) {
+            return Err(ClientError::InvalidConfig(format!("invalid cache header name '{}'", name)));
+        }
+        if self.cache_header.is_some() && self.cache_hit_value.trim().is_empty() {
+            return Err(ClientError::InvalidConfig("cache hit value must not be empty".to_string()));
+        }
+        if self.include_cache_status && self.cache_header.is_none() && self.cache_dir.is_none() {
+            return Err(ClientError::InvalidConfig("include cache status needs a cache header or cache dir".to_string()));
+        }
+        if self.cache_ttl.is_some() && self.cache_dir.is_none() {
+            return Err(ClientError::InvalidConfig("cache ttl needs a cache dir".to_string()));
+        }
+        if self.cache_ttl == Some(Duration::ZERO) {
+            return Err(ClientError::InvalidConfig("cache ttl must be above 0".to_string()));
+        }
+        for path in self.output_fields.iter().flatten() {
+            if !path.root().is_some_and(|root| OUTPUT_FIELD_ROOTS.contains(&root)) {
+                return Err(ClientError::InvalidConfig(format!(
+                    "output field '{}' must start with one of {}",
+                    path,
+                    OUTPUT_FIELD_ROOTS.join(", ")
+                )));
+            }
+        }
+        if self.batch_size > 1 && self.body_encoding != BodyEncoding::Json {
+            return Err(ClientError::InvalidConfig("batches can only be sent as JSON bodies".to_string()));
+        }
+        if self.record_dir.is_some() && self.replay_dir.is_some() {
+            return Err(ClientError::InvalidConfig("record_dir and replay_dir cannot be combined".to_string()));
+        }
+        if self.max_connections_per_host == Some(0) {
+            return Err(ClientError::InvalidConfig("max_connections_per_host must be at least 1".to_string()));
+        }
+        if self.http1_max_buf_size.is_some_and(|size| size < MIN_HTTP1_BUF_SIZE) {
+            return Err(ClientError::InvalidConfig(format!("http1_max_buf_size must be at least {}", MIN_HTTP1_BUF_SIZE)));
+        }
+        if self.max_pending_writes == Some(0) {
+            return Err(ClientError::InvalidConfig("max_pending_writes must be at least 1".to_string()));
+        }
+        if !(self.backoff.multiplier.is_finite() && self.backoff.multiplier >= 1.0) {
+            return Err(ClientError::InvalidConfig("backoff multiplier must be a finite number of at least 1".to_string()));
+        }
+        if let Some(category) = self
+            .category_backoff
+            .iter()
+            .find(|(_, backoff)| !(backoff.multiplier.is_finite() && backoff.multiplier >= 1.0))
+            .map(|(category, _)| category)
+        {
+            return Err(ClientError::InvalidConfig(format!("backoff multiplier for {} must be a finite number of at least 1", category)));
+        }
+        if self.max_retry_rate.is_some_and(|rate| !(rate.is_finite() && rate > 0.0)) {
+            return Err(ClientError::InvalidConfig("max_retry_rate must be a finite number above 0".to_string()));
+        }
+        if self.max_tokens_per_minute == Some(0) {
+            return Err(ClientError::InvalidConfig("max_tokens_per_minute must be at least 1".to_string()));
+        }
+        if self.output_format == OutputFormat::Csv(Vec::new()) {
+            return Err(ClientError::InvalidConfig("CSV output needs at least one column".to_string()));
+        }
+        if self.upsert && self.output_format != OutputFormat::Jsonl {
+            return Err(ClientError::InvalidConfig("upsert needs JSONL output, as CSV rows may not hold the task id".to_string()));
+        }
+        if self.no_save && self.upsert {
+            return Err(ClientError::InvalidConfig("no_save cannot be combined with upsert, as there is no save file to compact".to_string()));
+        }
+        if self.ndjson_response && self.upsert {
+            return Err(ClientError::InvalidConfig("ndjson_response cannot be combined with upsert, which keeps one record per task id".to_string()));
+        }
+        if self.ndjson_response && self.batch_size > 1 {
+            return Err(ClientError::InvalidConfig("ndjson_response cannot be combined with a batch_size above 1".to_string()));
+        }
+        if hyper::header::HeaderValue::from_str(&self.user_agent).is_err() {
+            return Err(ClientError::InvalidConfig(format!("invalid user agent '{}'", self.user_agent)));
+        }
+        if let Some(endpoint) = &self.otel_endpoint {
+            if !cfg!(feature = "otel") {
+                return Err(ClientError::InvalidConfig("otel_endpoint is set, but this build lacks the `otel` feature".to_string()));
+            }
+            match endpoint.parse::<hyper::Uri>() {
+                Ok(uri) if matches!(uri.scheme_str(), Some("http") | Some("https")) && uri.authority().is_some() => {}
+                _ => return Err(ClientError::InvalidConfig(format!("invalid OpenTelemetry collector URL '{}'", endpoint))),
+            }
+        }
+        if let Some(header) = &self.idempotency_key_header {
+            if hyper::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                return Err(ClientError::InvalidConfig(format!("invalid idempotency key header '{}'", header)));
+            }
+        }
+        for endpoint in &self.endpoints {
+            if endpoint.client_cert.is_some() != endpoint.client_key.is_some() {
+                return Err(ClientError::InvalidConfig(format!(
+                    "endpoint {} needs both client_cert and client_key",
+                    endpoint.url
+                )));
+            }
+        }
+        // Compile the schema once up front
+        let input_schema = match &self.input_schema {
+            Some(schema) => Some(Arc::new(jsonschema::validator_for(schema).map_err(|e| {
+                ClientError::InvalidConfig(format!("invalid input schema: {}", e))
+            })?)),
+            None => None,
+        };
+        let response_schema = match &self.response_schema {
+            Some(schema) => Some(Arc::new(jsonschema::validator_for(schema).map_err(|e| {
+                ClientError::InvalidConfig(format!("invalid response schema: {}", e))
+            })?)),
+            None => None,
+        };
+        let input_field = &self.input_field;
+        let request_body = match (self.passthrough_body, self.payload_template) {
+            (true, template) => RequestBody::Passthrough(template),
+            (false, Some(template)) => RequestBody::Template(template),
+            (false, None) => RequestBody::Template(
+                PayloadTemplate::chat(input_field)
+                    .map_err(|e| ClientError::InvalidConfig(format!("invalid input field '{}': {}", input_field, e)))?,
+            ),
+        };
+        let config = Config {
+            endpoints: self.endpoints,
+            save_filepath: self.save_filepath,
+            error_filepath: self.error_filepath,
+            dead_letter_filepath: self.dead_letter_filepath,
+            flush_interval: self.flush_interval,
+            flush_every: self.flush_every,
+            fsync: self.fsync,
+            output_writers: self.output_writers,
+            append: self.append || self.resume,
+            upsert: self.upsert,
+            no_save: self.no_save,
+            max_requests_per_second: self.max_requests_per_second,
+            max_attempts: self.max_attempts,
+            backoff: self.backoff,
+            category_backoff: self.category_backoff,
+            min_retry_interval: self.min_retry_interval,
+            skip: self.skip,
+            limit: self.limit,
+            shard: self.shard,
+            sample: self.sample,
+            max_line_bytes: self.max_line_bytes,
+            skip_if: self.skip_if,
+            input_schema,
+            derived_fields: self.derived_fields,
+            response_schema,
+            max_response_bytes: self.max_response_bytes,
+            max_redirects: self.max_redirects,
+            client_cert: self.client_cert,
+            client_key: self.client_key,
+            ca_cert: self.ca_cert,
+            insecure_skip_verify: self.insecure_skip_verify,
+            lb_strategy: self.lb_strategy,
+            queue_capacity: self.queue_capacity,
+            queue_spill_dir: self.queue_spill_dir,
+            workers: self.workers,
+            adaptive_concurrency: self.adaptive_concurrency,
+            max_concurrency: self.max_concurrency,
+            max_requests: self.max_requests,
+            abort_after_consecutive_failures: self.abort_after_consecutive_failures,
+            success_rule: self.success_rule,
+            success_status: self.success_status,
+            tags: self.tags,
+            retry_on: self.retry_on,
+            retry_on_body_errors: self.retry_on_body_errors,
+            idempotency_key_header: self.idempotency_key_header,
+            user_agent: self.user_agent,
+            otel_endpoint: self.otel_endpoint,
+            otel_service_name: self.otel_service_name,
+            connect_timeout: self.connect_timeout,
+            ttfb_timeout: self.ttfb_timeout,
+            max_runtime: self.max_runtime,
+            shutdown_grace: self.shutdown_grace,
+            resume_filepath: self.resume_filepath,
+            unprocessed_filepath: self.unprocessed_filepath,
+            retry_state_filepath: self.retry_state_filepath,
+            retry_state_interval: self.retry_state_interval,
+            checkpoint_filepath: self.checkpoint_filepath,
+            checkpoint_every: self.checkpoint_every,
+            checkpoint_interval: self.checkpoint_interval,
+            resume: self.resume,
+            tcp_keepalive: self.tcp_keepalive,
+            max_connections_per_host: self.max_connections_per_host,
+            record_dir: self.record_dir,
+            har_filepath: self.har_filepath,
+            replay_dir: self.replay_dir,
+            cache_dir: self.cache_dir,
+            cache_ttl: self.cache_ttl,
+            http1_max_buf_size: self.http1_max_buf_size,
+            log_connections: self.log_connections,
+            warmup: self.warmup,
+            startup_jitter: self.startup_jitter,
+            seed: self.seed,
+            max_pending_retries: self.max_pending_retries,
+            max_pending_writes: self.max_pending_writes,
+            batch_size: self.batch_size,
+            batch_linger: self.batch_linger,
+            repeat: self.repeat,
+            cancel_groups: self.cancel_groups,
+            max_retry_rate: self.max_retry_rate,
+            max_tokens_per_minute: self.max_tokens_per_minute,
+            token_cost_field: self.token_cost_field,
+            output_format: self.output_format,
+            ndjson_response: self.ndjson_response,
+            extract: self.extract,
+            output_fields: self.output_fields,
+            include_response_meta: self.include_response_meta,
+            response_meta_headers: self.response_meta_headers.iter().map(|name| name.to_ascii_lowercase()).collect(),
+            cache_header: self.cache_header.as_ref().map(|name| name.to_ascii_lowercase()),
+            cache_hit_value: self.cache_hit_value.trim().to_string(),
+            include_cache_status: self.include_cache_status,
+            output_by: self.output_by,
+            max_open_files: self.max_open_files,
+            request_body,
+            body_encoding: self.body_encoding,
+            provider: self.provider,
+            input_field: self.input_field,
+        };
+        config.check_output_files()?;
+        Ok(config)
+    }
+}
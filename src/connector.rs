@@ -0,0 +1,295 @@
+use crate::config::Config;
+use crate::endpoint::Endpoint;
+use crate::error::ClientError;
+use crate::record::{Backend, Recordings};
+use hyper::client::connect::{Connected, Connection, HttpInfo};
+use hyper::client::HttpConnector;
+use hyper::http::Extensions;
+use hyper::service::Service;
+use hyper::Uri;
+use hyper_tls::HttpsConnector;
+use log::{debug, warn};
+use native_tls::{Certificate, Identity};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Smallest read buffer hyper accepts for `Config::http1_max_buf_size`
+pub(crate) const MIN_HTTP1_BUF_SIZE: usize = 8192;
+
+/// The HTTPS client used to talk to endpoints
+pub type HttpClient = hyper::Client<HostLimit<HttpsConnector<HttpConnector>>>;
+
+/// A client certificate and its private key, both PEM files
+type IdentityPaths = (String, String);
+
+/// HTTP clients for a run, one per distinct client certificate
+pub(crate) struct HttpClients {
+    default: HttpClient,
+    by_identity: HashMap<IdentityPaths, HttpClient>,
+    /// Connections responses have arrived on, kept when `Config::log_connections` is set
+    connections: Option<Mutex<HashSet<(SocketAddr, SocketAddr)>>>,
+    /// Responses recorded or replayed in place of the network, with `Config::record_dir` or `Config::replay_dir`
+    recordings: Option<Recordings>,
+}
+
+impl HttpClients {
+    /// Build the shared client plus one client for each endpoint-specific certificate
+    pub fn new(config: &Config) -> Result<Self, ClientError> {
+        if config.insecure_skip_verify {
+            warn!("TLS certificate verification is DISABLED: any server can impersonate the endpoints");
+        }
+        // Shared by every client, so the limit holds per host whichever certificate is used
+        let host_slots = config.max_connections_per_host.map(HostSlots::new);
+        let default_identity = identity_paths(&config.client_cert, &config.client_key);
+        let default = build_http_client(config, default_identity.as_ref(), &host_slots)?;
+
+        let mut by_identity = HashMap::new();
+        for endpoint in &config.endpoints {
+            if let Some(paths) = identity_paths(&endpoint.client_cert, &endpoint.client_key) {
+                if let Entry::Vacant(entry) = by_identity.entry(paths) {
+                    let client = build_http_client(config, Some(entry.key()), &host_slots)?;
+                    entry.insert(client);
+                }
+            }
+        }
+        let connections = if config.log_connections { Some(Mutex::default()) } else { None };
+        let recordings = match (&config.record_dir, &config.replay_dir) {
+            (Some(dir), _) => Some(Recordings::record(dir, config.max_response_bytes)?),
+            (_, Some(dir)) => Some(Recordings::replay(dir)?),
+            _ => None,
+        };
+        Ok(HttpClients {
+            default,
+            by_identity,
+            connections,
+            recordings,
+        })
+    }
+
+    /// Whether a response arrived on a connection not seen before
+    ///
+    /// Connections are told apart by their local and remote addresses. `None`
+    /// unless connection logging is on.
+    pub fn is_new_connection(&self, extensions: &Extensions) -> Option<bool> {
+        let info = extensions.get::<HttpInfo>()?;
+        let connections = self.connections.as_ref()?;
+        Some(connections.lock().unwrap().insert((info.local_addr(), info.remote_addr())))
+    }
+
+    /// The client for requests that go to no particular endpoint
+    pub fn default_client(&self) -> &HttpClient {
+        &self.default
+    }
+
+    /// Where requests to an endpoint are sent, which is the network unless they are recorded or replayed
+    pub fn backend(&self, endpoint: &Endpoint) -> Backend<'_> {
+        match &self.recordings {
+            Some(recordings) if recordings.is_replay() => Backend::Replay(recordings),
+            Some(recordings) => Backend::Record(self.for_endpoint(endpoint), recordings),
+            None => Backend::Network(self.for_endpoint(endpoint)),
+        }
+    }
+
+    /// The client to use for requests to an endpoint
+    pub fn for_endpoint(&self, endpoint: &Endpoint) -> &HttpClient {
+        identity_paths(&endpoint.client_cert, &endpoint.client_key)
+            .and_then(|paths| self.by_identity.get(&paths))
+            .unwrap_or(&self.default)
+    }
+}
+
+fn identity_paths(cert: &Option<String>, key: &Option<String>) -> Option<IdentityPaths> {
+    match (cert, key) {
+        (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+        _ => None,
+    }
+}
+
+/// Read every certificate in a PEM file, which may be a bundle
+fn read_ca_certs(path: &str) -> Result<Vec<Certificate>, ClientError> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    let pem = std::fs::read_to_string(path)?;
+    let certs = pem
+        .match_indices(BEGIN)
+        .map(|(start, _)| {
+            let end = pem[start + BEGIN.len()..].find(BEGIN).map_or(pem.len(), |end| start + BEGIN.len() + end);
+            Certificate::from_pem(&pem.as_bytes()[start..end])
+                .map_err(|e| ClientError::InvalidConfig(format!("invalid CA certificate in {}: {}", path, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(ClientError::InvalidConfig(format!("no certificates found in {}", path)));
+    }
+    Ok(certs)
+}
+
+/// Build an HTTPS client, presenting a client certificate when one is given
+///
+/// Servers are verified against the system trust store plus `Config::ca_cert`.
+fn build_http_client(
+    config: &Config,
+    identity: Option<&IdentityPaths>,
+    host_slots: &Option<Arc<HostSlots>>,
+) -> Result<HttpClient, ClientError> {
+    let mut tls = native_tls::TlsConnector::builder();
+    if let Some(path) = &config.ca_cert {
+        for cert in read_ca_certs(path)? {
+            tls.add_root_certificate(cert);
+        }
+    }
+    if config.insecure_skip_verify {
+        tls.danger_accept_invalid_certs(true);
+        tls.danger_accept_invalid_hostnames(true);
+    }
+    if let Some((cert_path, key_path)) = identity {
+        let cert = std::fs::read(cert_path)?;
+        let key = std::fs::read(key_path)?;
+        let identity = Identity::from_pkcs8(&cert, &key).map_err(|e| {
+            ClientError::InvalidConfig(format!("invalid client certificate {}: {}", cert_path, e))
+        })?;
+        tls.identity(identity);
+    }
+    let tls = tls
+        .build()
+        .map_err(|e| ClientError::InvalidConfig(format!("failed to set up TLS: {}", e)))?;
+
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_connect_timeout(config.connect_timeout);
+    http.set_keepalive(config.tcp_keepalive);
+    let https = HttpsConnector::from((http, tls.into()));
+    let mut builder = hyper::Client::builder();
+    if let Some(size) = config.http1_max_buf_size {
+        builder.http1_max_buf_size(size);
+    }
+    if let Some(max) = config.max_connections_per_host {
+        builder.pool_max_idle_per_host(max);
+    }
+    let connector = HostLimit {
+        inner: https,
+        slots: host_slots.clone(),
+    };
+    Ok(builder.build::<_, hyper::Body>(connector))
+}
+
+/// Connection slots of each host, `Config::max_connections_per_host` apiece
+pub struct HostSlots {
+    max: usize,
+    by_host: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostSlots {
+    fn new(max: usize) -> Arc<Self> {
+        Arc::new(HostSlots {
+            max,
+            by_host: Mutex::default(),
+        })
+    }
+
+    /// Take a slot for a new connection to the host, waiting while all of them are held
+    async fn acquire(&self, host: String) -> OwnedSemaphorePermit {
+        let slots = Arc::clone(
+            self.by_host
+                .lock()
+                .unwrap()
+                .entry(host.clone())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max))),
+        );
+        match Arc::clone(&slots).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                debug!("{} connections to {} are open, waiting for one to close", self.max, host);
+                // The semaphore is never closed
+                slots.acquire_owned().await.unwrap()
+            }
+        }
+    }
+}
+
+/// A connector that opens at most `Config::max_connections_per_host` connections to each host
+///
+/// Without a limit it connects like the connector it wraps. A request
+/// needing a new connection while the host is at its limit waits for an
+/// idle pooled connection, or for a connection to close.
+#[derive(Clone)]
+pub struct HostLimit<C> {
+    inner: C,
+    slots: Option<Arc<HostSlots>>,
+}
+
+impl<C> Service<Uri> for HostLimit<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+    C::Response: Send,
+    C::Error: Send,
+{
+    type Response = Limited<C::Response>;
+    type Error = C::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let slots = self.slots.clone();
+        let connecting = self.inner.call(uri.clone());
+        Box::pin(async move {
+            let permit = match (slots, uri.authority()) {
+                (Some(slots), Some(authority)) => Some(slots.acquire(authority.to_string()).await),
+                _ => None,
+            };
+            let io = connecting.await?;
+            Ok(Limited { io, _permit: permit })
+        })
+    }
+}
+
+/// A connection holding its host's slot until it is dropped
+pub struct Limited<T> {
+    io: T,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<T: Connection> Connection for Limited<T> {
+    fn connected(&self) -> Connected {
+        self.io.connected()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Limited<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Limited<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+}
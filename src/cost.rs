@@ -0,0 +1,41 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Price of an endpoint's tokens, in any currency, per 1000 tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Pricing {
+    #[serde(default)]
+    pub input_per_1k: f64,
+    #[serde(default)]
+    pub output_per_1k: f64,
+}
+
+impl Pricing {
+    /// Estimated cost of one response's token usage
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input_per_1k + usage.completion_tokens as f64 * self.output_per_1k) / 1000.0
+    }
+}
+
+/// Token counts reported by the API in a response's `usage` object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl Usage {
+    /// Read `usage.prompt_tokens` and `usage.completion_tokens`, if the response has either
+    pub fn from_response(response: &Value) -> Option<Usage> {
+        let usage = response.get("usage")?;
+        let prompt_tokens = usage.get("prompt_tokens").and_then(Value::as_u64);
+        let completion_tokens = usage.get("completion_tokens").and_then(Value::as_u64);
+        if prompt_tokens.is_none() && completion_tokens.is_none() {
+            return None;
+        }
+        Some(Usage {
+            prompt_tokens: prompt_tokens.unwrap_or(0),
+            completion_tokens: completion_tokens.unwrap_or(0),
+        })
+    }
+}
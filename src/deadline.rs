@@ -0,0 +1,103 @@
+use hyper::header::{HeaderName, HeaderValue};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header telling an endpoint how long the client still waits for each request, so it can give up on it in time
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct DeadlineHeader {
+    /// Name of the header, e.g. `X-Request-Deadline` or `grpc-timeout`
+    pub name: String,
+    /// How the deadline is written
+    #[serde(default)]
+    pub format: DeadlineFormat,
+}
+
+impl DeadlineHeader {
+    /// Send the deadline in a header of this name, in `timeout-ms` format
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        DeadlineHeader {
+            name: name.into(),
+            format: DeadlineFormat::default(),
+        }
+    }
+
+    /// Write the deadline in this format
+    pub fn format(mut self, format: DeadlineFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Whether the name is a valid header name
+    pub(crate) fn is_valid(&self) -> bool {
+        HeaderName::from_bytes(self.name.as_bytes()).is_ok()
+    }
+
+    /// The header's value for a request with this much time left
+    pub(crate) fn value(&self, remaining: Duration) -> HeaderValue {
+        HeaderValue::from_str(&self.format.render(remaining)).expect("deadline values are ASCII")
+    }
+}
+
+/// How a `DeadlineHeader` writes the time left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum DeadlineFormat {
+    /// Whole milliseconds left, e.g. `29950`
+    #[default]
+    TimeoutMs,
+    /// Seconds left, to the millisecond, e.g. `29.950`
+    TimeoutSecs,
+    /// A gRPC `grpc-timeout` value: at most 8 digits and a unit, e.g. `29950m`
+    Grpc,
+    /// The deadline itself, as milliseconds since the Unix epoch, e.g. `1760000029950`
+    UnixMs,
+}
+
+impl DeadlineFormat {
+    /// Write this much time left, rounded down to what the format can express
+    pub fn render(self, remaining: Duration) -> String {
+        match self {
+            DeadlineFormat::TimeoutMs => remaining.as_millis().to_string(),
+            DeadlineFormat::TimeoutSecs => {
+                let millis = remaining.as_millis();
+                format!("{}.{:03}", millis / 1000, millis % 1000)
+            }
+            DeadlineFormat::Grpc => {
+                // The finest unit whose value fits in 8 digits, rounded down so the server gives up no later than the client
+                let millis = remaining.as_millis();
+                [(1, 'm'), (1000, 'S'), (60_000, 'M'), (3_600_000, 'H')]
+                    .iter()
+                    .map(|(per_unit, unit)| (millis / per_unit, unit))
+                    .find(|(value, _)| *value <= 99_999_999)
+                    .map_or_else(|| "99999999H".to_string(), |(value, unit)| format!("{}{}", value, unit))
+            }
+            DeadlineFormat::UnixMs => {
+                let deadline = SystemTime::now() + remaining;
+                deadline.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis().to_string()
+            }
+        }
+    }
+}
+
+impl FromStr for DeadlineFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timeout-ms" => Ok(DeadlineFormat::TimeoutMs),
+            "timeout-secs" => Ok(DeadlineFormat::TimeoutSecs),
+            "grpc" => Ok(DeadlineFormat::Grpc),
+            "unix-ms" => Ok(DeadlineFormat::UnixMs),
+            _ => Err(format!("unknown deadline format '{}' (expected timeout-ms, timeout-secs, grpc or unix-ms)", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for DeadlineFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
@@ -0,0 +1,120 @@
+use crate::oauth::form_encode;
+use hyper::body::Bytes;
+use hyper::header::HeaderValue;
+use serde_json::{Map, Value};
+use std::path::Path;
+use std::str::FromStr;
+
+/// How request bodies are serialized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BodyEncoding {
+    /// `application/json`
+    #[default]
+    Json,
+    /// `application/x-www-form-urlencoded`, one pair per top-level field
+    Form,
+    /// `multipart/form-data`, one part per top-level field
+    ///
+    /// String values starting with `@` name a local file, uploaded as a file part.
+    Multipart,
+}
+
+impl FromStr for BodyEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" | "application/json" => Ok(BodyEncoding::Json),
+            "form" | "application/x-www-form-urlencoded" => Ok(BodyEncoding::Form),
+            "multipart" | "multipart/form-data" => Ok(BodyEncoding::Multipart),
+            _ => Err(format!("unknown content type '{}' (expected json, form or multipart)", s)),
+        }
+    }
+}
+
+impl BodyEncoding {
+    /// Serialize a request body, returning it with its `Content-Type`
+    ///
+    /// Form and multipart bodies must be JSON objects. Arrays become one value
+    /// per element, objects are sent as JSON text and `null` fields are left out.
+    pub(crate) async fn encode(&self, body: &Value) -> Result<(Bytes, HeaderValue), String> {
+        match self {
+            BodyEncoding::Json => {
+                let json = serde_json::to_vec(body).map_err(|e| format!("body does not serialize: {}", e))?;
+                Ok((Bytes::from(json), HeaderValue::from_static("application/json")))
+            }
+            BodyEncoding::Form => {
+                let mut form = String::new();
+                for (name, value) in fields(body)? {
+                    for value in form_values(value) {
+                        if !form.is_empty() {
+                            form.push('&');
+                        }
+                        form.push_str(&form_encode(name));
+                        form.push('=');
+                        form.push_str(&form_encode(&value));
+                    }
+                }
+                Ok((Bytes::from(form), HeaderValue::from_static("application/x-www-form-urlencoded")))
+            }
+            BodyEncoding::Multipart => {
+                let boundary = format!("api-processor-{:016x}", rand::random::<u64>());
+                let mut multipart = Vec::new();
+                for (name, value) in fields(body)? {
+                    for value in form_values(value) {
+                        multipart.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+                        match value.strip_prefix('@') {
+                            Some(path) => {
+                                let contents = tokio::fs::read(path)
+                                    .await
+                                    .map_err(|e| format!("cannot read file '{}' for field '{}': {}", path, name, e))?;
+                                let filename = Path::new(path).file_name().map(|f| f.to_string_lossy()).unwrap_or_default();
+                                multipart.extend_from_slice(
+                                    format!(
+                                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                                         Content-Type: application/octet-stream\r\n\r\n",
+                                        quote(name),
+                                        quote(&filename)
+                                    )
+                                    .as_bytes(),
+                                );
+                                multipart.extend_from_slice(&contents);
+                            }
+                            None => {
+                                multipart.extend_from_slice(
+                                    format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", quote(name)).as_bytes(),
+                                );
+                                multipart.extend_from_slice(value.as_bytes());
+                            }
+                        }
+                        multipart.extend_from_slice(b"\r\n");
+                    }
+                }
+                multipart.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+                let content_type = HeaderValue::from_str(&format!("multipart/form-data; boundary={}", boundary))
+                    .expect("boundary is a valid header value");
+                Ok((Bytes::from(multipart), content_type))
+            }
+        }
+    }
+}
+
+/// The fields of a body sent as form data
+fn fields(body: &Value) -> Result<&Map<String, Value>, String> {
+    body.as_object().ok_or_else(|| "form and multipart bodies must be JSON objects".to_string())
+}
+
+/// The values a field contributes to form data
+fn form_values(value: &Value) -> Vec<String> {
+    match value {
+        Value::Null => Vec::new(),
+        Value::String(s) => vec![s.clone()],
+        Value::Array(values) => values.iter().flat_map(form_values).collect(),
+        value => vec![value.to_string()],
+    }
+}
+
+/// Escape a name for a quoted `Content-Disposition` parameter
+fn quote(name: &str) -> String {
+    name.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
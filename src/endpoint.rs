@@ -0,0 +1,630 @@
+use crate::cost::Pricing;
+use crate::deadline::DeadlineHeader;
+use crate::error::ClientError;
+use crate::keys::KeyRotation;
+use crate::oauth::OAuthConfig;
+use crate::rate::Quota;
+use crate::request::APIRequest;
+use crate::sigv4::SigV4Config;
+use crate::success::{SuccessRule, SuccessStatuses};
+use crate::template::PayloadTemplate;
+use crate::transform::Provider;
+use crate::url_template::{self, UrlTemplate};
+use log::debug;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Struct representing an API endpoint
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct Endpoint {
+    /// Name used to look up the API key in a secrets file
+    #[serde(default)]
+    pub id: Option<String>,
+    /// May have `{field}` placeholders in its path and query, filled in from each request
+    pub url: String,
+    #[serde(default)]
+    pub api_key: String,
+    /// Environment variable holding the API key, read when the config is built
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    /// Several keys for the same URL, used in turn in place of `api_key`
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// How the key for each request is picked from `api_keys`
+    #[serde(default)]
+    pub key_rotation: KeyRotation,
+    /// Header the API key is sent in as is, e.g. `api-key`, instead of as an `Authorization` bearer token
+    #[serde(default)]
+    pub auth_header: Option<String>,
+    /// Query parameters added to every request's URL, e.g. `api-version`
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    pub weight: usize,
+    /// Pool the endpoint belongs to; higher tiers are only used while every lower-tier endpoint is unavailable
+    #[serde(default)]
+    pub tier: usize,
+    /// PEM client certificate presented to this endpoint, overriding the global one
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    /// PKCS#8 PEM private key for `client_cert`
+    #[serde(default)]
+    pub client_key: Option<String>,
+    /// How responses from this endpoint are judged, overriding the global rule
+    #[serde(default)]
+    pub success_rule: Option<SuccessRule>,
+    /// HTTP statuses counted as success by the `status` rule, overriding the global list
+    #[serde(default)]
+    pub success_status: Option<SuccessStatuses>,
+    /// Requests with this `route_key` are sent only to endpoints that share it
+    #[serde(default)]
+    pub route_key: Option<String>,
+    /// Token prices used to estimate the cost of responses that report usage
+    #[serde(default)]
+    pub pricing: Option<Pricing>,
+    /// Fetch short-lived access tokens with these credentials instead of using `api_key`
+    #[serde(default)]
+    pub oauth: Option<OAuthConfig>,
+    /// Sign requests with AWS Signature Version 4 instead of sending `api_key`
+    #[serde(default)]
+    pub sigv4: Option<SigV4Config>,
+    /// Most requests in flight to this endpoint at once; unlimited if unset
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+    /// Request body sent to this endpoint, overriding the global payload template
+    #[serde(default)]
+    pub payload_template: Option<PayloadTemplate>,
+    /// Request and response shape of this endpoint's API, overriding the global provider
+    #[serde(default)]
+    pub provider: Option<Provider>,
+    /// Header telling this endpoint how much of `Config::ttfb_timeout` is left when each request is sent
+    #[serde(default)]
+    pub deadline_header: Option<DeadlineHeader>,
+}
+
+impl Endpoint {
+    /// Start building an endpoint for this URL, with weight 1
+    pub fn builder<U: Into<String>>(url: U) -> EndpointBuilder {
+        EndpointBuilder {
+            endpoint: Endpoint {
+                url: url.into(),
+                weight: 1,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Check that the URL is an absolute http or https URL with placeholders only in its path and query
+    pub fn validate(&self) -> Result<(), String> {
+        if self.api_keys.iter().any(String::is_empty) {
+            return Err(format!("api_keys for endpoint {} must not be empty strings", self.url));
+        }
+        if self.max_concurrency == Some(0) {
+            return Err(format!("max_concurrency for endpoint {} must be at least 1", self.url));
+        }
+        if self.sigv4.is_some() && !cfg!(feature = "sigv4") {
+            return Err(format!("endpoint {} uses sigv4, but this build lacks the `sigv4` feature", self.url));
+        }
+        if let Some(header) = &self.auth_header {
+            if hyper::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                return Err(format!("invalid auth_header '{}' for endpoint {}", header, self.url));
+            }
+            if self.oauth.is_some() || self.sigv4.is_some() {
+                return Err(format!("auth_header for endpoint {} only applies to API keys, not oauth or sigv4", self.url));
+            }
+        }
+        if self.deadline_header.as_ref().is_some_and(|header| !header.is_valid()) {
+            return Err(format!("invalid deadline_header name for endpoint {}", self.url));
+        }
+        UrlTemplate::new(&self.request_url()).map(drop)
+    }
+
+    /// The URL with `query` added, which requests are sent to
+    pub fn request_url(&self) -> String {
+        self.with_query(&self.url)
+    }
+
+    /// Add `query` to a URL or path, percent-encoded so it cannot add placeholders
+    pub(crate) fn with_query(&self, url: &str) -> String {
+        let mut url = url.to_string();
+        for (name, value) in &self.query {
+            if !url.ends_with(['?', '&']) {
+                url.push(if url.contains('?') { '&' } else { '?' });
+            }
+            url_template::encode(name, &mut url);
+            url.push('=');
+            url_template::encode(value, &mut url);
+        }
+        url
+    }
+
+    /// Fill in `api_key` from `api_key_env`, or from secrets keyed by endpoint id or URL
+    ///
+    /// An inline `api_key` is kept when neither applies. An endpoint with an id
+    /// but no key from any source is an error. SigV4 endpoints need no key and
+    /// take AWS credentials missing from their config from the environment.
+    pub fn resolve_api_key(&mut self, secrets: &HashMap<String, String>) -> Result<(), String> {
+        if let Some(sigv4) = &mut self.sigv4 {
+            return sigv4.resolve_credentials();
+        }
+        if let Some(var) = &self.api_key_env {
+            self.api_key = std::env::var(var)
+                .map_err(|e| format!("cannot read the API key for endpoint {} from ${}: {}", self.url, var, e))?;
+        } else if let Some(key) = self.id.as_ref().and_then(|id| secrets.get(id)).or_else(|| secrets.get(&self.url)) {
+            self.api_key = key.clone();
+        } else if let (Some(id), true, None) = (&self.id, self.api_key.is_empty() && self.api_keys.is_empty(), &self.oauth) {
+            return Err(format!("no API key for endpoint '{}' in the secrets", id));
+        }
+        Ok(())
+    }
+
+    /// The API keys requests are sent with: `api_keys` if set, otherwise `api_key`
+    pub fn keys(&self) -> Vec<&str> {
+        if self.api_keys.is_empty() {
+            vec![self.api_key.as_str()]
+        } else {
+            self.api_keys.iter().map(String::as_str).collect()
+        }
+    }
+
+    /// Whether this endpoint may serve the request, given its routing key
+    pub fn serves(&self, request: &APIRequest) -> bool {
+        match request.route_key() {
+            Some(route_key) => self.route_key.as_deref() == Some(route_key),
+            None => true,
+        }
+    }
+
+    /// Weight of this endpoint for the request, zero if it cannot serve it
+    ///
+    /// With `failover`, the endpoint the request last failed on gets no weight either.
+    fn weight_for(&self, request: &APIRequest, failover: bool) -> f64 {
+        let failed_here = failover && request.last_endpoint.as_deref() == Some(self.url.as_str());
+        if self.serves(request) && !failed_here {
+            self.weight as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Builder for `Endpoint`
+#[derive(Debug, Clone)]
+pub struct EndpointBuilder {
+    endpoint: Endpoint,
+}
+
+impl EndpointBuilder {
+    /// API key sent as a bearer token
+    pub fn api_key<K: Into<String>>(mut self, api_key: K) -> Self {
+        self.endpoint.api_key = api_key.into();
+        self
+    }
+
+    /// Several API keys for this URL, rotated between requests
+    pub fn api_keys<I, K>(mut self, api_keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        self.endpoint.api_keys = api_keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// How the key for each request is picked from the API keys
+    pub fn key_rotation(mut self, rotation: KeyRotation) -> Self {
+        self.endpoint.key_rotation = rotation;
+        self
+    }
+
+    /// Send the API key as is in this header, e.g. `api-key` for Azure OpenAI
+    pub fn auth_header<H: Into<String>>(mut self, header: H) -> Self {
+        self.endpoint.auth_header = Some(header.into());
+        self
+    }
+
+    /// Add a query parameter to every request's URL, e.g. `api-version`
+    pub fn query<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.endpoint.query.insert(name.into(), value.into());
+        self
+    }
+
+    /// Environment variable to read the API key from when the config is built
+    pub fn api_key_env<V: Into<String>>(mut self, var: V) -> Self {
+        self.endpoint.api_key_env = Some(var.into());
+        self
+    }
+
+    /// Name used to look up the API key in the secrets
+    pub fn id<I: Into<String>>(mut self, id: I) -> Self {
+        self.endpoint.id = Some(id.into());
+        self
+    }
+
+    /// Relative share of requests sent to this endpoint
+    pub fn weight(mut self, weight: usize) -> Self {
+        self.endpoint.weight = weight;
+        self
+    }
+
+    /// Fallback pool of this endpoint, 0 being the primary one
+    pub fn tier(mut self, tier: usize) -> Self {
+        self.endpoint.tier = tier;
+        self
+    }
+
+    /// PEM client certificate and PKCS#8 key presented to this endpoint
+    pub fn client_cert<C: Into<String>, K: Into<String>>(mut self, cert: C, key: K) -> Self {
+        self.endpoint.client_cert = Some(cert.into());
+        self.endpoint.client_key = Some(key.into());
+        self
+    }
+
+    /// How responses from this endpoint are judged, overriding the global rule
+    pub fn success_rule(mut self, rule: SuccessRule) -> Self {
+        self.endpoint.success_rule = Some(rule);
+        self
+    }
+
+    /// HTTP statuses counted as success by the `status` rule, overriding the global list
+    pub fn success_status(mut self, statuses: SuccessStatuses) -> Self {
+        self.endpoint.success_status = Some(statuses);
+        self
+    }
+
+    /// Serve only requests with this `route_key`
+    pub fn route_key<R: Into<String>>(mut self, route_key: R) -> Self {
+        self.endpoint.route_key = Some(route_key.into());
+        self
+    }
+
+    /// Token prices used to estimate the cost of responses
+    pub fn pricing(mut self, pricing: Pricing) -> Self {
+        self.endpoint.pricing = Some(pricing);
+        self
+    }
+
+    /// Fetch access tokens with these credentials instead of using an API key
+    pub fn oauth(mut self, oauth: OAuthConfig) -> Self {
+        self.endpoint.oauth = Some(oauth);
+        self
+    }
+
+    /// Sign requests with AWS Signature Version 4 instead of using an API key
+    pub fn sigv4(mut self, sigv4: SigV4Config) -> Self {
+        self.endpoint.sigv4 = Some(sigv4);
+        self
+    }
+
+    /// Limit the requests in flight to this endpoint at once
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.endpoint.max_concurrency = Some(max_concurrency);
+        self
+    }
+
+    /// Request body sent to this endpoint, overriding the global payload template
+    pub fn payload_template(mut self, template: PayloadTemplate) -> Self {
+        self.endpoint.payload_template = Some(template);
+        self
+    }
+
+    /// Adapt requests and responses to this provider's API, overriding the global provider
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.endpoint.provider = Some(provider);
+        self
+    }
+
+    /// Tell this endpoint in a header how long each request may still take before the client gives up on it
+    pub fn deadline_header(mut self, header: DeadlineHeader) -> Self {
+        self.endpoint.deadline_header = Some(header);
+        self
+    }
+
+    /// Validate the URL and build the endpoint
+    pub fn build(self) -> Result<Endpoint, ClientError> {
+        self.endpoint.validate().map_err(ClientError::InvalidConfig)?;
+        Ok(self.endpoint)
+    }
+}
+
+/// Select an endpoint for a request based on weight
+///
+/// A retry avoids the endpoint its last attempt went to, unless no other
+/// endpoint can serve it, escalating to the next tier if needed. Returns
+/// `None` if no endpoint with a non-zero weight serves the request's routing key.
+pub fn select_endpoint<'a>(endpoints: &'a [Endpoint], request: &APIRequest) -> Option<&'a Endpoint> {
+    let mut rng = rand::thread_rng();
+    let tiers = tiers(endpoints);
+    [true, false]
+        .iter()
+        .find_map(|&failover| {
+            tiers.iter().find_map(|&tier| {
+                let weights = endpoints.iter().map(|e| if e.tier == tier { e.weight_for(request, failover) } else { 0.0 });
+                select_weighted_index(&mut rng, weights)
+            })
+        })
+        .map(|index| &endpoints[index])
+}
+
+/// The endpoints' tiers, lowest first
+fn tiers(endpoints: &[Endpoint]) -> Vec<usize> {
+    let mut tiers: Vec<usize> = endpoints.iter().map(|e| e.tier).collect();
+    tiers.sort_unstable();
+    tiers.dedup();
+    tiers
+}
+
+/// How requests are spread across endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LbStrategy {
+    /// Pick endpoints at random in proportion to their static weight
+    #[default]
+    Weighted,
+    /// Scale each static weight by the inverse of the endpoint's recent latency
+    LatencyWeighted,
+    /// Cycle through endpoints deterministically in proportion to their static weight
+    ///
+    /// The nginx smooth weighted round-robin: over any window the split tracks
+    /// the weights closely, and heavy endpoints are interleaved with light ones.
+    SmoothWeighted,
+}
+
+impl FromStr for LbStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weighted" => Ok(LbStrategy::Weighted),
+            "latency-weighted" => Ok(LbStrategy::LatencyWeighted),
+            "smooth-weighted" => Ok(LbStrategy::SmoothWeighted),
+            _ => Err(format!(
+                "unknown load-balancing strategy '{}' (expected weighted, latency-weighted or smooth-weighted)",
+                s
+            )),
+        }
+    }
+}
+
+/// Smoothing factor for the latency moving average; higher reacts faster
+const LATENCY_EWMA_ALPHA: f64 = 0.2;
+
+/// Selects endpoints for requests, keeping whatever state the strategy needs
+#[derive(Debug)]
+pub struct LoadBalancer {
+    strategy: LbStrategy,
+    /// Exponentially-weighted moving average of latency in seconds, per endpoint
+    latency_ewma: Mutex<Vec<Option<f64>>>,
+    /// Current weight of each endpoint for smooth weighted round-robin
+    current_weights: Mutex<Vec<f64>>,
+    /// Latest quota reported in rate-limit headers, per endpoint
+    quotas: Mutex<Vec<Option<Quota>>>,
+    /// Source of randomness for every selection, seeded for reproducible runs
+    rng: Mutex<StdRng>,
+    /// Free request slots of each endpoint with a `max_concurrency`
+    slots: Vec<Option<Arc<Semaphore>>>,
+    /// Free request slots across all endpoints, with a global limit
+    global_slots: Option<Arc<Semaphore>>,
+}
+
+/// A request's slots under the global and its endpoint's concurrency limits, given back when dropped
+#[derive(Debug)]
+pub(crate) struct Slot {
+    _global: Option<OwnedSemaphorePermit>,
+    _endpoint: Option<OwnedSemaphorePermit>,
+}
+
+impl LoadBalancer {
+    /// Create a balancer for the endpoints; with a seed the sequence of selections is reproducible
+    pub fn new(strategy: LbStrategy, endpoints: &[Endpoint], seed: Option<u64>) -> Self {
+        let num_endpoints = endpoints.len();
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        LoadBalancer {
+            strategy,
+            latency_ewma: Mutex::new(vec![None; num_endpoints]),
+            current_weights: Mutex::new(vec![0.0; num_endpoints]),
+            quotas: Mutex::new(vec![None; num_endpoints]),
+            rng: Mutex::new(rng),
+            slots: endpoints
+                .iter()
+                .map(|e| e.max_concurrency.map(|limit| Arc::new(Semaphore::new(limit))))
+                .collect(),
+            global_slots: None,
+        }
+    }
+
+    /// Also limit the requests in flight across all endpoints, on top of each endpoint's `max_concurrency`
+    pub fn with_max_concurrency(mut self, max_concurrency: Option<usize>) -> Self {
+        self.global_slots = max_concurrency.map(|limit| Arc::new(Semaphore::new(limit)));
+        self
+    }
+
+    /// Select the index of the endpoint for a request
+    ///
+    /// Only endpoints serving the request's routing key are considered;
+    /// returns `None` if there are none. A retry goes to a different endpoint
+    /// than its last attempt whenever another one serves it, and endpoints
+    /// at their concurrency limit or whose quota is used up are avoided while
+    /// others remain. Endpoints of a higher tier are only picked when no
+    /// endpoint of a lower tier is available on those terms, so a retry
+    /// escalates to the next tier once its own tier has no other endpoint.
+    pub fn select(&self, endpoints: &[Endpoint], request: &APIRequest) -> Option<usize> {
+        let tiers = tiers(endpoints);
+        // Fail over to another endpoint if there is one
+        let levels = [(true, true, true), (false, true, true), (false, false, true), (false, false, false)];
+        let selected = levels.iter().find_map(|&(failover, within_quota, with_free_slot)| {
+            tiers
+                .iter()
+                .find_map(|&tier| self.select_with(endpoints, request, tier, failover, within_quota, with_free_slot))
+        })?;
+        if endpoints[selected].tier > tiers[0] {
+            debug!("Request {} falls back to tier {} endpoint {}", request.task_id, endpoints[selected].tier, endpoints[selected].url);
+        }
+        Some(selected)
+    }
+
+    fn select_with(
+        &self,
+        endpoints: &[Endpoint],
+        request: &APIRequest,
+        tier: usize,
+        failover: bool,
+        within_quota: bool,
+        with_free_slot: bool,
+    ) -> Option<usize> {
+        let exhausted: Vec<bool> = self.quotas.lock().unwrap().iter().map(|quota| quota.is_some_and(|q| q.exhausted())).collect();
+        let mut rng = self.rng.lock().unwrap();
+        let weight = |(index, e): (usize, &Endpoint)| {
+            let full = self.slots[index].as_ref().is_some_and(|slots| slots.available_permits() == 0);
+            if e.tier != tier || (within_quota && exhausted[index]) || (with_free_slot && full) {
+                0.0
+            } else {
+                e.weight_for(request, failover)
+            }
+        };
+        match self.strategy {
+            LbStrategy::Weighted => select_weighted_index(&mut *rng, endpoints.iter().enumerate().map(weight)),
+            LbStrategy::LatencyWeighted => {
+                let latencies = self.latency_ewma.lock().unwrap();
+                // Endpoints without samples yet are assumed to be as fast as the average
+                let observed: Vec<f64> = latencies.iter().flatten().copied().collect();
+                let mean = if observed.is_empty() {
+                    1.0
+                } else {
+                    observed.iter().sum::<f64>() / observed.len() as f64
+                };
+                select_weighted_index(&mut *rng, endpoints.iter().enumerate().zip(latencies.iter()).map(|(e, latency)| {
+                    let latency = latency.unwrap_or(mean).max(f64::EPSILON);
+                    weight(e) / latency
+                }))
+            }
+            LbStrategy::SmoothWeighted => {
+                let weights: Vec<f64> = endpoints.iter().enumerate().map(weight).collect();
+                select_smooth_weighted_index(&mut self.current_weights.lock().unwrap(), &weights)
+            }
+        }
+    }
+
+    /// Replace the endpoint's quota with one read from its latest response
+    pub(crate) fn record_quota(&self, index: usize, quota: Quota) {
+        self.quotas.lock().unwrap()[index] = Some(quota);
+    }
+
+    /// The endpoint's quota as last reported, if it has not reset yet
+    pub(crate) fn quota(&self, index: usize) -> Option<Quota> {
+        self.quotas.lock().unwrap()[index].filter(|quota| quota.reset() > Instant::now())
+    }
+
+    /// The soonest reset when every endpoint that takes requests has used up its quota
+    pub(crate) fn throttled_until(&self, endpoints: &[Endpoint]) -> Option<Instant> {
+        let quotas = self.quotas.lock().unwrap();
+        let mut soonest: Option<Instant> = None;
+        for (endpoint, quota) in endpoints.iter().zip(quotas.iter()) {
+            if endpoint.weight == 0 {
+                continue;
+            }
+            match quota {
+                Some(quota) if quota.exhausted() => soonest = Some(soonest.map_or(quota.reset(), |soonest| soonest.min(quota.reset()))),
+                _ => return None,
+            }
+        }
+        soonest
+    }
+
+    /// Reserve the time the next request to the endpoint may leave under its quota
+    ///
+    /// Returns `None` when no quota applies, i.e. none was reported or it has reset.
+    pub(crate) fn reserve_quota(&self, index: usize) -> Option<Instant> {
+        let mut quotas = self.quotas.lock().unwrap();
+        let slot = quotas[index].as_mut().and_then(Quota::reserve);
+        if slot.is_none() {
+            quotas[index] = None;
+        }
+        slot
+    }
+
+    /// Take a global request slot and one of the endpoint's, waiting for them to free up
+    ///
+    /// So the in-flight limit of a request is the smaller of the two. Every
+    /// request takes the global slot first and the endpoint's second, and
+    /// none waits for a global slot while holding an endpoint's, so two
+    /// requests never each hold the slot the other waits for. Limits that are
+    /// not set are not waited for; the slots are given back when dropped.
+    pub(crate) async fn acquire_slot(&self, index: usize) -> Slot {
+        // The semaphores are never closed
+        let global = match &self.global_slots {
+            Some(slots) => Arc::clone(slots).acquire_owned().await.ok(),
+            None => None,
+        };
+        let endpoint = match &self.slots[index] {
+            Some(slots) => Arc::clone(slots).acquire_owned().await.ok(),
+            None => None,
+        };
+        Slot {
+            _global: global,
+            _endpoint: endpoint,
+        }
+    }
+
+    /// Fold an observed request latency into the endpoint's moving average
+    pub fn record_latency(&self, index: usize, latency: Duration) {
+        let mut latencies = self.latency_ewma.lock().unwrap();
+        let sample = latency.as_secs_f64();
+        latencies[index] = Some(match latencies[index] {
+            Some(average) => LATENCY_EWMA_ALPHA * sample + (1.0 - LATENCY_EWMA_ALPHA) * average,
+            None => sample,
+        });
+    }
+}
+
+/// Pick the next index by smooth weighted round-robin, if any weight is non-zero
+///
+/// Each candidate's current weight grows by its weight; the largest is picked
+/// and lowered by the total. Endpoints that cannot take the request keep their
+/// current weight untouched.
+fn select_smooth_weighted_index(current: &mut [f64], weights: &[f64]) -> Option<usize> {
+    let mut total = 0.0;
+    let mut best: Option<usize> = None;
+    for (index, &weight) in weights.iter().enumerate() {
+        if weight <= 0.0 {
+            continue;
+        }
+        current[index] += weight;
+        total += weight;
+        if best.is_none_or(|best| current[index] > current[best]) {
+            best = Some(index);
+        }
+    }
+    let best = best?;
+    current[best] -= total;
+    Some(best)
+}
+
+/// Pick an index at random in proportion to the given weights, if any is non-zero
+fn select_weighted_index<R: Rng, I: Iterator<Item = f64> + Clone>(rng: &mut R, weights: I) -> Option<usize> {
+    let total_weight: f64 = weights.clone().sum();
+    if total_weight <= 0.0 {
+        return None;
+    }
+    let mut rand_val = rng.gen_range(0.0..total_weight);
+    let mut last_candidate = None;
+    for (index, weight) in weights.enumerate() {
+        if weight <= 0.0 {
+            continue;
+        }
+        if rand_val < weight {
+            return Some(index);
+        }
+        rand_val -= weight;
+        last_candidate = Some(index);
+    }
+    // Rounding can leave a sliver past the last weight
+    last_candidate
+}
@@ -0,0 +1,49 @@
+use crate::report::FailureCategory;
+use serde_json::Value;
+use std::fmt;
+use std::io;
+
+/// Errors returned by the client
+#[derive(Debug)]
+pub enum ClientError {
+    /// Reading input or writing output failed
+    Io(io::Error),
+    /// The configuration is incomplete or inconsistent
+    InvalidConfig(String),
+    /// A request failed and will not be retried
+    Request {
+        task_id: usize,
+        input: Value,
+        error: Value,
+        /// Why the request failed, for grouping failures
+        category: FailureCategory,
+    },
+    /// The run was stopped as too many requests failed in a row
+    Aborted(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "I/O error: {}", e),
+            ClientError::InvalidConfig(msg) => write!(f, "invalid configuration: {}", msg),
+            ClientError::Request { task_id, error, .. } => write!(f, "request {} failed: {}", task_id, error),
+            ClientError::Aborted(reason) => write!(f, "run aborted: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            ClientError::InvalidConfig(_) | ClientError::Request { .. } | ClientError::Aborted(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
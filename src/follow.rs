@@ -0,0 +1,112 @@
+use crate::lines::LineError;
+use log::{error, info, warn};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+
+/// How often a followed file is checked for new data once its end is reached
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Identity of a file on disk, to notice when a path is rotated to a new file
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Open a file, waiting for it to appear if it does not exist yet
+async fn open_when_present(path: &str) -> File {
+    let mut logged = false;
+    loop {
+        match File::open(path).await {
+            Ok(file) => return file,
+            Err(e) => {
+                if !logged {
+                    warn!("Waiting for {} to be readable: {}", path, e);
+                    logged = true;
+                }
+                sleep(FOLLOW_POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Send each complete line of a file, then keep waiting for more like `tail -f`
+///
+/// A trailing line without a newline is held back until it is finished. When
+/// the file is truncated or replaced by a new one (log rotation), reading
+/// starts again from the beginning of the new file. Lines longer than
+/// `max_bytes` are not held in memory and are sent as `LineError::TooLong`.
+/// Returns once the receiver is dropped.
+pub(crate) async fn follow_lines(path: String, max_bytes: Option<usize>, lines: mpsc::Sender<Result<String, LineError>>) {
+    let max_bytes = max_bytes.unwrap_or(usize::MAX);
+    let mut file = open_when_present(&path).await;
+    let mut id = file.metadata().await.ok().and_then(|metadata| file_id(&metadata));
+    let mut position: u64 = 0;
+    let mut pending: Vec<u8> = Vec::new();
+    // Bytes dropped so far of an overlong line still being read
+    let mut too_long: Option<usize> = None;
+    let mut chunk = vec![0; 64 * 1024];
+
+    loop {
+        let read = match file.read(&mut chunk).await {
+            Ok(read) => read,
+            Err(e) => {
+                error!("Failed to read {}: {}", path, e);
+                sleep(FOLLOW_POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        if read > 0 {
+            position += read as u64;
+            pending.extend_from_slice(&chunk[..read]);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let line = match too_long.take() {
+                    Some(dropped) => Err(LineError::TooLong(dropped + end)),
+                    None if end > max_bytes => Err(LineError::TooLong(end)),
+                    None => Ok(String::from_utf8_lossy(&line[..end]).trim_end_matches('\r').to_string()),
+                };
+                if lines.send(line).await.is_err() {
+                    return;
+                }
+            }
+            if pending.len() > max_bytes {
+                *too_long.get_or_insert(0) += pending.len();
+                pending.clear();
+            }
+            continue;
+        }
+
+        // At the end of the file: wait, then check whether the path now points elsewhere
+        if lines.is_closed() {
+            return;
+        }
+        sleep(FOLLOW_POLL_INTERVAL).await;
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            // Rotated away and not recreated yet
+            Err(_) => continue,
+        };
+        let replaced = id.is_some() && file_id(&metadata) != id;
+        if replaced || metadata.len() < position {
+            info!("{} was rotated or truncated, reading it from the start", path);
+            if !pending.is_empty() || too_long.is_some() {
+                warn!("Dropping an unfinished line at the end of the previous {}", path);
+                pending.clear();
+                too_long = None;
+            }
+            file = open_when_present(&path).await;
+            id = file.metadata().await.ok().and_then(|metadata| file_id(&metadata));
+            position = 0;
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::client::{is_secret_header, redact};
+use chrono::{SecondsFormat, Utc};
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+use hyper::{Body, Request, StatusCode, Version};
+use log::error;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Closes the entries array and the log, rewritten after every entry so the file is always a whole HAR
+const TRAILER: &str = "\n]}}\n";
+
+/// Every request sent and the response it got, written to `Config::har_filepath` as an HTTP Archive
+///
+/// Entries are added as attempts finish, so they are in order of completion.
+/// Credentials are hidden: the values of headers that look like they hold
+/// one, and the API key wherever it appears in URLs and bodies.
+#[derive(Debug)]
+pub(crate) struct HarLog {
+    path: String,
+    /// Bodies larger than this are left out, from `Config::max_response_bytes`
+    max_body_bytes: Option<usize>,
+    file: Mutex<HarFile>,
+}
+
+#[derive(Debug)]
+struct HarFile {
+    file: File,
+    entries: usize,
+}
+
+impl HarLog {
+    /// Start an empty log, replacing the file
+    pub(crate) fn create(path: &str, max_body_bytes: Option<usize>) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        let creator = json!({ "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") });
+        write!(file, "{{\"log\": {{\"version\": \"1.2\", \"creator\": {}, \"entries\": [{}", creator, TRAILER)?;
+        Ok(HarLog {
+            path: path.to_string(),
+            max_body_bytes,
+            file: Mutex::new(HarFile { file, entries: 0 }),
+        })
+    }
+
+    /// The HAR request of a request about to be sent with this body
+    pub(crate) fn request(&self, req: &Request<Body>, body: &[u8], api_key: &str) -> Value {
+        let url = redact(&req.uri().to_string(), api_key);
+        let query: Vec<Value> = req
+            .uri()
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                json!({ "name": name, "value": redact(value, api_key) })
+            })
+            .collect();
+        let mut post_data = self.content(req.headers(), Ok(body), api_key);
+        // A request's body is `postData`, which has no size of its own
+        post_data.as_object_mut().map(|post_data| post_data.remove("size"));
+        json!({
+            "method": req.method().as_str(),
+            "url": url,
+            "httpVersion": version(req.version()),
+            "cookies": [],
+            "headers": headers(req.headers()),
+            "queryString": query,
+            "postData": post_data,
+            "headersSize": -1,
+            "bodySize": body.len(),
+        })
+    }
+
+    /// The HAR response of a response with its decoded body, or why the body could not be read
+    pub(crate) fn response(&self, status: StatusCode, http_version: Version, headers: &HeaderMap, body: Result<&[u8], String>, api_key: &str) -> Value {
+        let body_size = body.as_ref().map_or(-1, |body| body.len() as i64);
+        json!({
+            "status": status.as_u16(),
+            "statusText": status.canonical_reason().unwrap_or_default(),
+            "httpVersion": version(http_version),
+            "cookies": [],
+            "headers": self::headers(headers),
+            "content": self.content(headers, body, api_key),
+            "redirectURL": "",
+            "headersSize": -1,
+            "bodySize": body_size,
+        })
+    }
+
+    /// The HAR response of a request that got none, such as one that timed out
+    pub(crate) fn no_response(error: &str) -> Value {
+        json!({
+            "status": 0,
+            "statusText": "",
+            "httpVersion": "",
+            "cookies": [],
+            "headers": [],
+            "content": { "size": 0, "mimeType": "" },
+            "redirectURL": "",
+            "headersSize": -1,
+            "bodySize": -1,
+            "_error": error,
+        })
+    }
+
+    /// Add an attempt's request and response, logging rather than failing the request if that is not possible
+    ///
+    /// `wait` is the time from sending the request until the response
+    /// headers arrived, and `receive` the time reading its body.
+    pub(crate) fn add(&self, request_id: &str, request: Value, response: Value, wait: Duration, receive: Duration) {
+        let time = wait + receive;
+        let started = Utc::now() - chrono::Duration::from_std(time).unwrap_or_default();
+        let entry = json!({
+            "startedDateTime": started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "time": millis(time),
+            "request": request,
+            "response": response,
+            "cache": {},
+            "timings": { "blocked": -1, "dns": -1, "connect": -1, "ssl": -1, "send": 0, "wait": millis(wait), "receive": millis(receive) },
+            "_requestId": request_id,
+        });
+        let mut har = self.file.lock().unwrap();
+        let separator = if har.entries == 0 { "\n" } else { ",\n" };
+        let written = har
+            .file
+            .seek(SeekFrom::End(-(TRAILER.len() as i64)))
+            .and_then(|_| write!(har.file, "{}{}{}", separator, entry, TRAILER));
+        match written {
+            Ok(()) => har.entries += 1,
+            Err(e) => error!("Failed to write request {} to {}: {}", request_id, self.path, e),
+        }
+    }
+
+    /// The HAR content of a body, left out when it is over the size limit or could not be read
+    fn content(&self, headers: &HeaderMap, body: Result<&[u8], String>, api_key: &str) -> Value {
+        let mime_type = headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => return json!({ "size": 0, "mimeType": mime_type, "comment": format!("body not read: {}", e) }),
+        };
+        if self.max_body_bytes.is_some_and(|max_bytes| body.len() > max_bytes) {
+            return json!({ "size": body.len(), "mimeType": mime_type, "comment": "body left out, over max_response_bytes" });
+        }
+        match std::str::from_utf8(body) {
+            Ok(text) => json!({ "size": body.len(), "mimeType": mime_type, "text": redact(text, api_key) }),
+            Err(_) => json!({ "size": body.len(), "mimeType": mime_type, "text": base64(body), "encoding": "base64" }),
+        }
+    }
+}
+
+/// HAR headers, with the values of those holding credentials hidden
+fn headers(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_secret_header(name.as_str()) { "<redacted>" } else { value.to_str().unwrap_or("<binary>") };
+            json!({ "name": name.as_str(), "value": value })
+        })
+        .collect()
+}
+
+fn version(version: Version) -> String {
+    format!("{:?}", version)
+}
+
+/// Milliseconds, as HAR timings are written
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Standard base64 with padding, for bodies that are not text
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | u32::from(*byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,48 @@
+use serde_json::Value;
+use std::env;
+
+/// Replace `${VAR}` in every string of a JSON document with that environment variable
+///
+/// Keys are left alone. `$${` stands for a literal `${`. Fails on the first
+/// variable that is unset or not valid Unicode, naming it.
+pub fn interpolate_env(value: &mut Value) -> Result<(), String> {
+    match value {
+        Value::String(s) if s.contains('$') => *s = interpolate_str(s)?,
+        Value::Array(items) => items.iter_mut().try_for_each(interpolate_env)?,
+        Value::Object(fields) => fields.values_mut().try_for_each(interpolate_env)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| format!("unclosed '${{' in '{}'", s))?;
+            let name = &reference[..end];
+            if name.is_empty() {
+                return Err(format!("empty variable name in '{}'", s));
+            }
+            match env::var(name) {
+                Ok(value) => out.push_str(&value),
+                Err(env::VarError::NotPresent) => return Err(format!("environment variable {} is not set", name)),
+                Err(env::VarError::NotUnicode(_)) => return Err(format!("environment variable {} is not valid Unicode", name)),
+            }
+            rest = &reference[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
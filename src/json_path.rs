@@ -0,0 +1,128 @@
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// One step of a JSON path
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A path into a JSON document such as `choices[0].message.content`
+///
+/// Keys are separated by dots and array elements are addressed with `[n]`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct JsonPath {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    /// Look up the value the path points to
+    pub fn get<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.segments.iter().try_fold(value, |value, segment| match segment {
+            Segment::Key(key) => value.get(key),
+            Segment::Index(index) => value.get(index),
+        })
+    }
+
+    /// The key the path starts with, unless it starts by indexing an array
+    pub(crate) fn root(&self) -> Option<&str> {
+        match self.segments.first()? {
+            Segment::Key(key) => Some(key),
+            Segment::Index(_) => None,
+        }
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("invalid JSON path '{}': {}", path, reason);
+        let mut segments = Vec::new();
+        for (position, part) in path.split('.').enumerate() {
+            // Split `key[0][1]` into the key and its indexes
+            let (key, mut rest) = match part.find('[') {
+                Some(pos) => part.split_at(pos),
+                None => (part, ""),
+            };
+            // Only the first part may omit its key, to index into a top-level array
+            if key.is_empty() && (position > 0 || rest.is_empty()) {
+                return Err(invalid("empty key"));
+            }
+            if !key.is_empty() {
+                segments.push(Segment::Key(key.to_string()));
+            }
+            while !rest.is_empty() {
+                let end = rest.find(']').ok_or_else(|| invalid("unclosed '['"))?;
+                let index = rest[1..end].parse().map_err(|_| invalid("array index must be a number"))?;
+                segments.push(Segment::Index(index));
+                rest = &rest[end + 1..];
+                if !rest.is_empty() && !rest.starts_with('[') {
+                    return Err(invalid("unexpected characters after ']'"));
+                }
+            }
+        }
+        Ok(JsonPath {
+            source: path.to_string(),
+            segments,
+        })
+    }
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = String;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        path.parse()
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// A JSON path with the value expected there, written `<json-path>=<value>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldMatch {
+    pub path: JsonPath,
+    /// Expected value; strings match their contents, anything else is compared as JSON
+    pub value: String,
+}
+
+impl FieldMatch {
+    /// Whether the value at the path is the expected one
+    pub fn matches(&self, value: &Value) -> bool {
+        match self.path.get(value) {
+            Some(Value::String(s)) => *s == self.value,
+            Some(found) => serde_json::from_str::<Value>(&self.value).is_ok_and(|expected| expected == *found),
+            None => false,
+        }
+    }
+}
+
+impl FromStr for FieldMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((path, value)) => Ok(FieldMatch {
+                path: path.parse()?,
+                value: value.to_string(),
+            }),
+            None => Err(format!("expected <json-path>=<value>, got '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for FieldMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.path, self.value)
+    }
+}
@@ -0,0 +1,98 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// How an endpoint with several API keys picks the key for each request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum KeyRotation {
+    /// Use the keys in turn
+    #[default]
+    RoundRobin,
+    /// Use the key that has gone longest without a request
+    LeastRecentlyUsed,
+}
+
+impl FromStr for KeyRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(KeyRotation::RoundRobin),
+            "least-recently-used" => Ok(KeyRotation::LeastRecentlyUsed),
+            _ => Err(format!("unknown key rotation '{}' (expected round-robin or least-recently-used)", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for KeyRotation {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Rotation state of one endpoint's API keys
+#[derive(Debug)]
+pub(crate) struct KeyPool {
+    rotation: KeyRotation,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    /// Next key in turn for round-robin
+    next: usize,
+    /// When each key was last handed out
+    last_used: Vec<Option<Instant>>,
+    /// Keys refused by the endpoint, skipped while any other key is left
+    quarantined: Vec<bool>,
+}
+
+impl KeyPool {
+    pub(crate) fn new(rotation: KeyRotation, num_keys: usize) -> Self {
+        KeyPool {
+            rotation,
+            state: Mutex::new(PoolState {
+                next: 0,
+                last_used: vec![None; num_keys],
+                quarantined: vec![false; num_keys],
+            }),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().unwrap().last_used.len()
+    }
+
+    /// Pick the index of the key for the next request
+    ///
+    /// Quarantined keys are skipped unless every key is quarantined.
+    pub(crate) fn select(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let num_keys = state.last_used.len();
+        let all_quarantined = state.quarantined.iter().all(|quarantined| *quarantined);
+        let usable: Vec<usize> = (0..num_keys).filter(|index| all_quarantined || !state.quarantined[*index]).collect();
+        let index = match self.rotation {
+            KeyRotation::RoundRobin => {
+                let start = state.next;
+                let index = (0..num_keys).map(|offset| (start + offset) % num_keys).find(|index| usable.contains(index)).unwrap_or(0);
+                state.next = (index + 1) % num_keys;
+                index
+            }
+            // Keys never used sort first, as `None` is less than any time
+            KeyRotation::LeastRecentlyUsed => usable.iter().copied().min_by_key(|index| state.last_used[*index]).unwrap_or(0),
+        };
+        state.last_used[index] = Some(Instant::now());
+        index
+    }
+
+    /// Stop using a key the endpoint refused; returns whether another key is left to use
+    pub(crate) fn quarantine(&self, index: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.quarantined[index] = true;
+        state.quarantined.iter().any(|quarantined| !quarantined)
+    }
+}
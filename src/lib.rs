@@ -0,0 +1,84 @@
+//! Weighted load-balanced API client with retries and rate limiting.
+//!
+//! Build a [`Config`] with [`Config::builder`], create a [`Client`] from it and
+//! feed it requests either from a JSONL file or from an iterator of JSON objects.
+//! Outcomes can be written to the save and error files, or consumed as a
+//! stream with [`Client::stream`] and [`Client::stream_file`].
+
+mod backoff;
+mod cache;
+mod checkpoint;
+mod client;
+mod concurrency;
+mod config;
+mod connector;
+mod cost;
+mod deadline;
+mod encoding;
+mod endpoint;
+mod error;
+mod follow;
+mod har;
+mod interpolate;
+mod json_path;
+mod keys;
+mod lines;
+mod merge;
+pub mod metrics;
+mod oauth;
+mod otel;
+mod observer;
+mod output;
+mod pause;
+mod preflight;
+mod progress;
+mod queue;
+mod rate;
+mod record;
+mod redirect;
+mod report;
+mod request;
+mod response;
+mod retry;
+mod retry_state;
+mod sample;
+mod shard;
+mod shutdown;
+mod sigv4;
+mod success;
+mod template;
+mod tracker;
+mod transform;
+mod url_template;
+
+pub use backoff::BackoffPolicy;
+pub use client::{process_api_requests_from_file, Client};
+pub use config::{Config, ConfigBuilder};
+pub use connector::HttpClient;
+pub use cost::{Pricing, Usage};
+pub use deadline::{DeadlineFormat, DeadlineHeader};
+pub use encoding::BodyEncoding;
+pub use endpoint::{select_endpoint, Endpoint, EndpointBuilder, LbStrategy, LoadBalancer};
+pub use error::ClientError;
+pub use interpolate::interpolate_env;
+pub use json_path::{FieldMatch, JsonPath};
+pub use keys::KeyRotation;
+pub use merge::{compact_result_file, merge_result_files, MergeStats};
+pub use oauth::OAuthConfig;
+pub use observer::RequestObserver;
+pub use output::{append_to_csv, append_to_jsonl, write_results, CsvColumn, OutputFormat};
+pub use pause::PauseHandle;
+pub use preflight::{CanaryResult, Probe, ProbeResult};
+pub use progress::ProgressBar;
+pub use report::{CategoryFailures, FailureCategory, FailureReport};
+pub use request::{task_id_generator, APIRequest, ProcessedResult};
+pub use response::BodyError;
+pub use retry::{BodyRetryRule, RetryCondition, RetryPolicy};
+pub use sample::Sample;
+pub use shard::Shard;
+pub use shutdown::ShutdownHandle;
+pub use sigv4::SigV4Config;
+pub use success::{SuccessRule, SuccessStatuses};
+pub use template::{DerivedFields, PayloadTemplate, RequestBody};
+pub use tracker::{EndpointStats, KeyStats, LatencyHistogram, StatusTracker};
+pub use transform::{EndpointTransform, Provider};
@@ -0,0 +1,204 @@
+use log::error;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+
+/// Why an input line cannot be used
+#[derive(Debug)]
+pub(crate) enum LineError {
+    /// The line could not be read or is not JSON; it is logged and skipped
+    Invalid(String),
+    /// The line is longer than `Config::max_line_bytes`, at this many bytes; it is written to the error file
+    TooLong(usize),
+}
+
+/// Read one line without its line ending, keeping at most `max_bytes` of it in memory
+///
+/// A longer line is read to its end and dropped. Returns `None` at the end of the input.
+pub(crate) async fn read_line<R>(reader: &mut R, max_bytes: Option<usize>) -> std::io::Result<Option<Result<String, LineError>>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let max_bytes = max_bytes.unwrap_or(usize::MAX);
+    let mut line = Vec::new();
+    let mut len = 0;
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            if len == 0 {
+                return Ok(None);
+            }
+            break;
+        }
+        let newline = buf.iter().position(|&b| b == b'\n');
+        let end = newline.unwrap_or(buf.len());
+        if len + end <= max_bytes {
+            line.extend_from_slice(&buf[..end]);
+        } else if !line.is_empty() {
+            line = Vec::new();
+        }
+        len += end;
+        reader.consume(newline.map_or(end, |newline| newline + 1));
+        if newline.is_some() {
+            break;
+        }
+    }
+    if len > max_bytes {
+        return Ok(Some(Err(LineError::TooLong(len))));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(String::from_utf8(line).map_err(|e| LineError::Invalid(format!("Failed to read line from file: {}", e)))))
+}
+
+/// Read the next element of a JSON array whose opening `[` has been consumed, as JSON text
+///
+/// Elements are split at top-level commas without being parsed, so a huge
+/// array is never held in memory, and an element longer than `max_bytes` is
+/// dropped like an overlong line. Returns `None` after the closing `]`.
+async fn read_element<R>(reader: &mut R, max_bytes: Option<usize>) -> std::io::Result<Option<Result<String, LineError>>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let max_bytes = max_bytes.unwrap_or(usize::MAX);
+    let mut element = Vec::new();
+    let mut len = 0;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut at_comma = false;
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            // A truncated array ends with what was read of its last element
+            break;
+        }
+        let mut end = None;
+        for (index, &byte) in buf.iter().enumerate() {
+            if in_string {
+                match (escaped, byte) {
+                    (true, _) => escaped = false,
+                    (false, b'\\') => escaped = true,
+                    (false, b'"') => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' if depth > 0 => depth -= 1,
+                b',' | b']' if depth == 0 => {
+                    end = Some((index, byte == b']'));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let taken = end.map_or(buf.len(), |(index, _)| index);
+        if len + taken <= max_bytes {
+            element.extend_from_slice(&buf[..taken]);
+        } else if !element.is_empty() {
+            element = Vec::new();
+        }
+        len += taken;
+        reader.consume(end.map_or(taken, |(index, _)| index + 1));
+        if let Some((_, closed)) = end {
+            at_comma = !closed;
+            break;
+        }
+    }
+    // `[]`, a `]` after a trailing comma and the end of the input hold no element;
+    // an empty element between two commas is reported like a blank line
+    if !at_comma && len <= max_bytes && element.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    if len > max_bytes {
+        return Ok(Some(Err(LineError::TooLong(len))));
+    }
+    Ok(Some(String::from_utf8(element).map_err(|e| LineError::Invalid(format!("Failed to read element from file: {}", e)))))
+}
+
+/// Send each request of a file, parsed by `parse`, returning false once the receiver is gone
+///
+/// A file whose first non-blank character is `[` is read as a JSON array,
+/// one request per element; any other file as JSONL, one request per line.
+/// A read error is logged and ends the file.
+pub(crate) async fn send_lines<R, T, F>(path: &str, mut reader: R, max_bytes: Option<usize>, lines: &mpsc::Sender<T>, parse: F) -> bool
+where
+    R: AsyncBufRead + Unpin,
+    F: Fn(Result<String, LineError>) -> T,
+{
+    let array = match starts_array(&mut reader).await {
+        Ok((array, blank_lines)) => {
+            // Blank lines before the first JSONL line still use up their task ids
+            let blank_lines = if array { 0 } else { blank_lines };
+            for _ in 0..blank_lines {
+                if lines.send(parse(Ok(String::new()))).await.is_err() {
+                    return false;
+                }
+            }
+            array
+        }
+        Err(e) => {
+            error!("Failed to read {}, skipping the rest of it: {}", path, e);
+            return true;
+        }
+    };
+    loop {
+        let next = if array {
+            read_element(&mut reader, max_bytes).await
+        } else {
+            read_line(&mut reader, max_bytes).await
+        };
+        let line = match next {
+            Ok(Some(line)) => line,
+            Ok(None) => return true,
+            Err(e) => {
+                error!("Failed to read {}, skipping the rest of it: {}", path, e);
+                return true;
+            }
+        };
+        if lines.send(parse(line)).await.is_err() {
+            return false;
+        }
+    }
+}
+
+/// How many requests `send_lines` reads from a file, so how many task ids its lines use up
+pub(crate) async fn count_lines(path: &str, max_bytes: Option<usize>) -> std::io::Result<usize> {
+    let file = File::open(path).await?;
+    let (lines_tx, mut lines_rx) = mpsc::channel(1024);
+    let path = path.to_string();
+    tokio::spawn(async move { send_lines(&path, BufReader::new(file), max_bytes, &lines_tx, drop).await });
+    let mut count = 0;
+    while lines_rx.recv().await.is_some() {
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Skip leading whitespace and tell whether the input is a JSON array, consuming its `[`
+///
+/// Also returns how many line breaks were skipped.
+async fn starts_array<R>(reader: &mut R) -> std::io::Result<(bool, usize)>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut blank_lines = 0;
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok((false, blank_lines));
+        }
+        let first = buf.iter().position(|byte| !byte.is_ascii_whitespace());
+        let skipped = first.unwrap_or(buf.len());
+        blank_lines += buf[..skipped].iter().filter(|&&byte| byte == b'\n').count();
+        let array = first.is_some_and(|first| buf[first] == b'[');
+        reader.consume(skipped + array as usize);
+        if first.is_some() {
+            return Ok((array, blank_lines));
+        }
+    }
+}
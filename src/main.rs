@@ -0,0 +1,1108 @@
+use api_processor::metrics::serve_metrics;
+use api_processor::{
+    interpolate_env, merge_result_files, BackoffPolicy, BodyEncoding, BodyRetryRule, Client, Config, CsvColumn, DerivedFields, Endpoint, FailureCategory, FieldMatch, JsonPath, LbStrategy, OutputFormat,
+    PayloadTemplate, Probe, ProbeResult, ProgressBar, Provider, RetryPolicy, Sample, Shard, SuccessRule, SuccessStatuses,
+};
+use hyper::Method;
+use log::{error, info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
+use tokio::sync::oneshot;
+
+/// Command-line arguments structure
+#[derive(StructOpt)]
+#[structopt(after_help = "Run `api_processor merge --help` to combine result files into one ordered file.\n\
+                          Run `api_processor validate <arguments of a run>` to check them without sending any request.\n\
+                          A requests file named `merge` or `validate` must be passed as `./merge` or `./validate`.")]
+struct Cli {
+    /// Log more: `-v` per-task progress, `-vv` response statuses, `-vvv` full request and response bodies
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+    /// Log only errors that are not about a single request
+    #[structopt(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Keep reading the requests file as it grows, like `tail -f`
+    #[structopt(long)]
+    follow: bool,
+    /// Show a live progress bar on a terminal instead of per-request logs
+    #[structopt(long)]
+    progress_bar: bool,
+    /// Count the input's requests before the run, so progress shows the total and the time left
+    #[structopt(long)]
+    count_lines: bool,
+    /// Write the final counters as one JSON object to this path, or `-` for stdout
+    #[structopt(long)]
+    summary_json: Option<String>,
+    /// JSONL file of requests, or several comma-separated files processed in turn
+    requests_filepath: String,
+    /// Requests sent per second, e.g. `0.5` for one every two seconds
+    max_requests_per_second: f64,
+    max_attempts: usize,
+    save_filepath: Option<String>,
+    /// Path to write failed requests to
+    #[structopt(long)]
+    error_filepath: Option<String>,
+    /// Path to write the input lines of failed requests to, for a later run
+    #[structopt(long)]
+    dead_letter_file: Option<String>,
+    /// Seconds results may stay buffered before they are written out; 0 writes each one at once
+    #[structopt(long, default_value = "1")]
+    flush_interval_secs: f64,
+    /// Write buffered results out once this many are waiting
+    #[structopt(long, default_value = "100")]
+    flush_every: usize,
+    /// Tasks rendering results side by side before writing them
+    #[structopt(long, default_value = "1")]
+    output_writers: usize,
+    /// Sync output files to disk on every flush
+    #[structopt(long)]
+    fsync: bool,
+    /// Append to existing save and error files instead of overwriting them
+    #[structopt(long)]
+    append: bool,
+    /// Keep only the latest record of each task id in the save file after the run, e.g. with `--append`
+    #[structopt(long)]
+    upsert: bool,
+    /// Don't write successful results, only errors and the summary, e.g. for load tests
+    #[structopt(long)]
+    no_save: bool,
+    /// Field of each input line sent as the user message, e.g. `prompt`
+    #[structopt(long, default_value = "input")]
+    input_field: String,
+    /// Path to write failures grouped by category to at the end of the run
+    #[structopt(long)]
+    report_filepath: Option<String>,
+    /// Address to serve Prometheus metrics on, e.g. `127.0.0.1:9898`
+    #[structopt(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Log progress, including the estimated cost so far, every this many seconds
+    #[structopt(long)]
+    progress_secs: Option<f64>,
+    /// Delay before the first retry, in milliseconds
+    #[structopt(long, default_value = "1000")]
+    backoff_base_ms: u64,
+    /// Factor the retry delay grows by with each attempt
+    #[structopt(long, default_value = "2")]
+    backoff_multiplier: f64,
+    /// Upper bound on a single retry delay, in seconds
+    #[structopt(long, default_value = "60")]
+    backoff_max_secs: u64,
+    /// Backoff for retries after one failure category, as `category=base_ms,multiplier,max_secs`; may be repeated
+    #[structopt(long = "backoff-for", parse(try_from_str = parse_category_backoff), number_of_values = 1)]
+    category_backoff: Vec<(FailureCategory, BackoffPolicy)>,
+    /// Shortest wait before any retry, in milliseconds, whatever the backoff gives
+    #[structopt(long, default_value = "0")]
+    min_retry_interval_ms: u64,
+    /// Number of input lines to discard before sending anything
+    #[structopt(long, default_value = "0")]
+    skip: usize,
+    /// Maximum number of input lines to process, counted after `--skip`
+    #[structopt(long)]
+    limit: Option<usize>,
+    /// Process only lines whose task id modulo N is i, written `i/N`
+    #[structopt(long)]
+    shard: Option<Shard>,
+    /// Process a random fraction of the lines, from above 0 to 1; `--seed` makes the choice reproducible
+    #[structopt(long)]
+    sample_rate: Option<f64>,
+    /// Longest input line to accept, in bytes; longer lines go to the error file unparsed
+    #[structopt(long)]
+    max_line_bytes: Option<usize>,
+    /// Save lines with this value as skipped instead of sending them, as `<json-path>=<value>`; may be repeated
+    #[structopt(long, number_of_values = 1)]
+    skip_if: Vec<FieldMatch>,
+    /// JSON Schema file that every input line must satisfy
+    #[structopt(long)]
+    input_schema: Option<String>,
+    /// JSON file of fields computed from each input line, each a template with `{{field}}` placeholders
+    #[structopt(long)]
+    derive_fields: Option<String>,
+    /// JSON Schema file that every successful response must satisfy
+    #[structopt(long)]
+    response_schema: Option<String>,
+    /// Largest response body to accept; larger responses count as API errors
+    #[structopt(long)]
+    max_response_bytes: Option<usize>,
+    /// Redirects to follow per request; 0 disables following them
+    #[structopt(long, default_value = "5")]
+    max_redirects: usize,
+    /// JSON file listing the endpoints to balance across
+    #[structopt(long)]
+    endpoints_file: Option<String>,
+    /// JSON object mapping endpoint ids or URLs to API keys
+    #[structopt(long)]
+    secrets_file: Option<String>,
+    /// PEM client certificate for mutual TLS
+    #[structopt(long, requires = "client-key")]
+    client_cert: Option<String>,
+    /// PKCS#8 PEM private key for `--client-cert`
+    #[structopt(long, requires = "client-cert")]
+    client_key: Option<String>,
+    /// PEM file of root CA certificates trusted besides the system store
+    #[structopt(long)]
+    ca_cert: Option<String>,
+    /// Accept any server certificate; insecure, for testing only
+    #[structopt(long)]
+    insecure_skip_verify: bool,
+    /// Load-balancing strategy: `weighted`, `latency-weighted` or `smooth-weighted`
+    #[structopt(long, default_value = "weighted")]
+    lb_strategy: LbStrategy,
+    /// Number of requests buffered between reading and sending
+    #[structopt(long)]
+    queue_capacity: Option<usize>,
+    /// Directory to spill requests to once the queue is full, so reading never waits for sending
+    #[structopt(long)]
+    queue_spill_dir: Option<String>,
+    /// Number of long-lived tasks sending requests, bounding the requests in flight; one task per request if unset
+    #[structopt(long)]
+    workers: Option<usize>,
+    /// Adjust the requests in flight to the endpoints' latency and errors instead of a fixed limit
+    #[structopt(long)]
+    adaptive_concurrency: bool,
+    /// Most requests in flight across all endpoints at once; endpoint `max_concurrency` still applies
+    #[structopt(long)]
+    max_concurrency: Option<usize>,
+    /// Most HTTP requests to send in total, counting retries
+    #[structopt(long)]
+    max_requests: Option<usize>,
+    /// Stop the run and exit non-zero once this many requests fail in a row
+    #[structopt(long)]
+    abort_after_consecutive_failures: Option<usize>,
+    /// How responses are judged: `status`, `error-path=<path>` or `require=<path>`
+    #[structopt(long, default_value = "error-path=errors")]
+    success_rule: SuccessRule,
+    /// HTTP statuses the `status` rule accepts, e.g. `200-299` or `200,202`
+    #[structopt(long, default_value = "200-299")]
+    success_status: SuccessStatuses,
+    /// `key=value` pair added to every saved result; may be repeated
+    #[structopt(long = "tag", parse(try_from_str = parse_tag), number_of_values = 1)]
+    tags: Vec<(String, String)>,
+    /// Comma-separated failures to retry: connect, timeout, transport, body, 5xx or status codes; `none` disables retries
+    #[structopt(long, default_value = "connect,timeout,body,429")]
+    retry_on: RetryPolicy,
+    /// Retry responses with this value in the body, as `<json-path>=<value>`; may be repeated
+    #[structopt(long, number_of_values = 1)]
+    retry_on_body_error: Vec<BodyRetryRule>,
+    /// Header to send the task id in so the API can deduplicate retries, e.g. `Idempotency-Key`
+    #[structopt(long)]
+    idempotency_key_header: Option<String>,
+    /// `User-Agent` header to send, by default `api_processor/<version>`
+    #[structopt(long)]
+    user_agent: Option<String>,
+    /// OTLP/HTTP traces URL to post a span per task and attempt to; needs the `otel` feature
+    #[structopt(long)]
+    otel_endpoint: Option<String>,
+    /// `service.name` of the exported spans
+    #[structopt(long, default_value = "api_processor")]
+    otel_service_name: String,
+    /// Longest to wait for a TCP connection to an endpoint, in seconds
+    #[structopt(long)]
+    connect_timeout_secs: Option<f64>,
+    /// Longest to wait from sending a request for its response to start arriving, in seconds
+    #[structopt(long)]
+    ttfb_timeout_secs: Option<f64>,
+    /// Stop sending new requests after this many seconds
+    #[structopt(long)]
+    max_runtime_secs: Option<f64>,
+    /// Seconds requests in flight at the maximum runtime, SIGTERM or Ctrl-C may take to finish
+    #[structopt(long, default_value = "30")]
+    shutdown_grace_secs: f64,
+    /// File to write lines left unprocessed at the maximum runtime, SIGTERM or Ctrl-C to
+    #[structopt(long)]
+    resume_file: Option<String>,
+    /// File to write requests never attempted before the run stopped early to, replayable with --retry-state-file
+    #[structopt(long)]
+    unprocessed_file: Option<String>,
+    /// File to save requests waiting to be retried to, and to retry requests from at startup
+    #[structopt(long)]
+    retry_state_file: Option<String>,
+    /// Seconds between saves of the retry state file
+    #[structopt(long, default_value = "5")]
+    retry_state_interval_secs: f64,
+    /// File to save how far the run has got to, for --resume to start from
+    #[structopt(long)]
+    checkpoint_file: Option<String>,
+    /// Outcomes written between saves of the checkpoint file
+    #[structopt(long, default_value = "10000")]
+    checkpoint_every: usize,
+    /// Longest time between saves of the checkpoint file, in seconds
+    #[structopt(long, default_value = "10")]
+    checkpoint_interval_secs: f64,
+    /// Skip the input lines the checkpoint file says are done, adding to the output files
+    #[structopt(long)]
+    resume: bool,
+    /// Seconds a connection is idle before TCP keepalive probes are sent
+    #[structopt(long)]
+    tcp_keepalive_secs: Option<f64>,
+    /// Largest HTTP/1 read buffer per connection, in bytes (at least 8192)
+    #[structopt(long)]
+    http1_max_buf_size: Option<usize>,
+    /// Most connections to open to one host at once
+    #[structopt(long)]
+    max_connections_per_host: Option<usize>,
+    /// Directory to record every endpoint response to, for replaying later
+    #[structopt(long)]
+    record: Option<String>,
+    /// HAR file to write every request and response to, with credentials hidden
+    #[structopt(long)]
+    har_output: Option<String>,
+    /// Directory of recorded responses to answer requests from, instead of the network
+    #[structopt(long)]
+    replay: Option<String>,
+    /// Directory to keep successful responses in, answering the same requests from it in later runs
+    #[structopt(long)]
+    cache_dir: Option<String>,
+    /// Seconds a response in `--cache-dir` answers requests before they are sent again
+    #[structopt(long)]
+    cache_ttl_secs: Option<f64>,
+    /// Log whether each response came on a new or a reused connection
+    #[structopt(long)]
+    log_connections: bool,
+    /// Seconds over which the send rate ramps up to the maximum
+    #[structopt(long)]
+    warmup_secs: Option<f64>,
+    /// Longest random wait in milliseconds before the first request, to stagger instances started together
+    #[structopt(long)]
+    startup_jitter_ms: Option<u64>,
+    /// Seed for endpoint selection, for reproducible runs
+    #[structopt(long)]
+    seed: Option<u64>,
+    /// Probe every endpoint before the run and drop unhealthy ones from the pool
+    #[structopt(long)]
+    preflight: bool,
+    /// HTTP method of the preflight probe
+    #[structopt(long, default_value = "HEAD")]
+    preflight_method: Method,
+    /// Path to probe instead of each endpoint's own path, e.g. `/v1/models`
+    #[structopt(long)]
+    preflight_path: Option<String>,
+    /// Abort instead of dropping endpoints when a preflight probe fails
+    #[structopt(long, requires = "preflight")]
+    preflight_abort: bool,
+    /// Send one input line to every endpoint before the run and abort unless each succeeds
+    #[structopt(long)]
+    canary: bool,
+    /// JSON input line to send as the canary instead of the input's first line
+    #[structopt(long, requires = "canary")]
+    canary_line: Option<String>,
+    /// Most retries pending at once; further failures are not retried
+    #[structopt(long)]
+    max_pending_retries: Option<usize>,
+    /// Most outcomes waiting to be written; sending pauses beyond this
+    #[structopt(long)]
+    max_pending_writes: Option<usize>,
+    /// Input lines sent together in one API call, as an array of bodies
+    #[structopt(long, default_value = "1")]
+    batch_size: usize,
+    /// Milliseconds a partial batch waits for more input lines before it is sent
+    #[structopt(long, default_value = "100")]
+    batch_linger_ms: u64,
+    /// Times each input line is sent, each as a task with its own id and a `repeat_index` in its metadata
+    #[structopt(long, default_value = "1")]
+    repeat: usize,
+    /// Save the unsent tasks of a `group_id`, or of a repeated line, as skipped once one of them fails
+    #[structopt(long)]
+    cancel_groups: bool,
+    /// Most retries sent per second across all requests, e.g. `0.5`
+    #[structopt(long)]
+    max_retry_rate: Option<f64>,
+    /// Most tokens sent per minute across all requests, by each request's estimated cost
+    #[structopt(long)]
+    max_tokens_per_minute: Option<u64>,
+    /// JSON path into each input line holding its estimated token cost, e.g. `estimated_tokens`
+    #[structopt(long)]
+    token_cost_field: Option<JsonPath>,
+    /// Format of the save file: `jsonl` or `csv`
+    #[structopt(long, default_value = "jsonl", possible_values = &["jsonl", "csv"])]
+    output_format: String,
+    /// Comma-separated CSV columns: `task_id`, `input` or a JSON path into the saved record
+    #[structopt(long, default_value = "task_id,input,choices[0].message.content", use_delimiter = true)]
+    csv_columns: Vec<CsvColumn>,
+    /// Read responses as newline-delimited JSON, saving each object as a record of its own
+    #[structopt(long)]
+    ndjson_response: bool,
+    /// JSON path of the part of each response to save, e.g. `choices[0].message.content`
+    #[structopt(long)]
+    extract: Option<JsonPath>,
+    /// Comma-separated JSON paths making up each saved record, e.g. `task_id,input.id,response`
+    #[structopt(long, use_delimiter = true)]
+    output_fields: Vec<JsonPath>,
+    /// Save each result's HTTP status and the `--response-meta-headers` under `response_meta`
+    #[structopt(long)]
+    include_response_meta: bool,
+    /// Comma-separated response headers to save with `--include-response-meta`, e.g. `x-request-id`
+    #[structopt(long, use_delimiter = true)]
+    response_meta_headers: Vec<String>,
+    /// Response header telling whether the endpoint answered from its cache, e.g. `x-cache`
+    #[structopt(long)]
+    cache_header: Option<String>,
+    /// Value of `--cache-header` that marks a cache hit, compared case-insensitively
+    #[structopt(long, default_value = "HIT")]
+    cache_hit_value: String,
+    /// Save whether each result was a cache hit as `"cached": true` or `false`
+    #[structopt(long)]
+    include_cache_status: bool,
+    /// JSON path of an input field whose value splits results into `<save file>_<value>` files
+    #[structopt(long)]
+    output_by: Option<JsonPath>,
+    /// Most output files to keep open at once with `--output-by`
+    #[structopt(long, default_value = "64")]
+    max_open_files: usize,
+    /// JSON file with the request body to send, using `{{field}}` placeholders
+    #[structopt(long)]
+    payload_template: Option<String>,
+    /// Send each input line itself as the request body; a payload template then only adds missing fields
+    #[structopt(long)]
+    passthrough_body: bool,
+    /// Body encoding: `json`, `form` (url-encoded) or `multipart`
+    #[structopt(long, default_value = "json")]
+    content_type: BodyEncoding,
+    /// API shape of endpoints without their own `provider`: `openai` or `anthropic`
+    #[structopt(long, default_value = "openai")]
+    provider: Provider,
+}
+
+/// Parse a `--tag` argument
+fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected key=value, got '{}'", tag)),
+    }
+}
+
+/// Parse a `category=base_ms,multiplier,max_secs` backoff, e.g. `rate_limited=5000,1.5,120`
+fn parse_category_backoff(spec: &str) -> Result<(FailureCategory, BackoffPolicy), String> {
+    let (category, policy) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("expected category=base_ms,multiplier,max_secs, got '{}'", spec))?;
+    let category: FailureCategory = category.parse()?;
+    let (base_ms, multiplier, max_secs) = match policy.split(',').collect::<Vec<_>>()[..] {
+        [base_ms, multiplier, max_secs] => (base_ms, multiplier, max_secs),
+        _ => return Err(format!("expected base_ms,multiplier,max_secs for {}, got '{}'", category, policy)),
+    };
+    let invalid = |what: &str, value: &str| format!("invalid {} '{}' for {}", what, value, category);
+    let policy = BackoffPolicy {
+        base: Duration::from_millis(base_ms.trim().parse().map_err(|_| invalid("base_ms", base_ms))?),
+        multiplier: multiplier.trim().parse().map_err(|_| invalid("multiplier", multiplier))?,
+        max: Duration::from_secs(max_secs.trim().parse().map_err(|_| invalid("max_secs", max_secs))?),
+    };
+    Ok((category, policy))
+}
+
+/// Endpoints that requests are balanced across, from `--endpoints-file` if given
+///
+/// `${VAR}` in any string of the file is replaced by that environment variable.
+fn endpoints(endpoints_file: Option<&str>) -> Vec<Endpoint> {
+    match endpoints_file {
+        Some(path) => {
+            let mut endpoints = read_json_file(path);
+            if let Err(e) = interpolate_env(&mut endpoints) {
+                error!("Invalid endpoints in {}: {}", path, e);
+                std::process::exit(2);
+            }
+            serde_json::from_value(endpoints).unwrap_or_else(|e| {
+                error!("Invalid endpoints in {}: {}", path, e);
+                std::process::exit(2);
+            })
+        }
+        None => vec![Endpoint::builder("https://api.example.com/endpoint")
+            .api_key("your_api_key_here")
+            .weight(20)
+            .build()
+            .unwrap()],
+    }
+}
+
+/// Load API keys by endpoint id or URL
+fn secrets(secrets_file: Option<&str>) -> HashMap<String, String> {
+    match secrets_file {
+        Some(path) => serde_json::from_value(read_json_file(path)).unwrap_or_else(|e| {
+            error!("Invalid secrets in {}: {}", path, e);
+            std::process::exit(2);
+        }),
+        None => HashMap::new(),
+    }
+}
+
+/// Read a JSON document, exiting with a clear message if that fails
+fn read_json_file(path: &str) -> Value {
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        error!("Failed to read {}: {}", path, e);
+        std::process::exit(2);
+    });
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        error!("Failed to parse {}: {}", path, e);
+        std::process::exit(2);
+    })
+}
+
+/// Probe every endpoint, then abort or drop unhealthy endpoints from the pool
+async fn run_preflight(client: Client, probe: &Probe, abort_on_failure: bool) -> Client {
+    let results = client.preflight(probe).await;
+    for result in &results {
+        match &result.outcome {
+            Ok(status) if result.is_healthy() => info!("Preflight {}: {}", result.url, status),
+            Ok(status) => error!("Preflight {}: {}", result.url, status),
+            Err(e) => error!("Preflight {}: {}", result.url, e),
+        }
+    }
+    let num_unhealthy = results.iter().filter(|result| !result.is_healthy()).count();
+    if num_unhealthy == 0 {
+        return client;
+    }
+    if abort_on_failure {
+        error!("Preflight failed for {} endpoints, aborting", num_unhealthy);
+        std::process::exit(2);
+    }
+
+    let mut config = client.config().clone();
+    let mut healthy = results.iter().map(ProbeResult::is_healthy);
+    config.endpoints.retain(|_| healthy.next().unwrap());
+    if config.endpoints.iter().all(|e| e.weight == 0) {
+        error!("No healthy endpoints left after preflight");
+        std::process::exit(2);
+    }
+    warn!("Excluding {} unhealthy endpoints from the pool", num_unhealthy);
+    Client::new(config).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(2);
+    })
+}
+
+/// Send the canary line to every endpoint, exiting unless each one succeeds
+async fn run_canary(client: &Client, line: Value) {
+    let results = client.canary(line).await;
+    for result in &results {
+        let secs = result.latency.as_secs_f64();
+        match &result.outcome {
+            Ok(()) => info!("Canary {}: succeeded in {:.3} sec", result.url, secs),
+            Err(e) => error!("Canary {}: failed after {:.3} sec: {}", result.url, secs, e),
+        }
+    }
+    let num_failed = results.iter().filter(|result| result.outcome.is_err()).count();
+    if num_failed > 0 {
+        error!("Canary failed for {} of {} endpoints, aborting before the run", num_failed, results.len());
+        std::process::exit(2);
+    }
+}
+
+/// Check the input files and their first request against the config, then exit
+///
+/// Exits with status 2 if anything is wrong; nothing is sent or written.
+fn validate(client: &Client, requests_filepaths: &[&str]) -> ! {
+    let mut problems = Vec::new();
+    for path in requests_filepaths.iter().filter(|path| **path != "-") {
+        if let Err(e) = std::fs::File::open(path) {
+            problems.push(format!("cannot open {}: {}", path, e));
+        }
+    }
+    let path = requests_filepaths[0];
+    if path == "-" || !std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file()) {
+        warn!("Not checking a request against the config, as {} is not a regular file", path);
+    } else {
+        match first_request(path) {
+            Ok(line) => problems.extend(client.check_line(&line).into_iter().map(|problem| format!("first request: {}", problem))),
+            Err(e) => problems.push(e),
+        }
+    }
+    if problems.is_empty() {
+        info!("Configuration is valid");
+        std::process::exit(0);
+    }
+    for problem in &problems {
+        error!("{}", problem);
+    }
+    error!("Found {} problems", problems.len());
+    std::process::exit(2);
+}
+
+/// First request of an input file, its first non-blank line or the first element of its JSON array
+fn first_request(path: &str) -> Result<Value, String> {
+    use std::io::BufRead;
+    // Standard input and pipes cannot be read twice
+    if path == "-" || !std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file()) {
+        return Err(format!("{} is not a regular file, so its first request cannot be read ahead of the run", path));
+    }
+    let file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let mut reader = std::io::BufReader::new(file);
+    let start = reader.fill_buf().map_err(|e| e.to_string())?;
+    let leading = start.iter().take_while(|byte| byte.is_ascii_whitespace()).count();
+    if start.get(leading) == Some(&b'[') {
+        reader.consume(leading + 1);
+        // Only the first element is parsed, however large the array
+        return match serde_json::Deserializer::from_reader(reader).into_iter::<Value>().next() {
+            Some(Ok(request)) => Ok(request),
+            _ => Err(format!("{} holds no request to send as the canary", path)),
+        };
+    }
+    let line = reader
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| !line.trim().is_empty())
+        .ok_or_else(|| format!("{} holds no request to send as the canary", path))?;
+    serde_json::from_str(&line).map_err(|e| format!("Failed to parse the first line of {}: {}", path, e))
+}
+
+/// Number of lines the run will process, after `--skip`, `--limit`, `--shard` and `--sample-rate`
+///
+/// Files holding a JSON array count their elements. `None` if a file cannot
+/// be read, in which case the run itself reports why, or is not a regular file.
+fn count_requests(paths: &[&str], args: &Cli, sample: Option<Sample>) -> Option<usize> {
+    use std::io::BufRead;
+    let mut num_lines = 0;
+    for path in paths {
+        // Standard input and pipes cannot be read twice
+        if *path == "-" || !std::fs::metadata(path).ok()?.is_file() {
+            return None;
+        }
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+        let array = reader.fill_buf().ok()?.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'[');
+        num_lines += if array { count_elements(reader)? } else { reader.split(b'\n').count() };
+    }
+    let end = num_lines.min(args.skip.saturating_add(args.limit.unwrap_or(usize::MAX)));
+    let num_lines = (args.skip..end)
+        .filter(|line| args.shard.is_none_or(|shard| shard.owns(*line)) && sample.is_none_or(|sample| sample.includes(*line)))
+        .count();
+    Some(num_lines * args.repeat)
+}
+
+/// Number of elements of a JSON array, without keeping them in memory
+fn count_elements<R: std::io::Read>(reader: R) -> Option<usize> {
+    struct Elements;
+
+    impl<'de> serde::de::Visitor<'de> for Elements {
+        type Value = usize;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a JSON array")
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+            let mut count = 0;
+            while seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                count += 1;
+            }
+            Ok(count)
+        }
+    }
+
+    serde::Deserializer::deserialize_seq(&mut serde_json::Deserializer::from_reader(reader), Elements).ok()
+}
+
+/// Convert a seconds flag to a duration, exiting if it is negative or not finite
+fn seconds(flag: &str, secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or_else(|e| {
+        error!("Invalid {}: {}", flag, e);
+        std::process::exit(2);
+    })
+}
+
+/// Merge JSONL result files into one file ordered by task id
+#[derive(StructOpt)]
+#[structopt(name = "api_processor merge", bin_name = "api_processor merge")]
+struct MergeArgs {
+    /// File to write the merged, ordered records to
+    #[structopt(short, long)]
+    output: String,
+    /// JSONL result files, e.g. one per shard; for a repeated task id the record read last wins
+    #[structopt(required = true)]
+    inputs: Vec<String>,
+}
+
+/// Merge result files into one file ordered by task id
+fn merge(args: MergeArgs) {
+    env_logger::Builder::from_default_env().filter_module("api_processor", log::LevelFilter::Info).init();
+    match merge_result_files(&args.inputs, &args.output) {
+        Ok(stats) => info!(
+            "Merged {} records into {} ({} duplicates replaced, {} lines skipped)",
+            stats.records, args.output, stats.duplicates, stats.skipped
+        ),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // `merge` is told apart by hand, as the main command's arguments are positional, so a requests
+    // file of that name, or `validate`, has to be given with a directory, e.g. `./merge`
+    if std::env::args().nth(1).as_deref() == Some("merge") {
+        return merge(MergeArgs::from_iter(std::env::args().skip(1)));
+    }
+    // `validate` takes the arguments of a run and stops before sending anything
+    let validate_only = std::env::args().nth(1).as_deref() == Some("validate");
+    let args = if validate_only {
+        Cli::from_iter(std::env::args().skip(1))
+    } else {
+        Cli::from_args()
+    };
+    let requests_filepaths: Vec<&str> = args.requests_filepath.split(',').collect();
+    // Made here so the count below picks the same lines as the run
+    let sample = args.sample_rate.map(|rate| Sample::new(rate, args.seed));
+
+    // The bar needs a terminal to redraw on, and its total is unknown while following a file
+    let show_bar = args.progress_bar && !validate_only && std::io::stderr().is_terminal();
+    // Counted once, for the bar and the progress lines alike
+    let total = if !args.follow && !validate_only && (args.count_lines || show_bar) {
+        count_requests(&requests_filepaths, &args, sample)
+    } else {
+        None
+    };
+    let progress_bar = if !args.progress_bar || validate_only {
+        None
+    } else if show_bar {
+        Some(ProgressBar::new(total))
+    } else {
+        eprintln!("--progress-bar needs stderr to be a terminal, logging progress instead");
+        None
+    };
+
+    // Each -v raises this crate's log level; RUST_LOG still applies otherwise
+    let mut logger = env_logger::Builder::from_default_env();
+    if validate_only {
+        logger.filter_module("api_processor", log::LevelFilter::Info);
+    }
+    if args.verbose > 0 {
+        let level = match args.verbose {
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        };
+        logger.filter_module("api_processor", level);
+    }
+    if args.quiet {
+        logger.filter_module("api_processor", log::LevelFilter::Error);
+        logger.filter_module("api_processor::client", log::LevelFilter::Off);
+    }
+    // Per-request logs would scroll the bar away; warnings and errors are still written above it
+    if let Some(bar) = &progress_bar {
+        logger.filter_module("api_processor::client", log::LevelFilter::Warn);
+        logger.target(env_logger::Target::Pipe(bar.log_writer()));
+    }
+    logger.init();
+    // Argument checks come before anything is sent, including a preflight or canary
+    if args.follow && requests_filepaths.len() > 1 {
+        error!("--follow takes a single requests file");
+        std::process::exit(2);
+    }
+    // Output paths are derived from the first input file
+    let save_suffix = if args.output_format == "csv" { "_results.csv" } else { "_results.jsonl" };
+    let save_filepath = args.save_filepath.clone().unwrap_or_else(|| requests_filepaths[0].replace(".jsonl", save_suffix));
+    let error_filepath = args.error_filepath.clone().unwrap_or_else(|| requests_filepaths[0].replace(".jsonl", "_errors.jsonl"));
+    // The failure report sits next to the save file
+    let report_filepath = args.report_filepath.clone().unwrap_or_else(|| {
+        let save_path = std::path::Path::new(&save_filepath);
+        format!("{}_report.json", save_path.with_extension("").display())
+    });
+
+    let mut builder = Config::builder();
+    if let Some(path) = &args.input_schema {
+        builder = builder.input_schema(read_json_file(path));
+    }
+    if let Some(path) = &args.derive_fields {
+        let fields = DerivedFields::new(&read_json_file(path)).unwrap_or_else(|e| {
+            error!("Invalid derived fields in {}: {}", path, e);
+            std::process::exit(2);
+        });
+        builder = builder.derived_fields(fields);
+    }
+    if let Some(path) = &args.response_schema {
+        builder = builder.response_schema(read_json_file(path));
+    }
+    if let Some(path) = &args.payload_template {
+        let template = PayloadTemplate::new(&read_json_file(path)).unwrap_or_else(|e| {
+            error!("Invalid payload template in {}: {}", path, e);
+            std::process::exit(2);
+        });
+        builder = builder.payload_template(template);
+    }
+    if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+        builder = builder.client_identity(cert.as_str(), key.as_str());
+    }
+    if let Some(path) = &args.ca_cert {
+        builder = builder.ca_cert(path.as_str());
+    }
+    for (key, value) in &args.tags {
+        builder = builder.tag(key.as_str(), value.as_str());
+    }
+    if let Some(user_agent) = &args.user_agent {
+        builder = builder.user_agent(user_agent.as_str());
+    }
+    for rule in &args.retry_on_body_error {
+        builder = builder.retry_on_body_error(rule.clone());
+    }
+    for rule in &args.skip_if {
+        builder = builder.skip_if(rule.clone());
+    }
+    for (category, backoff) in &args.category_backoff {
+        builder = builder.category_backoff(*category, *backoff);
+    }
+
+    let config = builder
+        .endpoints(endpoints(args.endpoints_file.as_deref()))
+        .secrets(secrets(args.secrets_file.as_deref()))
+        .save_filepath(save_filepath)
+        .error_filepath(error_filepath)
+        .dead_letter_filepath(args.dead_letter_file.clone())
+        .flush_interval(seconds("--flush-interval-secs", args.flush_interval_secs))
+        .flush_every(args.flush_every)
+        .output_writers(args.output_writers)
+        .fsync(args.fsync)
+        .append(args.append)
+        .upsert(args.upsert)
+        .no_save(args.no_save)
+        .input_field(args.input_field.as_str())
+        .max_requests_per_second(args.max_requests_per_second)
+        .max_attempts(args.max_attempts)
+        .backoff(BackoffPolicy {
+            base: Duration::from_millis(args.backoff_base_ms),
+            multiplier: args.backoff_multiplier,
+            max: Duration::from_secs(args.backoff_max_secs),
+        })
+        .min_retry_interval(Duration::from_millis(args.min_retry_interval_ms))
+        .skip(args.skip)
+        .limit(args.limit)
+        .shard(args.shard)
+        .sample(sample)
+        .max_line_bytes(args.max_line_bytes)
+        .max_response_bytes(args.max_response_bytes)
+        .max_redirects(args.max_redirects)
+        .lb_strategy(args.lb_strategy)
+        .queue_capacity(args.queue_capacity)
+        .queue_spill_dir(args.queue_spill_dir.clone())
+        .workers(args.workers)
+        .adaptive_concurrency(args.adaptive_concurrency)
+        .max_concurrency(args.max_concurrency)
+        .max_requests(args.max_requests)
+        .abort_after_consecutive_failures(args.abort_after_consecutive_failures)
+        .success_rule(args.success_rule)
+        .success_status(args.success_status)
+        .retry_on(args.retry_on)
+        .idempotency_key_header(args.idempotency_key_header)
+        .otel_endpoint(args.otel_endpoint)
+        .otel_service_name(args.otel_service_name)
+        .connect_timeout(args.connect_timeout_secs.map(|secs| seconds("--connect-timeout-secs", secs)))
+        .ttfb_timeout(args.ttfb_timeout_secs.map(|secs| seconds("--ttfb-timeout-secs", secs)))
+        .max_runtime(args.max_runtime_secs.map(|secs| seconds("--max-runtime-secs", secs)))
+        .shutdown_grace(seconds("--shutdown-grace-secs", args.shutdown_grace_secs))
+        .resume_filepath(args.resume_file.clone())
+        .unprocessed_filepath(args.unprocessed_file.clone())
+        .retry_state_filepath(args.retry_state_file)
+        .retry_state_interval(seconds("--retry-state-interval-secs", args.retry_state_interval_secs))
+        .checkpoint_filepath(args.checkpoint_file.clone())
+        .checkpoint_every(args.checkpoint_every)
+        .checkpoint_interval(seconds("--checkpoint-interval-secs", args.checkpoint_interval_secs))
+        .resume(args.resume)
+        .tcp_keepalive(args.tcp_keepalive_secs.map(|secs| seconds("--tcp-keepalive-secs", secs)))
+        .http1_max_buf_size(args.http1_max_buf_size)
+        .max_connections_per_host(args.max_connections_per_host)
+        .record_dir(args.record.clone())
+        .har_filepath(args.har_output.clone())
+        .replay_dir(args.replay.clone())
+        .cache_dir(args.cache_dir.clone())
+        .cache_ttl(args.cache_ttl_secs.map(|secs| seconds("--cache-ttl-secs", secs)))
+        .log_connections(args.log_connections)
+        .warmup(args.warmup_secs.map(|secs| seconds("--warmup-secs", secs)))
+        .startup_jitter(args.startup_jitter_ms.map(Duration::from_millis))
+        .seed(args.seed)
+        .max_pending_retries(args.max_pending_retries)
+        .insecure_skip_verify(args.insecure_skip_verify)
+        .max_pending_writes(args.max_pending_writes)
+        .batch_size(args.batch_size)
+        .batch_linger(Duration::from_millis(args.batch_linger_ms))
+        .repeat(args.repeat)
+        .cancel_groups(args.cancel_groups)
+        .max_retry_rate(args.max_retry_rate)
+        .max_tokens_per_minute(args.max_tokens_per_minute)
+        .token_cost_field(args.token_cost_field.clone())
+        .ndjson_response(args.ndjson_response)
+        .extract(args.extract.clone())
+        .output_fields(Some(args.output_fields.clone()).filter(|fields| !fields.is_empty()))
+        .include_response_meta(args.include_response_meta)
+        .response_meta_headers(args.response_meta_headers)
+        .cache_header(args.cache_header.clone())
+        .cache_hit_value(args.cache_hit_value)
+        .include_cache_status(args.include_cache_status)
+        .output_by(args.output_by.clone())
+        .max_open_files(args.max_open_files)
+        .passthrough_body(args.passthrough_body)
+        .body_encoding(args.content_type)
+        .provider(args.provider)
+        .output_format(match args.output_format.as_str() {
+            "csv" => OutputFormat::Csv(args.csv_columns.clone()),
+            _ => OutputFormat::Jsonl,
+        })
+        .build()
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(2);
+        });
+    // Checked before anything is sent, although a run checks it again
+    if let Some(e) = requests_filepaths.iter().find_map(|path| config.check_requests_file(path).err()) {
+        error!("{}", e);
+        std::process::exit(2);
+    }
+    // Read before `validate` stops, so it checks the canary's line too
+    let given_line = &args.canary_line;
+    let canary_line = args.canary.then(|| {
+        let line = match given_line {
+            Some(line) => serde_json::from_str(line).map_err(|e| format!("Invalid --canary-line: {}", e)),
+            None => first_request(requests_filepaths[0]).map_err(|e| format!("{}; pass one with --canary-line", e)),
+        };
+        line.unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(2);
+        })
+    });
+    let mut client = Client::new(config).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(2);
+    });
+    if validate_only {
+        validate(&client, &requests_filepaths);
+    }
+    if args.preflight {
+        let probe = Probe {
+            method: args.preflight_method.clone(),
+            path: args.preflight_path.clone(),
+        };
+        client = run_preflight(client, &probe, args.preflight_abort).await;
+    }
+    if let Some(line) = canary_line {
+        run_canary(&client, line).await;
+    }
+    let status_tracker = client.status_tracker();
+
+    // SIGUSR1 toggles pausing, SIGUSR2 resumes
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let pause = client.pause_handle();
+        match (signal(SignalKind::user_defined1()), signal(SignalKind::user_defined2())) {
+            (Ok(mut toggle), Ok(mut resume)) => {
+                tokio::spawn(async move {
+                    loop {
+                        tokio::select! {
+                            Some(()) = toggle.recv() => pause.toggle(),
+                            Some(()) = resume.recv() => pause.resume(),
+                            else => break,
+                        }
+                    }
+                });
+            }
+            (Err(e), _) | (_, Err(e)) => error!("Failed to install pause signal handlers: {}", e),
+        }
+    }
+
+    // SIGTERM, as sent by container orchestrators, and Ctrl-C stop the run like the maximum runtime;
+    // a second one exits at once
+    {
+        let shutdown = client.shutdown_handle();
+        tokio::spawn(async move {
+            #[cfg(unix)]
+            let mut terminate = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(terminate) => Some(terminate),
+                Err(e) => {
+                    error!("Failed to install SIGTERM handler: {}", e);
+                    None
+                }
+            };
+            loop {
+                #[cfg(unix)]
+                let terminated = async {
+                    match terminate.as_mut() {
+                        Some(terminate) => terminate.recv().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                #[cfg(not(unix))]
+                let terminated = std::future::pending::<Option<()>>();
+                tokio::select! {
+                    result = tokio::signal::ctrl_c() => {
+                        if let Err(e) = result {
+                            error!("Failed to listen for Ctrl-C: {}", e);
+                            return;
+                        }
+                    }
+                    _ = terminated => {}
+                }
+                if shutdown.is_requested() {
+                    warn!("Exiting without waiting for the requests in flight");
+                    std::process::exit(130);
+                }
+                shutdown.shutdown();
+            }
+        });
+    }
+
+    // Start the metrics server, if requested
+    let metrics_server = args.metrics_addr.map(|addr| {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let handle = tokio::spawn(serve_metrics(addr, client.status_tracker(), shutdown_rx));
+        (shutdown_tx, handle)
+    });
+
+    if args.count_lines {
+        match total {
+            Some(total) => info!("Counted {} requests in the input", total),
+            None if args.follow => info!("Not counting the input, as it is followed"),
+            None => info!("Could not count the input, e.g. as it is not a regular file; the total is unknown"),
+        }
+    }
+
+    // Report progress periodically, if requested
+    let progress = args.progress_secs.map(|secs| {
+        let period = seconds("--progress-secs", secs);
+        let status_tracker = client.status_tracker();
+        let started = std::time::Instant::now();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let tracker = status_tracker.lock().unwrap();
+                // With a known total, how far along the run is and how long is left at the rate so far
+                let done = tracker.num_tasks_succeeded + tracker.num_tasks_failed + tracker.num_tasks_invalid + tracker.num_tasks_skipped;
+                let position = match total {
+                    Some(total) if done > 0 => {
+                        let left = started.elapsed().as_secs_f64() * total.saturating_sub(done) as f64 / done as f64;
+                        format!("{}/{} done, {:.0} sec left, ", done, total, left)
+                    }
+                    Some(total) => format!("0/{} done, ", total),
+                    None => String::new(),
+                };
+                info!(
+                    "Progress: {}{} succeeded, {} failed, {} in progress, {} tokens, estimated cost {:.4}",
+                    position,
+                    tracker.num_tasks_succeeded,
+                    tracker.num_tasks_failed,
+                    tracker.num_tasks_in_progress,
+                    tracker.num_prompt_tokens + tracker.num_completion_tokens,
+                    tracker.estimated_cost,
+                );
+            }
+        })
+    });
+
+    let progress_bar = progress_bar.map(|bar| {
+        let handle = tokio::spawn(Arc::clone(&bar).run(client.status_tracker(), Duration::from_millis(200)));
+        (bar, handle)
+    });
+
+    // A failed write stops the run early; the summary is still reported
+    let outcome = if args.follow {
+        client.process_file_follow(requests_filepaths[0]).await
+    } else if requests_filepaths.len() == 1 {
+        client.process_file(requests_filepaths[0]).await
+    } else {
+        client.process_files(&requests_filepaths).await
+    };
+
+    if let Some(progress) = progress {
+        progress.abort();
+    }
+    if let Some((bar, handle)) = progress_bar {
+        handle.abort();
+        bar.finish(&status_tracker.lock().unwrap());
+    }
+
+    // Shut down the metrics server now that processing has completed
+    if let Some((shutdown_tx, handle)) = metrics_server {
+        let _ = shutdown_tx.send(());
+        match handle.await {
+            Ok(Err(e)) => error!("Metrics server failed: {}", e),
+            Err(e) => error!("Metrics server task panicked: {}", e),
+            Ok(Ok(())) => {}
+        }
+    }
+
+    let tracker = status_tracker.lock().unwrap();
+    if let Some(path) = &args.summary_json {
+        let summary = tracker.summary().to_string();
+        if path == "-" {
+            println!("{}", summary);
+        } else if let Err(e) = std::fs::write(path, summary + "\n") {
+            error!("Failed to write summary to {}: {}", path, e);
+        }
+    }
+    let report = serde_json::to_string_pretty(&tracker.failures.to_json()).unwrap();
+    if let Err(e) = std::fs::write(&report_filepath, report + "\n") {
+        error!("Failed to write failure report to {}: {}", report_filepath, e);
+    }
+    info!("Processing completed.");
+    info!("Total tasks started: {}", tracker.num_tasks_started);
+    info!("Total tasks succeeded: {}", tracker.num_tasks_succeeded);
+    info!("Total tasks failed: {}", tracker.num_tasks_failed);
+    info!("Total invalid tasks: {}", tracker.num_tasks_invalid);
+    if tracker.num_tasks_skipped > 0 {
+        info!("Total skipped tasks: {}", tracker.num_tasks_skipped);
+    }
+    if args.cancel_groups {
+        info!("Tasks cancelled after a failure in their group: {}", tracker.num_tasks_cancelled);
+    }
+    if args.cache_header.is_some() || args.cache_dir.is_some() {
+        info!("Cache hits: {} of {} succeeded tasks", tracker.num_cache_hits, tracker.num_tasks_succeeded);
+    }
+    if let Some(sample) = sample {
+        let num_lines = tracker.num_lines_sampled + tracker.num_lines_not_sampled;
+        info!("Sampled {} of {} lines at a rate of {}", tracker.num_lines_sampled, num_lines, sample.rate);
+    }
+    if tracker.num_budget_exhausted > 0 {
+        info!("Request budget exhausted: {} tasks were not sent", tracker.num_budget_exhausted);
+    }
+    if let Some(retry_rate) = tracker.retry_rate() {
+        info!("Total retries: {} ({:.1}% of {} requests sent)", tracker.num_retries, retry_rate * 100.0, tracker.num_requests());
+    }
+    if tracker.num_retries_shed > 0 {
+        info!("Retries shed: {}", tracker.num_retries_shed);
+    }
+    info!("Total rate limit errors: {}", tracker.num_rate_limit_errors);
+    info!("Total API errors: {}", tracker.num_api_errors);
+    info!("Total other errors: {}", tracker.num_other_errors);
+    if tracker.num_tasks_unprocessed > 0 {
+        warn!("Stopped early with {} lines left unprocessed", tracker.num_tasks_unprocessed);
+        if let Some(path) = &args.resume_file {
+            info!("Unprocessed lines were written to {}", path);
+        }
+    }
+    if let Some(path) = args.unprocessed_file.as_ref().filter(|_| tracker.num_tasks_unprocessed + tracker.num_budget_exhausted > 0) {
+        info!("Requests never attempted were written to {}, to send with --retry-state-file", path);
+    }
+    if args.log_connections {
+        info!(
+            "Connections: {} opened, {} reused",
+            tracker.num_connections_opened, tracker.num_connections_reused
+        );
+    }
+    if tracker.num_schema_failures > 0 {
+        info!("Responses failing the response schema: {}", tracker.num_schema_failures);
+    }
+    if tracker.num_prompt_tokens + tracker.num_completion_tokens > 0 {
+        info!(
+            "Tokens used: {} prompt, {} completion; estimated cost {:.4}",
+            tracker.num_prompt_tokens, tracker.num_completion_tokens, tracker.estimated_cost
+        );
+    }
+    if let Some(mean) = tracker.latency.mean() {
+        let percentile = |p| tracker.latency.percentile(p).unwrap_or_default().as_secs_f64();
+        info!(
+            "Latency: mean {:.3} sec, p50 {:.3} sec, p90 {:.3} sec, p99 {:.3} sec ({} responses)",
+            mean.as_secs_f64(),
+            percentile(50.0),
+            percentile(90.0),
+            percentile(99.0),
+            tracker.latency.count(),
+        );
+    }
+    if let Err(e) = outcome {
+        error!("Run stopped early: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::error::ClientError;
+use crate::output::write_error;
+use log::warn;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+/// What merging result files found
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MergeStats {
+    /// Records written to the merged file, one per task id
+    pub records: usize,
+    /// Records replaced by a later record for the same task id
+    pub duplicates: usize,
+    /// Lines that were not JSON objects with a `task_id`
+    pub skipped: usize,
+}
+
+/// Merge JSONL result files into one file ordered by `task_id`
+///
+/// Files are read in the order given; when a task id appears more than once,
+/// the record read last wins, so later runs and later attempts take precedence.
+pub fn merge_result_files<P: AsRef<str>>(inputs: &[P], output: &str) -> Result<MergeStats, ClientError> {
+    let mut stats = MergeStats::default();
+    let mut records: BTreeMap<u64, Value> = BTreeMap::new();
+    for path in inputs {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| read_error(path, e))?;
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| read_error(path, e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Value = match serde_json::from_str(&line) {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!("Skipping line {} of {}: {}", number + 1, path, e);
+                    stats.skipped += 1;
+                    continue;
+                }
+            };
+            match record.get("task_id").and_then(Value::as_u64) {
+                Some(task_id) => {
+                    if records.insert(task_id, record).is_some() {
+                        stats.duplicates += 1;
+                    }
+                }
+                None => {
+                    warn!("Skipping line {} of {}: no task_id", number + 1, path);
+                    stats.skipped += 1;
+                }
+            }
+        }
+    }
+
+    let file = File::create(output).map_err(|e| write_error(output, e))?;
+    let mut writer = BufWriter::new(file);
+    for record in records.values() {
+        writeln!(writer, "{}", record).map_err(|e| write_error(output, e))?;
+    }
+    writer.flush().map_err(|e| write_error(output, e))?;
+    stats.records = records.len();
+    Ok(stats)
+}
+
+/// Keep only the last record of each `task_id` in a JSONL result file, in place
+///
+/// Records keep their order in the file. The file is read twice: once to find
+/// each task id's last record, keeping one entry per task id in memory, and
+/// once to copy the records to keep to a temporary file that then replaces
+/// it. Lines without a task id are kept as they are. A missing file has nothing to compact.
+pub fn compact_result_file(path: &str) -> Result<MergeStats, ClientError> {
+    let mut stats = MergeStats::default();
+    let lines = |path: &str| -> Result<_, ClientError> {
+        let file = File::open(path).map_err(|e| read_error(path, e))?;
+        Ok(BufReader::new(file).lines().enumerate())
+    };
+    let task_id = |line: &str| serde_json::from_str::<Value>(line).ok().and_then(|record| record.get("task_id").and_then(Value::as_u64));
+    if !std::path::Path::new(path).exists() {
+        return Ok(stats);
+    }
+
+    let mut last: HashMap<u64, usize> = HashMap::new();
+    for (number, line) in lines(path)? {
+        let line = line.map_err(|e| read_error(path, e))?;
+        if let Some(task_id) = task_id(&line) {
+            last.insert(task_id, number);
+        }
+    }
+
+    let temp_path = format!("{}.tmp", path);
+    let file = File::create(&temp_path).map_err(|e| write_error(&temp_path, e))?;
+    let mut writer = BufWriter::new(file);
+    for (number, line) in lines(path)? {
+        let line = line.map_err(|e| read_error(path, e))?;
+        match task_id(&line) {
+            Some(task_id) if last[&task_id] != number => {
+                stats.duplicates += 1;
+                continue;
+            }
+            Some(_) => stats.records += 1,
+            None if line.trim().is_empty() => continue,
+            None => stats.skipped += 1,
+        }
+        writeln!(writer, "{}", line).map_err(|e| write_error(&temp_path, e))?;
+    }
+    writer.flush().map_err(|e| write_error(&temp_path, e))?;
+    std::fs::rename(&temp_path, path).map_err(|e| write_error(path, e))?;
+    Ok(stats)
+}
+
+/// Name the file in a failed read
+fn read_error(path: &str, e: std::io::Error) -> ClientError {
+    ClientError::Io(std::io::Error::new(e.kind(), format!("failed to read {}: {}", path, e)))
+}
@@ -0,0 +1,102 @@
+use crate::tracker::StatusTracker;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::info;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Escape a Prometheus label value
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Write the HELP and TYPE lines for a metric
+fn write_metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Render the tracker counters in the Prometheus text exposition format
+pub fn render_metrics(tracker: &StatusTracker) -> String {
+    let mut out = String::new();
+    let num_requests = tracker.num_requests();
+    let totals = [
+        ("api_processor_requests_total", "counter", "HTTP requests sent to endpoints.", num_requests),
+        ("api_processor_tasks_started_total", "counter", "Tasks read from the input file.", tracker.num_tasks_started),
+        ("api_processor_tasks_succeeded_total", "counter", "Tasks that completed successfully.", tracker.num_tasks_succeeded),
+        ("api_processor_failures_total", "counter", "Tasks that failed permanently.", tracker.num_tasks_failed),
+        ("api_processor_tasks_invalid_total", "counter", "Input lines rejected by the input schema.", tracker.num_tasks_invalid),
+        ("api_processor_tasks_skipped_total", "counter", "Input lines saved as skipped without being sent.", tracker.num_tasks_skipped),
+        ("api_processor_tasks_cancelled_total", "counter", "Skipped tasks whose group already had a failed task.", tracker.num_tasks_cancelled),
+        ("api_processor_cache_hits_total", "counter", "Succeeded tasks answered from an endpoint's cache.", tracker.num_cache_hits),
+        ("api_processor_rate_limit_errors_total", "counter", "Rate limit errors returned by endpoints.", tracker.num_rate_limit_errors),
+        ("api_processor_api_errors_total", "counter", "API errors returned by endpoints.", tracker.num_api_errors),
+        ("api_processor_schema_failures_total", "counter", "Responses that did not match the response schema.", tracker.num_schema_failures),
+        ("api_processor_other_errors_total", "counter", "Other errors encountered while processing.", tracker.num_other_errors),
+        ("api_processor_budget_exhausted_total", "counter", "Tasks not sent because the request budget was spent.", tracker.num_budget_exhausted),
+        ("api_processor_retries_total", "counter", "Requests queued to be sent again after a failed attempt.", tracker.num_retries),
+        ("api_processor_retries_shed_total", "counter", "Retries dropped because too many were already pending.", tracker.num_retries_shed),
+        ("api_processor_in_flight", "gauge", "Tasks currently in progress.", tracker.num_tasks_in_progress),
+    ];
+    for (name, kind, help, value) in totals.iter() {
+        write_metric_header(&mut out, name, kind, help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    // Per-endpoint counters, sorted so the output is stable between scrapes
+    let mut endpoints: Vec<_> = tracker.endpoint_stats.iter().collect();
+    endpoints.sort_by(|a, b| a.0.cmp(b.0));
+    let per_endpoint = [
+        ("api_processor_endpoint_requests_total", "HTTP requests sent to each endpoint."),
+        ("api_processor_endpoint_successes_total", "Successful responses from each endpoint."),
+        ("api_processor_endpoint_failures_total", "Failed requests to each endpoint."),
+    ];
+    for (i, (name, help)) in per_endpoint.iter().enumerate() {
+        write_metric_header(&mut out, name, "counter", help);
+        for (url, stats) in &endpoints {
+            let value = [stats.num_requests, stats.num_succeeded, stats.num_failed][i];
+            let _ = writeln!(out, "{}{{endpoint=\"{}\"}} {}", name, escape_label_value(url), value);
+        }
+    }
+    out
+}
+
+/// Serve `/metrics` until the shutdown signal fires
+pub async fn serve_metrics(
+    addr: SocketAddr,
+    status_tracker: Arc<Mutex<StatusTracker>>,
+    shutdown: oneshot::Receiver<()>,
+) -> hyper::Result<()> {
+    let make_svc = make_service_fn(move |_conn| {
+        let status_tracker = Arc::clone(&status_tracker);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let status_tracker = Arc::clone(&status_tracker);
+                async move {
+                    let response = if req.method() == Method::GET && req.uri().path() == "/metrics" {
+                        let body = render_metrics(&status_tracker.lock().unwrap());
+                        Response::builder()
+                            .header("Content-Type", "text/plain; version=0.0.4")
+                            .body(Body::from(body))
+                    } else {
+                        Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty())
+                    };
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+
+    info!("Serving metrics on http://{}/metrics", addr);
+    Server::try_bind(&addr)?
+        .serve(make_svc)
+        .with_graceful_shutdown(async {
+            shutdown.await.ok();
+        })
+        .await
+}
@@ -0,0 +1,158 @@
+use crate::connector::HttpClient;
+use hyper::{Body, Request};
+use log::{error, info};
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time::sleep;
+
+/// How long before expiry a token is replaced
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+/// Lifetime assumed when the token response has no `expires_in`
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(3600);
+/// Wait before asking again after a failed token request
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// OAuth 2 client-credentials settings for an endpoint
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct OAuthConfig {
+    /// Where access tokens are requested
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Space-separated scopes to request
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+/// Keeps an endpoint's access token fresh in the background
+pub(crate) struct TokenSource {
+    config: OAuthConfig,
+    http: HttpClient,
+    /// The current token; `None` until the first one arrives or after the API rejects one
+    token: watch::Sender<Option<String>>,
+    /// Signalled to refresh before the current token expires
+    refresh: Arc<Notify>,
+    started: AtomicBool,
+}
+
+impl TokenSource {
+    pub fn new(config: OAuthConfig, http: HttpClient) -> Arc<Self> {
+        Arc::new(TokenSource {
+            config,
+            http,
+            token: watch::Sender::new(None),
+            refresh: Arc::new(Notify::new()),
+            started: AtomicBool::new(false),
+        })
+    }
+
+    /// Start refreshing the token, unless that has already begun
+    ///
+    /// The refresh task ends once the source is dropped.
+    pub fn start(self: &Arc<Self>) {
+        if !self.started.swap(true, Ordering::SeqCst) {
+            tokio::spawn(refresh_loop(Arc::downgrade(self), Arc::clone(&self.refresh)));
+        }
+    }
+
+    /// The current access token, waiting for one if there is none yet
+    pub async fn token(&self) -> String {
+        let mut token = self.token.subscribe();
+        // The sender lives as long as `self`, so the channel cannot close here
+        let current = match token.wait_for(Option::is_some).await {
+            Ok(current) => current.clone().unwrap_or_default(),
+            Err(_) => String::new(),
+        };
+        current
+    }
+
+    /// Drop a token the API rejected and fetch a new one right away
+    ///
+    /// Does nothing to the current token if it has already been replaced.
+    pub fn invalidate(&self, rejected: &str) {
+        let dropped = self.token.send_if_modified(|token| {
+            if token.as_deref() == Some(rejected) {
+                *token = None;
+                true
+            } else {
+                false
+            }
+        });
+        if dropped {
+            self.refresh.notify_one();
+        }
+    }
+
+    /// Request a new token, returning it with its lifetime
+    async fn fetch(&self) -> Result<(String, Duration), String> {
+        let mut form = format!(
+            "grant_type=client_credentials&client_id={}&client_secret={}",
+            form_encode(&self.config.client_id),
+            form_encode(&self.config.client_secret)
+        );
+        if let Some(scope) = &self.config.scope {
+            let _ = write!(form, "&scope={}", form_encode(scope));
+        }
+        let request = Request::post(&self.config.token_url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", "application/json")
+            .body(Body::from(form))
+            .map_err(|e| format!("invalid token URL: {}", e))?;
+        let response = self.http.request(request).await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+        if !status.is_success() {
+            return Err(format!("HTTP status {}: {}", status, String::from_utf8_lossy(&body)));
+        }
+        let body: Value = serde_json::from_slice(&body).map_err(|e| format!("invalid token response: {}", e))?;
+        let token = body
+            .get("access_token")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "token response has no access_token".to_string())?;
+        let lifetime = body.get("expires_in").and_then(Value::as_u64).map_or(DEFAULT_LIFETIME, Duration::from_secs);
+        Ok((token.to_string(), lifetime))
+    }
+}
+
+/// Fetch a token, then keep replacing it shortly before it expires or when asked to
+async fn refresh_loop(source: Weak<TokenSource>, refresh: Arc<Notify>) {
+    loop {
+        let wait = match source.upgrade() {
+            Some(source) => match source.fetch().await {
+                Ok((token, lifetime)) => {
+                    info!("Fetched an access token from {}", source.config.token_url);
+                    source.token.send_replace(Some(token));
+                    lifetime.saturating_sub(REFRESH_MARGIN).max(Duration::from_secs(1))
+                }
+                Err(e) => {
+                    error!("Failed to fetch an access token from {}: {}", source.config.token_url, e);
+                    RETRY_DELAY
+                }
+            },
+            None => return,
+        };
+        tokio::select! {
+            _ = sleep(wait) => {}
+            _ = refresh.notified() => {}
+        }
+    }
+}
+
+/// Percent-encode a value for an `application/x-www-form-urlencoded` body
+pub(crate) fn form_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => encoded.push(byte as char),
+            b' ' => encoded.push('+'),
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,23 @@
+use crate::error::ClientError;
+use crate::request::{APIRequest, ProcessedResult};
+use std::time::Duration;
+
+/// Hooks called as requests are sent, retried and finished
+///
+/// Lets embedders feed their own metrics, tracing or side effects alongside
+/// the built-in `StatusTracker`. Every method does nothing by default, so an
+/// observer implements only the events it needs. Methods are called from the
+/// tasks sending requests and should return quickly.
+pub trait RequestObserver: Send + Sync {
+    /// An attempt of the request is about to be sent to the endpoint at this URL
+    fn on_start(&self, _request: &APIRequest, _endpoint: &str) {}
+
+    /// A task finished successfully; `result.latency` is that of its last attempt
+    fn on_success(&self, _result: &ProcessedResult) {}
+
+    /// An attempt failed and the request will be sent again after `delay`
+    fn on_retry(&self, _request: &APIRequest, _delay: Duration) {}
+
+    /// A task failed for good; the error is always `ClientError::Request`
+    fn on_failure(&self, _error: &ClientError) {}
+}
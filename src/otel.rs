@@ -0,0 +1,298 @@
+//! OpenTelemetry spans for tasks and their attempts, exported as OTLP/HTTP JSON
+//!
+//! Each task is a trace: a span for the task with one child span per attempt,
+//! retries included. Attempts carry a W3C `traceparent` header, so the
+//! endpoints' own spans join the same trace. Spans are batched and posted to
+//! the collector in the background. Needs the crate's `otel` feature.
+
+use crate::config::Config;
+use crate::connector::HttpClient;
+use std::sync::Arc;
+
+#[cfg(feature = "otel")]
+pub(crate) use exporter::{AttemptSpan, Tracer};
+
+#[cfg(not(feature = "otel"))]
+pub(crate) use disabled::{AttemptSpan, Tracer};
+
+/// The tracer for `Config::otel_endpoint`, if one is set
+#[cfg(feature = "otel")]
+pub(crate) fn tracer(config: &Config, http: &HttpClient) -> Option<Arc<Tracer>> {
+    let endpoint = config.otel_endpoint.clone()?;
+    Some(Tracer::new(endpoint, config.otel_service_name.clone(), http.clone()))
+}
+
+/// Spans are never recorded without the `otel` feature; a config that asks for them fails validation
+#[cfg(not(feature = "otel"))]
+pub(crate) fn tracer(_config: &Config, _http: &HttpClient) -> Option<Arc<Tracer>> {
+    None
+}
+
+#[cfg(feature = "otel")]
+mod exporter {
+    use super::HttpClient;
+    use hyper::{Body, Request, StatusCode};
+    use log::{debug, warn};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex, Weak};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// How often finished spans are posted to the collector
+    const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+    /// Finished spans kept while the collector is unreachable; newer spans are dropped beyond this
+    const MAX_BUFFERED_SPANS: usize = 10_000;
+
+    /// One attempt's span, open until its response or failure
+    #[derive(Debug)]
+    pub(crate) struct AttemptSpan {
+        task_id: usize,
+        trace_id: u128,
+        span_id: u64,
+        parent_id: u64,
+        endpoint: String,
+        attempt: usize,
+        start: SystemTime,
+    }
+
+    impl AttemptSpan {
+        /// The `traceparent` header value naming this span as the parent of the endpoint's spans
+        pub(crate) fn traceparent(&self) -> String {
+            format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+        }
+    }
+
+    /// The span of a task whose attempts are still being made
+    struct TaskSpan {
+        trace_id: u128,
+        span_id: u64,
+        start: SystemTime,
+        attempts: usize,
+    }
+
+    /// Records spans and posts them to an OTLP/HTTP collector
+    pub(crate) struct Tracer {
+        endpoint: String,
+        service_name: String,
+        http: HttpClient,
+        tasks: Mutex<HashMap<usize, TaskSpan>>,
+        /// Finished spans waiting to be exported, in OTLP JSON
+        finished: Mutex<Vec<Value>>,
+        /// Whether dropping spans for a full buffer was logged
+        overflow_logged: AtomicBool,
+        started: AtomicBool,
+    }
+
+    impl Tracer {
+        pub(crate) fn new(endpoint: String, service_name: String, http: HttpClient) -> Arc<Self> {
+            Arc::new(Tracer {
+                endpoint,
+                service_name,
+                http,
+                tasks: Mutex::default(),
+                finished: Mutex::default(),
+                overflow_logged: AtomicBool::new(false),
+                started: AtomicBool::new(false),
+            })
+        }
+
+        /// Start exporting spans periodically, unless that has already begun
+        ///
+        /// The export task ends once the tracer is dropped.
+        pub(crate) fn start(self: &Arc<Self>) {
+            if !self.started.swap(true, Ordering::SeqCst) {
+                tokio::spawn(export_loop(Arc::downgrade(self)));
+            }
+        }
+
+        /// Open the span of an attempt, and of its task if this is the first attempt
+        pub(crate) fn start_attempt(&self, task_id: usize, endpoint: &str) -> AttemptSpan {
+            let mut tasks = self.tasks.lock().unwrap();
+            let task = tasks.entry(task_id).or_insert_with(|| TaskSpan {
+                trace_id: rand::random::<u128>().max(1),
+                span_id: span_id(),
+                start: SystemTime::now(),
+                attempts: 0,
+            });
+            task.attempts += 1;
+            AttemptSpan {
+                task_id,
+                trace_id: task.trace_id,
+                span_id: span_id(),
+                parent_id: task.span_id,
+                endpoint: endpoint.to_string(),
+                attempt: task.attempts,
+                start: SystemTime::now(),
+            }
+        }
+
+        /// Close an attempt's span with its response status, or with the error that ended it
+        pub(crate) fn end_attempt(&self, span: AttemptSpan, status: Option<StatusCode>, error: Option<&str>, latency: Duration) {
+            let mut attributes = vec![
+                attribute("http.request.method", json!({"stringValue": "POST"})),
+                attribute("url.full", json!({"stringValue": span.endpoint})),
+                attribute("api_processor.task_id", json!({"intValue": span.task_id.to_string()})),
+                attribute("api_processor.attempt", json!({"intValue": span.attempt.to_string()})),
+                attribute("api_processor.latency_ms", json!({"doubleValue": latency.as_secs_f64() * 1000.0})),
+            ];
+            if let Some(status) = status {
+                attributes.push(attribute("http.response.status_code", json!({"intValue": status.as_u16().to_string()})));
+            }
+            let failed = error.is_some() || status.is_some_and(|status| status.is_client_error() || status.is_server_error());
+            let message = error.map(str::to_string).or_else(|| status.map(|status| format!("HTTP status {}", status)));
+            self.finish(json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "parentSpanId": format!("{:016x}", span.parent_id),
+                "name": "POST",
+                // Client
+                "kind": 3,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.start + latency),
+                "attributes": attributes,
+                "status": status_of(failed, message),
+            }));
+        }
+
+        /// Close a task's span once it has succeeded or failed for good
+        pub(crate) fn end_task(&self, task_id: usize, error: Option<String>) {
+            let task = match self.tasks.lock().unwrap().remove(&task_id) {
+                Some(task) => task,
+                // Never sent, e.g. a member of a batch other than its first
+                None => return,
+            };
+            let attributes = vec![
+                attribute("api_processor.task_id", json!({"intValue": task_id.to_string()})),
+                attribute("api_processor.attempts", json!({"intValue": task.attempts.to_string()})),
+            ];
+            self.finish(json!({
+                "traceId": format!("{:032x}", task.trace_id),
+                "spanId": format!("{:016x}", task.span_id),
+                "name": "task",
+                // Internal
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(task.start),
+                "endTimeUnixNano": unix_nanos(SystemTime::now()),
+                "attributes": attributes,
+                "status": status_of(error.is_some(), error),
+            }));
+        }
+
+        fn finish(&self, span: Value) {
+            let mut finished = self.finished.lock().unwrap();
+            if finished.len() >= MAX_BUFFERED_SPANS {
+                if !self.overflow_logged.swap(true, Ordering::SeqCst) {
+                    warn!("More than {} spans are waiting for {}, dropping new ones", MAX_BUFFERED_SPANS, self.endpoint);
+                }
+                return;
+            }
+            finished.push(span);
+        }
+
+        /// Post the finished spans to the collector
+        ///
+        /// Spans the collector did not take are dropped, so a broken collector cannot hold up the run.
+        pub(crate) async fn export(&self) {
+            let spans = std::mem::take(&mut *self.finished.lock().unwrap());
+            if spans.is_empty() {
+                return;
+            }
+            let num_spans = spans.len();
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": {"attributes": [attribute("service.name", json!({"stringValue": self.service_name}))]},
+                    "scopeSpans": [{
+                        "scope": {"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")},
+                        "spans": spans,
+                    }],
+                }],
+            });
+            let request = Request::post(&self.endpoint)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .expect("the collector URL was validated with the config");
+            match self.http.request(request).await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Exported {} spans to {}", num_spans, self.endpoint);
+                    self.overflow_logged.store(false, Ordering::SeqCst);
+                }
+                Ok(response) => warn!("Failed to export {} spans to {}: HTTP status {}", num_spans, self.endpoint, response.status()),
+                Err(e) => warn!("Failed to export {} spans to {}: {}", num_spans, self.endpoint, e),
+            }
+        }
+    }
+
+    /// Export spans at `EXPORT_INTERVAL` until the tracer is dropped
+    async fn export_loop(tracer: Weak<Tracer>) {
+        let mut ticks = tokio::time::interval(EXPORT_INTERVAL);
+        loop {
+            ticks.tick().await;
+            match tracer.upgrade() {
+                Some(tracer) => tracer.export().await,
+                None => return,
+            }
+        }
+    }
+
+    /// A random span id; zero is not a valid one
+    fn span_id() -> u64 {
+        rand::random::<u64>().max(1)
+    }
+
+    fn attribute(key: &str, value: Value) -> Value {
+        json!({"key": key, "value": value})
+    }
+
+    /// An OTLP span status: unset for success, error with a message otherwise
+    fn status_of(failed: bool, message: Option<String>) -> Value {
+        if failed {
+            json!({"code": 2, "message": message.unwrap_or_default()})
+        } else {
+            json!({"code": 0})
+        }
+    }
+
+    fn unix_nanos(time: SystemTime) -> String {
+        time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+    }
+}
+
+/// Stand-ins that cannot be created, so the calls compile away without the feature
+#[cfg(not(feature = "otel"))]
+mod disabled {
+    use hyper::StatusCode;
+    use std::time::Duration;
+
+    pub(crate) enum AttemptSpan {}
+
+    impl AttemptSpan {
+        pub(crate) fn traceparent(&self) -> String {
+            match *self {}
+        }
+    }
+
+    pub(crate) enum Tracer {}
+
+    impl Tracer {
+        pub(crate) fn start(self: &std::sync::Arc<Self>) {
+            match **self {}
+        }
+
+        pub(crate) fn start_attempt(&self, _task_id: usize, _endpoint: &str) -> AttemptSpan {
+            match *self {}
+        }
+
+        pub(crate) fn end_attempt(&self, span: AttemptSpan, _status: Option<StatusCode>, _error: Option<&str>, _latency: Duration) {
+            match span {}
+        }
+
+        pub(crate) fn end_task(&self, _task_id: usize, _error: Option<String>) {
+            match *self {}
+        }
+
+        pub(crate) async fn export(&self) {
+            match *self {}
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use crate::checkpoint::Checkpoint;
+use crate::config::Config;
+use crate::error::ClientError;
+use crate::json_path::JsonPath;
+use crate::request::ProcessedResult;
+use log::warn;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::time::Interval;
+use tokio_stream::{Stream, StreamExt};
+
+/// Format of the save file
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One JSON record per line
+    #[default]
+    Jsonl,
+    /// One CSV row per result with these columns, after a header row
+    Csv(Vec<CsvColumn>),
+}
+
+/// A column of CSV output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvColumn {
+    /// The task id
+    TaskId,
+    /// The input field of the request, `Config::input_field`
+    Input,
+    /// A value from the saved record: the response plus `endpoint`, `latency_ms` and tags
+    Field(JsonPath),
+}
+
+impl FromStr for CsvColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "task_id" => Ok(CsvColumn::TaskId),
+            "input" => Ok(CsvColumn::Input),
+            _ => Ok(CsvColumn::Field(s.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for CsvColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvColumn::TaskId => f.write_str("task_id"),
+            CsvColumn::Input => f.write_str("input"),
+            CsvColumn::Field(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+/// Append data to a JSONL file
+pub fn append_to_jsonl(data: Value, filename: &str) -> std::io::Result<()> {
+    let json_string = data.to_string();
+    let mut file = std::fs::OpenOptions::new().append(true).create(true).open(filename)?;
+    writeln!(file, "{}", json_string)?;
+    Ok(())
+}
+
+/// Append one row to a CSV file, preceded by the header if the file is empty
+pub fn append_to_csv(row: &[String], header: &[String], filename: &str) -> std::io::Result<()> {
+    let file = std::fs::OpenOptions::new().append(true).create(true).open(filename)?;
+    let is_empty = file.metadata()?.len() == 0;
+    let mut writer = csv::Writer::from_writer(file);
+    if is_empty {
+        writer.write_record(header)?;
+    }
+    writer.write_record(row)?;
+    writer.flush()
+}
+
+/// Empty the save, error and dead-letter files left by an earlier run, if they exist
+pub(crate) fn truncate_outputs(config: &Config) -> Result<(), ClientError> {
+    let save_filepath = Some(&config.save_filepath).filter(|_| !config.no_save);
+    for filename in save_filepath.into_iter().chain([&config.error_filepath]).chain(&config.dead_letter_filepath) {
+        match std::fs::OpenOptions::new().write(true).truncate(true).open(filename) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(write_error(filename, e)),
+        }
+    }
+    Ok(())
+}
+
+/// Render a CSV cell; strings are written as-is and other values as JSON
+fn csv_cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// The CSV row saved for a successful request
+fn csv_row(result: ProcessedResult, columns: &[CsvColumn], config: &Config) -> Vec<String> {
+    let task_id = result.task_id;
+    let input = result.input.get(&config.input_field).cloned();
+    let record = success_record(result, config);
+    columns
+        .iter()
+        .map(|column| match column {
+            CsvColumn::TaskId => task_id.to_string(),
+            CsvColumn::Input => csv_cell(input.as_ref()),
+            CsvColumn::Field(path) => csv_cell(path.get(&record)),
+        })
+        .collect()
+}
+
+/// What paths in `Config::output_fields` may start from
+pub(crate) const OUTPUT_FIELD_ROOTS: [&str; 9] = ["task_id", "input", "response", "metadata", "endpoint", "latency_ms", "skipped", "response_meta", "cached"];
+
+/// The line saved for a result when `Config::output_fields` picks its fields
+///
+/// Each field is saved under its path, and as `null` when the path does not resolve.
+fn selected_record(result: ProcessedResult, fields: &[JsonPath]) -> Value {
+    let source = serde_json::json!({
+        "task_id": result.task_id,
+        "input": result.input,
+        "response": result.response,
+        "metadata": result.metadata,
+        "endpoint": result.endpoint,
+        "latency_ms": result.latency.as_millis() as u64,
+        "skipped": result.skipped,
+        "response_meta": result.response_meta,
+        "cached": result.cached,
+    });
+    let record: Map<String, Value> = fields
+        .iter()
+        .map(|path| (path.to_string(), path.get(&source).cloned().unwrap_or(Value::Null)))
+        .collect();
+    Value::Object(record)
+}
+
+/// The line saved for a successful request: the response plus its task id and run metadata
+///
+/// Values pulled out with `Config::extract` are saved as `{"task_id": ..., "result": ...}`,
+/// and responses that are not JSON objects, including empty ones, as
+/// `{"task_id": ..., "response": ...}`.
+fn success_record(result: ProcessedResult, config: &Config) -> Value {
+    // A skipped line was never sent, so it has no response, endpoint or latency
+    if result.skipped {
+        let mut record = Map::new();
+        record.insert("task_id".to_string(), Value::from(result.task_id));
+        record.insert("skipped".to_string(), Value::Bool(true));
+        record.insert("input".to_string(), result.input);
+        for (key, value) in &config.tags {
+            record.insert(key.clone(), Value::String(value.clone()));
+        }
+        return Value::Object(record);
+    }
+    let mut record = match result.response {
+        response if config.extract.is_some() => {
+            let mut object = Map::new();
+            object.insert("result".to_string(), response);
+            object
+        }
+        Value::Object(object) => object,
+        response => {
+            let mut object = Map::new();
+            object.insert("response".to_string(), response);
+            object
+        }
+    };
+    if let Some(metadata) = result.metadata {
+        record.insert("metadata".to_string(), Value::Object(metadata.into_iter().collect()));
+    }
+    // Lets records be matched to input lines, and result files be merged in order
+    record.insert("task_id".to_string(), Value::from(result.task_id));
+    record.insert("endpoint".to_string(), Value::String(result.endpoint));
+    record.insert("latency_ms".to_string(), Value::from(result.latency.as_millis() as u64));
+    if let Some(response_meta) = result.response_meta {
+        record.insert("response_meta".to_string(), response_meta);
+    }
+    if let Some(cached) = result.cached.filter(|_| config.include_cache_status) {
+        record.insert("cached".to_string(), Value::Bool(cached));
+    }
+    for (key, value) in &config.tags {
+        record.insert(key.clone(), Value::String(value.clone()));
+    }
+    Value::Object(record)
+}
+
+/// One result per object of an NDJSON response, each with the task id of the request
+fn split_objects(mut result: ProcessedResult, config: &Config) -> Vec<ProcessedResult> {
+    match std::mem::take(&mut result.response) {
+        Value::Array(objects) if config.ndjson_response && !result.skipped => objects
+            .into_iter()
+            .map(|response| ProcessedResult { response, ..result.clone() })
+            .collect(),
+        response => vec![ProcessedResult { response, ..result }],
+    }
+}
+
+/// Name the file in a failed write, so a full disk or a permission problem is easy to place
+pub(crate) fn write_error(filename: &str, e: std::io::Error) -> ClientError {
+    ClientError::Io(std::io::Error::new(e.kind(), format!("failed to write {}: {}", filename, e)))
+}
+
+/// Longest part of a file name taken from an `output_by` value
+const MAX_PARTITION_NAME_LEN: usize = 100;
+
+/// The file a result is saved to: the save file, or with `output_by` the one for its input's value
+fn save_path(input: &Value, config: &Config) -> String {
+    let value = config.output_by.as_ref().and_then(|path| path.get(input)).filter(|value| !value.is_null());
+    let value = match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+        None => return config.save_filepath.clone(),
+    };
+    // Only characters safe in file names on every platform, so a value cannot leave the directory
+    let mut name: String = value
+        .chars()
+        .take(MAX_PARTITION_NAME_LEN)
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect();
+    if name.is_empty() {
+        name.push('_');
+    }
+    let save_path = Path::new(&config.save_filepath);
+    let extension = save_path.extension().map(|extension| extension.to_string_lossy()).unwrap_or_else(|| "jsonl".into());
+    format!("{}_{}.{}", save_path.with_extension("").display(), name, extension)
+}
+
+/// An output file kept open between records
+struct OutputFile {
+    writer: BufWriter<File>,
+    /// Whether the file held nothing when opened and nothing was written since, so a CSV header is due
+    empty: bool,
+    /// When the file was last written to, in writes since the run started
+    last_write: u64,
+}
+
+/// The files outcomes are written to, buffered until the next flush
+struct Outputs {
+    files: HashMap<String, OutputFile>,
+    writes: u64,
+    max_open_files: usize,
+    /// Records written since the last flush
+    buffered: usize,
+    flush_every: usize,
+    fsync: bool,
+    /// Save files of `output_by` values that were emptied, or kept with `append`
+    partitions: HashSet<String>,
+    append: bool,
+    /// Told which tasks' outcomes each flush wrote, with `Config::checkpoint_filepath`
+    checkpoint: Option<Arc<Checkpoint>>,
+    /// Tasks whose outcomes were written since the last flush, kept for the checkpoint
+    unflushed: Vec<usize>,
+}
+
+impl Outputs {
+    fn new(config: &Config, partitions: HashSet<String>, checkpoint: Option<Arc<Checkpoint>>) -> Self {
+        Outputs {
+            files: HashMap::new(),
+            writes: 0,
+            max_open_files: config.max_open_files,
+            buffered: 0,
+            flush_every: if config.flush_interval.is_zero() { 1 } else { config.flush_every },
+            fsync: config.fsync,
+            partitions,
+            append: config.append,
+            checkpoint,
+            unflushed: Vec::new(),
+        }
+    }
+
+    /// Empty the save file of an `output_by` value the first time it is used, like the save file before a run
+    fn start_partition(&mut self, filename: &str) -> Result<(), ClientError> {
+        if !self.partitions.insert(filename.to_string()) || self.append {
+            return Ok(());
+        }
+        match OpenOptions::new().write(true).truncate(true).open(filename) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(write_error(filename, e)),
+        }
+    }
+
+    fn file(&mut self, filename: &str) -> std::io::Result<&mut OutputFile> {
+        if !self.files.contains_key(filename) {
+            // Make room by closing the file that has gone longest without a write
+            if self.files.len() >= self.max_open_files {
+                let oldest = self.files.iter().min_by_key(|(_, file)| file.last_write).map(|(name, _)| name.clone());
+                if let Some(mut file) = oldest.and_then(|oldest| self.files.remove(&oldest)) {
+                    file.writer.flush()?;
+                    if self.fsync {
+                        file.writer.get_ref().sync_data()?;
+                    }
+                }
+            }
+            let file = OpenOptions::new().append(true).create(true).open(filename)?;
+            let empty = file.metadata()?.len() == 0;
+            let writer = BufWriter::new(file);
+            self.files.insert(filename.to_string(), OutputFile { writer, empty, last_write: 0 });
+        }
+        self.writes += 1;
+        let file = self.files.get_mut(filename).expect("file was just opened");
+        file.last_write = self.writes;
+        Ok(file)
+    }
+
+    fn write_line(&mut self, line: &str, filename: &str) -> Result<(), ClientError> {
+        let write = |outputs: &mut Self| -> std::io::Result<()> {
+            let file = outputs.file(filename)?;
+            writeln!(file.writer, "{}", line)?;
+            file.empty = false;
+            Ok(())
+        };
+        write(self).map_err(|e| write_error(filename, e))
+    }
+
+    fn write_csv(&mut self, row: &[String], header: &[String], filename: &str) -> Result<(), ClientError> {
+        let write = |outputs: &mut Self| -> std::io::Result<()> {
+            let file = outputs.file(filename)?;
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            if file.empty {
+                writer.write_record(header)?;
+            }
+            writer.write_record(row)?;
+            file.writer.write_all(&writer.into_inner().map_err(|e| e.into_error())?)?;
+            file.empty = false;
+            Ok(())
+        };
+        write(self).map_err(|e| write_error(filename, e))
+    }
+
+    /// Write the records of one task's outcome
+    fn write(&mut self, task_id: Option<usize>, rendered: Rendered, config: &Config) -> Result<(), ClientError> {
+        match rendered {
+            Rendered::Nothing => {}
+            Rendered::Saved { path, records } => {
+                if path != config.save_filepath {
+                    self.start_partition(&path)?;
+                }
+                let header: Vec<String> = match &config.output_format {
+                    OutputFormat::Csv(columns) => columns.iter().map(ToString::to_string).collect(),
+                    OutputFormat::Jsonl => Vec::new(),
+                };
+                for record in records {
+                    match record {
+                        Record::Line(line) => self.write_line(&line, &path)?,
+                        Record::Row(row) => self.write_csv(&row, &header, &path)?,
+                    }
+                }
+            }
+            Rendered::Failed { error, input } => {
+                self.write_line(&error, &config.error_filepath)?;
+                if let (Some(path), Some(input)) = (&config.dead_letter_filepath, input) {
+                    self.write_line(&input, path)?;
+                }
+            }
+        }
+        if let (Some(task_id), Some(_)) = (task_id, &self.checkpoint) {
+            self.unflushed.push(task_id);
+        }
+        self.record_written()
+    }
+
+    /// Count a written record, flushing once enough are buffered or the checkpoint is due
+    fn record_written(&mut self) -> Result<(), ClientError> {
+        self.buffered += 1;
+        let checkpoint_due = self.checkpoint.as_ref().is_some_and(|checkpoint| checkpoint.due(self.unflushed.len()));
+        if self.buffered >= self.flush_every || checkpoint_due {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Hand buffered records to the OS, and with `fsync` wait until they are on disk
+    fn flush(&mut self) -> Result<(), ClientError> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        for (filename, file) in &mut self.files {
+            file.writer.flush().map_err(|e| write_error(filename, e))?;
+            if self.fsync {
+                file.writer.get_ref().sync_data().map_err(|e| write_error(filename, e))?;
+            }
+        }
+        self.buffered = 0;
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.flushed(self.unflushed.drain(..));
+        }
+        Ok(())
+    }
+}
+
+/// A record rendered for its file
+enum Record {
+    /// A JSONL line, without its newline
+    Line(String),
+    /// A CSV row, preceded by the header in an empty file
+    Row(Vec<String>),
+}
+
+/// What an outcome is saved as, rendered apart from the files so that writers can do it side by side
+enum Rendered {
+    /// A success with `Config::no_save`
+    Nothing,
+    /// Records for the save file, or the `output_by` file of the input
+    Saved { path: String, records: Vec<Record> },
+    /// A line for the error file, and the input line for the dead-letter file
+    Failed { error: String, input: Option<String> },
+}
+
+fn render(outcome: Result<ProcessedResult, ClientError>, config: &Config) -> Result<Rendered, ClientError> {
+    match outcome {
+        // Already judged and counted; only failures are kept
+        Ok(_) if config.no_save => Ok(Rendered::Nothing),
+        Ok(result) => {
+            let path = save_path(&result.input, config);
+            let records = split_objects(result, config)
+                .into_iter()
+                .map(|result| match &config.output_format {
+                    OutputFormat::Jsonl => {
+                        let record = match &config.output_fields {
+                            Some(fields) => selected_record(result, fields),
+                            None => success_record(result, config),
+                        };
+                        Record::Line(record.to_string())
+                    }
+                    OutputFormat::Csv(columns) => Record::Row(csv_row(result, columns, config)),
+                })
+                .collect();
+            Ok(Rendered::Saved { path, records })
+        }
+        Err(ClientError::Request { input, error, .. }) => {
+            // Written to the error file
+            let error_data = serde_json::json!({
+                "input": input.get(&config.input_field),
+                "error": error,
+            });
+            // The whole line, so it can be sent again as is; overlong lines were never read into memory
+            let input = Some(input).filter(|input| config.dead_letter_filepath.is_some() && !input.is_null());
+            Ok(Rendered::Failed {
+                error: error_data.to_string(),
+                input: input.map(|input| input.to_string()),
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The task an outcome belongs to; other errors belong to none
+fn task_id(outcome: &Result<ProcessedResult, ClientError>) -> Option<usize> {
+    match outcome {
+        Ok(ProcessedResult { task_id, .. }) | Err(ClientError::Request { task_id, .. }) => Some(*task_id),
+        Err(_) => None,
+    }
+}
+
+/// Wait for the next flush, or forever when every record is flushed as it is written
+async fn next_flush(ticks: &mut Option<Interval>) {
+    match ticks {
+        Some(ticks) => {
+            ticks.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Append each outcome to the save file or the error file
+///
+/// Only the first outcome for a task id is written; later ones, e.g. a slow
+/// response arriving after its retry already finished, are logged and dropped.
+/// Records are buffered and flushed every `Config::flush_interval`, after
+/// `Config::flush_every` records and when the stream ends. Stops at the first
+/// write that fails; dropping the stream then stops the run.
+pub async fn write_results<S>(results: S, config: &Config) -> Result<(), ClientError>
+where
+    S: Stream<Item = Result<ProcessedResult, ClientError>>,
+{
+    write_partitioned_results(results, config, &mut HashSet::new(), None).await
+}
+
+/// `write_results`, leaving alone the `output_by` save files in `partitions` and adding those it empties
+///
+/// The checkpoint, if there is one, is told about every outcome once it is flushed.
+pub(crate) async fn write_partitioned_results<S>(
+    results: S,
+    config: &Config,
+    partitions: &mut HashSet<String>,
+    checkpoint: Option<Arc<Checkpoint>>,
+) -> Result<(), ClientError>
+where
+    S: Stream<Item = Result<ProcessedResult, ClientError>>,
+{
+    let outputs = Arc::new(Mutex::new(Outputs::new(config, std::mem::take(partitions), checkpoint)));
+    let written = if config.output_writers > 1 {
+        write_in_parallel(results, config, &outputs).await
+    } else {
+        write_outcomes(results, config, &outputs, &[]).await
+    };
+    // Every writer has finished, so nothing else holds the files
+    let mut outputs = outputs.lock().unwrap();
+    // Whatever was written before a failure is still flushed
+    let flushed = outputs.flush();
+    *partitions = std::mem::take(&mut outputs.partitions);
+    written.and(flushed)
+}
+
+/// Outcomes each writer task may have waiting
+const WRITER_QUEUE_CAPACITY: usize = 256;
+
+/// Render outcomes in `Config::output_writers` tasks, which take turns writing to the files
+async fn write_in_parallel<S>(results: S, config: &Config, outputs: &Arc<Mutex<Outputs>>) -> Result<(), ClientError>
+where
+    S: Stream<Item = Result<ProcessedResult, ClientError>>,
+{
+    let shared_config = Arc::new(config.clone());
+    let mut queues = Vec::with_capacity(config.output_writers);
+    let mut writers = Vec::with_capacity(config.output_writers);
+    for _ in 0..config.output_writers {
+        let (queue, mut outcomes) = mpsc::channel(WRITER_QUEUE_CAPACITY);
+        let (config, outputs) = (Arc::clone(&shared_config), Arc::clone(outputs));
+        writers.push(tokio::spawn(async move {
+            while let Some(outcome) = outcomes.recv().await {
+                let task_id = task_id(&outcome);
+                let rendered = render(outcome, &config)?;
+                outputs.lock().unwrap().write(task_id, rendered, &config)?;
+            }
+            Ok::<_, ClientError>(())
+        }));
+        queues.push(queue);
+    }
+    let mut written = write_outcomes(results, config, outputs, &queues).await;
+    // Closing the queues lets the writers finish what they hold
+    drop(queues);
+    for writer in writers {
+        written = written.and(writer.await.expect("output writer panicked"));
+    }
+    written
+}
+
+/// Write outcomes as they come, or hand them round-robin to the writer tasks' queues if there are any
+async fn write_outcomes<S>(
+    results: S,
+    config: &Config,
+    outputs: &Mutex<Outputs>,
+    queues: &[mpsc::Sender<Result<ProcessedResult, ClientError>>],
+) -> Result<(), ClientError>
+where
+    S: Stream<Item = Result<ProcessedResult, ClientError>>,
+{
+    let mut completed = HashSet::new();
+    let mut ticks = Some(config.flush_interval).filter(|interval| !interval.is_zero()).map(tokio::time::interval);
+    pin_utils::pin_mut!(results);
+    loop {
+        let outcome = tokio::select! {
+            outcome = results.next() => match outcome {
+                Some(outcome) => outcome,
+                None => break,
+            },
+            _ = next_flush(&mut ticks) => {
+                outputs.lock().unwrap().flush()?;
+                continue;
+            }
+        };
+        let task_id = task_id(&outcome);
+        if let Some(task_id) = task_id {
+            if !completed.insert(task_id) {
+                warn!("Dropping another outcome for task {}, which was already written", task_id);
+                continue;
+            }
+        }
+        if queues.is_empty() {
+            let rendered = render(outcome, config)?;
+            outputs.lock().unwrap().write(task_id, rendered, config)?;
+        } else if queues[completed.len() % queues.len()].send(outcome).await.is_err() {
+            // The writer stopped at a failed write, which it reports
+            break;
+        }
+    }
+    Ok(())
+}
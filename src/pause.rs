@@ -0,0 +1,56 @@
+use log::info;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Switch that pauses and resumes dispatching requests
+///
+/// While paused, queued requests stay in the queue and in-flight requests
+/// still finish. Clones control the same client.
+#[derive(Debug, Clone)]
+pub struct PauseHandle {
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl Default for PauseHandle {
+    fn default() -> Self {
+        PauseHandle {
+            paused: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl PauseHandle {
+    /// Stop dispatching new requests
+    pub fn pause(&self) {
+        if !self.paused.send_replace(true) {
+            info!("Paused sending requests");
+        }
+    }
+
+    /// Continue dispatching requests
+    pub fn resume(&self) {
+        if self.paused.send_replace(false) {
+            info!("Resumed sending requests");
+        }
+    }
+
+    /// Pause if running, resume if paused
+    pub fn toggle(&self) {
+        if self.is_paused() {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Wait until the switch is not paused
+    pub(crate) async fn wait_while_paused(&self) {
+        let mut paused = self.paused.subscribe();
+        // The sender lives in `self`, so this cannot fail
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+}
@@ -0,0 +1,108 @@
+use crate::connector::HttpClients;
+use crate::endpoint::Endpoint;
+use crate::url_template::UrlTemplate;
+use hyper::{Body, Method, Request, StatusCode, Uri};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The request sent to each endpoint before a run
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub method: Method,
+    /// Path to probe instead of the endpoint's own path, e.g. `/v1/models`
+    pub path: Option<String>,
+}
+
+impl Default for Probe {
+    fn default() -> Self {
+        Probe {
+            method: Method::HEAD,
+            path: None,
+        }
+    }
+}
+
+/// What probing one endpoint returned
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    pub url: String,
+    /// The response status, or why no response arrived
+    pub outcome: Result<StatusCode, String>,
+}
+
+impl ProbeResult {
+    /// Whether the endpoint answered and accepted the key
+    ///
+    /// Auth failures, missing paths and server errors count as unhealthy;
+    /// anything else, such as 405 for a HEAD to a POST-only path, shows the
+    /// endpoint is up.
+    pub fn is_healthy(&self) -> bool {
+        match self.outcome {
+            Ok(status) => {
+                !(status == StatusCode::UNAUTHORIZED
+                    || status == StatusCode::FORBIDDEN
+                    || status == StatusCode::NOT_FOUND
+                    || status.is_server_error())
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// What sending the canary line to one endpoint returned
+#[derive(Debug, Clone)]
+pub struct CanaryResult {
+    pub url: String,
+    /// Time from sending the request to reading the whole response, or to its failure
+    pub latency: Duration,
+    /// Why the request failed, judged like any request of a run
+    pub outcome: Result<(), String>,
+}
+
+/// Probe every endpoint concurrently, returning results in endpoint order
+pub(crate) async fn probe_endpoints(clients: &Arc<HttpClients>, endpoints: &[Endpoint], probe: &Probe) -> Vec<ProbeResult> {
+    let handles: Vec<_> = endpoints
+        .iter()
+        .map(|endpoint| {
+            let clients = Arc::clone(clients);
+            let endpoint = endpoint.clone();
+            let probe = probe.clone();
+            tokio::spawn(async move {
+                let outcome = probe_endpoint(&clients, &endpoint, &probe).await;
+                ProbeResult { url: endpoint.url, outcome }
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(handle.await.expect("probe task panicked"));
+    }
+    results
+}
+
+async fn probe_endpoint(clients: &HttpClients, endpoint: &Endpoint, probe: &Probe) -> Result<StatusCode, String> {
+    let mut uri: Uri = match (UrlTemplate::new(&endpoint.request_url())?.fixed(), &probe.path) {
+        (Some(uri), _) => uri.clone(),
+        // Placeholders are only allowed after the host, which is all the probe keeps
+        (None, Some(_)) => endpoint.url.split('{').next().unwrap_or_default().parse().map_err(|e| format!("invalid URL: {}", e))?,
+        (None, None) => return Err("the URL has placeholders, so a probe path is needed".to_string()),
+    };
+    if let Some(path) = &probe.path {
+        let mut parts = uri.into_parts();
+        parts.path_and_query = Some(endpoint.with_query(path).parse().map_err(|e| format!("invalid probe path: {}", e))?);
+        uri = Uri::from_parts(parts).map_err(|e| format!("invalid probe URL: {}", e))?;
+    }
+    let authorization = match &endpoint.auth_header {
+        Some(header) => (header.as_str(), endpoint.keys()[0].to_string()),
+        None => ("Authorization", format!("Bearer {}", endpoint.keys()[0])),
+    };
+    let req = Request::builder()
+        .method(probe.method.clone())
+        .uri(uri)
+        .header(authorization.0, authorization.1)
+        .body(Body::empty())
+        .map_err(|e| e.to_string())?;
+    let response = clients.for_endpoint(endpoint).request(req).await.map_err(|e| e.to_string())?;
+    Ok(response.status())
+}
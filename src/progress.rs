@@ -0,0 +1,135 @@
+use crate::tracker::StatusTracker;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Width of the bar itself, in characters
+const BAR_WIDTH: usize = 30;
+
+/// A one-line progress bar on stderr, redrawn from the `StatusTracker`
+///
+/// Log lines written through `log_writer` clear the bar first and draw it
+/// again after, so logs and the bar do not overwrite each other.
+#[derive(Debug)]
+pub struct ProgressBar {
+    /// Tasks expected in the run, if known up front
+    total: Option<usize>,
+    started: Instant,
+    /// The line currently on screen, empty once finished
+    line: Mutex<String>,
+}
+
+impl ProgressBar {
+    /// Start a bar for this many tasks, or for an unknown number
+    pub fn new(total: Option<usize>) -> Arc<Self> {
+        Arc::new(ProgressBar {
+            total,
+            started: Instant::now(),
+            line: Mutex::new(String::new()),
+        })
+    }
+
+    /// A writer for the logger that keeps the bar below the log lines
+    pub fn log_writer(self: &Arc<Self>) -> Box<dyn Write + Send> {
+        Box::new(LogWriter(Arc::clone(self)))
+    }
+
+    /// Draw the bar for the tracker's current counts
+    pub fn draw(&self, tracker: &StatusTracker) {
+        let mut line = self.line.lock().unwrap();
+        *line = render(tracker, self.total, self.started.elapsed());
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", line);
+        let _ = stderr.flush();
+    }
+
+    /// Draw the final counts and leave the bar on its own line
+    pub fn finish(&self, tracker: &StatusTracker) {
+        let mut line = self.line.lock().unwrap();
+        line.clear();
+        // Written like the bar itself, as the terminal's line, not a log record
+        let mut stderr = io::stderr().lock();
+        let _ = writeln!(stderr, "\r\x1b[2K{}", render(tracker, self.total, self.started.elapsed()));
+        let _ = stderr.flush();
+    }
+
+    /// Redraw the bar at this interval until the task is aborted
+    pub async fn run(self: Arc<Self>, status_tracker: Arc<Mutex<StatusTracker>>, period: Duration) {
+        let mut ticks = tokio::time::interval(period);
+        loop {
+            ticks.tick().await;
+            self.draw(&status_tracker.lock().unwrap());
+        }
+    }
+}
+
+/// Writes log lines above the progress bar
+struct LogWriter(Arc<ProgressBar>);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = self.0.line.lock().unwrap();
+        let mut stderr = io::stderr().lock();
+        if !line.is_empty() {
+            stderr.write_all(b"\r\x1b[2K")?;
+        }
+        stderr.write_all(buf)?;
+        if !line.is_empty() {
+            stderr.write_all(line.as_bytes())?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// The bar's text: done out of total, rate, time left and outcome counts
+fn render(tracker: &StatusTracker, total: Option<usize>, elapsed: Duration) -> String {
+    let done = tracker.num_tasks_succeeded + tracker.num_tasks_failed + tracker.num_tasks_invalid + tracker.num_tasks_skipped;
+    let rate = done as f64 / elapsed.as_secs_f64().max(0.001);
+    let counts = format!(
+        "{} ok, {} failed, {} in progress",
+        tracker.num_tasks_succeeded,
+        tracker.num_tasks_failed + tracker.num_tasks_invalid,
+        tracker.num_tasks_in_progress
+    );
+    match total {
+        Some(total) => {
+            let fraction = if total == 0 { 1.0 } else { (done as f64 / total as f64).min(1.0) };
+            let filled = (fraction * BAR_WIDTH as f64) as usize;
+            let bar = if filled < BAR_WIDTH {
+                format!("{}>{}", "=".repeat(filled), " ".repeat(BAR_WIDTH - filled - 1))
+            } else {
+                "=".repeat(BAR_WIDTH)
+            };
+            let eta = if rate > 0.0 {
+                format_duration(Duration::from_secs_f64(total.saturating_sub(done) as f64 / rate))
+            } else {
+                "--:--".to_string()
+            };
+            format!(
+                "[{}] {}/{} {:3.0}% | {:.1}/s | ETA {} | {}",
+                bar,
+                done,
+                total,
+                fraction * 100.0,
+                rate,
+                eta,
+                counts
+            )
+        }
+        None => format!("{} done | {:.1}/s | {} elapsed | {}", done, rate, format_duration(elapsed), counts),
+    }
+}
+
+/// `mm:ss`, or `h:mm:ss` from an hour on
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,263 @@
+use crate::request::APIRequest;
+use log::{debug, error};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::{Notify, Semaphore};
+
+/// A bounded queue of fresh requests handing out the highest `priority` first
+///
+/// Requests of equal priority come out in the order they were queued, so
+/// input without priorities is sent in file order. Like a bounded `mpsc`
+/// channel, sending waits while the queue is full, and the receiver sees the
+/// end of the queue once every sender is dropped and the queue is empty.
+///
+/// With a spill file, sending never waits: once the queue is full, requests
+/// go to the file instead and come back into the queue in the order they
+/// were spilled as room frees up.
+pub(crate) fn channel(capacity: usize, spill: Option<Spill>) -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            heap: BinaryHeap::new(),
+            next_seq: 0,
+            senders: 1,
+            spill,
+        }),
+        space: Semaphore::new(capacity),
+        queued: Notify::new(),
+    });
+    (Sender { shared: Arc::clone(&shared) }, Receiver { shared })
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// One permit per free place in the queue; closed along with the queue
+    space: Semaphore,
+    /// Wakes the receiver when a request is queued or the last sender goes
+    queued: Notify,
+}
+
+struct State {
+    heap: BinaryHeap<Queued>,
+    next_seq: u64,
+    senders: usize,
+    spill: Option<Spill>,
+}
+
+impl State {
+    fn push(&mut self, request: APIRequest) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(Queued {
+            priority: request.priority(),
+            seq,
+            request,
+        });
+    }
+
+    /// Move the oldest spilled request into the queue, returning whether there was one
+    fn unspill(&mut self) -> bool {
+        while let Some(spill) = self.spill.as_mut().filter(|spill| spill.len > 0) {
+            match spill.pop() {
+                Ok(request) => {
+                    self.push(request);
+                    return true;
+                }
+                // The request is lost, which only a damaged file can cause
+                Err(e) => error!("Failed to read a request back from {}: {}", spill.path.display(), e),
+            }
+        }
+        false
+    }
+}
+
+/// Overflow of the queue on disk, kept for `Config::queue_spill_dir`
+///
+/// The file holds one serialized `APIRequest` per line, like the retry state
+/// file, and is removed once the queue is dropped.
+pub(crate) struct Spill {
+    path: PathBuf,
+    writer: File,
+    reader: BufReader<File>,
+    /// Requests written to the file and not yet read back
+    len: usize,
+}
+
+impl Spill {
+    /// Make a new spill file in a directory
+    pub(crate) fn create(dir: &Path) -> io::Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let path = dir.join(format!("queue-{}-{}.jsonl", std::process::id(), nanos));
+        let writer = OpenOptions::new().append(true).create_new(true).open(&path)?;
+        let reader = BufReader::new(File::open(&path)?);
+        Ok(Spill { path, writer, reader, len: 0 })
+    }
+
+    fn push(&mut self, request: &APIRequest) -> io::Result<()> {
+        let line = serde_json::to_string(request)?;
+        writeln!(self.writer, "{}", line)?;
+        if self.len == 0 {
+            debug!("Request queue full, spilling requests to {}", self.path.display());
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> io::Result<APIRequest> {
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        self.len -= 1;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            error!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+struct Queued {
+    priority: i64,
+    /// Order of queueing, breaking ties between equal priorities
+    seq: u64,
+    request: APIRequest,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // The heap is a max-heap, so the earlier of two equal priorities must compare greater
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+pub(crate) struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    /// Queue a request, waiting for room; fails with the request once the receiver closed the queue
+    pub(crate) async fn send(&self, request: APIRequest) -> Result<(), SendError<APIRequest>> {
+        let request = match self.spill(request) {
+            Spilled::Queued => {
+                self.shared.queued.notify_one();
+                return Ok(());
+            }
+            Spilled::Closed(request) => return Err(SendError(request)),
+            Spilled::Wait(request) => request,
+        };
+        match self.shared.space.acquire().await {
+            // Given back by the receiver when it takes a request out
+            Ok(permit) => permit.forget(),
+            Err(_) => return Err(SendError(request)),
+        }
+        self.shared.state.lock().unwrap().push(request);
+        self.shared.queued.notify_one();
+        Ok(())
+    }
+
+    /// Queue a request without waiting if there is a spill file, spilling it when the queue is full
+    fn spill(&self, request: APIRequest) -> Spilled {
+        let mut state = self.shared.state.lock().unwrap();
+        let spilling = match &state.spill {
+            Some(spill) => spill.len > 0,
+            None => return Spilled::Wait(request),
+        };
+        if self.shared.space.is_closed() {
+            return Spilled::Closed(request);
+        }
+        // Requests already spilled go first, so nothing overtakes them into the queue
+        if !spilling {
+            if let Ok(permit) = self.shared.space.try_acquire() {
+                permit.forget();
+                state.push(request);
+                return Spilled::Queued;
+            }
+        }
+        let spill = state.spill.as_mut().unwrap();
+        match spill.push(&request) {
+            Ok(()) => Spilled::Queued,
+            Err(e) => {
+                error!("Failed to spill a request to {}, waiting for room instead: {}", spill.path.display(), e);
+                Spilled::Wait(request)
+            }
+        }
+    }
+}
+
+/// What became of a request offered to the spill
+enum Spilled {
+    Queued,
+    Closed(APIRequest),
+    /// There is no spill file, or it cannot be written
+    Wait(APIRequest),
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().senders -= 1;
+        self.shared.queued.notify_one();
+    }
+}
+
+pub(crate) struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    /// Take the highest-priority request, waiting for one; `None` once the queue is empty and ended
+    pub(crate) async fn recv(&mut self) -> Option<APIRequest> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(queued) = state.heap.pop() {
+                    // The place freed goes to the oldest spilled request, if there is one
+                    if !state.unspill() {
+                        self.shared.space.add_permits(1);
+                    }
+                    return Some(queued.request);
+                }
+                if state.senders == 0 || self.shared.space.is_closed() {
+                    return None;
+                }
+            }
+            // A wakeup sent before this point is kept, so none is missed
+            self.shared.queued.notified().await;
+        }
+    }
+
+    /// Stop further sends, keeping the requests already queued for `recv`
+    pub(crate) fn close(&mut self) {
+        self.shared.space.close();
+    }
+}
@@ -0,0 +1,269 @@
+use hyper::HeaderMap;
+use std::convert::TryFrom;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep_until, Instant};
+
+/// Rate the warmup starts at, as a fraction of the configured maximum
+const WARMUP_START_FRACTION: f64 = 0.1;
+
+/// How far the pacer may fall behind its schedule and still catch up
+///
+/// Timer wakeups are late by up to a millisecond; catching up keeps rates with
+/// sub-millisecond intervals accurate, while the cap stops a long stall from
+/// turning into a burst.
+const MAX_CATCH_UP: Duration = Duration::from_millis(10);
+
+/// Longest wait a rate can ask for, so a vanishingly small one cannot overflow a `Duration` or an `Instant`
+const MAX_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A wait of this many seconds, capped at `MAX_WAIT`
+fn wait_secs(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).map_or(MAX_WAIT, |wait| wait.min(MAX_WAIT))
+}
+
+/// Spaces out sends to stay under the configured rate
+///
+/// A continuous token bucket: each send reserves a slot one exact interval
+/// after the previous one, so fractional intervals do not drift.
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    max_requests_per_second: f64,
+    warmup: Option<Duration>,
+    start: Instant,
+    next_slot: Instant,
+}
+
+impl Pacer {
+    pub fn new(max_requests_per_second: f64, warmup: Option<Duration>) -> Self {
+        let start = Instant::now();
+        Pacer {
+            max_requests_per_second,
+            warmup,
+            start,
+            next_slot: start,
+        }
+    }
+
+    /// Requests per second allowed right now
+    ///
+    /// During the warmup the rate climbs linearly from a tenth of the maximum.
+    pub fn current_rate(&self) -> f64 {
+        let max_rate = self.max_requests_per_second;
+        match self.warmup {
+            Some(warmup) if self.start.elapsed() < warmup => {
+                let progress = self.start.elapsed().as_secs_f64() / warmup.as_secs_f64();
+                max_rate * (WARMUP_START_FRACTION + (1.0 - WARMUP_START_FRACTION) * progress)
+            }
+            _ => max_rate,
+        }
+    }
+
+    /// Time to wait after a send before the next one
+    pub fn interval(&self) -> Duration {
+        wait_secs(1.0 / self.current_rate())
+    }
+
+    /// Wait for the slot after a send
+    pub async fn wait(&mut self) {
+        let earliest = Instant::now().checked_sub(MAX_CATCH_UP).unwrap_or(self.start);
+        self.next_slot = self.next_slot.max(earliest) + self.interval();
+        sleep_until(self.next_slot).await;
+    }
+}
+
+/// Caps the rate at which retries are re-dispatched across a whole run
+///
+/// A token bucket holding a single token: each retry reserves the next free
+/// slot, so retries whose backoff expires at the same moment leave one by one.
+#[derive(Debug)]
+pub(crate) struct RetryLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RetryLimiter {
+    pub fn new(max_retries_per_second: f64) -> Self {
+        RetryLimiter {
+            interval: wait_secs(1.0 / max_retries_per_second),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for this retry's turn
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
+
+/// Caps the tokens sent per minute across a whole run
+///
+/// A token bucket holding a minute's budget that refills continuously. Each
+/// attempt takes its estimated cost up front, going into debt for a cost
+/// larger than what is left, and waits until the debt is paid off. Once a
+/// response reports its actual usage the difference is given back or taken.
+#[derive(Debug)]
+pub(crate) struct TokenLimiter {
+    tokens_per_minute: f64,
+    state: Mutex<TokenBucket>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// Tokens left to spend, negative while in debt
+    available: f64,
+    updated: Instant,
+}
+
+impl TokenLimiter {
+    pub fn new(max_tokens_per_minute: u64) -> Self {
+        TokenLimiter {
+            tokens_per_minute: max_tokens_per_minute as f64,
+            state: Mutex::new(TokenBucket {
+                available: max_tokens_per_minute as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `cost` tokens and wait until the budget covers them
+    pub async fn acquire(&self, cost: u64) {
+        let slot = {
+            let mut state = self.refilled();
+            state.available -= cost as f64;
+            state.updated + wait_secs((-state.available).max(0.0) * 60.0 / self.tokens_per_minute)
+        };
+        sleep_until(slot).await;
+    }
+
+    /// Settle an attempt's estimated cost against the tokens it actually used
+    pub fn reconcile(&self, estimated: u64, actual: u64) {
+        let mut state = self.refilled();
+        state.available = (state.available + estimated as f64 - actual as f64).min(self.tokens_per_minute);
+    }
+
+    /// The bucket with the tokens earned since its last update added
+    fn refilled(&self) -> MutexGuard<'_, TokenBucket> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let earned = (now - state.updated).as_secs_f64() * self.tokens_per_minute / 60.0;
+        state.available = (state.available + earned).min(self.tokens_per_minute);
+        state.updated = now;
+        state
+    }
+}
+
+/// Reset values at least this large are Unix timestamps rather than seconds from now
+const RESET_EPOCH_THRESHOLD: f64 = 1_000_000_000.0;
+
+/// Quota an endpoint reported in its `X-RateLimit-Remaining` and `X-RateLimit-Reset` headers
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Quota {
+    remaining: u64,
+    reset: Instant,
+    /// Earliest time the next request to the endpoint may leave
+    next_slot: Instant,
+}
+
+impl Quota {
+    /// Read the quota from response headers, if both are present and valid
+    ///
+    /// The reset may be given in seconds from now or as a Unix timestamp.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Quota> {
+        let number = |name: &str| {
+            let value = headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok()?;
+            Some(value).filter(|n| n.is_finite() && *n >= 0.0)
+        };
+        // More than a u32 of requests is as good as no limit, and keeps the spacing in `reserve` above zero
+        let remaining = number("x-ratelimit-remaining")?.min(f64::from(u32::MAX)) as u64;
+        let reset = number("x-ratelimit-reset")?;
+        let reset_in = if reset >= RESET_EPOCH_THRESHOLD {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+            (reset - now).max(0.0)
+        } else {
+            reset
+        };
+        let now = Instant::now();
+        Some(Quota {
+            remaining,
+            reset: now.checked_add(wait_secs(reset_in))?,
+            next_slot: now,
+        })
+    }
+
+    /// Read a `Retry-After` header, in seconds or as an HTTP date, as a quota used up until then
+    pub fn from_retry_after(headers: &HeaderMap) -> Option<Quota> {
+        let value = headers.get(hyper::header::RETRY_AFTER)?.to_str().ok()?.trim();
+        let retry_in = match value.parse::<f64>() {
+            Ok(secs) if secs.is_finite() && secs >= 0.0 => wait_secs(secs),
+            Ok(_) => return None,
+            Err(_) => {
+                let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+                (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default().min(MAX_WAIT)
+            }
+        };
+        let now = Instant::now();
+        Some(Quota {
+            remaining: 0,
+            reset: now.checked_add(retry_in)?,
+            next_slot: now,
+        })
+    }
+
+    /// When the quota resets
+    pub fn reset(&self) -> Instant {
+        self.reset
+    }
+
+    /// The requests left and the reset as a Unix timestamp, as kept in a checkpoint
+    pub fn to_json(self) -> serde_json::Value {
+        let reset_in = self.reset.saturating_duration_since(Instant::now());
+        let reset_at = (SystemTime::now() + reset_in).duration_since(UNIX_EPOCH).unwrap_or_default();
+        serde_json::json!({ "remaining": self.remaining, "reset_at": reset_at.as_secs_f64() })
+    }
+
+    /// A quota kept by `to_json`, unless it has reset since
+    pub fn from_json(saved: &serde_json::Value) -> Option<Quota> {
+        let remaining = saved["remaining"].as_u64()?;
+        let reset_at = UNIX_EPOCH + Duration::try_from_secs_f64(saved["reset_at"].as_f64()?).ok()?;
+        let reset_in = reset_at.duration_since(SystemTime::now()).ok()?;
+        let now = Instant::now();
+        Some(Quota {
+            remaining,
+            reset: now + reset_in,
+            next_slot: now,
+        })
+    }
+
+    /// Whether the quota is used up until its reset
+    pub fn exhausted(&self) -> bool {
+        self.remaining == 0 && Instant::now() < self.reset
+    }
+
+    /// Reserve the time the next request may be sent, or `None` once the quota has reset
+    ///
+    /// The remaining requests are spread evenly until the reset, so sending
+    /// slows down as the quota runs low and stops when it is used up.
+    pub fn reserve(&mut self) -> Option<Instant> {
+        let now = Instant::now();
+        if now >= self.reset {
+            return None;
+        }
+        let slot = if self.remaining == 0 {
+            self.reset
+        } else {
+            let interval = (self.reset - now) / u32::try_from(self.remaining).unwrap_or(u32::MAX);
+            let slot = self.next_slot.max(now);
+            self.next_slot = slot + interval;
+            self.remaining -= 1;
+            slot
+        };
+        Some(slot)
+    }
+}
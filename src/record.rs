@@ -0,0 +1,175 @@
+use crate::connector::HttpClient;
+use crate::redirect::SendError;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use log::{debug, error};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Responses saved to a directory by `Config::record_dir`, or answered from it with `Config::replay_dir`
+///
+/// Each response is kept under a key made of a hash of the request's method,
+/// path, query and body, plus how many times the same request was sent
+/// before, so a retry finds the response its own attempt got. The host is
+/// left out, so a replay does not depend on which endpoint a request went to.
+/// A response is two files: `<key>.json` with its status and headers, and
+/// `<key>.body` with the body as received.
+#[derive(Debug)]
+pub(crate) struct Recordings {
+    dir: PathBuf,
+    replay: bool,
+    /// Responses larger than this are handed on without being recorded, from `Config::max_response_bytes`
+    max_body_bytes: Option<usize>,
+    /// Times each request hash was sent so far
+    sent: Mutex<HashMap<u64, usize>>,
+}
+
+impl Recordings {
+    /// Record responses into a directory, creating it if needed
+    pub(crate) fn record(dir: &str, max_body_bytes: Option<usize>) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Recordings {
+            dir: PathBuf::from(dir),
+            replay: false,
+            max_body_bytes,
+            sent: Mutex::default(),
+        })
+    }
+
+    /// Answer requests from the responses recorded in a directory
+    pub(crate) fn replay(dir: &str) -> io::Result<Self> {
+        if !std::path::Path::new(dir).is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("replay directory {} does not exist", dir)));
+        }
+        Ok(Recordings {
+            dir: PathBuf::from(dir),
+            replay: true,
+            max_body_bytes: None,
+            sent: Mutex::default(),
+        })
+    }
+
+    pub(crate) fn is_replay(&self) -> bool {
+        self.replay
+    }
+
+    /// The key of a request, counting it as sent once more
+    fn key(&self, parts: &hyper::http::request::Parts, body: &[u8]) -> String {
+        let path = parts.uri.path_and_query().map(|path| path.as_str()).unwrap_or("/");
+        let hash = fnv1a([parts.method.as_str().as_bytes(), b"\0", path.as_bytes(), b"\0", body]);
+        let mut sent = self.sent.lock().unwrap();
+        let times = sent.entry(hash).or_insert(0);
+        *times += 1;
+        format!("{:016x}-{}", hash, *times)
+    }
+
+    /// Save a response under a key, logging rather than failing the request if that is not possible
+    fn save(&self, key: &str, parts: &hyper::http::response::Parts, body: &[u8]) {
+        if let Err(e) = save_response(&self.dir, key, parts.status, &parts.headers, body) {
+            error!("Failed to record response {} in {}: {}", key, self.dir.display(), e);
+        }
+    }
+
+    /// The response recorded under a key
+    fn load(&self, key: &str) -> Result<Response<Body>, String> {
+        load_response(&self.dir, key)
+    }
+}
+
+/// Save a response as `<key>.body` and then `<key>.json`, so a response whose `.json` exists is whole
+pub(crate) fn save_response(dir: &Path, key: &str, status: StatusCode, headers: &HeaderMap, body: &[u8]) -> io::Result<()> {
+    let headers: Vec<Value> = headers
+        .iter()
+        .filter_map(|(name, value)| Some(json!([name.as_str(), value.to_str().ok()?])))
+        .collect();
+    let head = json!({ "status": status.as_u16(), "headers": headers });
+    std::fs::write(dir.join(format!("{}.body", key)), body)?;
+    std::fs::write(dir.join(format!("{}.json", key)), head.to_string())
+}
+
+/// The response saved under a key by `save_response`
+pub(crate) fn load_response(dir: &Path, key: &str) -> Result<Response<Body>, String> {
+    let head = std::fs::read_to_string(dir.join(format!("{}.json", key)))
+        .map_err(|e| format!("no response recorded for request {} in {}: {}", key, dir.display(), e))?;
+    let body = std::fs::read(dir.join(format!("{}.body", key)))
+        .map_err(|e| format!("no body recorded for request {} in {}: {}", key, dir.display(), e))?;
+    let head: Value = serde_json::from_str(&head).map_err(|e| format!("invalid recording {}: {}", key, e))?;
+    let status = head["status"]
+        .as_u64()
+        .and_then(|status| StatusCode::from_u16(status as u16).ok())
+        .ok_or_else(|| format!("invalid recording {}: no status", key))?;
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    for header in head["headers"].as_array().into_iter().flatten() {
+        if let (Some(Ok(name)), Some(Ok(value))) = (
+            header[0].as_str().map(|name| HeaderName::from_bytes(name.as_bytes())),
+            header[1].as_str().map(HeaderValue::from_str),
+        ) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    Ok(response)
+}
+
+/// Where an endpoint's requests go: the network, or recorded responses
+pub(crate) enum Backend<'a> {
+    /// Send over the network
+    Network(&'a HttpClient),
+    /// Send over the network and save every response
+    Record(&'a HttpClient, &'a Recordings),
+    /// Answer from saved responses, without any network access
+    Replay(&'a Recordings),
+}
+
+impl Backend<'_> {
+    pub(crate) async fn request(&self, req: Request<Body>) -> Result<Response<Body>, SendError> {
+        let (client, recordings) = match self {
+            Backend::Network(client) => return client.request(req).await.map_err(SendError::Http),
+            Backend::Record(client, recordings) => (Some(client), recordings),
+            Backend::Replay(recordings) => (None, recordings),
+        };
+        let (parts, body) = req.into_parts();
+        let body = hyper::body::to_bytes(body).await.map_err(SendError::Http)?;
+        let key = recordings.key(&parts, &body);
+        match client {
+            Some(client) => {
+                let response = client.request(Request::from_parts(parts, Body::from(body))).await.map_err(SendError::Http)?;
+                // Read whole, so the body can be both saved and handed on, but
+                // only up to the size limit: a larger body is handed on unsaved,
+                // cut just past the limit, for reading it to refuse
+                let (parts, mut body) = response.into_parts();
+                let max_bytes = recordings.max_body_bytes.unwrap_or(usize::MAX);
+                let mut received = Vec::new();
+                while let Some(chunk) = body.data().await {
+                    received.extend_from_slice(&chunk.map_err(SendError::Http)?);
+                    if received.len() > max_bytes {
+                        debug!("Not recording response {}, larger than {} bytes", key, max_bytes);
+                        return Ok(Response::from_parts(parts, Body::from(received)));
+                    }
+                }
+                let body = hyper::body::Bytes::from(received);
+                recordings.save(&key, &parts, &body);
+                debug!("Recorded response {} with HTTP status {}", key, parts.status);
+                Ok(Response::from_parts(parts, Body::from(body)))
+            }
+            None => {
+                debug!("Replaying response {} for {} {}", key, parts.method, parts.uri);
+                recordings.load(&key).map_err(SendError::Replay)
+            }
+        }
+    }
+}
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher is the same in every build
+pub(crate) fn fnv1a<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in parts.into_iter().flatten() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
@@ -0,0 +1,121 @@
+use crate::record::Backend;
+use hyper::header::{AUTHORIZATION, LOCATION};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use log::debug;
+use std::fmt;
+use std::time::Duration;
+
+/// Reasons sending a request and following its redirects can fail
+#[derive(Debug)]
+pub enum SendError {
+    /// The request itself failed
+    Http(hyper::Error),
+    /// A redirect looped, went past the limit or could not be followed
+    Redirect(String),
+    /// No response arrived within `Config::ttfb_timeout`
+    Timeout(Duration),
+    /// No response was recorded for the request in `Config::replay_dir`
+    Replay(String),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Http(e) => write!(f, "{}", e),
+            SendError::Redirect(e) | SendError::Replay(e) => write!(f, "{}", e),
+            SendError::Timeout(timeout) => write!(f, "no response within {:.1} sec", timeout.as_secs_f64()),
+        }
+    }
+}
+
+/// Send a request, following up to `max_redirects` redirects to their `Location`
+///
+/// `build` makes the request for each hop from its URI, its method and whether
+/// it carries the body. 307 and 308 resend the same request; 301, 302 and 303
+/// switch to a `GET` without a body, as browsers and curl do. Credentials are
+/// not sent to another host, and `https` is never redirected to `http`.
+pub(crate) async fn send_following_redirects<F>(
+    backend: Backend<'_>,
+    req: Request<Body>,
+    build: F,
+    max_redirects: usize,
+) -> Result<Response<Body>, SendError>
+where
+    F: Fn(Uri, Method, bool) -> Request<Body>,
+{
+    let origin = req.uri().clone();
+    let mut uri = origin.clone();
+    let mut method = req.method().clone();
+    let mut with_body = true;
+    let mut visited = vec![(method.clone(), uri.clone())];
+    let mut response = backend.request(req).await?;
+
+    while max_redirects > 0 && is_followed(response.status()) {
+        let location = match response.headers().get(LOCATION).and_then(|location| location.to_str().ok()) {
+            Some(location) => location,
+            // Nothing to follow, so the response is judged like any other
+            None => break,
+        };
+        if visited.len() > max_redirects {
+            return Err(SendError::Redirect(format!("more than {} redirects from {}", max_redirects, origin)));
+        }
+        let next = resolve(&uri, location).map_err(SendError::Redirect)?;
+        if uri.scheme_str() == Some("https") && next.scheme_str() == Some("http") {
+            return Err(SendError::Redirect(format!("refusing redirect from {} to insecure {}", uri, next)));
+        }
+        if response.status() != StatusCode::TEMPORARY_REDIRECT && response.status() != StatusCode::PERMANENT_REDIRECT {
+            method = Method::GET;
+            with_body = false;
+        }
+        if visited.contains(&(method.clone(), next.clone())) {
+            return Err(SendError::Redirect(format!("redirect loop at {}", next)));
+        }
+        debug!("Following HTTP status {} from {} to {}", response.status(), uri, next);
+        visited.push((method.clone(), next.clone()));
+        uri = next;
+
+        let mut req = build(uri.clone(), method.clone(), with_body);
+        if uri.authority() != origin.authority() {
+            req.headers_mut().remove(AUTHORIZATION);
+            req.headers_mut().remove("x-amz-security-token");
+        }
+        response = backend.request(req).await?;
+    }
+    Ok(response)
+}
+
+/// Redirect statuses with a `Location` to follow
+fn is_followed(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+/// Resolve a `Location` header against the URI it redirects from
+fn resolve(base: &Uri, location: &str) -> Result<Uri, String> {
+    let location = location.trim().split('#').next().unwrap_or_default();
+    let scheme = base.scheme_str().unwrap_or("http");
+    let authority = base.authority().map(|authority| authority.as_str()).unwrap_or_default();
+    let target = if location.contains("://") {
+        location.to_string()
+    } else if let Some(rest) = location.strip_prefix("//") {
+        format!("{}://{}", scheme, rest)
+    } else if location.starts_with('/') {
+        format!("{}://{}{}", scheme, authority, location)
+    } else if location.starts_with('?') {
+        format!("{}://{}{}{}", scheme, authority, base.path(), location)
+    } else {
+        let directory = base.path().rsplit_once('/').map(|(directory, _)| directory).unwrap_or_default();
+        format!("{}://{}{}/{}", scheme, authority, directory, location)
+    };
+    let uri: Uri = target.parse().map_err(|e| format!("invalid redirect location '{}': {}", location, e))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") if uri.authority().is_some() => Ok(uri),
+        _ => Err(format!("cannot follow redirect to '{}'", location)),
+    }
+}
@@ -0,0 +1,179 @@
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of example failures kept per category
+const SAMPLES_PER_CATEGORY: usize = 5;
+
+/// Why a task failed for good
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FailureCategory {
+    /// The input line was rejected before sending
+    InvalidInput,
+    /// The request body or URL could not be built from the input
+    Payload,
+    /// No endpoint serves the request's routing key
+    NoEndpoint,
+    /// The request budget was spent before the task could be sent
+    BudgetExhausted,
+    /// No connection could be established
+    Connect,
+    /// The request timed out
+    Timeout,
+    /// The connection failed after the request was sent
+    Transport,
+    /// HTTP 429
+    RateLimited,
+    /// Any 5xx status
+    ServerError,
+    /// Any other status that was retried until the attempts ran out
+    HttpStatus,
+    /// The response body could not be read, decompressed or was too large
+    Body,
+    /// The response was not valid JSON
+    Parse,
+    /// The response was JSON but failed the success rule or extraction
+    Rejected,
+    /// The response did not match the response schema
+    SchemaMismatch,
+    /// Redirects looped, went on too long or could not be followed
+    Redirect,
+    /// No response was recorded for the request in `Config::replay_dir`
+    NotRecorded,
+}
+
+impl FailureCategory {
+    /// Category of a failed HTTP request
+    pub fn of_error(error: &hyper::Error) -> Self {
+        if error.is_connect() {
+            FailureCategory::Connect
+        } else if error.is_timeout() {
+            FailureCategory::Timeout
+        } else {
+            FailureCategory::Transport
+        }
+    }
+
+    /// Category of a response status that ended a task
+    pub fn of_status(status: StatusCode) -> Self {
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            FailureCategory::RateLimited
+        } else if status.is_server_error() {
+            FailureCategory::ServerError
+        } else {
+            FailureCategory::HttpStatus
+        }
+    }
+
+    /// Whether tasks failing this way were sent to an endpoint
+    pub fn was_sent(&self) -> bool {
+        !matches!(
+            self,
+            FailureCategory::InvalidInput | FailureCategory::Payload | FailureCategory::NoEndpoint | FailureCategory::BudgetExhausted
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureCategory::InvalidInput => "invalid_input",
+            FailureCategory::Payload => "payload",
+            FailureCategory::NoEndpoint => "no_endpoint",
+            FailureCategory::BudgetExhausted => "budget_exhausted",
+            FailureCategory::Connect => "connect",
+            FailureCategory::Timeout => "timeout",
+            FailureCategory::Transport => "transport",
+            FailureCategory::RateLimited => "rate_limited",
+            FailureCategory::ServerError => "server_error",
+            FailureCategory::HttpStatus => "http_status",
+            FailureCategory::Body => "body",
+            FailureCategory::Parse => "parse",
+            FailureCategory::Rejected => "rejected",
+            FailureCategory::SchemaMismatch => "schema_mismatch",
+            FailureCategory::Redirect => "redirect",
+            FailureCategory::NotRecorded => "not_recorded",
+        }
+    }
+}
+
+impl FromStr for FailureCategory {
+    type Err = String;
+
+    /// Parse a category by the name it is reported under, e.g. `rate_limited`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "invalid_input" => FailureCategory::InvalidInput,
+            "payload" => FailureCategory::Payload,
+            "no_endpoint" => FailureCategory::NoEndpoint,
+            "budget_exhausted" => FailureCategory::BudgetExhausted,
+            "connect" => FailureCategory::Connect,
+            "timeout" => FailureCategory::Timeout,
+            "transport" => FailureCategory::Transport,
+            "rate_limited" => FailureCategory::RateLimited,
+            "server_error" => FailureCategory::ServerError,
+            "http_status" => FailureCategory::HttpStatus,
+            "body" => FailureCategory::Body,
+            "parse" => FailureCategory::Parse,
+            "rejected" => FailureCategory::Rejected,
+            "schema_mismatch" => FailureCategory::SchemaMismatch,
+            "redirect" => FailureCategory::Redirect,
+            "not_recorded" => FailureCategory::NotRecorded,
+            _ => return Err(format!("unknown failure category '{}'", s)),
+        })
+    }
+}
+
+impl fmt::Display for FailureCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Failures of one category
+#[derive(Debug, Clone, Default)]
+pub struct CategoryFailures {
+    pub count: usize,
+    /// The first few failures, as task id and error
+    pub samples: Vec<(usize, Value)>,
+}
+
+/// Failed tasks grouped by why they failed, with a few examples of each
+#[derive(Debug, Clone, Default)]
+pub struct FailureReport {
+    pub categories: BTreeMap<FailureCategory, CategoryFailures>,
+}
+
+impl FailureReport {
+    /// Count a failed task, keeping it as an example if its category has few so far
+    pub fn record(&mut self, category: FailureCategory, task_id: usize, error: &Value) {
+        let failures = self.categories.entry(category).or_default();
+        failures.count += 1;
+        if failures.samples.len() < SAMPLES_PER_CATEGORY {
+            failures.samples.push((task_id, error.clone()));
+        }
+    }
+
+    /// Total number of failed tasks
+    pub fn total(&self) -> usize {
+        self.categories.values().map(|failures| failures.count).sum()
+    }
+
+    /// The report as a JSON object, with the most common categories first
+    pub fn to_json(&self) -> Value {
+        let mut categories: Vec<_> = self.categories.iter().collect();
+        categories.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(b.0)));
+        let categories: Vec<Value> = categories
+            .into_iter()
+            .map(|(category, failures)| {
+                let samples: Vec<Value> = failures
+                    .samples
+                    .iter()
+                    .map(|(task_id, error)| json!({ "task_id": task_id, "error": error }))
+                    .collect();
+                json!({ "category": category.as_str(), "count": failures.count, "samples": samples })
+            })
+            .collect();
+        json!({ "total_failures": self.total(), "categories": categories })
+    }
+}
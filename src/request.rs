@@ -0,0 +1,108 @@
+use crate::error::ClientError;
+use crate::report::FailureCategory;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Struct representing an API request
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct APIRequest {
+    pub task_id: usize,
+    pub request_json: HashMap<String, Value>,
+    pub attempts_left: usize,
+    /// Attempts this request started with, from the input line or the config
+    pub max_attempts: usize,
+    pub metadata: Option<HashMap<String, Value>>,
+    pub result: Vec<Value>,
+    pub original_input: HashMap<String, Value>,
+    /// URL of the endpoint the last attempt went to, avoided by the next attempt
+    #[serde(default)]
+    pub last_endpoint: Option<String>,
+    /// HTTP requests made for this task so far, numbering each attempt's `X-Request-Id`
+    #[serde(default)]
+    pub attempts_sent: usize,
+    /// Whether the request was already resent after its access token was rejected
+    #[serde(default)]
+    pub auth_refreshed: bool,
+    /// Requests grouped into this one, sent as an array of their bodies
+    #[serde(default)]
+    pub batch: Vec<APIRequest>,
+}
+
+impl APIRequest {
+    /// Group consecutive requests into one call, identified by the first task id
+    ///
+    /// The batch keeps the routing key of its first member and is attempted as
+    /// often as the member allowing the fewest attempts.
+    pub fn batch(members: Vec<APIRequest>) -> APIRequest {
+        let max_attempts = members.iter().map(|member| member.max_attempts).min().unwrap_or(1);
+        let request_json = members[0].request_json.get("route_key").map(|route_key| ("route_key".to_string(), route_key.clone()));
+        APIRequest {
+            task_id: members[0].task_id,
+            request_json: request_json.into_iter().collect(),
+            attempts_left: max_attempts,
+            max_attempts,
+            metadata: None,
+            result: vec![],
+            original_input: HashMap::new(),
+            last_endpoint: None,
+            attempts_sent: 0,
+            auth_refreshed: false,
+            batch: members,
+        }
+    }
+
+    /// The original input line as a JSON object
+    pub fn input(&self) -> Value {
+        Value::Object(self.original_input.clone().into_iter().collect())
+    }
+
+    /// The `route_key` of the input line, pinning it to matching endpoints
+    pub fn route_key(&self) -> Option<&str> {
+        self.request_json.get("route_key").and_then(Value::as_str)
+    }
+
+    /// The `priority` of the input line, 0 without one; a batch takes the highest of its lines
+    pub fn priority(&self) -> i64 {
+        if !self.batch.is_empty() {
+            return self.batch.iter().map(APIRequest::priority).max().unwrap_or(0);
+        }
+        self.request_json.get("priority").and_then(Value::as_i64).unwrap_or(0)
+    }
+
+    /// Build the error reported when this request fails for good
+    pub fn failure(&self, category: FailureCategory, error: Value) -> ClientError {
+        ClientError::Request {
+            task_id: self.task_id,
+            input: self.input(),
+            error,
+            category,
+        }
+    }
+}
+
+/// Struct representing a successfully completed request
+#[derive(Debug, Clone)]
+pub struct ProcessedResult {
+    pub task_id: usize,
+    pub input: Value,
+    /// The `metadata` object of the input line, if it had one
+    pub metadata: Option<HashMap<String, Value>>,
+    /// With `Config::ndjson_response`, an array of the objects read from the response
+    pub response: Value,
+    /// URL of the endpoint that answered
+    pub endpoint: String,
+    /// Time from sending the request to reading the whole response
+    pub latency: Duration,
+    /// Whether the line matched `Config::skip_if` and was never sent; its input stands in for the response
+    pub skipped: bool,
+    /// HTTP status and chosen headers of the response, with `Config::include_response_meta`
+    pub response_meta: Option<Value>,
+    /// Whether the endpoint answered from its cache, by `Config::cache_header`; `None` when not configured
+    pub cached: Option<bool>,
+}
+
+/// Generator for task IDs
+pub fn task_id_generator() -> impl Iterator<Item = usize> {
+    0..
+}
@@ -0,0 +1,102 @@
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use hyper::body::{Bytes, HttpBody};
+use hyper::Body;
+use std::fmt;
+use std::io::Read;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+/// Reasons reading a response body can fail
+#[derive(Debug)]
+pub enum BodyError {
+    /// The body grew past the configured limit
+    TooLarge(usize),
+    /// The connection failed while the body was being read
+    Read(hyper::Error),
+    /// The body could not be decompressed
+    Decode(String),
+    /// The body did not start arriving within `Config::ttfb_timeout`
+    Timeout(Duration),
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "response body exceeded {} bytes", limit),
+            BodyError::Read(e) => write!(f, "{}", e),
+            BodyError::Decode(e) => write!(f, "failed to decode response body: {}", e),
+            BodyError::Timeout(timeout) => write!(f, "no response body within {:.1} sec", timeout.as_secs_f64()),
+        }
+    }
+}
+
+/// Read a response body into memory, aborting once it exceeds `max_bytes`
+///
+/// With a `first_byte` deadline and its timeout, the body must start arriving by then.
+pub async fn read_body(mut body: Body, max_bytes: Option<usize>, first_byte: Option<(Instant, Duration)>) -> Result<Bytes, BodyError> {
+    if max_bytes.is_none() && first_byte.is_none() {
+        return hyper::body::to_bytes(body).await.map_err(BodyError::Read);
+    }
+    let max_bytes = max_bytes.unwrap_or(usize::MAX);
+
+    // Refuse early when the server announces a body that is too large
+    if body.size_hint().lower() > max_bytes as u64 {
+        return Err(BodyError::TooLarge(max_bytes));
+    }
+
+    let mut buf = Vec::new();
+    let mut first_byte = first_byte;
+    loop {
+        let next = match first_byte.take() {
+            Some((deadline, timeout)) => timeout_at(deadline, body.data()).await.map_err(|_| BodyError::Timeout(timeout))?,
+            None => body.data().await,
+        };
+        let chunk = match next {
+            Some(chunk) => chunk.map_err(BodyError::Read)?,
+            None => break,
+        };
+        if buf.len() + chunk.len() > max_bytes {
+            return Err(BodyError::TooLarge(max_bytes));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(buf))
+}
+
+/// Undo a `Content-Encoding` of gzip or deflate, keeping to `max_bytes` once decompressed
+pub fn decode_body(body: Bytes, encoding: Option<&str>, max_bytes: Option<usize>) -> Result<Bytes, BodyError> {
+    let encoding = match encoding {
+        Some(encoding) => encoding.trim().to_ascii_lowercase(),
+        None => return Ok(body),
+    };
+    let decoded = match encoding.as_str() {
+        "" | "identity" => return Ok(body),
+        "gzip" | "x-gzip" => read_limited(GzDecoder::new(&body[..]), max_bytes),
+        // Servers disagree on whether deflate is zlib-wrapped, so accept both
+        "deflate" => read_limited(ZlibDecoder::new(&body[..]), max_bytes)
+            .or_else(|_| read_limited(DeflateDecoder::new(&body[..]), max_bytes)),
+        _ => return Err(BodyError::Decode(format!("unsupported content encoding '{}'", encoding))),
+    }?;
+    Ok(Bytes::from(decoded))
+}
+
+/// Read a decoder to the end, refusing output larger than `max_bytes`
+fn read_limited<R: Read>(mut reader: R, max_bytes: Option<usize>) -> Result<Vec<u8>, BodyError> {
+    let mut decoded = Vec::new();
+    match max_bytes {
+        Some(max_bytes) => {
+            // Read one byte past the limit to tell a full body from an oversized one
+            reader
+                .take(max_bytes as u64 + 1)
+                .read_to_end(&mut decoded)
+                .map_err(|e| BodyError::Decode(e.to_string()))?;
+            if decoded.len() > max_bytes {
+                return Err(BodyError::TooLarge(max_bytes));
+            }
+        }
+        None => {
+            reader.read_to_end(&mut decoded).map_err(|e| BodyError::Decode(e.to_string()))?;
+        }
+    }
+    Ok(decoded)
+}
@@ -0,0 +1,116 @@
+use crate::json_path::FieldMatch;
+use hyper::StatusCode;
+use std::str::FromStr;
+
+/// A condition under which a failed attempt is sent again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryCondition {
+    /// The connection could not be established, so the request never reached the server
+    Connect,
+    /// The request timed out
+    Timeout,
+    /// The connection failed after the request may have been sent
+    ///
+    /// Retrying these can duplicate side effects on the server.
+    Transport,
+    /// The connection failed while the response body was being read
+    ///
+    /// The server has answered, so a retry repeats work it already did.
+    Body,
+    /// Any 5xx response
+    ServerError,
+    /// A specific response status, e.g. 429
+    Status(u16),
+}
+
+/// Which failures are retried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub conditions: Vec<RetryCondition>,
+}
+
+impl Default for RetryPolicy {
+    /// Retry failures where the server cannot have acted on the request, and lost response bodies
+    fn default() -> Self {
+        RetryPolicy {
+            conditions: vec![
+                RetryCondition::Connect,
+                RetryCondition::Timeout,
+                RetryCondition::Body,
+                RetryCondition::Status(429),
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a failed HTTP request should be retried
+    pub fn retries_error(&self, error: &hyper::Error) -> bool {
+        let condition = if error.is_connect() {
+            RetryCondition::Connect
+        } else if error.is_timeout() {
+            RetryCondition::Timeout
+        } else {
+            RetryCondition::Transport
+        };
+        self.conditions.contains(&condition)
+    }
+
+    /// Whether an attempt whose response did not start in time should be retried
+    pub fn retries_timeout(&self) -> bool {
+        self.conditions.contains(&RetryCondition::Timeout)
+    }
+
+    /// Whether a response whose body could not be read to the end should be retried
+    pub fn retries_body_read(&self) -> bool {
+        self.conditions.contains(&RetryCondition::Body)
+    }
+
+    /// Whether a response with this status should be retried
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.conditions.iter().any(|condition| match condition {
+            RetryCondition::ServerError => status.is_server_error(),
+            RetryCondition::Status(code) => status.as_u16() == *code,
+            _ => false,
+        })
+    }
+}
+
+impl FromStr for RetryCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "connect" => Ok(RetryCondition::Connect),
+            "timeout" => Ok(RetryCondition::Timeout),
+            "transport" => Ok(RetryCondition::Transport),
+            "body" => Ok(RetryCondition::Body),
+            "5xx" => Ok(RetryCondition::ServerError),
+            _ => match s.parse::<u16>() {
+                Ok(code) if (100..600).contains(&code) => Ok(RetryCondition::Status(code)),
+                _ => Err(format!(
+                    "unknown retry condition '{}' (expected connect, timeout, transport, body, 5xx or a status code)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl FromStr for RetryPolicy {
+    type Err = String;
+
+    /// Parse a comma-separated list of conditions; `none` disables retries
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(RetryPolicy { conditions: Vec::new() });
+        }
+        let conditions = s.split(',').map(|c| c.trim().parse()).collect::<Result<_, _>>()?;
+        Ok(RetryPolicy { conditions })
+    }
+}
+
+/// Retry a response whose body has `value` at `path`, e.g. `error.code=server_overloaded`
+///
+/// For APIs that report transient errors in a successful response.
+pub type BodyRetryRule = FieldMatch;
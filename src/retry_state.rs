@@ -0,0 +1,89 @@
+use crate::request::APIRequest;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::sync::Mutex;
+
+/// Requests waiting to be retried, saved to a file so a restarted run can pick them up
+///
+/// The file holds one serialized `APIRequest` per line, with the attempts it
+/// has left. A request is kept from the moment its retry is scheduled until
+/// the task finishes, so one killed mid-backoff or mid-retry is not lost.
+#[derive(Debug)]
+pub(crate) struct RetryState {
+    path: String,
+    pending: Mutex<BTreeMap<usize, APIRequest>>,
+}
+
+impl RetryState {
+    pub(crate) fn new(path: String) -> Self {
+        RetryState {
+            path,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Read the requests a previous run left in the file; a missing file holds none
+    pub(crate) fn load(&self) -> io::Result<Vec<APIRequest>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut requests = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<APIRequest>(&line) {
+                Ok(request) if request.attempts_left > 0 => requests.push(request),
+                Ok(_) => {}
+                Err(e) => warn!("Skipping line {} of {}: {}", number + 1, self.path, e),
+            }
+        }
+        if !requests.is_empty() {
+            info!("Loaded {} requests to retry from {}", requests.len(), self.path);
+        }
+        Ok(requests)
+    }
+
+    /// Keep a request that is due another attempt
+    pub(crate) fn insert(&self, request: &APIRequest) {
+        self.pending.lock().unwrap().insert(request.task_id, request.clone());
+    }
+
+    /// Forget a task once it has finished
+    pub(crate) fn remove(&self, task_id: usize) {
+        self.pending.lock().unwrap().remove(&task_id);
+    }
+
+    /// Whether a task is kept here, on its own or as part of a batch
+    pub(crate) fn contains(&self, task_id: usize) -> bool {
+        self.pending
+            .lock()
+            .unwrap()
+            .values()
+            .any(|request| request.task_id == task_id || request.batch.iter().any(|member| member.task_id == task_id))
+    }
+
+    /// Replace the file with the requests kept now
+    ///
+    /// Written to a temporary file first, so a kill mid-write leaves the previous state.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let requests: Vec<APIRequest> = self.pending.lock().unwrap().values().cloned().collect();
+        let temp_path = format!("{}.tmp", self.path);
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        for request in &requests {
+            serde_json::to_writer(&mut writer, request)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+}
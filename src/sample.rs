@@ -0,0 +1,30 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// A random fraction of the input lines, chosen line by line
+///
+/// Whether a line is included depends only on the seed and its task id, so
+/// runs with the same seed, and shards of one run, pick the same lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Chance of including each line, above 0 and at most 1
+    pub rate: f64,
+    pub seed: u64,
+}
+
+impl Sample {
+    /// Sample at this rate; without a seed one is picked at random
+    pub fn new(rate: f64, seed: Option<u64>) -> Self {
+        Sample {
+            rate,
+            seed: seed.unwrap_or_else(rand::random),
+        }
+    }
+
+    /// Whether the line with this task id is in the sample
+    pub fn includes(&self, task_id: usize) -> bool {
+        // Spread consecutive ids apart so neighbouring lines get unrelated draws
+        let seed = self.seed ^ (task_id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        StdRng::seed_from_u64(seed).gen::<f64>() < self.rate
+    }
+}
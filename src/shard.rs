@@ -0,0 +1,39 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// One of `count` disjoint slices of the input, written `index/count`
+///
+/// A shard owns the lines whose task id leaves `index` when divided by
+/// `count`, so `count` processes with different indexes cover every line once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    /// Whether the line with this task id belongs to the shard
+    pub fn owns(&self, task_id: usize) -> bool {
+        task_id % self.count == self.index
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s.split_once('/').ok_or_else(|| format!("expected <index>/<count>, got '{}'", s))?;
+        let index: usize = index.trim().parse().map_err(|_| format!("invalid shard index '{}'", index))?;
+        let count: usize = count.trim().parse().map_err(|_| format!("invalid shard count '{}'", count))?;
+        if index >= count {
+            return Err(format!("shard index must be below the shard count, got {}/{}", index, count));
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
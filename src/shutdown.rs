@@ -0,0 +1,40 @@
+use log::info;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Switch that stops a run early, as if its maximum runtime had passed
+///
+/// No more input is read and queued requests are left unprocessed; requests
+/// in flight get `Config::shutdown_grace` to finish. Clones control the same client.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    requested: Arc<watch::Sender<bool>>,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        ShutdownHandle {
+            requested: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Stop reading input and let the requests in flight finish
+    pub fn shutdown(&self) {
+        if !self.requested.send_replace(true) {
+            info!("Shutting down, no longer sending new requests");
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Wait until a shutdown is requested
+    pub(crate) async fn requested(&self) {
+        let mut requested = self.requested.subscribe();
+        // The sender lives in `self`, so this cannot fail
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+}
@@ -0,0 +1,197 @@
+use hyper::{Body, Request};
+
+/// AWS Signature Version 4 settings for an endpoint, e.g. Amazon Bedrock
+///
+/// Requests to the endpoint are signed instead of carrying a bearer token.
+/// Signing needs the crate's `sigv4` feature.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct SigV4Config {
+    /// Region the endpoint is in, e.g. `us-east-1`
+    pub region: String,
+    /// Signing name of the service, e.g. `bedrock`
+    pub service: String,
+    /// Read from `AWS_ACCESS_KEY_ID` when empty
+    #[serde(default)]
+    pub access_key_id: String,
+    /// Read from `AWS_SECRET_ACCESS_KEY` when empty
+    #[serde(default)]
+    pub secret_access_key: String,
+    /// Session token of temporary credentials; read from `AWS_SESSION_TOKEN` when the keys come from the environment
+    #[serde(default)]
+    pub session_token: Option<String>,
+}
+
+impl SigV4Config {
+    /// Fill in credentials left out of the config from the standard AWS environment variables
+    pub(crate) fn resolve_credentials(&mut self) -> Result<(), String> {
+        if !self.access_key_id.is_empty() {
+            return Ok(());
+        }
+        let var = |name: &str| std::env::var(name).map_err(|e| format!("cannot read AWS credentials from ${}: {}", name, e));
+        self.access_key_id = var("AWS_ACCESS_KEY_ID")?;
+        if self.secret_access_key.is_empty() {
+            self.secret_access_key = var("AWS_SECRET_ACCESS_KEY")?;
+        }
+        if self.session_token.is_none() {
+            self.session_token = std::env::var("AWS_SESSION_TOKEN").ok();
+        }
+        Ok(())
+    }
+}
+
+/// Add the `Host`, `X-Amz-Date` and `Authorization` headers that sign a request
+///
+/// The body must be the one the request carries, since its hash is signed.
+#[cfg(feature = "sigv4")]
+pub(crate) fn sign(config: &SigV4Config, req: &mut Request<Body>, body: &[u8]) {
+    use hyper::header::{HeaderValue, AUTHORIZATION, HOST};
+
+    let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let host = req.uri().authority().map(|authority| authority.as_str().to_string()).unwrap_or_default();
+    let headers = req.headers_mut();
+    headers.insert(HOST, HeaderValue::from_str(&host).expect("URI authority is a valid header value"));
+    headers.insert("x-amz-date", HeaderValue::from_str(&amz_date).expect("date is a valid header value"));
+    if let Some(token) = &config.session_token {
+        match HeaderValue::from_str(token) {
+            Ok(mut token) => {
+                token.set_sensitive(true);
+                headers.insert("x-amz-security-token", token);
+            }
+            Err(_) => log::error!("AWS session token is not a valid header value, sending the request without it"),
+        }
+    }
+
+    let signed: Vec<(&str, &str)> = ["content-type", "host", "x-amz-date", "x-amz-security-token"]
+        .iter()
+        .filter_map(|name| Some((*name, req.headers().get(*name)?.to_str().ok()?)))
+        .collect();
+    let authorization = config.authorization(req.method().as_str(), req.uri(), &signed, body, &amz_date);
+    let mut authorization = HeaderValue::from_str(&authorization).expect("signature is a valid header value");
+    authorization.set_sensitive(true);
+    req.headers_mut().insert(AUTHORIZATION, authorization);
+}
+
+/// Requests are never signed without the `sigv4` feature; endpoints that need it fail validation
+#[cfg(not(feature = "sigv4"))]
+pub(crate) fn sign(_config: &SigV4Config, _req: &mut Request<Body>, _body: &[u8]) {}
+
+#[cfg(feature = "sigv4")]
+impl SigV4Config {
+    /// The `Authorization` header value of a request signed at `amz_date`, e.g. `20150830T123600Z`
+    ///
+    /// `headers` are the headers to sign, with lowercase names, and must
+    /// include `host` and `x-amz-date`. Paths are encoded once more, as every
+    /// service but S3 expects, so a `:` in a Bedrock model id is signed as `%3A`.
+    pub fn authorization(&self, method: &str, uri: &hyper::Uri, headers: &[(&str, &str)], body: &[u8], amz_date: &str) -> String {
+        let mut headers = headers.to_vec();
+        headers.sort_unstable();
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+
+        // S3 is the one service whose paths are not encoded a second time
+        let path = if uri.path().is_empty() { "/" } else { uri.path() };
+        let canonical_uri = if self.service == "s3" {
+            path.to_string()
+        } else {
+            path.split('/').map(uri_encode).collect::<Vec<_>>().join("/")
+        };
+        let mut query: Vec<(String, String)> = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (uri_encode(&percent_decode(name)), uri_encode(&percent_decode(value)))
+            })
+            .collect();
+        query.sort();
+        let canonical_query = query.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            canonical_uri,
+            canonical_query,
+            canonical_headers,
+            signed_headers,
+            hex(&openssl::sha::sha256(body))
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&openssl::sha::sha256(canonical_request.as_bytes()))
+        );
+
+        let key = [date, self.region.as_str(), self.service.as_str(), "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.secret_access_key).into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id,
+            scope,
+            signed_headers,
+            hex(&hmac(&key, string_to_sign.as_bytes()))
+        )
+    }
+}
+
+#[cfg(feature = "sigv4")]
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::sign::Signer;
+
+    let key = PKey::hmac(key).expect("any key is a valid HMAC key");
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).expect("HMAC-SHA256 is always available");
+    signer.update(data).expect("HMAC-SHA256 is always available");
+    signer.sign_to_vec().expect("HMAC-SHA256 is always available")
+}
+
+#[cfg(feature = "sigv4")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encode everything but unreserved characters, as SigV4 requires
+#[cfg(feature = "sigv4")]
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+/// Undo percent-encoding, so query values are encoded exactly once when signed
+#[cfg(feature = "sigv4")]
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,148 @@
+use crate::json_path::JsonPath;
+use hyper::StatusCode;
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// How a response is judged to be a success or a failure
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum SuccessRule {
+    /// Fail when the value at the path is present and not empty
+    ErrorPath(JsonPath),
+    /// Fail unless the value at the path is present
+    RequireField(JsonPath),
+    /// Fail on any HTTP status outside the success statuses, ignoring the body
+    HttpStatus,
+}
+
+impl Default for SuccessRule {
+    fn default() -> Self {
+        SuccessRule::ErrorPath("errors".parse().unwrap())
+    }
+}
+
+impl SuccessRule {
+    /// Check a parsed response, returning the error to record when it is a failure
+    pub fn check(&self, status: StatusCode, statuses: &SuccessStatuses, response: &Value) -> Result<(), Value> {
+        match self {
+            SuccessRule::ErrorPath(path) => match path.get(response) {
+                Some(error) if !is_empty(error) => Err(error.clone()),
+                _ => Ok(()),
+            },
+            SuccessRule::RequireField(path) => match path.get(response) {
+                Some(_) => Ok(()),
+                None => Err(Value::String(format!("response is missing '{}'", path))),
+            },
+            SuccessRule::HttpStatus if statuses.contains(status) => Ok(()),
+            SuccessRule::HttpStatus => Err(Value::String(format!("HTTP status {}", status))),
+        }
+    }
+}
+
+/// Whether a value carries no error: null, false, or an empty string, array or object
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => true,
+        Value::String(s) => s.is_empty(),
+        Value::Array(a) => a.is_empty(),
+        Value::Object(o) => o.is_empty(),
+        _ => false,
+    }
+}
+
+impl FromStr for SuccessRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "status" {
+            return Ok(SuccessRule::HttpStatus);
+        }
+        match s.split_once('=') {
+            Some(("error-path", path)) => Ok(SuccessRule::ErrorPath(path.parse()?)),
+            Some(("require", path)) => Ok(SuccessRule::RequireField(path.parse()?)),
+            _ => Err(format!(
+                "unknown success rule '{}' (expected status, error-path=<path> or require=<path>)",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for SuccessRule {
+    type Error = String;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+
+/// HTTP statuses counted as success, e.g. `200-299` or `200,201,202`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct SuccessStatuses(Vec<RangeInclusive<u16>>);
+
+impl Default for SuccessStatuses {
+    fn default() -> Self {
+        SuccessStatuses(vec![200..=299])
+    }
+}
+
+impl SuccessStatuses {
+    /// Whether a response status counts as success
+    pub fn contains(&self, status: StatusCode) -> bool {
+        self.0.iter().any(|range| range.contains(&status.as_u16()))
+    }
+}
+
+impl FromStr for SuccessStatuses {
+    type Err = String;
+
+    /// Parse a comma-separated list of statuses and inclusive ranges
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = |code: &str| match code.trim().parse::<u16>() {
+            Ok(code) if (100..600).contains(&code) => Ok(code),
+            _ => Err(format!("invalid HTTP status '{}' (expected 100-599)", code.trim())),
+        };
+        let ranges = s
+            .split(',')
+            .map(|item| {
+                let (start, end) = match item.split_once('-') {
+                    Some((start, end)) => (code(start)?, code(end)?),
+                    None => (code(item)?, code(item)?),
+                };
+                if start > end {
+                    return Err(format!("empty status range '{}'", item.trim()));
+                }
+                Ok(start..=end)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SuccessStatuses(ranges))
+    }
+}
+
+impl fmt::Display for SuccessStatuses {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, range) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            if range.start() == range.end() {
+                write!(f, "{}", range.start())?;
+            } else {
+                write!(f, "{}-{}", range.start(), range.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for SuccessStatuses {
+    type Error = String;
+
+    fn try_from(statuses: String) -> Result<Self, Self::Error> {
+        statuses.parse()
+    }
+}
@@ -0,0 +1,381 @@
+use crate::json_path::JsonPath;
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// A change made to a placeholder's text, written after the path as `{{input | lower}}`
+#[derive(Debug, Clone, Copy)]
+enum Filter {
+    Lower,
+    Upper,
+    Trim,
+    /// Keep at most this many characters
+    Truncate(usize),
+}
+
+impl Filter {
+    fn apply(self, text: String) -> String {
+        match self {
+            Filter::Lower => text.to_lowercase(),
+            Filter::Upper => text.to_uppercase(),
+            Filter::Trim => text.trim().to_string(),
+            Filter::Truncate(max_chars) => match text.char_indices().nth(max_chars) {
+                Some((end, _)) => text[..end].to_string(),
+                None => text,
+            },
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':').map_or((s, None), |(name, arg)| (name.trim(), Some(arg.trim()))) {
+            ("lower", None) => Ok(Filter::Lower),
+            ("upper", None) => Ok(Filter::Upper),
+            ("trim", None) => Ok(Filter::Trim),
+            ("truncate", Some(max_chars)) => max_chars
+                .parse()
+                .map(Filter::Truncate)
+                .map_err(|_| format!("truncate needs a number of characters, got '{}'", max_chars)),
+            _ => Err(format!("unknown template filter '{}' (expected lower, upper, trim or truncate:<n>)", s)),
+        }
+    }
+}
+
+/// A `{{path | filter | ...}}` placeholder
+#[derive(Debug, Clone)]
+struct Placeholder {
+    path: JsonPath,
+    filters: Vec<Filter>,
+}
+
+impl Placeholder {
+    /// The value's text with the filters applied
+    fn text(&self, value: &Value) -> String {
+        let text = match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+        self.filters.iter().fold(text, |text, filter| filter.apply(text))
+    }
+}
+
+impl FromStr for Placeholder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces = s.split('|');
+        let path = pieces.next().unwrap_or_default().trim().parse()?;
+        let filters = pieces.map(|filter| filter.trim().parse()).collect::<Result<_, _>>()?;
+        Ok(Placeholder { path, filters })
+    }
+}
+
+/// A piece of a templated string
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(Placeholder),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(Value),
+    /// A string that is exactly one placeholder, replaced by the value itself so numbers stay numbers
+    Field(Placeholder),
+    /// A string with placeholders inside it
+    Text(Vec<Part>),
+    Array(Vec<Node>),
+    Object(Vec<(String, Node)>),
+}
+
+/// A JSON request body with `{{field}}` placeholders filled in from each request
+///
+/// Placeholders are JSON paths into the input line, e.g. `{{input}}` or
+/// `{{metadata.temperature}}`. A string that is exactly one placeholder is
+/// replaced by the value as-is; placeholders inside longer strings are
+/// replaced by the value's text. Filters after the path, e.g.
+/// `{{input | trim | truncate:2000}}`, turn the value into changed text.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(try_from = "Value")]
+pub struct PayloadTemplate {
+    root: Node,
+}
+
+impl Default for PayloadTemplate {
+    /// The chat completion payload sent when no template is configured
+    fn default() -> Self {
+        PayloadTemplate::chat("input").unwrap()
+    }
+}
+
+impl PayloadTemplate {
+    /// A chat completion payload whose user message is the given field of each request
+    pub fn chat(input_field: &str) -> Result<Self, String> {
+        PayloadTemplate::new(&serde_json::json!({
+            "messages": [
+                {
+                  "role": "system",
+                  "content": "Your system message here"
+                },
+                {
+                  "role": "user",
+                  "content": format!("{{{{{}}}}}", input_field)
+                }
+            ],
+            "temperature": 0.4,
+            "max_tokens": 120
+        }))
+    }
+
+    /// Parse the placeholders in a template
+    pub fn new(template: &Value) -> Result<Self, String> {
+        Ok(PayloadTemplate {
+            root: compile(template)?,
+        })
+    }
+
+    /// Build the payload for one request
+    pub fn render(&self, request: &Value) -> Result<Value, String> {
+        render(&self.root, request)
+    }
+}
+
+impl TryFrom<Value> for PayloadTemplate {
+    type Error = String;
+
+    fn try_from(template: Value) -> Result<Self, Self::Error> {
+        PayloadTemplate::new(&template)
+    }
+}
+
+/// Fields computed from each input line before its body is built, e.g. a prompt made of several columns
+///
+/// A JSON object whose values are templates like a payload template's,
+/// rendered against the line. A field can use other derived fields, which
+/// are then rendered before it.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(try_from = "Value")]
+pub struct DerivedFields {
+    fields: Vec<(String, Node)>,
+}
+
+impl DerivedFields {
+    /// Parse the placeholders of each field's template and order the fields by what they use
+    pub fn new(fields: &Value) -> Result<Self, String> {
+        let fields = fields.as_object().ok_or("derived fields must be a JSON object of templates")?;
+        let mut pending = fields
+            .iter()
+            .map(|(name, template)| Ok((name.clone(), compile(template)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let mut ordered: Vec<(String, Node)> = Vec::with_capacity(pending.len());
+        while !pending.is_empty() {
+            // A field is ready once every other derived field it uses is rendered before it
+            let ready = |(name, node): &(String, Node)| {
+                let mut roots = Vec::new();
+                collect_roots(node, &mut roots);
+                roots
+                    .iter()
+                    .all(|root| root == name || !pending.iter().any(|(other, _)| other == root))
+            };
+            let (next, rest): (Vec<_>, Vec<_>) = pending.iter().cloned().partition(ready);
+            if next.is_empty() {
+                let names: Vec<&str> = rest.iter().map(|(name, _)| name.as_str()).collect();
+                return Err(format!("derived fields use each other in a cycle: {}", names.join(", ")));
+            }
+            ordered.extend(next);
+            pending = rest;
+        }
+        Ok(DerivedFields { fields: ordered })
+    }
+
+    /// The fields computed for one line, replacing any of the line's own fields with the same names
+    pub fn derive(&self, line: &Map<String, Value>) -> Result<Map<String, Value>, String> {
+        let mut fields = Value::Object(line.clone());
+        let mut derived = Map::new();
+        for (name, template) in &self.fields {
+            let value = render(template, &fields)?;
+            if let Value::Object(fields) = &mut fields {
+                fields.insert(name.clone(), value.clone());
+            }
+            derived.insert(name.clone(), value);
+        }
+        Ok(derived)
+    }
+}
+
+impl TryFrom<Value> for DerivedFields {
+    type Error = String;
+
+    fn try_from(fields: Value) -> Result<Self, Self::Error> {
+        DerivedFields::new(&fields)
+    }
+}
+
+/// Fields of an input line that steer the client and are never sent in a passthrough body
+const CONTROL_FIELDS: &[&str] = &["metadata", "route_key", "max_attempts", "priority", "group_id"];
+
+/// Fields of an input line that set a generation setting of its body, overriding the payload template's
+const OVERRIDE_FIELDS: &[(&str, &str)] = &[("_temperature", "temperature"), ("_max_tokens", "max_tokens")];
+
+/// How the POST body is built from each request
+#[derive(Debug, Clone)]
+pub enum RequestBody {
+    /// Render the payload template
+    Template(PayloadTemplate),
+    /// Send the input line itself, with fields from an optional template added where the line lacks them
+    Passthrough(Option<PayloadTemplate>),
+}
+
+impl Default for RequestBody {
+    fn default() -> Self {
+        RequestBody::Template(PayloadTemplate::default())
+    }
+}
+
+impl RequestBody {
+    /// Build the body for one request
+    ///
+    /// An endpoint's own template takes the place of the configured one, and
+    /// the line's `_temperature` and `_max_tokens` take the place of the body's
+    /// `temperature` and `max_tokens`.
+    pub fn build(&self, request: &Value, endpoint_template: Option<&PayloadTemplate>) -> Result<Value, String> {
+        let mut body = self.render(request, endpoint_template)?;
+        for (field, setting) in OVERRIDE_FIELDS {
+            if let Some(value) = request.get(field) {
+                match &mut body {
+                    Value::Object(body) => body.insert(setting.to_string(), value.clone()),
+                    _ => return Err(format!("{} needs a payload template that renders a JSON object", field)),
+                };
+            }
+        }
+        Ok(body)
+    }
+
+    fn render(&self, request: &Value, endpoint_template: Option<&PayloadTemplate>) -> Result<Value, String> {
+        match self {
+            RequestBody::Template(template) => endpoint_template.unwrap_or(template).render(request),
+            RequestBody::Passthrough(defaults) => {
+                let mut body = match request {
+                    Value::Object(fields) => fields.clone(),
+                    _ => return Err("request is not a JSON object".to_string()),
+                };
+                for field in CONTROL_FIELDS.iter().chain(OVERRIDE_FIELDS.iter().map(|(field, _)| field)) {
+                    body.remove(*field);
+                }
+                if let Some(defaults) = endpoint_template.or(defaults.as_ref()) {
+                    match defaults.render(request)? {
+                        Value::Object(fixed) => {
+                            for (key, value) in fixed {
+                                body.entry(key).or_insert(value);
+                            }
+                        }
+                        _ => return Err("payload template must be a JSON object to merge into a passthrough body".to_string()),
+                    }
+                }
+                Ok(Value::Object(body))
+            }
+        }
+    }
+}
+
+fn compile(template: &Value) -> Result<Node, String> {
+    Ok(match template {
+        Value::String(s) => {
+            let parts = parse_parts(s)?;
+            match parts.as_slice() {
+                [Part::Field(placeholder)] => Node::Field(placeholder.clone()),
+                _ if parts.iter().all(|part| matches!(part, Part::Text(_))) => Node::Literal(template.clone()),
+                _ => Node::Text(parts),
+            }
+        }
+        Value::Array(items) => Node::Array(items.iter().map(compile).collect::<Result<_, _>>()?),
+        Value::Object(fields) => Node::Object(
+            fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), compile(value)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        _ => Node::Literal(template.clone()),
+    })
+}
+
+/// Split a string into literal text and `{{field | filter}}` placeholders
+fn parse_parts(s: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("{{") {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| format!("unclosed placeholder in template string '{}'", s))?;
+        parts.push(Part::Field(rest[start + 2..start + end].trim().parse()?));
+        rest = &rest[start + end + 2..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+fn lookup<'a>(placeholder: &Placeholder, request: &'a Value) -> Result<&'a Value, String> {
+    placeholder
+        .path
+        .get(request)
+        .ok_or_else(|| format!("request has no value for template field '{}'", placeholder.path))
+}
+
+/// The first keys of the paths a template's placeholders use
+fn collect_roots<'a>(node: &'a Node, roots: &mut Vec<&'a str>) {
+    match node {
+        Node::Literal(_) => {}
+        Node::Field(placeholder) => roots.extend(placeholder.path.root()),
+        Node::Text(parts) => {
+            for part in parts {
+                if let Part::Field(placeholder) = part {
+                    roots.extend(placeholder.path.root());
+                }
+            }
+        }
+        Node::Array(items) => {
+            for item in items {
+                collect_roots(item, roots);
+            }
+        }
+        Node::Object(fields) => {
+            for (_, value) in fields {
+                collect_roots(value, roots);
+            }
+        }
+    }
+}
+
+fn render(node: &Node, request: &Value) -> Result<Value, String> {
+    Ok(match node {
+        Node::Literal(value) => value.clone(),
+        Node::Field(placeholder) if placeholder.filters.is_empty() => lookup(placeholder, request)?.clone(),
+        Node::Field(placeholder) => Value::String(placeholder.text(lookup(placeholder, request)?)),
+        Node::Text(parts) => {
+            let mut text = String::new();
+            for part in parts {
+                match part {
+                    Part::Text(s) => text.push_str(s),
+                    Part::Field(placeholder) => text.push_str(&placeholder.text(lookup(placeholder, request)?)),
+                }
+            }
+            Value::String(text)
+        }
+        Node::Array(items) => Value::Array(items.iter().map(|item| render(item, request)).collect::<Result<_, _>>()?),
+        Node::Object(fields) => {
+            let mut object = Map::new();
+            for (key, value) in fields {
+                object.insert(key.clone(), render(value, request)?);
+            }
+            Value::Object(object)
+        }
+    })
+}
@@ -0,0 +1,251 @@
+use crate::cost::{Pricing, Usage};
+use crate::report::{FailureCategory, FailureReport};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// Struct to track the status of requests
+#[derive(Debug, Default, Clone)]
+pub struct StatusTracker {
+    pub num_tasks_started: usize,
+    pub num_tasks_in_progress: usize,
+    pub num_tasks_succeeded: usize,
+    pub num_tasks_failed: usize,
+    pub num_rate_limit_errors: usize,
+    pub num_api_errors: usize,
+    pub num_other_errors: usize,
+    pub num_tasks_invalid: usize,
+    /// Lines matching `Config::skip_if`, saved without being sent
+    pub num_tasks_skipped: usize,
+    /// Skipped tasks of a group that another task failed in, by `Config::cancel_groups`
+    pub num_tasks_cancelled: usize,
+    /// Succeeded tasks the endpoint answered from its cache, by `Config::cache_header`
+    pub num_cache_hits: usize,
+    pub num_budget_exhausted: usize,
+    /// Requests queued to be sent again after a failed attempt
+    pub num_retries: usize,
+    /// Retries dropped because too many were already pending
+    pub num_retries_shed: usize,
+    /// Lines kept and left out by `Config::sample`
+    pub num_lines_sampled: usize,
+    pub num_lines_not_sampled: usize,
+    /// Lines never sent because the run reached its maximum runtime
+    pub num_tasks_unprocessed: usize,
+    /// Responses that did not match the response schema
+    pub num_schema_failures: usize,
+    /// Responses on new and on reused connections, counted when connection logging is on
+    pub num_connections_opened: usize,
+    pub num_connections_reused: usize,
+    /// Tokens reported in the `usage` of responses
+    pub num_prompt_tokens: u64,
+    pub num_completion_tokens: u64,
+    /// Cost of those tokens at the endpoints' configured prices
+    pub estimated_cost: f64,
+    pub endpoint_stats: HashMap<String, EndpointStats>,
+    /// Failed tasks grouped by cause
+    pub failures: FailureReport,
+    pub latency: LatencyHistogram,
+}
+
+/// Struct to track the requests sent to a single endpoint
+#[derive(Debug, Default, Clone)]
+pub struct EndpointStats {
+    pub num_requests: usize,
+    pub num_succeeded: usize,
+    pub num_failed: usize,
+    pub estimated_cost: f64,
+    /// Usage of each of the endpoint's `api_keys`, by index; empty for a single key
+    pub keys: BTreeMap<usize, KeyStats>,
+}
+
+/// Struct to track the requests sent with one API key of an endpoint
+#[derive(Debug, Default, Clone)]
+pub struct KeyStats {
+    pub num_requests: usize,
+    /// Requests the endpoint refused with 401 or 403
+    pub num_refused: usize,
+    /// Whether the key was taken out of rotation
+    pub quarantined: bool,
+}
+
+impl StatusTracker {
+    /// Get the stats entry for an endpoint, creating it on first use
+    pub fn endpoint(&mut self, url: &str) -> &mut EndpointStats {
+        self.endpoint_stats.entry(url.to_string()).or_default()
+    }
+
+    /// Count a response that was judged a failure
+    ///
+    /// Schema mismatches are counted apart from other API errors.
+    pub fn record_rejection(&mut self, category: FailureCategory) {
+        match category {
+            FailureCategory::SchemaMismatch => self.num_schema_failures += 1,
+            _ => self.num_api_errors += 1,
+        }
+    }
+
+    /// Add a response's token usage, and its cost if the endpoint has prices
+    pub fn record_usage(&mut self, url: &str, usage: Usage, pricing: Option<&Pricing>) {
+        self.num_prompt_tokens += usage.prompt_tokens;
+        self.num_completion_tokens += usage.completion_tokens;
+        if let Some(pricing) = pricing {
+            let cost = pricing.cost(&usage);
+            self.estimated_cost += cost;
+            self.endpoint(url).estimated_cost += cost;
+        }
+    }
+
+    /// Requests sent to all endpoints, first attempts and retries alike
+    pub fn num_requests(&self) -> usize {
+        self.endpoint_stats.values().map(|stats| stats.num_requests).sum()
+    }
+
+    /// Share of the requests sent that were retries, if any were sent
+    pub fn retry_rate(&self) -> Option<f64> {
+        match self.num_requests() {
+            0 => None,
+            num_requests => Some(self.num_retries as f64 / num_requests as f64),
+        }
+    }
+
+    /// All counters, per-endpoint stats and latency percentiles as one JSON object
+    pub fn summary(&self) -> Value {
+        let endpoints: Map<String, Value> = self
+            .endpoint_stats
+            .iter()
+            .map(|(url, stats)| {
+                let mut stats_json = json!({
+                    "requests": stats.num_requests,
+                    "succeeded": stats.num_succeeded,
+                    "failed": stats.num_failed,
+                    "estimated_cost": stats.estimated_cost,
+                });
+                if !stats.keys.is_empty() {
+                    let keys: Map<String, Value> = stats
+                        .keys
+                        .iter()
+                        .map(|(index, key)| {
+                            let key = json!({
+                                "requests": key.num_requests,
+                                "refused": key.num_refused,
+                                "quarantined": key.quarantined,
+                            });
+                            (index.to_string(), key)
+                        })
+                        .collect();
+                    stats_json["keys"] = Value::Object(keys);
+                }
+                (url.clone(), stats_json)
+            })
+            .collect();
+        let secs = |latency: Option<Duration>| latency.map(|latency| latency.as_secs_f64());
+        json!({
+            "tasks_started": self.num_tasks_started,
+            "tasks_in_progress": self.num_tasks_in_progress,
+            "tasks_succeeded": self.num_tasks_succeeded,
+            "tasks_failed": self.num_tasks_failed,
+            "tasks_invalid": self.num_tasks_invalid,
+            "tasks_skipped": self.num_tasks_skipped,
+            "tasks_cancelled": self.num_tasks_cancelled,
+            "cache_hits": self.num_cache_hits,
+            "budget_exhausted": self.num_budget_exhausted,
+            "tasks_unprocessed": self.num_tasks_unprocessed,
+            "lines_sampled": self.num_lines_sampled,
+            "lines_not_sampled": self.num_lines_not_sampled,
+            "retries": self.num_retries,
+            "retry_rate": self.retry_rate(),
+            "retries_shed": self.num_retries_shed,
+            "rate_limit_errors": self.num_rate_limit_errors,
+            "api_errors": self.num_api_errors,
+            "schema_failures": self.num_schema_failures,
+            "connections_opened": self.num_connections_opened,
+            "connections_reused": self.num_connections_reused,
+            "other_errors": self.num_other_errors,
+            "prompt_tokens": self.num_prompt_tokens,
+            "completion_tokens": self.num_completion_tokens,
+            "estimated_cost": self.estimated_cost,
+            "endpoints": endpoints,
+            "latency_secs": {
+                "count": self.latency.count(),
+                "mean": secs(self.latency.mean()),
+                "p50": secs(self.latency.percentile(50.0)),
+                "p90": secs(self.latency.percentile(90.0)),
+                "p99": secs(self.latency.percentile(99.0)),
+            },
+        })
+    }
+}
+
+/// Relative width of each histogram bucket; percentiles are accurate to about this much
+const BUCKET_GROWTH: f64 = 1.05;
+/// Enough buckets to cover 1 microsecond up to a few hours
+const NUM_BUCKETS: usize = 500;
+
+/// Fixed-size histogram of request latencies with logarithmic buckets
+///
+/// Memory use is constant no matter how many latencies are recorded.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: vec![0; NUM_BUCKETS],
+            count: 0,
+            sum: Duration::from_secs(0),
+            max: Duration::from_secs(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Record one latency sample
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_secs_f64() * 1_000_000.0;
+        let index = if micros <= 1.0 {
+            0
+        } else {
+            ((micros.ln() / BUCKET_GROWTH.ln()).ceil() as usize).min(NUM_BUCKETS - 1)
+        };
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.sum += latency;
+        self.max = self.max.max(latency);
+    }
+
+    /// Number of samples recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Mean latency, if anything was recorded
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(self.sum.as_secs_f64() / self.count as f64))
+    }
+
+    /// Approximate latency below which `percentile` percent of samples fall
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                // Report the bucket's upper bound, but never more than the slowest sample
+                let upper = Duration::from_secs_f64(BUCKET_GROWTH.powi(index as i32) / 1_000_000.0);
+                return Some(upper.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+}
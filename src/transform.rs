@@ -0,0 +1,168 @@
+use crate::endpoint::Endpoint;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde_json::{json, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// Hooks adapting requests to an endpoint and its responses to a common shape
+///
+/// Every method does nothing by default. They are called for every attempt,
+/// from the tasks sending requests, and get the endpoint the attempt goes to,
+/// so one transform can treat endpoints differently.
+pub trait EndpointTransform: Send + Sync {
+    /// Adjust the JSON body before it is encoded; a batch's body is the array of its requests' bodies
+    fn transform_body(&self, _endpoint: &Endpoint, _body: &mut Value) {}
+
+    /// Adjust the headers of the request, before it is signed
+    fn transform_headers(&self, _endpoint: &Endpoint, _headers: &mut HeaderMap) {}
+
+    /// Turn a parsed response into the shape it is judged and saved in
+    fn normalize_response(&self, _endpoint: &Endpoint, response: Value) -> Value {
+        response
+    }
+}
+
+/// Built-in profiles for the request and response shapes of API providers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// OpenAI-compatible chat completions, which the client speaks as is
+    #[default]
+    OpenAi,
+    /// Anthropic's Messages API, with responses reshaped like chat completions
+    ///
+    /// Requests carry the API key in `x-api-key` with an `anthropic-version`,
+    /// `system` messages move to the top-level `system` field and `max_tokens`
+    /// defaults to 1024. Replies are saved as `choices[0].message.content`,
+    /// and their token counts as `usage.prompt_tokens` and `usage.completion_tokens`.
+    Anthropic,
+}
+
+/// Messages API version sent unless the request already names one
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+/// `max_tokens` is required by the Messages API; this is used where the body lacks it
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u64 = 1024;
+
+impl EndpointTransform for Provider {
+    fn transform_body(&self, _endpoint: &Endpoint, body: &mut Value) {
+        if let Provider::Anthropic = self {
+            anthropic_body(body);
+        }
+    }
+
+    fn transform_headers(&self, _endpoint: &Endpoint, headers: &mut HeaderMap) {
+        if let Provider::Anthropic = self {
+            if let Some(authorization) = headers.remove(AUTHORIZATION) {
+                let key = authorization.as_bytes().strip_prefix(b"Bearer ").unwrap_or(authorization.as_bytes());
+                if let Ok(mut key) = HeaderValue::from_bytes(key) {
+                    key.set_sensitive(true);
+                    headers.insert(HeaderName::from_static("x-api-key"), key);
+                }
+            }
+            headers
+                .entry(HeaderName::from_static("anthropic-version"))
+                .or_insert(HeaderValue::from_static(ANTHROPIC_VERSION));
+        }
+    }
+
+    fn normalize_response(&self, _endpoint: &Endpoint, response: Value) -> Value {
+        match self {
+            Provider::OpenAi => response,
+            Provider::Anthropic => anthropic_response(response),
+        }
+    }
+}
+
+fn anthropic_body(body: &mut Value) {
+    let object = match body {
+        Value::Array(bodies) => {
+            bodies.iter_mut().for_each(anthropic_body);
+            return;
+        }
+        Value::Object(object) => object,
+        _ => return,
+    };
+    let system = match object.get_mut("messages") {
+        Some(Value::Array(messages)) => {
+            let (system, rest): (Vec<Value>, Vec<Value>) =
+                messages.drain(..).partition(|message| message.get("role").and_then(Value::as_str) == Some("system"));
+            *messages = rest;
+            system
+                .iter()
+                .filter_map(|message| message.get("content").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        }
+        _ => String::new(),
+    };
+    if !system.is_empty() && !object.contains_key("system") {
+        object.insert("system".to_string(), Value::String(system));
+    }
+    object.entry("max_tokens").or_insert_with(|| Value::from(ANTHROPIC_DEFAULT_MAX_TOKENS));
+}
+
+/// Reshape a Messages API reply like a chat completion; errors and other bodies are left alone
+fn anthropic_response(response: Value) -> Value {
+    if response.get("type").and_then(Value::as_str) != Some("message") {
+        return response;
+    }
+    let content: String = response
+        .get("content")
+        .and_then(Value::as_array)
+        .map(|blocks| {
+            blocks
+                .iter()
+                .filter(|block| block.get("type").and_then(Value::as_str) == Some("text"))
+                .filter_map(|block| block.get("text").and_then(Value::as_str))
+                .collect()
+        })
+        .unwrap_or_default();
+    let finish_reason = match response.get("stop_reason").and_then(Value::as_str) {
+        Some("end_turn") | Some("stop_sequence") => Value::from("stop"),
+        Some("max_tokens") => Value::from("length"),
+        Some("tool_use") => Value::from("tool_calls"),
+        Some(reason) => Value::from(reason),
+        None => Value::Null,
+    };
+    let mut normalized = json!({
+        "id": response.get("id"),
+        "object": "chat.completion",
+        "model": response.get("model"),
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": finish_reason,
+        }],
+    });
+    if let Some(usage) = response.get("usage") {
+        let prompt_tokens = usage.get("input_tokens").and_then(Value::as_u64).unwrap_or(0);
+        let completion_tokens = usage.get("output_tokens").and_then(Value::as_u64).unwrap_or(0);
+        normalized["usage"] = json!({
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens,
+        });
+    }
+    normalized
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openai" => Ok(Provider::OpenAi),
+            "anthropic" => Ok(Provider::Anthropic),
+            _ => Err(format!("unknown provider '{}', expected openai or anthropic", s)),
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Provider::OpenAi => "openai",
+            Provider::Anthropic => "anthropic",
+        })
+    }
+}
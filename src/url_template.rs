@@ -0,0 +1,113 @@
+use crate::json_path::JsonPath;
+use hyper::Uri;
+use serde_json::Value;
+use std::fmt::Write as _;
+
+/// A piece of an endpoint URL
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(JsonPath),
+}
+
+/// An endpoint URL with `{field}` placeholders filled in from each request
+///
+/// Placeholders are JSON paths into the input line, e.g. `/v1/items/{id}` or
+/// `?lang={metadata.lang}`, and may only appear in the path and query.
+/// Substituted values are percent-encoded, so they cannot add path segments
+/// or query parameters of their own.
+#[derive(Debug, Clone)]
+pub(crate) struct UrlTemplate {
+    parts: Vec<Part>,
+    /// The parsed URL when there are no placeholders
+    fixed: Option<Uri>,
+}
+
+impl UrlTemplate {
+    /// Parse an endpoint URL, which must be an absolute http or https URL
+    pub fn new(url: &str) -> Result<Self, String> {
+        let parts = parse_parts(url)?;
+        // Placeholders stand in for a path segment here, so the URL can be checked as a whole
+        let mut sample = String::new();
+        for part in &parts {
+            match part {
+                Part::Text(s) => sample.push_str(s),
+                Part::Field(_) => sample.push('0'),
+            }
+        }
+        let uri: Uri = sample.parse().map_err(|e| format!("invalid endpoint URL '{}': {}", url, e))?;
+        if !matches!((uri.scheme_str(), uri.authority()), (Some("http") | Some("https"), Some(_))) {
+            return Err(format!("endpoint URL '{}' must be an absolute http or https URL", url));
+        }
+        let templated = parts.iter().any(|part| matches!(part, Part::Field(_)));
+        if let Some(Part::Text(prefix)) = parts.first().filter(|_| templated) {
+            let host_end = prefix.find("://").map(|scheme_end| scheme_end + 3);
+            if host_end.is_none_or(|host_end| !prefix[host_end..].contains('/')) {
+                return Err(format!("endpoint URL '{}' may only have placeholders in its path and query", url));
+            }
+        }
+        Ok(UrlTemplate {
+            fixed: if templated { None } else { Some(uri) },
+            parts,
+        })
+    }
+
+    /// The URL itself, unless it has placeholders
+    pub fn fixed(&self) -> Option<&Uri> {
+        self.fixed.as_ref()
+    }
+
+    /// The URL for one request
+    pub fn render(&self, request: &Value) -> Result<Uri, String> {
+        if let Some(uri) = &self.fixed {
+            return Ok(uri.clone());
+        }
+        let mut url = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(s) => url.push_str(s),
+                Part::Field(path) => {
+                    let value = match path.get(request) {
+                        Some(Value::String(s)) => s.clone(),
+                        Some(value @ (Value::Number(_) | Value::Bool(_))) => value.to_string(),
+                        Some(_) => return Err(format!("URL field '{}' must be a string, number or boolean", path)),
+                        None => return Err(format!("request has no value for URL field '{}'", path)),
+                    };
+                    encode(&value, &mut url);
+                }
+            }
+        }
+        url.parse().map_err(|e| format!("invalid URL '{}': {}", url, e))
+    }
+}
+
+/// Split a URL into literal text and `{field}` placeholders
+fn parse_parts(url: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = url;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder in endpoint URL '{}'", url))?;
+        parts.push(Part::Field(rest[start + 1..start + end].trim().parse()?));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+/// Percent-encode everything but the characters RFC 3986 leaves unreserved
+pub(crate) fn encode(value: &str, out: &mut String) {
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{:02X}", byte);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, Client, Config, ConfigBuilder};
+use common::{read_jsonl, MockServer, TempDir};
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+
+fn builder(server: &MockServer, dir: &TempDir) -> ConfigBuilder {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .max_requests_per_second(1000.0)
+        .max_attempts(1)
+        .batch_size(2)
+}
+
+fn lines(count: usize) -> Vec<Value> {
+    (0..count).map(|i| json!({ "input": format!("line-{}", i) })).collect()
+}
+
+fn task_ids(records: &[Value]) -> Vec<u64> {
+    let mut task_ids: Vec<u64> = records.iter().map(|record| record["task_id"].as_u64().unwrap()).collect();
+    task_ids.sort();
+    task_ids
+}
+
+/// The inputs of the records of the error file, which carry no task id
+fn failed_inputs(dir: &TempDir) -> Vec<String> {
+    let mut inputs: Vec<String> = read_jsonl(dir.file("errors.jsonl")).iter().map(|record| record["input"].as_str().unwrap().to_string()).collect();
+    inputs.sort();
+    inputs
+}
+
+/// The user messages of the bodies in each request the server received
+fn batches(server: &MockServer) -> Vec<Vec<String>> {
+    server
+        .requests()
+        .iter()
+        .map(|request| {
+            let bodies = request.body.as_array().expect("a batch body is an array");
+            bodies.iter().map(|body| body["messages"][1]["content"].as_str().unwrap().to_string()).collect()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn each_element_of_a_batch_response_is_its_lines_outcome() {
+    // The second line of every batch is rejected
+    let server = MockServer::with_responder(|_, _| (StatusCode::OK, json!([{ "echo": "ok" }, { "errors": ["bad"] }]))).await;
+    let dir = TempDir::new("batching-split");
+    let requests = dir.write_jsonl("requests.jsonl", &lines(4));
+
+    let tracker = process_api_requests_from_file(&requests, builder(&server, &dir).build().unwrap()).await.unwrap();
+
+    let mut sent = batches(&server);
+    sent.sort();
+    assert_eq!(sent, [["line-0", "line-1"], ["line-2", "line-3"]]);
+    let results = read_jsonl(dir.file("results.jsonl"));
+    assert_eq!(task_ids(&results), [0, 2]);
+    assert!(results.iter().all(|record| record["echo"] == "ok"));
+    assert_eq!(failed_inputs(&dir), ["line-1", "line-3"]);
+    let tracker = tracker.lock().unwrap();
+    assert_eq!((tracker.num_tasks_succeeded, tracker.num_tasks_failed), (2, 2));
+}
+
+#[tokio::test]
+async fn a_response_of_the_wrong_length_fails_the_whole_batch() {
+    let server = MockServer::with_responder(|_, _| (StatusCode::OK, json!([{ "echo": "ok" }]))).await;
+    let dir = TempDir::new("batching-length");
+    // The last line makes a partial batch of its own once the input ends
+    let requests = dir.write_jsonl("requests.jsonl", &lines(3));
+
+    process_api_requests_from_file(&requests, builder(&server, &dir).build().unwrap()).await.unwrap();
+
+    assert_eq!(batches(&server).iter().map(Vec::len).sum::<usize>(), 3);
+    assert_eq!(task_ids(&read_jsonl(dir.file("results.jsonl"))), [2]);
+    assert_eq!(failed_inputs(&dir), ["line-0", "line-1"]);
+}
+
+#[tokio::test]
+async fn a_followed_input_sends_a_partial_batch_after_the_linger() {
+    let server = MockServer::with_responder(|_, _| (StatusCode::OK, json!([{ "echo": "ok" }, { "echo": "ok" }, { "echo": "ok" }]))).await;
+    let dir = TempDir::new("batching-linger");
+    let requests = dir.write_jsonl("requests.jsonl", &lines(3));
+    let config = builder(&server, &dir).batch_size(10).batch_linger(Duration::from_millis(50)).build().unwrap();
+    let client = Client::new(config).unwrap();
+    let shutdown = client.shutdown_handle();
+
+    // The file never ends, so only the linger sends the three lines
+    let start = Instant::now();
+    let outcomes = client.stream_file_follow(&requests);
+    pin_utils::pin_mut!(outcomes);
+    for _ in 0..3 {
+        let outcome = tokio::time::timeout(Duration::from_secs(5), outcomes.next()).await.expect("the batch was held back");
+        assert!(outcome.unwrap().is_ok());
+    }
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(batches(&server), [["line-0", "line-1", "line-2"]]);
+
+    shutdown.shutdown();
+    assert!(tokio::time::timeout(Duration::from_secs(5), outcomes.next()).await.unwrap().is_none());
+}
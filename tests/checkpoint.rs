@@ -0,0 +1,152 @@
+mod common;
+
+use api_processor::{Client, ClientError, Config, ConfigBuilder};
+use common::{canned, MockServer, TempDir};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+fn builder(server: &MockServer, dir: &TempDir) -> ConfigBuilder {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .checkpoint_filepath(Some(dir.file("checkpoint.json")))
+        .max_requests_per_second(1000.0)
+}
+
+/// Task ids of the records of a file, leaving out a line still being written
+fn task_ids(path: String) -> Vec<u64> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .map(|record| record["task_id"].as_u64().unwrap())
+        .collect()
+}
+
+fn lines(count: usize) -> Vec<Value> {
+    (0..count).map(|i| json!({ "input": format!("line-{}", i) })).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_stopped_run_resumes_without_losing_or_repeating_tasks() {
+    let server = MockServer::with_responder(|content, attempt| {
+        std::thread::sleep(Duration::from_millis(5));
+        canned(content, attempt)
+    })
+    .await;
+    let dir = TempDir::new("checkpoint-resume");
+    let requests = dir.write_jsonl("requests.jsonl", &lines(60));
+    let config = builder(&server, &dir).checkpoint_every(5).workers(Some(2)).build().unwrap();
+    let client = Client::new(config).unwrap();
+    let shutdown = client.shutdown_handle();
+    let path = requests.clone();
+    let run = tokio::spawn(async move { client.process_file(&path).await });
+
+    // The checkpoint never gets ahead of the save file
+    let mut checked = 0;
+    while !run.is_finished() {
+        if let Ok(checkpoint) = std::fs::read_to_string(dir.file("checkpoint.json")) {
+            let next_task_id = serde_json::from_str::<Value>(&checkpoint).unwrap()["next_task_id"].as_u64().unwrap();
+            let written: BTreeSet<u64> = task_ids(dir.file("results.jsonl")).into_iter().collect();
+            assert!((0..next_task_id).all(|task_id| written.contains(&task_id)), "checkpoint at {} is ahead of {:?}", next_task_id, written);
+            checked = checked.max(next_task_id);
+            if next_task_id >= 20 {
+                shutdown.shutdown();
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    run.await.unwrap().unwrap();
+    assert!(checked >= 20);
+    assert!(task_ids(dir.file("results.jsonl")).len() < 60, "the run was not stopped partway");
+
+    let sent = server.requests().len();
+    let config = builder(&server, &dir).resume(true).upsert(true).append(true).build().unwrap();
+    Client::new(config).unwrap().process_file(&requests).await.unwrap();
+    // Only the lines from the checkpoint on are sent again
+    assert!(server.requests().len() - sent <= 60 - 20);
+
+    let mut written = task_ids(dir.file("results.jsonl"));
+    written.sort();
+    assert_eq!(written, (0..60).collect::<Vec<u64>>());
+}
+
+/// Write a checkpoint of a run with this repeat, which reached this task, and whose endpoint has this quota
+fn write_checkpoint(dir: &TempDir, server: &MockServer, repeat: usize, next_task_id: usize, quota: Value) {
+    let mut endpoints = HashMap::new();
+    endpoints.insert(server.endpoint().url, json!({ "quota": quota }));
+    let checkpoint = json!({
+        "version": 1,
+        "saved_at": 0.0,
+        "repeat": repeat,
+        "completed_through": next_task_id - 1,
+        "next_task_id": next_task_id,
+        "endpoints": endpoints,
+    });
+    std::fs::write(dir.file("checkpoint.json"), checkpoint.to_string()).unwrap();
+}
+
+#[tokio::test]
+async fn resuming_skips_the_lines_before_the_checkpoint() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("checkpoint-lines");
+    let requests = dir.write_jsonl("requests.jsonl", &lines(6));
+    // Task 7 is the second repeat of line 3, so the whole line is sent again
+    write_checkpoint(&dir, &server, 2, 7, Value::Null);
+    let config = builder(&server, &dir).repeat(2).resume(true).build().unwrap();
+    let client = Client::new(config).unwrap();
+
+    client.process_file(&requests).await.unwrap();
+    let mut written = task_ids(dir.file("results.jsonl"));
+    written.sort();
+    assert_eq!(written, [6, 7, 8, 9, 10, 11]);
+
+    // Only the first run of the client resumes; later ones start from the beginning
+    client.process_file(&requests).await.unwrap();
+    assert_eq!(task_ids(dir.file("results.jsonl")).len(), 6 + 12);
+}
+
+#[tokio::test]
+async fn a_larger_skip_than_the_checkpoint_wins() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("checkpoint-skip");
+    let requests = dir.write_jsonl("requests.jsonl", &lines(6));
+    write_checkpoint(&dir, &server, 1, 2, Value::Null);
+    let config = builder(&server, &dir).resume(true).skip(4).build().unwrap();
+
+    Client::new(config).unwrap().process_file(&requests).await.unwrap();
+    let mut written = task_ids(dir.file("results.jsonl"));
+    written.sort();
+    assert_eq!(written, [4, 5]);
+}
+
+#[tokio::test]
+async fn a_checkpoint_of_another_repeat_is_refused() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("checkpoint-repeat");
+    let requests = dir.write_jsonl("requests.jsonl", &lines(3));
+    write_checkpoint(&dir, &server, 2, 2, Value::Null);
+    let config = builder(&server, &dir).resume(true).build().unwrap();
+
+    let result = Client::new(config).unwrap().process_file(&requests).await;
+    assert!(matches!(result, Err(ClientError::InvalidConfig(message)) if message.contains("repeat 2")));
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn resuming_restores_the_endpoint_quotas() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("checkpoint-quota");
+    let requests = dir.write_jsonl("requests.jsonl", &lines(2));
+    // The endpoint had used up its quota, which resets a second from now
+    let reset_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64() + 1.0;
+    write_checkpoint(&dir, &server, 1, 1, json!({ "remaining": 0, "reset_at": reset_at }));
+    let config = builder(&server, &dir).resume(true).build().unwrap();
+
+    let start = Instant::now();
+    Client::new(config).unwrap().process_file(&requests).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(800), "sent before the quota reset");
+    assert_eq!(task_ids(dir.file("results.jsonl")), [1]);
+}
@@ -0,0 +1,202 @@
+//! Shared helpers for integration tests: a canned-response mock API, a raw HTTP server and scratch files
+#![allow(dead_code)]
+
+use api_processor::Endpoint;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Decides the status and body for a request from its user message and attempt number (starting at 1)
+///
+/// A `null` body is sent as an empty one.
+pub type Responder = dyn Fn(&str, usize) -> (StatusCode, Value) + Send + Sync;
+
+/// A request as the mock server saw it
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+/// A local API server answering chat-style requests with canned responses
+pub struct MockServer {
+    pub addr: SocketAddr,
+    attempts: Arc<Mutex<HashMap<String, usize>>>,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+impl MockServer {
+    /// Start a server answering with `canned`
+    pub async fn start() -> Self {
+        Self::with_responder(canned).await
+    }
+
+    /// Start a server answering with a custom responder
+    pub async fn with_responder<F>(respond: F) -> Self
+    where
+        F: Fn(&str, usize) -> (StatusCode, Value) + Send + Sync + 'static,
+    {
+        let respond: Arc<Responder> = Arc::new(respond);
+        let attempts: Arc<Mutex<HashMap<String, usize>>> = Arc::default();
+        let requests: Arc<Mutex<Vec<ReceivedRequest>>> = Arc::default();
+        let (service_attempts, service_requests) = (Arc::clone(&attempts), Arc::clone(&requests));
+        let make_service = make_service_fn(move |_| {
+            let (respond, attempts, requests) = (Arc::clone(&respond), Arc::clone(&service_attempts), Arc::clone(&service_requests));
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let (respond, attempts, requests) = (Arc::clone(&respond), Arc::clone(&attempts), Arc::clone(&requests));
+                    async move {
+                        let (parts, body) = req.into_parts();
+                        let body = hyper::body::to_bytes(body).await.unwrap();
+                        let payload: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                        let content = payload["messages"][1]["content"].as_str().unwrap_or_default().to_string();
+                        let attempt = {
+                            let mut attempts = attempts.lock().unwrap();
+                            let attempt = attempts.entry(content.clone()).or_insert(0);
+                            *attempt += 1;
+                            *attempt
+                        };
+                        requests.lock().unwrap().push(ReceivedRequest {
+                            path: parts.uri.path().to_string(),
+                            headers: parts.headers,
+                            body: payload,
+                        });
+                        let (status, body) = respond(&content, attempt);
+                        let body = if body.is_null() { Body::empty() } else { Body::from(body.to_string()) };
+                        let response = Response::builder().status(status).body(body);
+                        Ok::<_, Infallible>(response.unwrap())
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        MockServer { addr, attempts, requests }
+    }
+
+    /// An endpoint pointing at this server, with weight 1
+    pub fn endpoint(&self) -> Endpoint {
+        self.endpoint_at("/")
+    }
+
+    /// An endpoint pointing at `path` on this server, with weight 1
+    pub fn endpoint_at(&self, path: &str) -> Endpoint {
+        Endpoint::builder(format!("http://{}{}", self.addr, path)).api_key("test").build().unwrap()
+    }
+
+    /// How many times a user message has been received
+    pub fn attempts(&self, content: &str) -> usize {
+        self.attempts.lock().unwrap().get(content).copied().unwrap_or(0)
+    }
+
+    /// Every request received so far, with its JSON body
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Start a server writing raw HTTP responses, for replies hyper would not send, such as a body cut short
+///
+/// `respond` gets the number of each connection, counting from 0, and returns the whole response
+/// to write to it; the connection is closed after that.
+pub async fn start_raw_server<F, R>(respond: F) -> SocketAddr
+where
+    F: Fn(usize) -> R + Send + Sync + 'static,
+    R: Future<Output = String> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for connection in 0.. {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let response = respond(connection);
+            tokio::spawn(async move {
+                // The requests are small, so one read holds the whole of one
+                let mut buf = vec![0; 64 * 1024];
+                let _ = socket.read(&mut buf).await;
+                let _ = socket.write_all(response.await.as_bytes()).await;
+            });
+        }
+    });
+    addr
+}
+
+/// The default responses, keyed on the user message:
+/// - a status code such as `429` or `500` is always answered with that status
+/// - `flaky-<code>...` is answered with that status on the first attempt and 200 afterwards
+/// - `error...` is answered 200 with an `errors` array
+/// - anything else is echoed back with 200
+pub fn canned(content: &str, attempt: usize) -> (StatusCode, Value) {
+    if let Ok(status) = content.parse::<u16>() {
+        return (StatusCode::from_u16(status).unwrap(), json!({ "status": status }));
+    }
+    if let Some(rest) = content.strip_prefix("flaky-") {
+        let code: u16 = rest.get(..3).and_then(|code| code.parse().ok()).unwrap_or(429);
+        if attempt == 1 {
+            return (StatusCode::from_u16(code).unwrap(), json!({ "status": code }));
+        }
+    }
+    if content.starts_with("error") {
+        return (StatusCode::OK, json!({ "errors": [content] }));
+    }
+    (StatusCode::OK, json!({ "echo": content }))
+}
+
+/// A scratch directory removed when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a fresh directory named after `name`
+    pub fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "api_processor-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    /// Path of a file inside the directory, as a string for the config builder
+    pub fn file(&self, name: &str) -> String {
+        self.path.join(name).to_string_lossy().into_owned()
+    }
+
+    /// Write one JSON object per line to a file in the directory and return its path
+    pub fn write_jsonl(&self, name: &str, lines: &[Value]) -> String {
+        let path = self.file(name);
+        let contents: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Parse a JSONL file, treating a missing file as empty
+pub fn read_jsonl<P: AsRef<Path>>(path: P) -> Vec<Value> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
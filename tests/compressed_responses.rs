@@ -0,0 +1,64 @@
+use api_processor::{Client, Config, Endpoint};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::io::Write;
+use std::net::SocketAddr;
+use tokio_stream::StreamExt;
+
+/// Start a server that answers every request with a JSON body compressed as `encoding`
+async fn start_compressing_server(encoding: &'static str) -> SocketAddr {
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+            let accept_encoding = req.headers()["accept-encoding"].to_str().unwrap().to_string();
+            let body = json!({ "answer": 42, "accept_encoding": accept_encoding }).to_string();
+            let compressed = match encoding {
+                "gzip" => {
+                    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(body.as_bytes()).unwrap();
+                    encoder.finish().unwrap()
+                }
+                _ => {
+                    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(body.as_bytes()).unwrap();
+                    encoder.finish().unwrap()
+                }
+            };
+            let response = Response::builder().header("Content-Encoding", encoding).body(Body::from(compressed));
+            Ok::<_, Infallible>(response.unwrap())
+        }))
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+/// Send one request to a server using `encoding` and return the parsed response
+async fn fetch_compressed(encoding: &'static str) -> Value {
+    let addr = start_compressing_server(encoding).await;
+    let config = Config::builder()
+        .endpoint(Endpoint::builder(format!("http://{}/", addr)).api_key("test").build().unwrap())
+        .build()
+        .unwrap();
+    let client = Client::new(config).unwrap();
+    let mut results = client.stream(vec![json!({ "input": "hello" })]);
+    let outcome = results.next().await.expect("no outcome");
+    outcome.expect("request failed").response
+}
+
+#[tokio::test]
+async fn gzip_responses_are_decompressed() {
+    let response = fetch_compressed("gzip").await;
+    assert_eq!(response["answer"], 42);
+    assert_eq!(response["accept_encoding"], "gzip, deflate");
+}
+
+#[tokio::test]
+async fn deflate_responses_are_decompressed() {
+    let response = fetch_compressed("deflate").await;
+    assert_eq!(response["answer"], 42);
+}
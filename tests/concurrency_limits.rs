@@ -0,0 +1,74 @@
+mod common;
+
+use api_processor::{Client, Config, Endpoint};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+/// Requests a server is answering now, and the most it ever answered at once
+#[derive(Default)]
+struct InFlight {
+    now: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl InFlight {
+    fn enter(&self) {
+        let now = self.now.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+    }
+
+    fn leave(&self) {
+        self.now.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+/// Start a server that takes a while to answer, counting its requests in flight and those of all servers
+async fn start_slow_server(own: Arc<InFlight>, all: Arc<InFlight>) -> SocketAddr {
+    common::start_raw_server(move |_| {
+        let (own, all) = (Arc::clone(&own), Arc::clone(&all));
+        async move {
+            own.enter();
+            all.enter();
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            own.leave();
+            all.leave();
+            let body = json!({ "echo": "ok" }).to_string();
+            format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}", body.len(), body)
+        }
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn global_and_endpoint_concurrency_limits_are_never_exceeded() {
+    let all = Arc::new(InFlight::default());
+    let servers: Vec<Arc<InFlight>> = (0..3).map(|_| Arc::default()).collect();
+    let mut builder = Config::builder().max_requests_per_second(2000.0).max_attempts(1).max_concurrency(Some(4));
+    for server in &servers {
+        let addr = start_slow_server(Arc::clone(server), Arc::clone(&all)).await;
+        let endpoint = Endpoint::builder(format!("http://{}/", addr)).api_key("test").max_concurrency(2).build().unwrap();
+        builder = builder.endpoint(endpoint);
+    }
+    let client = Client::new(builder.build().unwrap()).unwrap();
+
+    let requests: Vec<Value> = (0..120).map(|i| json!({ "input": i.to_string() })).collect();
+    let outcomes: Vec<_> = tokio::time::timeout(Duration::from_secs(30), client.stream(requests).collect::<Vec<_>>())
+        .await
+        .expect("run did not finish");
+
+    assert_eq!(outcomes.len(), 120);
+    assert!(outcomes.iter().all(Result::is_ok));
+    // The endpoints could take six at once, so the global limit of four is what binds overall
+    assert_eq!(all.peak(), 4);
+    for server in &servers {
+        assert!(server.peak() <= 2, "an endpoint had {} requests in flight", server.peak());
+    }
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use api_processor::{Client, Config, DeadlineFormat, DeadlineHeader, Endpoint};
+use common::MockServer;
+use serde_json::json;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_stream::StreamExt;
+
+#[test]
+fn timeouts_are_rounded_down_to_the_millisecond() {
+    let remaining = Duration::from_micros(29_999_600);
+    assert_eq!(DeadlineFormat::TimeoutMs.render(remaining), "29999");
+    assert_eq!(DeadlineFormat::TimeoutSecs.render(remaining), "29.999");
+    assert_eq!(DeadlineFormat::TimeoutSecs.render(Duration::from_secs(5)), "5.000");
+    assert_eq!(DeadlineFormat::TimeoutSecs.render(Duration::from_micros(500)), "0.000");
+}
+
+#[test]
+fn grpc_timeouts_take_the_finest_unit_that_fits_in_8_digits() {
+    let render = |remaining| DeadlineFormat::Grpc.render(remaining);
+    assert_eq!(render(Duration::from_micros(29_950_900)), "29950m");
+    assert_eq!(render(Duration::from_millis(99_999_999)), "99999999m");
+    assert_eq!(render(Duration::from_millis(100_000_000)), "100000S");
+    assert_eq!(render(Duration::from_secs(100_000_000)), "1666666M");
+    assert_eq!(render(Duration::from_secs(6_000_000_000)), "1666666H");
+    // Beyond the largest unit the value is capped
+    assert_eq!(render(Duration::from_secs(400_000_000_000)), "99999999H");
+}
+
+#[test]
+fn unix_deadlines_are_now_plus_the_time_left() {
+    let now = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+    let before = now();
+    let deadline: u128 = DeadlineFormat::UnixMs.render(Duration::from_secs(30)).parse().unwrap();
+    let after = now();
+    assert!(before + 30_000 <= deadline && deadline <= after + 30_000);
+}
+
+#[tokio::test]
+async fn the_deadline_reaches_the_server() {
+    let server = MockServer::start().await;
+    let endpoint = |header| Endpoint::builder(format!("http://{}/", server.addr)).api_key("test").deadline_header(header).build().unwrap();
+    // Both formats write whole milliseconds for a 30 second timeout
+    for header in [DeadlineHeader::new("X-Request-Deadline"), DeadlineHeader::new("grpc-timeout").format(DeadlineFormat::Grpc)].iter() {
+        let config = Config::builder()
+            .endpoint(endpoint(header.clone()))
+            .ttfb_timeout(Some(Duration::from_secs(30)))
+            .max_requests_per_second(1000.0)
+            .build()
+            .unwrap();
+        let outcomes: Vec<_> = Client::new(config).unwrap().stream(vec![json!({ "input": "ok" })]).collect().await;
+        assert!(outcomes[0].is_ok());
+
+        let requests = server.requests();
+        let value = requests.last().unwrap().headers[header.name.as_str()].to_str().unwrap().to_string();
+        let millis: u64 = value.trim_end_matches('m').parse().unwrap();
+        assert!(millis <= 30_000 && millis > 25_000, "{} is not what is left of the timeout", value);
+    }
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use api_processor::{
+    process_api_requests_from_file, write_results, BackoffPolicy, ClientError, Config, ConfigBuilder, FailureCategory, ProcessedResult, RetryPolicy,
+    SuccessRule,
+};
+use common::{read_jsonl, MockServer, TempDir};
+use hyper::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// A config pointing at the mock server and writing into `dir`, with short backoffs
+fn config(server: &MockServer, dir: &TempDir) -> ConfigBuilder {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .max_requests_per_second(1000.0)
+        .max_attempts(2)
+        .backoff(BackoffPolicy {
+            base: Duration::from_millis(5),
+            multiplier: 1.0,
+            max: Duration::from_millis(5),
+        })
+}
+
+/// The `input` of every line in a results or errors file, sorted
+fn inputs(records: &[Value]) -> Vec<String> {
+    let mut inputs: Vec<String> = records
+        .iter()
+        .map(|record| record["input"].as_str().or_else(|| record["echo"].as_str()).unwrap().to_string())
+        .collect();
+    inputs.sort();
+    inputs
+}
+
+#[tokio::test]
+async fn canned_statuses_are_tracked_and_written() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("canned-statuses");
+    let requests = dir.write_jsonl(
+        "requests.jsonl",
+        &[
+            json!({ "input": "ok-1" }),
+            json!({ "input": "ok-2" }),
+            json!({ "input": "flaky-429" }),
+            json!({ "input": "429" }),
+            json!({ "input": "500" }),
+        ],
+    );
+    let config = config(&server, &dir).success_rule(SuccessRule::HttpStatus).build().unwrap();
+
+    let tracker = process_api_requests_from_file(&requests, config).await.unwrap();
+
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_started, 5);
+    assert_eq!(tracker.num_tasks_succeeded, 3);
+    assert_eq!(tracker.num_tasks_failed, 2);
+    assert_eq!(tracker.num_rate_limit_errors, 3);
+    assert_eq!(tracker.num_api_errors, 1);
+    assert_eq!(tracker.num_tasks_in_progress, 0);
+
+    assert_eq!(inputs(&read_jsonl(dir.file("results.jsonl"))), ["flaky-429", "ok-1", "ok-2"]);
+    assert_eq!(inputs(&read_jsonl(dir.file("errors.jsonl"))), ["429", "500"]);
+    // 429s are retried up to the attempt limit, 500s only when asked to
+    assert_eq!(server.attempts("429"), 2);
+    assert_eq!(server.attempts("500"), 1);
+}
+
+#[tokio::test]
+async fn server_errors_are_retried_when_configured() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("retry-5xx");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "flaky-503" }), json!({ "input": "500" })]);
+    let config = config(&server, &dir)
+        .retry_on("connect,timeout,429,5xx".parse::<RetryPolicy>().unwrap())
+        .build()
+        .unwrap();
+
+    let tracker = process_api_requests_from_file(&requests, config).await.unwrap();
+
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_succeeded, 1);
+    assert_eq!(tracker.num_tasks_failed, 1);
+    assert_eq!(tracker.num_api_errors, 3);
+    assert_eq!(server.attempts("flaky-503"), 2);
+    assert_eq!(server.attempts("500"), 2);
+    assert_eq!(inputs(&read_jsonl(dir.file("results.jsonl"))), ["flaky-503"]);
+    assert_eq!(inputs(&read_jsonl(dir.file("errors.jsonl"))), ["500"]);
+}
+
+#[tokio::test]
+async fn error_bodies_and_invalid_lines_are_not_saved_as_results() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("error-bodies");
+    let requests = dir.file("requests.jsonl");
+    std::fs::write(
+        &requests,
+        "{\"input\": \"ok\"}\nnot json\n{\"input\": \"bad-metadata\", \"metadata\": 5}\n{\"input\": \"error-1\"}\n",
+    )
+    .unwrap();
+    let config = config(&server, &dir).build().unwrap();
+
+    let tracker = process_api_requests_from_file(&requests, config).await.unwrap();
+
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_succeeded, 1);
+    assert_eq!(tracker.num_tasks_failed, 1);
+    assert_eq!(tracker.num_tasks_invalid, 1);
+    assert_eq!(inputs(&read_jsonl(dir.file("results.jsonl"))), ["ok"]);
+    let errors = read_jsonl(dir.file("errors.jsonl"));
+    assert_eq!(inputs(&errors), ["bad-metadata", "error-1"]);
+    let error_body = errors.iter().find(|record| record["input"] == "error-1").unwrap();
+    assert_eq!(error_body["error"], json!(["error-1"]));
+    // Unparseable and invalid lines never reach the server
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn a_failed_task_cancels_the_unsent_tasks_of_its_group() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("cancel-groups");
+    let lines = [
+        json!({ "input": "error-a", "group_id": "a" }),
+        json!({ "input": "ok-a1", "group_id": "a" }),
+        json!({ "input": "ok-b1", "group_id": "b" }),
+        json!({ "input": "ok-a2", "group_id": "a" }),
+        json!({ "input": "ok-none" }),
+    ];
+    let requests = dir.write_jsonl("requests.jsonl", &lines);
+    // One worker sends the tasks one after the other, so the failure comes before its siblings are sent
+    let config = config(&server, &dir).max_attempts(1).workers(Some(1)).cancel_groups(true).build().unwrap();
+
+    let tracker = process_api_requests_from_file(&requests, config).await.unwrap();
+
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_failed, 1);
+    assert_eq!(tracker.num_tasks_succeeded, 2);
+    assert_eq!((tracker.num_tasks_skipped, tracker.num_tasks_cancelled), (2, 2));
+    let results = read_jsonl(dir.file("results.jsonl"));
+    let skipped: Vec<u64> = results.iter().filter(|record| record["skipped"] == true).map(|record| record["task_id"].as_u64().unwrap()).collect();
+    assert_eq!(skipped, [1, 3]);
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn empty_bodies_on_success_statuses_are_saved_as_null() {
+    let server = MockServer::with_responder(|content, _| match content {
+        "no-content" => (StatusCode::NO_CONTENT, Value::Null),
+        "empty-200" => (StatusCode::OK, Value::Null),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, Value::Null),
+    })
+    .await;
+    let dir = TempDir::new("empty-bodies");
+    let requests = dir.write_jsonl(
+        "requests.jsonl",
+        &[json!({ "input": "no-content" }), json!({ "input": "empty-200" }), json!({ "input": "empty-500" })],
+    );
+    let config = config(&server, &dir).build().unwrap();
+
+    let tracker = process_api_requests_from_file(&requests, config).await.unwrap();
+
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_succeeded, 2);
+    assert_eq!(tracker.num_tasks_failed, 1);
+    let mut results = read_jsonl(dir.file("results.jsonl"));
+    results.sort_by_key(|record| record["task_id"].as_u64());
+    assert_eq!(results.len(), 2);
+    for (record, task_id) in results.iter().zip([0, 1]) {
+        assert_eq!(record["task_id"], json!(task_id));
+        assert_eq!(record["response"], Value::Null);
+    }
+    // An empty body on an error status is still a failure
+    assert_eq!(inputs(&read_jsonl(dir.file("errors.jsonl"))), ["empty-500"]);
+}
+
+#[tokio::test]
+async fn only_the_first_outcome_per_task_is_written() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("duplicate-outcomes");
+    let config = config(&server, &dir).build().unwrap();
+    let result = |task_id: usize, input: &str| ProcessedResult {
+        task_id,
+        input: json!({ "input": input }),
+        metadata: None,
+        response: json!({ "echo": input }),
+        endpoint: server.endpoint().url,
+        latency: Duration::from_millis(1),
+        skipped: false,
+        response_meta: None,
+        cached: None,
+    };
+    // Task 0 failed after a timeout and its slow first attempt then succeeded; task 1 succeeded twice
+    let outcomes = vec![
+        Err(ClientError::Request {
+            task_id: 0,
+            input: json!({ "input": "slow" }),
+            error: json!("operation timed out"),
+            category: FailureCategory::Timeout,
+        }),
+        Ok(result(1, "first")),
+        Ok(result(0, "slow")),
+        Ok(result(1, "second")),
+    ];
+
+    write_results(tokio_stream::iter(outcomes), &config).await.unwrap();
+
+    assert_eq!(inputs(&read_jsonl(dir.file("results.jsonl"))), ["first"]);
+    assert_eq!(inputs(&read_jsonl(dir.file("errors.jsonl"))), ["slow"]);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, BackoffPolicy, Client, Config};
+use common::{MockServer, TempDir};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+#[tokio::test]
+async fn every_attempt_is_written_to_the_har_file() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("har-export");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "ok-1" }), json!({ "input": "flaky-429" })]);
+    let config = Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .har_filepath(Some(dir.file("requests.har")))
+        .max_requests_per_second(1000.0)
+        .max_attempts(2)
+        .backoff(BackoffPolicy {
+            base: Duration::from_millis(5),
+            multiplier: 1.0,
+            max: Duration::from_millis(5),
+        })
+        .build()
+        .unwrap();
+
+    process_api_requests_from_file(&requests, config).await.unwrap();
+
+    let har = std::fs::read_to_string(dir.file("requests.har")).unwrap();
+    let har: Value = serde_json::from_str(&har).unwrap();
+    assert_eq!(har["log"]["version"], "1.2");
+    let entries = har["log"]["entries"].as_array().unwrap();
+    // The rate-limited attempt and its retry are entries of their own
+    let mut attempts: Vec<(&str, u64)> = entries
+        .iter()
+        .map(|entry| (entry["_requestId"].as_str().unwrap(), entry["response"]["status"].as_u64().unwrap()))
+        .collect();
+    attempts.sort();
+    assert_eq!(attempts, [("0-1", 200), ("1-1", 429), ("1-2", 200)]);
+
+    let entry = entries.iter().find(|entry| entry["_requestId"] == "0-1").unwrap();
+    assert_eq!(entry["request"]["method"], "POST");
+    assert!(entry["request"]["postData"]["text"].as_str().unwrap().contains("ok-1"));
+    assert!(entry["response"]["content"]["text"].as_str().unwrap().contains("ok-1"));
+    // The API key is never written
+    let authorization = entry["request"]["headers"].as_array().unwrap().iter().find(|header| header["name"] == "authorization").unwrap();
+    assert_eq!(authorization["value"], "<redacted>");
+    assert!(!har.to_string().contains("Bearer test"));
+}
+
+#[tokio::test]
+async fn creating_a_client_touches_no_file_until_it_runs() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("har-lazy");
+    std::fs::write(dir.file("requests.har"), "kept").unwrap();
+    let config = Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .har_filepath(Some(dir.file("requests.har")))
+        .cache_dir(Some(dir.file("cache")))
+        .queue_spill_dir(Some(dir.file("spill")))
+        .max_requests_per_second(1000.0)
+        .build()
+        .unwrap();
+
+    let client = Client::new(config).unwrap();
+    assert_eq!(client.check_line(&json!({ "input": "ok" })), Vec::<String>::new());
+    assert_eq!(std::fs::read_to_string(dir.file("requests.har")).unwrap(), "kept");
+    assert!(!std::path::Path::new(&dir.file("cache")).exists());
+    assert!(!std::path::Path::new(&dir.file("spill")).exists());
+
+    client.process_requests(vec![json!({ "input": "ok" })]).await.unwrap();
+    let har: Value = serde_json::from_str(&std::fs::read_to_string(dir.file("requests.har")).unwrap()).unwrap();
+    assert_eq!(har["log"]["entries"].as_array().unwrap().len(), 1);
+    assert!(std::path::Path::new(&dir.file("cache")).is_dir());
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, Config, Endpoint};
+use common::{read_jsonl, TempDir};
+use serde_json::json;
+
+/// Start a server answering every request with this body
+async fn start_server(body: &'static str) -> String {
+    let addr = common::start_raw_server(move |_| async move {
+        format!("HTTP/1.1 200 OK\r\ncontent-type: application/x-ndjson\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}", body.len(), body)
+    })
+    .await;
+    format!("http://{}/", addr)
+}
+
+fn config(url: String, dir: &TempDir) -> Config {
+    Config::builder()
+        .endpoint(Endpoint::builder(url).api_key("test").build().unwrap())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .ndjson_response(true)
+        .max_requests_per_second(1000.0)
+        .max_attempts(1)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn each_object_of_a_response_is_saved_as_a_record_of_its_task() {
+    let url = start_server("{\"part\":1}\n{\"part\":2}\nnot json\n\n{\"part\":3}\n").await;
+    let dir = TempDir::new("ndjson-records");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "a", "metadata": { "row": 7 } }), json!({ "input": "b" })]);
+
+    let tracker = process_api_requests_from_file(&requests, config(url, &dir)).await.unwrap();
+    assert_eq!(tracker.lock().unwrap().num_tasks_succeeded, 2);
+
+    let records = read_jsonl(dir.file("results.jsonl"));
+    let mut parts: Vec<(u64, u64)> = records.iter().map(|record| (record["task_id"].as_u64().unwrap(), record["part"].as_u64().unwrap())).collect();
+    parts.sort();
+    assert_eq!(parts, [(0, 1), (0, 2), (0, 3), (1, 1), (1, 2), (1, 3)]);
+    // Every record of a task carries its metadata, and the objects of a response stay in order
+    let first: Vec<_> = records.iter().filter(|record| record["task_id"] == 0).collect();
+    assert!(first.iter().all(|record| record["metadata"] == json!({ "row": 7 })), "{:?}", first);
+    assert_eq!(first.iter().map(|record| record["part"].as_u64().unwrap()).collect::<Vec<_>>(), [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn a_response_without_any_json_fails() {
+    let url = start_server("not json\nnor this\n").await;
+    let dir = TempDir::new("ndjson-empty");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "a" })]);
+
+    let tracker = process_api_requests_from_file(&requests, config(url, &dir)).await.unwrap();
+    assert_eq!(tracker.lock().unwrap().num_tasks_failed, 1);
+    assert!(read_jsonl(dir.file("results.jsonl")).is_empty());
+    let errors = read_jsonl(dir.file("errors.jsonl"));
+    assert!(errors[0]["error"].to_string().contains("no JSON objects"), "{}", errors[0]);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, ClientError, Config, ConfigBuilder};
+use common::{read_jsonl, MockServer, TempDir};
+use serde_json::json;
+
+/// A config sending to the mock server and writing into `dir`
+fn config(server: &MockServer, dir: &TempDir) -> ConfigBuilder {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .max_requests_per_second(1000.0)
+}
+
+/// The message of a config error, failing the test on any other outcome
+fn invalid_config<T>(result: Result<T, ClientError>) -> String {
+    match result {
+        Err(ClientError::InvalidConfig(message)) => message,
+        Err(e) => panic!("expected an invalid config, got {}", e),
+        Ok(_) => panic!("expected an invalid config"),
+    }
+}
+
+#[tokio::test]
+async fn save_and_error_files_must_differ() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("same-save-and-error");
+    let message = invalid_config(config(&server, &dir).error_filepath(dir.file("results.jsonl")).build());
+    assert!(message.contains("save file and the error file"), "{}", message);
+
+    // The same file written another way still collides
+    std::fs::create_dir(dir.file("sub")).unwrap();
+    let message = invalid_config(config(&server, &dir).error_filepath(dir.file("./sub/../results.jsonl")).build());
+    assert!(message.contains("save file and the error file"), "{}", message);
+}
+
+#[tokio::test]
+async fn dead_letter_file_must_differ_from_the_others() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("same-dead-letter");
+    let message = invalid_config(config(&server, &dir).dead_letter_filepath(Some(dir.file("results.jsonl"))).build());
+    assert!(message.contains("save file and the dead letter file"), "{}", message);
+    let message = invalid_config(config(&server, &dir).dead_letter_filepath(Some(dir.file("errors.jsonl"))).build());
+    assert!(message.contains("error file and the dead letter file"), "{}", message);
+
+    assert!(config(&server, &dir).dead_letter_filepath(Some(dir.file("dead.jsonl"))).build().is_ok());
+}
+
+#[tokio::test]
+async fn an_unused_save_file_may_match_another() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("no-save-collision");
+    let built = config(&server, &dir).error_filepath(dir.file("results.jsonl")).no_save(true).build();
+    assert!(built.is_ok());
+}
+
+#[tokio::test]
+async fn the_requests_file_is_never_written_to() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("save-over-input");
+    let lines = [json!({ "input": "ok-1" }), json!({ "input": "ok-2" })];
+    let requests = dir.write_jsonl("requests.jsonl", &lines);
+
+    let config = config(&server, &dir).save_filepath(requests.clone()).build().unwrap();
+    let message = invalid_config(process_api_requests_from_file(&requests, config).await);
+    assert!(message.contains("is also the save file"), "{}", message);
+
+    // Nothing was sent and the input is intact
+    assert!(server.requests().is_empty());
+    assert_eq!(read_jsonl(&requests), lines);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use api_processor::{Client, Config, ConfigBuilder};
+use common::{canned, read_jsonl, MockServer, TempDir};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+/// A server that takes a while over `slow` inputs, so the lines after them wait in the queue
+async fn start_server() -> MockServer {
+    MockServer::with_responder(|content, attempt| {
+        if content.starts_with("slow") {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        canned(content, attempt)
+    })
+    .await
+}
+
+fn builder(server: &MockServer) -> ConfigBuilder {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .max_requests_per_second(1000.0)
+        .max_attempts(1)
+        .workers(Some(1))
+}
+
+/// The inputs the server received, in arrival order
+fn sent(server: &MockServer) -> Vec<String> {
+    server
+        .requests()
+        .iter()
+        .map(|request| request.body["messages"][1]["content"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn higher_priorities_go_first_and_equal_ones_in_order() {
+    let server = start_server().await;
+    let client = Client::new(builder(&server).build().unwrap()).unwrap();
+    // The slow lines are taken as they are read and keep the worker busy while the rest queue up
+    let mut requests: Vec<Value> = (0..4).map(|i| json!({ "input": format!("slow-{}", i) })).collect();
+    requests.extend(vec![
+        json!({ "input": "a", "priority": 0 }),
+        json!({ "input": "b", "priority": 5 }),
+        json!({ "input": "c" }),
+        json!({ "input": "d", "priority": 5 }),
+        json!({ "input": "e", "priority": 9 }),
+    ]);
+
+    // The stream ends once the input is exhausted and the queue is empty
+    let outcomes: Vec<_> = tokio::time::timeout(Duration::from_secs(10), client.stream(requests).collect::<Vec<_>>())
+        .await
+        .expect("run did not finish");
+    assert_eq!(outcomes.len(), 9);
+    assert!(outcomes.iter().all(Result::is_ok));
+
+    let prioritized: Vec<String> = sent(&server).into_iter().filter(|input| !input.starts_with("slow")).collect();
+    assert_eq!(prioritized, ["e", "b", "d", "a", "c"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_shutdown_leaves_the_queued_requests_unprocessed() {
+    let server = start_server().await;
+    let dir = TempDir::new("priority-queue-shutdown");
+    let mut lines = vec![json!({ "input": "slow-0" })];
+    lines.extend((0..6).map(|i| json!({ "input": format!("queued-{}", i), "priority": i })));
+    let requests = dir.write_jsonl("requests.jsonl", &lines);
+    let config = builder(&server)
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .unprocessed_filepath(Some(dir.file("unprocessed.jsonl")))
+        .build()
+        .unwrap();
+    let client = Client::new(config).unwrap();
+    let shutdown = client.shutdown_handle();
+    let run = tokio::spawn(async move { client.process_file(&requests).await });
+
+    // Stop while the first request is still being answered, once the rest had time to queue up.
+    // The server's sleep can hold up tokio's timers, so this thread sleeps on its own.
+    while server.requests().is_empty() {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    std::thread::sleep(Duration::from_millis(50));
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(10), run).await.expect("run did not finish").unwrap().unwrap();
+
+    // The unprocessed file holds the serialized requests, the save file the results
+    let unprocessed: Vec<String> = read_jsonl(dir.file("unprocessed.jsonl"))
+        .iter()
+        .map(|request| request["original_input"]["input"].as_str().unwrap().to_string())
+        .collect();
+    let finished: Vec<String> = read_jsonl(dir.file("results.jsonl")).iter().map(|record| record["echo"].as_str().unwrap().to_string()).collect();
+    assert!(!unprocessed.is_empty());
+    // Every queued request is drained to the file, none of them sent
+    assert!(unprocessed.iter().all(|input| !sent(&server).contains(input)));
+    let mut accounted: Vec<String> = unprocessed.into_iter().chain(finished).collect();
+    accounted.sort();
+    let mut expected: Vec<String> = lines.iter().map(|line| line["input"].as_str().unwrap().to_string()).collect();
+    expected.sort();
+    assert_eq!(accounted, expected);
+}
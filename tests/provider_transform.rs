@@ -0,0 +1,55 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, Config, Endpoint, Provider};
+use common::{read_jsonl, MockServer, TempDir};
+use hyper::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn anthropic_requests_and_replies_round_trip_through_the_profile() {
+    // Answers like the Messages API
+    let server = MockServer::with_responder(|_, _| {
+        let reply = json!({
+            "type": "message",
+            "id": "msg_1",
+            "model": "claude-test",
+            "content": [{ "type": "text", "text": "Hello, " }, { "type": "tool_use", "id": "t" }, { "type": "text", "text": "world" }],
+            "stop_reason": "max_tokens",
+            "usage": { "input_tokens": 12, "output_tokens": 5 },
+        });
+        (StatusCode::OK, reply)
+    })
+    .await;
+    let dir = TempDir::new("provider-anthropic");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "hi" })]);
+    let config = Config::builder()
+        .endpoint(Endpoint::builder(format!("http://{}/", server.addr)).api_key("test").provider(Provider::Anthropic).build().unwrap())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .max_requests_per_second(1000.0)
+        .build()
+        .unwrap();
+
+    let tracker = process_api_requests_from_file(&requests, config).await.unwrap();
+
+    // The request went out in the Messages API's shape
+    let sent = &server.requests()[0];
+    assert_eq!(sent.headers["x-api-key"], "test");
+    assert_eq!(sent.headers["anthropic-version"], "2023-06-01");
+    assert!(sent.headers.get("authorization").is_none());
+    let messages = sent.body["messages"].as_array().unwrap();
+    assert!(messages.iter().all(|message| message["role"] != "system"), "{}", sent.body);
+    assert_eq!(messages.last().unwrap()["content"], "hi");
+    assert!(sent.body["system"].is_string(), "{}", sent.body);
+    // Required by the Messages API
+    assert!(sent.body["max_tokens"].is_u64(), "{}", sent.body);
+
+    // The reply was saved like a chat completion
+    let records = read_jsonl(dir.file("results.jsonl"));
+    assert_eq!(records[0]["object"], "chat.completion");
+    assert_eq!(records[0]["choices"][0]["message"], json!({ "role": "assistant", "content": "Hello, world" }));
+    assert_eq!(records[0]["choices"][0]["finish_reason"], "length");
+    assert_eq!(records[0]["usage"], json!({ "prompt_tokens": 12, "completion_tokens": 5, "total_tokens": 17 }));
+    let tracker = tracker.lock().unwrap();
+    assert_eq!((tracker.num_prompt_tokens, tracker.num_completion_tokens), (12, 5));
+}
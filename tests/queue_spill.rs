@@ -0,0 +1,46 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, Config};
+use common::{canned, read_jsonl, MockServer, TempDir};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requests_spilled_to_disk_are_all_sent() {
+    // Answers slowly, so the queue of two fills up long before the input is read
+    let server = MockServer::with_responder(|content, attempt| {
+        std::thread::sleep(Duration::from_millis(5));
+        canned(content, attempt)
+    })
+    .await;
+    let dir = TempDir::new("queue-spill");
+    let lines: Vec<Value> = (0..40).map(|i| json!({ "input": format!("line-{}", i) })).collect();
+    let requests = dir.write_jsonl("requests.jsonl", &lines);
+    let spill_dir = dir.file("spill");
+    let config = Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .max_requests_per_second(1000.0)
+        .workers(Some(1))
+        .queue_capacity(Some(2))
+        .queue_spill_dir(Some(spill_dir.clone()))
+        .build()
+        .unwrap();
+
+    let run = tokio::spawn(async move { process_api_requests_from_file(&requests, config).await });
+    let mut spilled = false;
+    while !run.is_finished() {
+        spilled |= std::fs::read_dir(&spill_dir).is_ok_and(|mut files| files.next().is_some());
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    run.await.unwrap().unwrap();
+    assert!(spilled, "no spill file was made");
+
+    // Every line was sent once, and the spill file is gone
+    let task_ids: BTreeSet<u64> = read_jsonl(dir.file("results.jsonl")).iter().map(|result| result["task_id"].as_u64().unwrap()).collect();
+    assert_eq!(task_ids, (0..40).collect());
+    assert_eq!(server.requests().len(), 40);
+    assert_eq!(std::fs::read_dir(dir.file("spill")).unwrap().count(), 0);
+}
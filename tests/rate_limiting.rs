@@ -0,0 +1,95 @@
+mod common;
+
+use api_processor::{Client, Config, Endpoint};
+use common::MockServer;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+
+/// Send two seconds' worth of requests at `rate` and return the measured requests per second
+async fn measured_rate(server: &MockServer, rate: usize) -> f64 {
+    let config = Config::builder()
+        .endpoint(server.endpoint())
+        .max_requests_per_second(rate as f64)
+        .max_attempts(1)
+        .build()
+        .unwrap();
+    let client = Client::new(config).unwrap();
+
+    // The first request leaves at once, so n + 1 requests span n intervals
+    let intervals = rate * 2;
+    let requests: Vec<Value> = (0..=intervals).map(|i| json!({ "input": format!("{}-{}", rate, i) })).collect();
+    let start = Instant::now();
+    // Time the last outcome rather than the end of the stream, which waits out one more interval
+    let outcomes: Vec<_> = tokio::time::timeout(
+        Duration::from_secs(10),
+        client.stream(requests).map(|outcome| (outcome, start.elapsed())).collect::<Vec<_>>(),
+    )
+    .await
+    .expect("run did not finish");
+
+    assert_eq!(outcomes.len(), intervals + 1);
+    assert!(outcomes.iter().all(|(outcome, _)| outcome.is_ok()));
+    let elapsed = outcomes.iter().map(|(_, elapsed)| *elapsed).max().unwrap();
+    intervals as f64 / elapsed.as_secs_f64()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn throughput_matches_rates_that_do_not_divide_a_second_evenly() {
+    let server = MockServer::start().await;
+    // 7/s and 13/s have fractional millisecond intervals; 1500/s is under a millisecond.
+    // The rates are measured side by side so the test takes as long as one run.
+    let rates = [7, 13, 1500];
+    let (a, b, c) = tokio::join!(
+        measured_rate(&server, rates[0]),
+        measured_rate(&server, rates[1]),
+        measured_rate(&server, rates[2])
+    );
+    for (rate, measured) in rates.iter().zip([a, b, c]) {
+        let error = (measured - *rate as f64).abs() / *rate as f64;
+        assert!(error < 0.05, "target {}/s, measured {:.2}/s", rate, measured);
+    }
+}
+
+/// Start a server answering every request with this status line and these rate limit headers
+async fn start_server_with_headers(status: &'static str, headers: &'static str) -> Endpoint {
+    let addr = common::start_raw_server(move |_| async move {
+        let body = json!({ "echo": "ok" }).to_string();
+        format!("HTTP/1.1 {}\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}", status, headers, body.len(), body)
+    })
+    .await;
+    Endpoint::builder(format!("http://{}/", addr)).api_key("test").build().unwrap()
+}
+
+#[tokio::test]
+async fn garbage_and_extreme_rate_limit_headers_do_not_stop_the_run() {
+    // Headers, and how many requests can follow them without waiting out a legitimately long reset
+    let cases = [
+        ("200 OK", "x-ratelimit-remaining: 4294967296\r\nx-ratelimit-reset: 1\r\n", 3),
+        ("200 OK", "x-ratelimit-remaining: 1e30\r\nx-ratelimit-reset: 1e300\r\n", 3),
+        ("200 OK", "x-ratelimit-remaining: 1\r\nx-ratelimit-reset: 1e20\r\n", 1),
+        ("200 OK", "x-ratelimit-remaining: 1\r\nx-ratelimit-reset: 1e300\r\n", 1),
+        ("200 OK", "x-ratelimit-remaining: lots\r\nx-ratelimit-reset: -5\r\n", 3),
+        ("200 OK", "x-ratelimit-remaining: NaN\r\nx-ratelimit-reset: inf\r\n", 3),
+        ("429 Too Many Requests", "retry-after: 1e20\r\n", 1),
+        ("429 Too Many Requests", "retry-after: 1e300\r\n", 1),
+        ("429 Too Many Requests", "retry-after: Fri, 31 Dec 9999 23:59:59 GMT\r\n", 1),
+        ("503 Service Unavailable", "retry-after: soon\r\n", 3),
+    ];
+    for (status, headers, requests) in cases.iter() {
+        let config = Config::builder()
+            .endpoint(start_server_with_headers(status, headers).await)
+            .max_requests_per_second(1000.0)
+            .max_attempts(1)
+            .build()
+            .unwrap();
+        let client = Client::new(config).unwrap();
+        let requests: Vec<Value> = (0..*requests).map(|i| json!({ "input": format!("line-{}", i) })).collect();
+
+        let outcomes: Vec<_> = tokio::time::timeout(Duration::from_secs(5), client.stream(requests.clone()).collect::<Vec<_>>())
+            .await
+            .unwrap_or_else(|_| panic!("run did not finish after {}", headers));
+        assert_eq!(outcomes.len(), requests.len(), "{}", headers);
+        assert_eq!(client.status_tracker().lock().unwrap().num_tasks_in_progress, 0, "{}", headers);
+    }
+}
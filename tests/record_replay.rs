@@ -0,0 +1,131 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, BackoffPolicy, Client, Config, ConfigBuilder, FailureCategory, RetryPolicy};
+use common::{read_jsonl, MockServer, TempDir};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// A config sending to the mock server and writing into `dir`, retrying 5xx with short backoffs
+fn config(server: &MockServer, dir: &TempDir) -> ConfigBuilder {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .max_requests_per_second(1000.0)
+        .max_attempts(2)
+        .retry_on("connect,timeout,429,5xx".parse::<RetryPolicy>().unwrap())
+        .backoff(BackoffPolicy {
+            base: Duration::from_millis(5),
+            multiplier: 1.0,
+            max: Duration::from_millis(5),
+        })
+}
+
+/// The records of a results or errors file in task order, without their latencies
+fn golden(path: String) -> Vec<Value> {
+    let mut records = read_jsonl(path);
+    for record in &mut records {
+        if let Some(record) = record.as_object_mut() {
+            record.remove("latency_ms");
+        }
+    }
+    records.sort_by_key(|record| record["task_id"].as_u64());
+    records
+}
+
+#[tokio::test]
+async fn replayed_runs_match_the_recorded_run_without_the_network() {
+    let server = MockServer::start().await;
+    let recordings = TempDir::new("recordings");
+    let lines = [json!({ "input": "ok-1" }), json!({ "input": "flaky-500" }), json!({ "input": "500" }), json!({ "input": "ok-2" })];
+
+    let recorded = TempDir::new("recorded-run");
+    let requests = recorded.write_jsonl("requests.jsonl", &lines);
+    let record = config(&server, &recorded).record_dir(Some(recordings.file(""))).build().unwrap();
+    let recorded_tracker = process_api_requests_from_file(&requests, record).await.unwrap();
+    let sent = server.requests().len();
+    assert_eq!(sent, 6);
+
+    let replayed = TempDir::new("replayed-run");
+    let requests = replayed.write_jsonl("requests.jsonl", &lines);
+    let replay = config(&server, &replayed).replay_dir(Some(recordings.file(""))).build().unwrap();
+    let replayed_tracker = process_api_requests_from_file(&requests, replay).await.unwrap();
+
+    // Nothing reached the server, yet retries and outputs came out the same
+    assert_eq!(server.requests().len(), sent);
+    assert_eq!(golden(replayed.file("results.jsonl")), golden(recorded.file("results.jsonl")));
+    assert_eq!(golden(replayed.file("errors.jsonl")), golden(recorded.file("errors.jsonl")));
+    let (recorded_tracker, replayed_tracker) = (recorded_tracker.lock().unwrap(), replayed_tracker.lock().unwrap());
+    assert_eq!(replayed_tracker.num_tasks_succeeded, 3);
+    assert_eq!(replayed_tracker.num_tasks_succeeded, recorded_tracker.num_tasks_succeeded);
+    assert_eq!(replayed_tracker.num_tasks_failed, recorded_tracker.num_tasks_failed);
+    assert_eq!(replayed_tracker.num_api_errors, recorded_tracker.num_api_errors);
+}
+
+#[tokio::test]
+async fn requests_that_were_not_recorded_fail_on_replay() {
+    let server = MockServer::start().await;
+    let recordings = TempDir::new("recordings");
+    let recorded = TempDir::new("recorded-run");
+    let requests = recorded.write_jsonl("requests.jsonl", &[json!({ "input": "ok-1" })]);
+    let record = config(&server, &recorded).record_dir(Some(recordings.file(""))).build().unwrap();
+    process_api_requests_from_file(&requests, record).await.unwrap();
+
+    let replayed = TempDir::new("replayed-run");
+    let requests = replayed.write_jsonl("requests.jsonl", &[json!({ "input": "ok-1" }), json!({ "input": "new" })]);
+    let replay = config(&server, &replayed).replay_dir(Some(recordings.file(""))).build().unwrap();
+    let tracker = process_api_requests_from_file(&requests, replay).await.unwrap();
+
+    assert_eq!(server.requests().len(), 1);
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_succeeded, 1);
+    assert_eq!(tracker.num_tasks_failed, 1);
+    assert_eq!(tracker.failures.categories.get(&FailureCategory::NotRecorded).map(|failures| failures.count), Some(1));
+}
+
+#[tokio::test]
+async fn responses_over_the_size_limit_are_not_recorded() {
+    let server = MockServer::start().await;
+    let recordings = TempDir::new("recordings");
+    let recorded = TempDir::new("recorded-run");
+    let long = "x".repeat(200);
+    let requests = recorded.write_jsonl("requests.jsonl", &[json!({ "input": "ok-1" }), json!({ "input": long })]);
+    let record = config(&server, &recorded)
+        .record_dir(Some(recordings.file("")))
+        .max_response_bytes(Some(64))
+        .build()
+        .unwrap();
+    let tracker = process_api_requests_from_file(&requests, record).await.unwrap();
+
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_succeeded, 1);
+    assert_eq!(tracker.num_tasks_failed, 1);
+    let errors = read_jsonl(recorded.file("errors.jsonl"));
+    assert!(errors[0]["error"].as_str().unwrap().contains("64"), "{}", errors[0]);
+    // Only the small response's head and body were saved
+    assert_eq!(std::fs::read_dir(recordings.file("")).unwrap().count(), 2);
+}
+
+#[tokio::test]
+async fn canaries_are_neither_recorded_nor_dead_lettered() {
+    let server = MockServer::start().await;
+    let recordings = TempDir::new("recordings");
+    let run = TempDir::new("canary-run");
+    let config = config(&server, &run)
+        .record_dir(Some(recordings.file("")))
+        .dead_letter_filepath(Some(run.file("dead-letter.jsonl")))
+        .build()
+        .unwrap();
+    let client = Client::new(config).unwrap();
+
+    let passed = client.canary(json!({ "input": "ok-1" })).await;
+    assert!(passed[0].outcome.is_ok(), "{:?}", passed[0].outcome);
+    let failed = client.canary(json!({ "input": "500" })).await;
+    assert!(failed[0].outcome.is_err(), "{:?}", failed[0].outcome);
+
+    // Both reached the server once, and left nothing behind
+    assert_eq!(server.requests().len(), 2);
+    let recorded = std::fs::read_dir(recordings.file("")).map(|entries| entries.count()).unwrap_or(0);
+    assert_eq!(recorded, 0);
+    assert!(!std::path::Path::new(&run.file("dead-letter.jsonl")).exists());
+}
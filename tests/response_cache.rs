@@ -0,0 +1,91 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, Config, ConfigBuilder};
+use common::{read_jsonl, MockServer, TempDir};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn config(server: &MockServer, dir: &TempDir, cache: &TempDir) -> ConfigBuilder {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .cache_dir(Some(cache.file("")))
+        .include_cache_status(true)
+        .max_requests_per_second(1000.0)
+        .max_attempts(1)
+        .workers(Some(1))
+}
+
+/// The `cached` marker of each record of a file, in task order
+fn cached(path: String) -> Vec<Value> {
+    let mut records = read_jsonl(path);
+    records.sort_by_key(|record| record["task_id"].as_u64());
+    records.iter().map(|record| record["cached"].clone()).collect()
+}
+
+#[tokio::test]
+async fn a_cached_response_answers_the_next_run_without_sending() {
+    let server = MockServer::start().await;
+    let cache = TempDir::new("cache");
+    let dir = TempDir::new("cache-run");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "ok-1" }), json!({ "input": "ok-2" })]);
+
+    process_api_requests_from_file(&requests, config(&server, &dir, &cache).build().unwrap()).await.unwrap();
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(cached(dir.file("results.jsonl")), [false, false]);
+
+    let tracker = process_api_requests_from_file(&requests, config(&server, &dir, &cache).build().unwrap()).await.unwrap();
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(cached(dir.file("results.jsonl")), [true, true]);
+    assert_eq!(tracker.lock().unwrap().num_cache_hits, 2);
+    let records = read_jsonl(dir.file("results.jsonl"));
+    assert!(records.iter().any(|record| record["echo"] == "ok-1"));
+}
+
+#[tokio::test]
+async fn expired_responses_are_sent_again() {
+    let server = MockServer::start().await;
+    let cache = TempDir::new("cache");
+    let dir = TempDir::new("cache-ttl");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "ok-1" })]);
+    let config = || config(&server, &dir, &cache).cache_ttl(Some(Duration::from_millis(100))).build().unwrap();
+
+    process_api_requests_from_file(&requests, config()).await.unwrap();
+    process_api_requests_from_file(&requests, config()).await.unwrap();
+    assert_eq!(server.requests().len(), 1);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    process_api_requests_from_file(&requests, config()).await.unwrap();
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(cached(dir.file("results.jsonl")), [false]);
+}
+
+#[tokio::test]
+async fn failed_responses_are_not_kept() {
+    let server = MockServer::start().await;
+    let cache = TempDir::new("cache");
+    let dir = TempDir::new("cache-failures");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "error-1" })]);
+
+    process_api_requests_from_file(&requests, config(&server, &dir, &cache).build().unwrap()).await.unwrap();
+    process_api_requests_from_file(&requests, config(&server, &dir, &cache).build().unwrap()).await.unwrap();
+    assert_eq!(server.attempts("error-1"), 2);
+    assert_eq!(std::fs::read_dir(cache.file("")).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn each_repeat_of_a_line_is_cached_on_its_own() {
+    let server = MockServer::start().await;
+    let cache = TempDir::new("cache");
+    let dir = TempDir::new("cache-repeat");
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "ok-1" })]);
+
+    process_api_requests_from_file(&requests, config(&server, &dir, &cache).repeat(3).build().unwrap()).await.unwrap();
+    assert_eq!(server.attempts("ok-1"), 3);
+    assert_eq!(cached(dir.file("results.jsonl")), [false, false, false]);
+
+    process_api_requests_from_file(&requests, config(&server, &dir, &cache).repeat(3).build().unwrap()).await.unwrap();
+    assert_eq!(server.attempts("ok-1"), 3);
+    assert_eq!(cached(dir.file("results.jsonl")), [true, true, true]);
+}
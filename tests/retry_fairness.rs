@@ -0,0 +1,87 @@
+use api_processor::{BackoffPolicy, Client, Config, Endpoint};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+/// Start a server that answers 429 to the first attempt of every `flaky` input
+async fn start_flaky_server() -> SocketAddr {
+    let attempts: Arc<Mutex<HashMap<String, usize>>> = Arc::default();
+    let make_service = make_service_fn(move |_| {
+        let attempts = Arc::clone(&attempts);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let attempts = Arc::clone(&attempts);
+                async move {
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    let payload: Value = serde_json::from_slice(&body).unwrap();
+                    let content = payload["messages"][1]["content"].as_str().unwrap().to_string();
+                    let attempt = {
+                        let mut attempts = attempts.lock().unwrap();
+                        let attempt = attempts.entry(content.clone()).or_insert(0);
+                        *attempt += 1;
+                        *attempt
+                    };
+                    let response = if content.starts_with("flaky") && attempt == 1 {
+                        Response::builder().status(StatusCode::TOO_MANY_REQUESTS).body(Body::from("{}"))
+                    } else {
+                        Response::builder().body(Body::from(json!({ "echo": content }).to_string()))
+                    };
+                    Ok::<_, Infallible>(response.unwrap())
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+#[tokio::test]
+async fn retries_and_fresh_work_both_complete_with_a_tiny_queue() {
+    let addr = start_flaky_server().await;
+    let config = Config::builder()
+        .endpoint(Endpoint::builder(format!("http://{}/", addr)).api_key("test").build().unwrap())
+        .max_requests_per_second(1000.0)
+        .max_attempts(3)
+        .backoff(BackoffPolicy {
+            base: Duration::from_millis(5),
+            multiplier: 1.0,
+            max: Duration::from_millis(5),
+        })
+        // A single slot means fresh work keeps the queue full the whole run
+        .queue_capacity(Some(1))
+        .build()
+        .unwrap();
+    let client = Client::new(config).unwrap();
+
+    let inputs: Vec<String> = (0..40)
+        .map(|i| if i % 2 == 0 { format!("flaky-{}", i) } else { format!("ok-{}", i) })
+        .collect();
+    let requests: Vec<Value> = inputs.iter().map(|input| json!({ "input": input })).collect();
+
+    let outcomes: Vec<_> = tokio::time::timeout(Duration::from_secs(10), client.stream(requests).collect::<Vec<_>>())
+        .await
+        .expect("run did not finish");
+
+    let mut echoed: Vec<String> = outcomes
+        .into_iter()
+        .map(|outcome| outcome.expect("task failed").response["echo"].as_str().unwrap().to_string())
+        .collect();
+    echoed.sort();
+    let mut expected = inputs.clone();
+    expected.sort();
+    assert_eq!(echoed, expected);
+
+    let tracker = client.status_tracker();
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_succeeded, 40);
+    assert_eq!(tracker.num_rate_limit_errors, 20);
+    assert_eq!(tracker.num_tasks_in_progress, 0);
+}
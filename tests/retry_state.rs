@@ -0,0 +1,124 @@
+mod common;
+
+use api_processor::{APIRequest, Client, ClientError, Config};
+use common::{read_jsonl, MockServer, TempDir};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// A request a previous run left in its retry state file, with one attempt left
+fn saved_request(task_id: usize, input: &str) -> String {
+    let mut line = HashMap::new();
+    line.insert("input".to_string(), json!(input));
+    let request = APIRequest {
+        task_id,
+        request_json: line.clone(),
+        attempts_left: 1,
+        max_attempts: 2,
+        metadata: None,
+        result: vec![],
+        original_input: line,
+        last_endpoint: None,
+        attempts_sent: 1,
+        auth_refreshed: false,
+        batch: vec![],
+    };
+    serde_json::to_string(&request).unwrap()
+}
+
+fn config(server: &MockServer, dir: &TempDir, skip: usize) -> Config {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .retry_state_filepath(Some(dir.file("retries.jsonl")))
+        .max_requests_per_second(1000.0)
+        .skip(skip)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn reloaded_retries_finish_alongside_the_rest_of_the_input() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("retry-state-continue");
+    std::fs::write(dir.file("retries.jsonl"), format!("{}\n", saved_request(1, "saved-1"))).unwrap();
+    let lines: Vec<Value> = (0..4).map(|i| json!({ "input": format!("line-{}", i) })).collect();
+    let requests = dir.write_jsonl("requests.jsonl", &lines);
+
+    // Lines 0 and 1 were handled by the previous run
+    Client::new(config(&server, &dir, 2)).unwrap().process_file(&requests).await.unwrap();
+
+    let mut task_ids: Vec<u64> = read_jsonl(dir.file("results.jsonl")).iter().map(|result| result["task_id"].as_u64().unwrap()).collect();
+    task_ids.sort();
+    assert_eq!(task_ids, [1, 2, 3]);
+    assert_eq!(server.attempts("saved-1"), 1);
+}
+
+#[tokio::test]
+async fn an_input_reaching_the_reloaded_task_ids_is_refused() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("retry-state-overlap");
+    let retries = format!("{}\n", saved_request(1, "saved-1"));
+    std::fs::write(dir.file("retries.jsonl"), &retries).unwrap();
+    // Restarts from task id 0, like the lines left in a resume file
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "line-0" }), json!({ "input": "line-1" })]);
+
+    let result = Client::new(config(&server, &dir, 0)).unwrap().process_file(&requests).await;
+
+    assert!(matches!(result, Err(ClientError::InvalidConfig(message)) if message.contains("task 1")));
+    assert!(server.requests().is_empty());
+    // The saved requests are kept for a run that can take them
+    assert_eq!(std::fs::read_to_string(dir.file("retries.jsonl")).unwrap(), retries);
+
+    // An input too short to reach them is not refused
+    let requests = dir.write_jsonl("requests.jsonl", &[json!({ "input": "line-0" })]);
+    Client::new(config(&server, &dir, 0)).unwrap().process_file(&requests).await.unwrap();
+    let mut task_ids: Vec<u64> = read_jsonl(dir.file("results.jsonl")).iter().map(|result| result["task_id"].as_u64().unwrap()).collect();
+    task_ids.sort();
+    assert_eq!(task_ids, [0, 1]);
+}
+
+#[tokio::test]
+async fn unprocessed_requests_are_finished_by_a_run_with_an_empty_input() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("retry-state-unprocessed");
+    let lines: Vec<Value> = (0..5).map(|i| json!({ "input": format!("line-{}", i) })).collect();
+    let requests = dir.write_jsonl("requests.jsonl", &lines);
+    // The budget stops the run after two requests, leaving the rest unprocessed
+    let config = Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .unprocessed_filepath(Some(dir.file("unprocessed.jsonl")))
+        .max_requests_per_second(1000.0)
+        .max_requests(Some(2))
+        .workers(Some(1))
+        .build()
+        .unwrap();
+    Client::new(config).unwrap().process_file(&requests).await.unwrap();
+    let unprocessed = read_jsonl(dir.file("unprocessed.jsonl"));
+    assert!(!unprocessed.is_empty());
+
+    // Continuing the input alongside them would reuse their task ids
+    std::fs::copy(dir.file("unprocessed.jsonl"), dir.file("retries.jsonl")).unwrap();
+    let result = Client::new(config_appending(&server, &dir)).unwrap().process_file(&requests).await;
+    assert!(matches!(result, Err(ClientError::InvalidConfig(_))));
+
+    let empty = dir.write_jsonl("empty.jsonl", &[]);
+    Client::new(config_appending(&server, &dir)).unwrap().process_file(&empty).await.unwrap();
+    let mut task_ids: Vec<u64> = read_jsonl(dir.file("results.jsonl")).iter().map(|result| result["task_id"].as_u64().unwrap()).collect();
+    task_ids.sort();
+    assert_eq!(task_ids, [0, 1, 2, 3, 4]);
+}
+
+fn config_appending(server: &MockServer, dir: &TempDir) -> Config {
+    Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .retry_state_filepath(Some(dir.file("retries.jsonl")))
+        .max_requests_per_second(1000.0)
+        .append(true)
+        .build()
+        .unwrap()
+}
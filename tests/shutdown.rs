@@ -0,0 +1,116 @@
+mod common;
+
+use api_processor::{process_api_requests_from_file, BackoffPolicy, Client, ClientError, Config};
+use common::{read_jsonl, MockServer, TempDir};
+use serde_json::json;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+/// Backoff long enough that the input is exhausted while retries are still waiting
+fn slow_backoff() -> BackoffPolicy {
+    BackoffPolicy {
+        base: Duration::from_millis(200),
+        multiplier: 1.0,
+        max: Duration::from_millis(200),
+    }
+}
+
+#[tokio::test]
+async fn run_ends_only_after_retries_in_flight_finish() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("shutdown-retries");
+    let requests = dir.write_jsonl(
+        "requests.jsonl",
+        &[json!({ "input": "ok" }), json!({ "input": "flaky-429" }), json!({ "input": "flaky-503" })],
+    );
+    let config = Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .max_requests_per_second(1000.0)
+        .max_attempts(3)
+        .retry_on("connect,timeout,429,503".parse().unwrap())
+        .backoff(slow_backoff())
+        .build()
+        .unwrap();
+
+    let tracker = tokio::time::timeout(Duration::from_secs(10), process_api_requests_from_file(&requests, config))
+        .await
+        .expect("run did not finish")
+        .unwrap();
+
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_succeeded, 3);
+    assert_eq!(tracker.num_tasks_in_progress, 0);
+    assert_eq!(server.attempts("flaky-429"), 2);
+    assert_eq!(server.attempts("flaky-503"), 2);
+    assert_eq!(read_jsonl(dir.file("results.jsonl")).len(), 3);
+    assert!(read_jsonl(dir.file("errors.jsonl")).is_empty());
+}
+
+#[tokio::test]
+async fn stream_ends_when_every_retry_is_exhausted() {
+    let server = MockServer::start().await;
+    let config = Config::builder()
+        .endpoint(server.endpoint())
+        .max_requests_per_second(1000.0)
+        .max_attempts(2)
+        .backoff(slow_backoff())
+        .build()
+        .unwrap();
+    let client = Client::new(config).unwrap();
+
+    let outcomes: Vec<_> = tokio::time::timeout(
+        Duration::from_secs(10),
+        client.stream(vec![json!({ "input": "429" }), json!({ "input": "429" })]).collect::<Vec<_>>(),
+    )
+    .await
+    .expect("stream did not end");
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(outcomes.iter().all(Result::is_err));
+    assert_eq!(server.attempts("429"), 4);
+    assert_eq!(client.status_tracker().lock().unwrap().num_tasks_in_progress, 0);
+}
+
+#[tokio::test]
+async fn empty_input_ends_immediately() {
+    let server = MockServer::start().await;
+    let config = Config::builder().endpoint(server.endpoint()).build().unwrap();
+    let client = Client::new(config).unwrap();
+
+    let outcomes: Vec<_> = tokio::time::timeout(Duration::from_secs(5), client.stream(Vec::new()).collect::<Vec<_>>())
+        .await
+        .expect("stream did not end");
+
+    assert!(outcomes.is_empty());
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn an_aborted_run_does_not_stop_the_next_one() {
+    let server = MockServer::start().await;
+    let dir = TempDir::new("abort-then-run");
+    let config = Config::builder()
+        .endpoint(server.endpoint())
+        .save_filepath(dir.file("results.jsonl"))
+        .error_filepath(dir.file("errors.jsonl"))
+        .max_requests_per_second(1000.0)
+        .max_attempts(1)
+        .workers(Some(1))
+        .abort_after_consecutive_failures(Some(2))
+        .build()
+        .unwrap();
+    let client = Client::new(config).unwrap();
+
+    let failing: Vec<_> = (0..5).map(|i| json!({ "input": format!("error-{}", i) })).collect();
+    let aborted = client.process_requests(failing).await;
+    assert!(matches!(aborted, Err(ClientError::Aborted(_))));
+
+    // The streak and the stop belong to the first run only
+    client.process_requests(vec![json!({ "input": "ok-1" }), json!({ "input": "ok-2" })]).await.unwrap();
+    let mut inputs: Vec<String> = read_jsonl(dir.file("results.jsonl")).iter().map(|result| result["input"].as_str().or_else(|| result["echo"].as_str()).unwrap().to_string()).collect();
+    inputs.sort();
+    assert_eq!(inputs, ["ok-1", "ok-2"]);
+    assert!(!client.shutdown_handle().is_requested());
+}
@@ -0,0 +1,93 @@
+//! Signatures checked against the AWS Signature Version 4 test suite
+#![cfg(feature = "sigv4")]
+
+use api_processor::SigV4Config;
+use hyper::Uri;
+
+/// The test suite's credentials and scope
+fn config(service: &str) -> SigV4Config {
+    SigV4Config {
+        region: "us-east-1".to_string(),
+        service: service.to_string(),
+        access_key_id: "AKIDEXAMPLE".to_string(),
+        secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        session_token: None,
+    }
+}
+
+const DATE: &str = "20150830T123600Z";
+const HEADERS: &[(&str, &str)] = &[("host", "example.amazonaws.com"), ("x-amz-date", DATE)];
+
+fn signature(method: &str, uri: &str, headers: &[(&str, &str)], body: &[u8]) -> String {
+    let authorization = config("service").authorization(method, &uri.parse::<Uri>().unwrap(), headers, body, DATE);
+    authorization.rsplit("Signature=").next().unwrap().to_string()
+}
+
+#[test]
+fn vanilla_requests_match_the_test_suite() {
+    assert_eq!(
+        config("service").authorization("GET", &"https://example.amazonaws.com/".parse().unwrap(), HEADERS, b"", DATE),
+        "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, \
+         Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+    );
+    assert_eq!(
+        signature("POST", "https://example.amazonaws.com/", HEADERS, b""),
+        "5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b"
+    );
+    // get-unreserved: unreserved characters are left as they are
+    assert_eq!(
+        signature("GET", "https://example.amazonaws.com/-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", HEADERS, b""),
+        "07ef7494c76fa4850883e2b006601f940f8a34d404d0cfa977f52a65bbf5f24f"
+    );
+}
+
+#[test]
+fn signed_headers_are_sorted_and_the_body_is_hashed() {
+    // post-x-www-form-urlencoded, with the headers given out of order
+    let headers = [("x-amz-date", DATE), ("host", "example.amazonaws.com"), ("content-type", "application/x-www-form-urlencoded")];
+    let authorization = config("service").authorization("POST", &"https://example.amazonaws.com/".parse().unwrap(), &headers, b"Param1=value1", DATE);
+    assert!(authorization.contains("SignedHeaders=content-type;host;x-amz-date,"), "{}", authorization);
+    assert!(authorization.ends_with("Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"));
+}
+
+#[test]
+fn query_parameters_are_signed_in_canonical_order() {
+    let cases = [
+        // get-vanilla-empty-query-key
+        ("Param1=value1", "a67d582fa61cc504c4bae71f336f98b97f1ea3c7a6bfe1b6e45aec72011b9aeb"),
+        // get-vanilla-query-order-key-case: sorted by name
+        ("Param2=value2&Param1=value1", "b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"),
+        // get-vanilla-query-order-key: by byte value, so uppercase first
+        ("Param1=value2&Param1=Value1", "eedbc4e291e521cf13422ffca22be7d2eb8146eecf653089df300a15b2382bd1"),
+        // get-vanilla-query-order-value: then by value
+        ("Param1=value2&Param1=value1", "5772eed61e12b33fae39ee5e7012498b51d56abc0abb7c60486157bd471c4694"),
+    ];
+    for (query, expected) in cases.iter() {
+        assert_eq!(signature("GET", &format!("https://example.amazonaws.com/?{}", query), HEADERS, b""), *expected, "query {}", query);
+    }
+}
+
+#[test]
+fn colons_in_bedrock_model_ids_are_encoded_in_the_canonical_uri() {
+    let bedrock = config("bedrock");
+    let headers = [
+        ("content-type", "application/json"),
+        ("host", "bedrock-runtime.us-east-1.amazonaws.com"),
+        ("x-amz-date", DATE),
+    ];
+    let sign = |path: &str| {
+        let uri: Uri = format!("https://bedrock-runtime.us-east-1.amazonaws.com{}", path).parse().unwrap();
+        let authorization = bedrock.authorization("POST", &uri, &headers, br#"{"prompt":"hi"}"#, DATE);
+        authorization.rsplit("Signature=").next().unwrap().to_string()
+    };
+    // Canonical URI /model/anthropic.claude-3-sonnet-20240229-v1%3A0/invoke
+    assert_eq!(
+        sign("/model/anthropic.claude-3-sonnet-20240229-v1:0/invoke"),
+        "9616fae9eabbaac885f08666b971bcc200d03566cb034fecf9b1871c3edf9453"
+    );
+    // An already encoded colon is encoded again, to %253A, as the AWS SDKs send it
+    assert_eq!(
+        sign("/model/anthropic.claude-3-sonnet-20240229-v1%3A0/invoke"),
+        "8c4adc3d1e34249e84714076e7234d9bfa375a9077659ede79b2d4ce9a1fe522"
+    );
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use api_processor::{BackoffPolicy, Client, ClientError, Config, Endpoint, FailureCategory, RetryPolicy};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+/// Start a server whose first `truncated` responses promise a longer body than
+/// they send before closing the connection; later responses are complete
+async fn start_truncating_server(truncated: usize) -> (SocketAddr, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    let addr = common::start_raw_server(move |request_number| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        async move {
+            let body = json!({ "echo": "ok" }).to_string();
+            // Closing the connection cuts a truncated body short
+            if request_number < truncated {
+                format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}", body.len() + 100, body)
+            } else {
+                format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}", body.len(), body)
+            }
+        }
+    })
+    .await;
+    (addr, requests)
+}
+
+/// A client for the server with short backoffs
+fn client(addr: SocketAddr, retry_on: RetryPolicy) -> Client {
+    let config = Config::builder()
+        .endpoint(Endpoint::builder(format!("http://{}/", addr)).api_key("test").build().unwrap())
+        .max_requests_per_second(1000.0)
+        .max_attempts(3)
+        .retry_on(retry_on)
+        .backoff(BackoffPolicy {
+            base: Duration::from_millis(5),
+            multiplier: 1.0,
+            max: Duration::from_millis(5),
+        })
+        .build()
+        .unwrap();
+    Client::new(config).unwrap()
+}
+
+#[tokio::test]
+async fn bodies_cut_short_are_retried_by_default() {
+    let (addr, requests) = start_truncating_server(1).await;
+    let client = client(addr, RetryPolicy::default());
+
+    let outcomes: Vec<_> = tokio::time::timeout(Duration::from_secs(10), client.stream(vec![json!({ "input": "a" })]).collect::<Vec<_>>())
+        .await
+        .expect("run did not finish");
+
+    assert_eq!(outcomes.len(), 1);
+    assert_eq!(outcomes[0].as_ref().expect("task failed").response["echo"], "ok");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    let tracker = client.status_tracker();
+    let tracker = tracker.lock().unwrap();
+    assert_eq!(tracker.num_tasks_succeeded, 1);
+    assert_eq!(tracker.num_other_errors, 1);
+}
+
+#[tokio::test]
+async fn bodies_cut_short_fail_without_the_body_condition() {
+    let (addr, requests) = start_truncating_server(1).await;
+    let client = client(addr, "connect,timeout,429".parse().unwrap());
+
+    let outcomes: Vec<_> = tokio::time::timeout(Duration::from_secs(10), client.stream(vec![json!({ "input": "a" })]).collect::<Vec<_>>())
+        .await
+        .expect("run did not finish");
+
+    assert_eq!(outcomes.len(), 1);
+    match &outcomes[0] {
+        Err(ClientError::Request { category, .. }) => assert_eq!(*category, FailureCategory::Body),
+        other => panic!("expected a body failure, got {:?}", other),
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    assert_eq!(client.status_tracker().lock().unwrap().num_other_errors, 1);
+}
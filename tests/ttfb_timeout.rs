@@ -0,0 +1,59 @@
+mod common;
+
+use api_processor::{BackoffPolicy, Client, ClientError, Config, Endpoint, FailureCategory, RetryPolicy};
+use serde_json::json;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+/// Start a server that holds back the response headers of its first `slow` requests for a second
+async fn start_slow_header_server(slow: usize) -> Endpoint {
+    let addr = common::start_raw_server(move |request_number| async move {
+        if request_number < slow {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        let body = json!({ "echo": "ok" }).to_string();
+        format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}", body.len(), body)
+    })
+    .await;
+    Endpoint::builder(format!("http://{}/", addr)).api_key("test").build().unwrap()
+}
+
+fn client(endpoint: Endpoint, retry_on: RetryPolicy) -> Client {
+    let config = Config::builder()
+        .endpoint(endpoint)
+        .ttfb_timeout(Some(Duration::from_millis(100)))
+        .max_requests_per_second(1000.0)
+        .max_attempts(3)
+        .retry_on(retry_on)
+        .backoff(BackoffPolicy {
+            base: Duration::from_millis(5),
+            multiplier: 1.0,
+            max: Duration::from_millis(5),
+        })
+        .build()
+        .unwrap();
+    Client::new(config).unwrap()
+}
+
+#[tokio::test]
+async fn late_response_headers_time_out_and_are_retried() {
+    let client = client(start_slow_header_server(1).await, RetryPolicy::default());
+
+    let outcomes: Vec<_> = client.stream(vec![json!({ "input": "ok" })]).collect().await;
+    assert_eq!(outcomes[0].as_ref().unwrap().response["echo"], "ok");
+    assert_eq!(client.status_tracker().lock().unwrap().num_other_errors, 1);
+}
+
+#[tokio::test]
+async fn a_timeout_that_is_not_retried_fails_the_request() {
+    let client = client(start_slow_header_server(1).await, "connect".parse().unwrap());
+
+    let outcomes: Vec<_> = client.stream(vec![json!({ "input": "ok" })]).collect().await;
+    match &outcomes[0] {
+        Err(ClientError::Request { category, .. }) => assert_eq!(*category, FailureCategory::Timeout),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    let tracker = client.status_tracker();
+    let tracker = tracker.lock().unwrap();
+    assert_eq!((tracker.num_other_errors, tracker.num_tasks_failed), (1, 1));
+}
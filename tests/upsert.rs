@@ -0,0 +1,62 @@
+mod common;
+
+use api_processor::{compact_result_file, process_api_requests_from_file, Config, MergeStats};
+use common::{read_jsonl, MockServer, TempDir};
+use hyper::StatusCode;
+use serde_json::{json, Value};
+
+#[test]
+fn compaction_keeps_the_last_record_of_each_task_in_file_order() {
+    let dir = TempDir::new("upsert-compact");
+    let path = dir.file("results.jsonl");
+    let lines = [
+        json!({ "task_id": 0, "run": 1 }).to_string(),
+        json!({ "task_id": 1, "run": 1 }).to_string(),
+        "not json".to_string(),
+        String::new(),
+        json!({ "task_id": 0, "run": 2 }).to_string(),
+        json!({ "note": "no task id" }).to_string(),
+        json!({ "task_id": 1, "run": 2 }).to_string(),
+        json!({ "task_id": 1, "run": 3 }).to_string(),
+    ];
+    std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+
+    let stats = compact_result_file(&path).unwrap();
+    assert_eq!(stats, MergeStats { records: 2, duplicates: 3, skipped: 2 });
+    let compacted = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(compacted.lines().collect::<Vec<_>>(), [&*lines[2], &*lines[4], &*lines[5], &*lines[7]]);
+
+    // A missing file has nothing to compact
+    assert_eq!(compact_result_file(&dir.file("missing.jsonl")).unwrap(), MergeStats::default());
+}
+
+#[tokio::test]
+async fn a_top_up_run_replaces_the_results_it_redoes() {
+    let server = MockServer::with_responder(|content, attempt| (StatusCode::OK, json!({ "echo": content, "attempt": attempt }))).await;
+    let dir = TempDir::new("upsert-run");
+    let lines: Vec<Value> = (0..3).map(|i| json!({ "input": format!("line-{}", i) })).collect();
+    let requests = dir.write_jsonl("requests.jsonl", &lines);
+    let config = |skip| {
+        Config::builder()
+            .endpoint(server.endpoint())
+            .save_filepath(dir.file("results.jsonl"))
+            .error_filepath(dir.file("errors.jsonl"))
+            .max_requests_per_second(1000.0)
+            .append(true)
+            .upsert(true)
+            .skip(skip)
+            .build()
+            .unwrap()
+    };
+
+    process_api_requests_from_file(&requests, config(0)).await.unwrap();
+    // Redo the last two lines
+    process_api_requests_from_file(&requests, config(1)).await.unwrap();
+
+    let mut records: Vec<(u64, u64)> = read_jsonl(dir.file("results.jsonl"))
+        .iter()
+        .map(|record| (record["task_id"].as_u64().unwrap(), record["attempt"].as_u64().unwrap()))
+        .collect();
+    records.sort();
+    assert_eq!(records, [(0, 1), (1, 2), (2, 2)]);
+}
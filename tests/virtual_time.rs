@@ -0,0 +1,198 @@
+//! Timing tests on tokio's paused clock: every wait in the client is a tokio
+//! timer, so delays of seconds are checked without sleeping for them.
+//!
+//! A paused clock jumps to the next timer whenever the runtime waits on real
+//! I/O, so send times come from an observer, taken just after the client's own
+//! timer fires, rather than from when the server sees a request. The server
+//! closes every connection so no pooled connection adds a timer of its own.
+
+mod common;
+
+use api_processor::{APIRequest, BackoffPolicy, Client, Config, Endpoint, RequestObserver};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+
+/// Start a server answering 429 to its first `failures` requests and 200 to later ones
+async fn start_server(failures: usize) -> SocketAddr {
+    common::start_raw_server(move |request_number| async move {
+        let (status, body) = if request_number < failures {
+            ("429 Too Many Requests", "{}".to_string())
+        } else {
+            ("200 OK", json!({ "echo": "ok" }).to_string())
+        };
+        format!("HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}", status, body.len(), body)
+    })
+    .await
+}
+
+/// Records when each attempt is sent
+#[derive(Default)]
+struct SendTimes(Mutex<Vec<(usize, Instant)>>);
+
+impl RequestObserver for SendTimes {
+    fn on_start(&self, request: &APIRequest, _endpoint: &str) {
+        self.0.lock().unwrap().push((request.task_id, Instant::now()));
+    }
+}
+
+impl SendTimes {
+    /// Time between consecutive sends
+    fn gaps(&self) -> Vec<Duration> {
+        self.0.lock().unwrap().windows(2).map(|pair| pair[1].1 - pair[0].1).collect()
+    }
+}
+
+/// A client for the server that records its send times
+fn client(config: Config) -> (Client, Arc<SendTimes>) {
+    let times = Arc::new(SendTimes::default());
+    let client = Client::new(config).unwrap().with_observer(Arc::clone(&times) as Arc<dyn RequestObserver>);
+    (client, times)
+}
+
+fn endpoint(addr: SocketAddr) -> Endpoint {
+    Endpoint::builder(format!("http://{}/", addr)).api_key("test").build().unwrap()
+}
+
+/// Assert a duration is within a millisecond of the expected one, the pacing of the other requests in flight
+fn assert_close(actual: Duration, expected: Duration) {
+    let tolerance = Duration::from_millis(1);
+    assert!(
+        actual + tolerance >= expected && actual <= expected + tolerance,
+        "expected {:?}, got {:?}",
+        expected,
+        actual
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn retries_wait_out_the_backoff_policy() {
+    let addr = start_server(2).await;
+    let config = Config::builder()
+        .endpoint(endpoint(addr))
+        .max_requests_per_second(1000.0)
+        .max_attempts(3)
+        .backoff(BackoffPolicy {
+            base: Duration::from_secs(5),
+            multiplier: 3.0,
+            max: Duration::from_secs(60),
+        })
+        .build()
+        .unwrap();
+    let (client, times) = client(config);
+
+    let outcomes: Vec<_> = client.stream(vec![json!({ "input": "a" })]).collect().await;
+
+    assert!(outcomes[0].is_ok());
+    let gaps = times.gaps();
+    assert_eq!(gaps.len(), 2);
+    // Retry n waits base * multiplier^n
+    assert_close(gaps[0], Duration::from_secs(15));
+    assert_close(gaps[1], Duration::from_secs(45));
+}
+
+#[tokio::test(start_paused = true)]
+async fn backoff_delays_stop_growing_at_the_maximum() {
+    let addr = start_server(3).await;
+    let config = Config::builder()
+        .endpoint(endpoint(addr))
+        .max_requests_per_second(1000.0)
+        .max_attempts(4)
+        .backoff(BackoffPolicy {
+            base: Duration::from_secs(10),
+            multiplier: 10.0,
+            max: Duration::from_secs(120),
+        })
+        .build()
+        .unwrap();
+    let (client, times) = client(config);
+
+    let outcomes: Vec<_> = client.stream(vec![json!({ "input": "a" })]).collect().await;
+
+    assert!(outcomes[0].is_ok());
+    let gaps = times.gaps();
+    assert_eq!(gaps.len(), 3);
+    assert_close(gaps[0], Duration::from_secs(100));
+    assert_close(gaps[1], Duration::from_secs(120));
+    assert_close(gaps[2], Duration::from_secs(120));
+}
+
+#[tokio::test(start_paused = true)]
+async fn requests_are_paced_at_slow_rates() {
+    let addr = start_server(0).await;
+    let config = Config::builder()
+        .endpoint(endpoint(addr))
+        .max_requests_per_second(0.25)
+        .max_attempts(1)
+        .build()
+        .unwrap();
+    let (client, times) = client(config);
+
+    let requests: Vec<Value> = (0..4).map(|i| json!({ "input": i.to_string() })).collect();
+    let outcomes: Vec<_> = client.stream(requests).collect().await;
+
+    assert_eq!(outcomes.len(), 4);
+    let gaps = times.gaps();
+    assert_eq!(gaps.len(), 3);
+    for gap in gaps {
+        assert_close(gap, Duration::from_secs(4));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn retries_ending_together_are_spread_by_the_retry_rate() {
+    let addr = start_server(3).await;
+    let config = Config::builder()
+        .endpoint(endpoint(addr))
+        .max_requests_per_second(1000.0)
+        .max_attempts(2)
+        .backoff(BackoffPolicy {
+            base: Duration::from_secs(1),
+            multiplier: 1.0,
+            max: Duration::from_secs(1),
+        })
+        .max_retry_rate(Some(0.5))
+        .build()
+        .unwrap();
+    let (client, times) = client(config);
+
+    let requests: Vec<Value> = (0..3).map(|i| json!({ "input": i.to_string() })).collect();
+    let outcomes: Vec<_> = client.stream(requests).collect().await;
+
+    assert!(outcomes.iter().all(Result::is_ok));
+    // Three first attempts a millisecond apart, then three retries two seconds apart
+    let gaps = times.gaps();
+    assert_eq!(gaps.len(), 5);
+    for gap in &gaps[3..] {
+        assert_close(*gap, Duration::from_secs(2));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn nothing_is_sent_after_the_maximum_runtime() {
+    let addr = start_server(0).await;
+    let config = Config::builder()
+        .endpoint(endpoint(addr))
+        .max_requests_per_second(1.0)
+        .max_attempts(1)
+        .max_runtime(Some(Duration::from_millis(3500)))
+        .build()
+        .unwrap();
+    let (client, times) = client(config);
+
+    let start = Instant::now();
+    let requests: Vec<Value> = (0..10).map(|i| json!({ "input": i.to_string() })).collect();
+    let outcomes: Vec<_> = client.stream(requests).collect().await;
+
+    // Sent at 0, 1, 2 and 3 seconds
+    assert_eq!(outcomes.len(), 4);
+    let sent: Vec<Duration> = times.0.lock().unwrap().iter().map(|(_, sent)| *sent - start).collect();
+    assert_eq!(sent.len(), 4);
+    for (i, sent) in sent.into_iter().enumerate() {
+        assert_close(sent, Duration::from_secs(i as u64));
+    }
+    assert_eq!(client.status_tracker().lock().unwrap().num_tasks_unprocessed, 6);
+}